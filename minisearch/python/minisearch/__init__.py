@@ -1 +1,2 @@
 from .main import MiniSearch
+from .rust import QueryBuilder, SearchOptions, levenshtein, within_distance
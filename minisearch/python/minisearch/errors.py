@@ -2,6 +2,9 @@ from .rust import (
     BincodeDecodeError,
     BincodeEncodeError,
     CompressException,
+    DocumentCorrupted,
+    DuplicatePosting,
+    InvalidEncoding,
     TryFromSliceException,
     UlidDecodeError,
     UlidMonotonicError,
@@ -27,6 +30,8 @@ class IndexGetError(UlidDecodeError):
 
 class IndexAddError(
     UlidMonotonicError,
+    DuplicatePosting,
+    InvalidEncoding,
     BincodeDecodeError,
     BincodeEncodeError,
     CompressException,
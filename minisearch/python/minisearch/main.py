@@ -1,16 +1,21 @@
 from .rust import Search as SearchRs
-from .rust import Document, Result
-from typing import Generator
+from .rust import Document, QueryBuilder, Result, SearchOptions
+from typing import Callable, Generator, Iterator
 from contextlib import contextmanager
 
 
 class Index:
     def __init__(self, dir: str, conf: str | None = None) -> None:
         """
-        Create or load an index stored in "dir"
+        Create or load an index stored in "dir", warnings (e.g. unreadable
+        tokens file being reset) are emitted to python "minisearch" logger
+
+        Directory already open in this process isn't loaded again, the new
+        index shares its state until the last of them is dropped
 
         Raises:
             IndexInitError: load/create index state failed
+            ValueError: directory is already open with a different config
         """
         self._search_rs = SearchRs(dir, conf)
 
@@ -27,23 +32,266 @@ class Index:
         finally:
             self.flush()
 
-    def get(self, id: str) -> Document:
+    def get(self, id: str, max_content_bytes: int | None = None) -> Document:
         """
         Fetch a document by ULID string
 
+        "max_content_bytes" cuts content of the document as in search
+
         Raises:
             IndexGetError: invalid ULID
+            ValueError: "max_content_bytes" is 0
         """
-        return self._search_rs.get(id)
+        return self._search_rs.get(id, max_content_bytes)
 
-    def add(self, document: str) -> bool:
+    def parse(self, query: str, fuzziness: int = 0) -> dict:
         """
-        Add a document and return its ULID string
+        Parse query without executing it and return its tree, useful for
+        validating or logging user queries
+
+        Every node is a dict with "type" ("clause", "and", "or", "not",
+        "optional", "any" or "all"), operators hold their operands in "queries"
+        or "query", clause holds "terms" (dicts with "text", "fuzz", "auto_fuzz"
+        and "literal"), "slop" and "field", "any" holds alternative forms of a
+        clause with multi word synonyms, e.g. "new york" and "nyc"
 
         Raises:
+            ValueError: invalid query syntax
+        """
+        return self._search_rs.parse(query, fuzziness)
+
+    def suggest_queries(self, prefix: str, limit: int = 10) -> list[str]:
+        """
+        Return recorded queries starting with "prefix", queries searched more
+        often and more recently come first, queries are recorded (in normalized
+        form, lowercase with single spaces) only if they returned any result
+
+        Raises:
+            ValueError: query log is disabled, i.e. "query_log_size" isn't set
+        """
+        return self._search_rs.suggest_queries(prefix, limit)
+
+    def health(self) -> dict[str, bool]:
+        """
+        Run quick structural checks suitable for a readiness probe, no document
+        or posting is read, returns result of every check and "healthy" which
+        is true if all of them passed:
+
+        - buffers: files buffered documents and index logs are flushed to are
+          writable
+        - segments: files of all documents segments are readable
+        - logs: the last index log of every shard is within index file and
+          decodes
+        - tokens: tokens store maps every token to its id consistently
+        """
+        return self._search_rs.health()
+
+    def export_stats(self, path: str) -> None:
+        """
+        Write corpus statistics as csv files into "path" directory, for offline
+        analysis without querying the index term by term:
+
+        - terms.csv: "term", "display" form, "df" (number of documents) and
+          "tf" (total number of occurrences) of every indexed term
+        - doc_lengths.csv: number of "documents" of every "length"
+        - vocabulary_growth.csv: number of distinct terms ("vocabulary") in
+          first "documents" documents, in order they were added
+        """
+        self._search_rs.export_stats(path)
+
+    def postings(
+        self, term: str, field: str | None = None
+    ) -> Iterator[tuple[str, int, list[int]]]:
+        """
+        Iterate postings of a term in order documents were added, every posting is
+        (document ULID string, term frequency, positions), term is normalized like
+        query terms, "field" selects postings of the term in given field instead,
+        useful for building custom retrieval logic or analytics on top of the index
+        """
+        return self._search_rs.postings(term, field)
+
+    def display_term(self, term: str) -> str | None:
+        """
+        Return human readable form of indexed term, i.e. original word from
+        which it was first indexed, e.g. "Running" for "runs", or None if
+        term isn't indexed
+        """
+        return self._search_rs.display_term(term)
+
+    def analyze(self, text: str) -> list[tuple[str, int]]:
+        """
+        Return (token, position) pairs produced from "text" by the same analysis
+        chain documents are indexed with (stop words, stemming, edge n-grams or
+        custom tokenizer), ordered by position, useful for finding out why a
+        query doesn't match a document
+        """
+        return self._search_rs.analyze(text)
+
+    def read_raw(self, id: str) -> bytes:
+        """
+        Fetch compressed document content exactly as stored on disk, useful
+        for recovery when reading content raises DocumentCorrupted
+
+        Raises:
+            IndexGetError: invalid ULID
+        """
+        return self._search_rs.read_raw(id)
+
+    def add(
+        self,
+        document: str | bytes,
+        attributes: dict[str, float] | None = None,
+        fields: dict[str, str | list[str]] | None = None,
+        boost: float = 1.0,
+        errors: str = "strict",
+        payload: dict[str, str] | None = None,
+    ) -> bool:
+        """
+        Add a document and return its ULID string, optional numeric "attributes"
+        are stored alongside the document and can be used in score scripts
+
+        Optional "fields" text is indexed (but not stored) per field name and can
+        be searched with field scoped queries, e.g. title:foo or body:"bar baz"~2,
+        field can hold list of values, e.g. tags, phrases never match across them
+
+        Optional "boost" is a static weight the document score is multiplied by,
+        e.g. 2.0 for editorially promoted documents
+
+        Optional "payload" is stored (but not indexed) with the document and
+        returned as its payload in results, e.g. url or thumbnail, payload isn't
+        searchable and doesn't affect scores
+
+        Document can be str or utf-8 encoded bytes, with "errors" set to "strict"
+        content which isn't valid utf-8 (e.g. str with lone surrogates) raises
+        InvalidEncoding, "replace" stores it with U+FFFD replacement characters
+
+        Words past configured max_positions aren't indexed, such document has
+        positions_truncated flag set, unless on_position_overflow = "reject" fails
+        the add
+
+        Raises:
+            IndexAddError: add operation failed
+            InvalidEncoding: document isn't valid utf-8 with "strict" errors
+            ValueError: invalid field name, errors mode or boost which isn't positive,
+                or document exceeds max_positions with "reject" overflow policy
+        """
+        return self._search_rs.add(
+            document, attributes, fields, boost, errors, payload
+        )
+
+    def add_idempotent(
+        self,
+        document: str | bytes,
+        attributes: dict[str, float] | None = None,
+        fields: dict[str, str | list[str]] | None = None,
+        boost: float = 1.0,
+        errors: str = "strict",
+        payload: dict[str, str] | None = None,
+    ) -> str:
+        """
+        Add a document unless document with the same content already exists,
+        return ULID string of added or already existing document, useful for
+        at-least-once ingestion pipelines, content is compared after decoding
+        with "errors" as in add, payload of existing document isn't replaced
+
+        Raises:
+            IndexAddError: add operation failed
+            InvalidEncoding: document isn't valid utf-8 with "strict" errors
+            ValueError: invalid field name, errors mode or boost which isn't positive,
+                or document exceeds max_positions with "reject" overflow policy
+        """
+        return self._search_rs.add_idempotent(
+            document, attributes, fields, boost, errors, payload
+        )
+
+    def reindex_into(
+        self,
+        dir: str,
+        conf: str | None = None,
+        transform: Callable[[Document, dict[str, float] | None], dict | None]
+        | None = None,
+    ) -> "Index":
+        """
+        Copy all documents into a fresh index in "dir" (e.g. with a different
+        "conf" after analyzer or schema change) and return it, documents keep
+        their ids, so the new index can replace this one with MiniSearch alias
+        swap, this index stays searchable meanwhile
+
+        "transform" is called with every document and its attributes and
+        returns None to skip the document, or a dict with any of "doc",
+        "attributes", "fields", "boost" and "payload" replacing the original
+        values, fields aren't stored, so transform has to return them for
+        every document added with fields (empty dict drops them), otherwise
+        reindexing fails instead of silently breaking field queries, without
+        transform it fails before anything is copied, payload is copied
+
+        Raises:
+            IndexInitError: load/create of the new index failed
             IndexAddError: add operation failed
+            ValueError: new index isn't empty or is this index, or document added
+                with fields isn't given them by transform
+        """
+        index = Index(dir, conf)
+        self._search_rs.reindex_into(index._search_rs, transform)
+        return index
+
+    def register_query(self, name: str, query: str) -> None:
+        """
+        Register alert query under "name", every added document is matched
+        against registered queries, registered queries are kept in memory only
+
+        Raises:
+            ValueError: invalid query syntax
+        """
+        return self._search_rs.register_query(name, query)
+
+    def unregister_query(self, name: str) -> bool:
+        """Remove registered alert query, return False if it didn't exist"""
+        return self._search_rs.unregister_query(name)
+
+    def on_match(self, callback: Callable[[str, list[str]], None] | None) -> None:
+        """
+        Set callback invoked with added document ULID and names of matched
+        registered queries, None disables matching
+        """
+        return self._search_rs.on_match(callback)
+
+    def set_tokenizer(
+        self, callback: Callable[[str], list[tuple[str, int]]] | None
+    ) -> None:
+        """
+        Replace native tokenization with callback receiving document or query text
+        and returning (token, position) pairs, tokens are indexed and searched as
+        returned, without stop words removal and stemming. The callback is kept in
+        memory only and has to be set again after the index is reopened, None
+        restores the native tokenizer.
+
+        Calling python for every document and query term makes indexing and
+        searching much slower, a warning is logged when the callback is set.
+        Exception raised by the callback is propagated from add and search.
+        """
+        return self._search_rs.set_tokenizer(callback)
+
+    def attributes(self, id: str) -> dict[str, float]:
         """
-        return self._search_rs.add(document)
+        Fetch numeric attributes of a document by ULID string
+
+        Raises:
+            IndexGetError: invalid ULID
+        """
+        return self._search_rs.attributes(id)
+
+    def update_attributes(self, id: str, attributes: dict[str, float]) -> None:
+        """
+        Merge "attributes" into numeric attributes of a document, e.g. to bump
+        a view counter used by "score_script", document text isn't tokenized
+        again and its postings aren't touched
+
+        Raises:
+            IndexGetError: invalid ULID
+            KeyError: document doesn't exist
+        """
+        self._search_rs.update_attributes(id, attributes)
 
     def delete(self, id: str) -> bool:
         """
@@ -54,14 +302,201 @@ class Index:
         """
         return self._search_rs.delete(id)
 
-    def search(self, query: str, top_k: int = 0) -> list[Result]:
+    def search(
+        self,
+        query: str | QueryBuilder,
+        top_k: int | None = None,
+        recency_boost: float | None = None,
+        score_script: str | None = None,
+        options: SearchOptions | None = None,
+        restrict_ids: list[str] | None = None,
+        exclude_ids: list[str] | None = None,
+        visibility: Callable[[str], bool] | None = None,
+        profile: str | None = None,
+        min_should_match: int | None = None,
+        position_boost: float | None = None,
+        diversify: float | None = None,
+        min_len: int | None = None,
+        max_len: int | None = None,
+        seed: int | None = None,
+        length_norm: bool | None = None,
+        max_content_bytes: int | None = None,
+        min_score: float | None = None,
+        shadow: bool = False,
+        analyze_query: bool = False,
+    ) -> (
+        list[Result]
+        | tuple[list[Result], dict]
+        | tuple[list[Result], list[dict]]
+        | tuple[list[Result], dict, list[dict]]
+    ):
+        """
+        Search the index and return scored results, "query" is either a query
+        string or a query composed with QueryBuilder
+
+        Empty query or "*" matches all documents in order they were added, with
+        "recency_boost" newest documents come first
+
+        "recency_boost" is a half-life in seconds, if set scores are multiplied
+        by exponential decay of document age so newer documents rank higher
+
+        "score_script" is an expression evaluated for every matched document,
+        e.g. "score * log(1 + attr.views)", missing attributes evaluate to 0,
+        documents whose script score isn't a finite positive number (e.g. log
+        of a missing attribute) are dropped
+
+        "restrict_ids" limits matching to given document ids (e.g. documents
+        user is allowed to see) and "exclude_ids" skips given documents, both
+        are applied during intersection rather than on returned results
+
+        "visibility" is called with document id and hides document if it returns
+        false, e.g. to enforce per user permissions, it's called at most once per
+        document and only for documents which could make it into results
+
+        "profile" set to "flame" returns results together with a tree of time
+        spent in search components, each node is a dict with "name", "seconds",
+        "calls" and "children", time of children is included in their parent
+
+        "min_should_match" allows clauses with more terms to match documents
+        containing at least given number of them, regardless of their positions,
+        score of such document is a sum of scores of matched terms
+
+        "position_boost" is a half-distance in positions, if set score of every
+        matched clause is multiplied by 1 + 0.5 ^ (position / half-distance),
+        where position is the earliest position of the match, so matches near
+        the beginning of document (or field) score up to twice as high
+
+        "diversify" is a lambda of maximal marginal relevance, if set results are
+        reranked so they aren't near-duplicates of each other, each next result
+        is picked from 4 * "top_k" best candidates by lambda * relevance minus
+        (1 - lambda) * its highest similarity to already picked results, 1 means
+        no diversification, results keep their original scores
+
+        "min_len" and "max_len" limit length of matched documents (in tokens,
+        including fields), e.g. to exclude stubs or extremely long pages, length
+        is checked before positions of terms are matched
+
+        "length_norm" set to false disables normalization of term frequency by
+        document (or field) length, which is bm25 with b = 0, e.g. for corpora of
+        titles where shorter documents shouldn't rank higher
+
+        "max_content_bytes" cuts content (and content_bytes) of results to at
+        most given number of bytes, e.g. for previews, content ends at the last
+        whole character which fits and "is_truncated" of document tells whether
+        something was cut off, only the beginning of content is decompressed,
+        it's unrelated to "positions_truncated", which marks documents indexed
+        only up to max_positions
+
+        "min_score" drops results scoring less than given score (after score
+        script), so weak matches aren't returned at all and fewer than "top_k"
+        results (or none) can be returned, without score script documents which
+        can't reach it are skipped the same way as ones which can't make top k
+
+        "shadow" runs also experiment registered with set_shadow and records
+        comparison of both runs, only live results are returned
+
+        "analyze_query" returns results together with analysis of query, a dict
+        for every query term with its "term" and "field", "tokens" it was
+        stemmed to, its "synonyms" and fuzzy "expansions" found in index, e.g.
+        to show "searched for: run (ran, running)", terms removed as stop words
+        have no tokens, profile report (if requested) comes before analysis
+
+        "options" groups all search parameters (including "timeout", default
+        "fuzziness" and "tie_break"), explicitly passed arguments override values
+        from options, "top_k", "fuzziness", "timeout" and "fields" which weren't
+        set in either are taken from [search_defaults] of index config,
+        fuzziness can't exceed "max_fuzziness" of index config
+
+        "fields" of options lists fields (given to add) which terms without field
+        are searched in besides document content, term matched in more of them
+        is scored by the best match, it's set only through options, sorting by
+        attributes, highlighting and facets aren't supported as search options
+
+        Results with equal score are ordered by document id, so they are stable
+        between runs and pages, older documents come first unless options set
+        "tie_break" to "newest"
+
+        "seed" shuffles results with equal score instead, order of ties depends
+        only on the seed and document ids, so it's stable between pages and the
+        same seed (e.g. per user or experiment bucket) always gives the same order,
+        it can't be combined with "tie_break"
+
+        Raises:
+            ValueError: invalid query, score script syntax or options, or
+                "shadow" is set, but no experiment is registered
+            UlidDecodeError: invalid id in "restrict_ids" or "exclude_ids"
+        """
+        return self._search_rs.search(
+            query,
+            top_k,
+            recency_boost,
+            score_script,
+            options,
+            restrict_ids,
+            exclude_ids,
+            visibility,
+            profile,
+            min_should_match,
+            position_boost,
+            diversify,
+            min_len,
+            max_len,
+            seed,
+            length_norm,
+            max_content_bytes,
+            min_score,
+            shadow,
+            analyze_query,
+        )
+
+    def set_shadow(self, options: SearchOptions | None = None) -> None:
         """
-        Search the index and return scored results
+        Register experimental search options (e.g. another "score_script" or
+        "length_norm"), searches with "shadow" set run them as well and record
+        comparison with live results, "top_k", "restrict_ids" and "exclude_ids"
+        are taken from the live search, None removes the experiment, registering
+        it again resets recorded stats
 
         Raises:
-            SearchQueryError: invalid query syntax
+            ValueError: invalid options
         """
-        return self._search_rs.search(query, top_k)
+        self._search_rs.set_shadow(options)
+
+    def shadow_stats(self) -> dict[str, float]:
+        """
+        Return stats recorded for the registered experiment: number of shadowed
+        "queries", "errors" of experimental runs, mean "overlap" (fraction of
+        results returned by both runs), "top_match" (fraction of runs with the
+        same first result) and mean "live_seconds" and "experiment_seconds"
+
+        Raises:
+            ValueError: no experiment is registered
+        """
+        return self._search_rs.shadow_stats()
+
+    def diff_results(
+        self,
+        query: str | QueryBuilder,
+        snapshot_ids: list[str],
+        top_k: int | None = None,
+        options: SearchOptions | None = None,
+    ) -> dict[str, list[tuple]]:
+        """
+        Compare current results of "query" with previously stored list of
+        document ids, e.g. to catch relevance regressions in CI, returns dict
+        with "added" (id, rank) documents which aren't in snapshot, "dropped"
+        (id, previous rank) documents which are no longer returned and "moved"
+        (id, previous rank, rank) documents which changed position, ranks are
+        positions in results starting at 0
+
+        "top_k" defaults to the length of snapshot, "options" are applied like
+        in search, query isn't recorded in query log
+
+        Raises:
+            ValueError: invalid query, score script syntax or options
+            UlidDecodeError: invalid id in "snapshot_ids"
+        """
+        return self._search_rs.diff_results(query, snapshot_ids, top_k, options)
 
     def flush(self) -> None:
         """
@@ -72,14 +507,52 @@ class Index:
         """
         return self._search_rs.flush()
 
-    def merge(self) -> None:
+    def reload(self) -> None:
+        """
+        Catch up with changes flushed by other process writing to the same
+        directory, only already flushed data becomes visible
+        """
+        return self._search_rs.reload()
+
+    def merge(self, progress: Callable[[int, int], bool | None] | None = None) -> bool:
         """
-        Merges segments for which merge conditions are met
+        Merges segments for which merge conditions are met, return False if the
+        merge was cancelled
+
+        Optional "progress" is called with processed and total bytes of merged
+        segments, returning False cancels the merge (e.g. when it runs into peak
+        traffic), segment being rewritten is finished first, so documents stay
+        consistent and the remaining segments are merged by the next merge,
+        exception raised by "progress" cancels the merge and is re-raised
 
         Raises:
             IndexMergeError: flush failed
         """
-        return self._search_rs.merge()
+        return self._search_rs.merge(progress)
+
+    def sweep(self) -> dict[str, int]:
+        """
+        Remove empty posting lists and tokens without postings, sweep also
+        runs as a part of merge
+
+        Returns:
+            dict[str, int]: number of reclaimed "postings" and "tokens"
+        """
+        return self._search_rs.sweep()
+
+    def memory_usage(self) -> dict[str, int]:
+        """
+        Estimate bytes held in memory by index components, e.g. for capacity
+        planning, memory mapped files and python objects aren't included
+
+        Returns:
+            dict[str, int]: bytes of "index" (posting lists), "impacts" (impact
+            ordered and capped copies of postings), "documents" (documents map),
+            "attributes", "trie" (fuzzy trie), "tokens" (token store), "buffers"
+            (unflushed writes), "caches" (keys of on disk documents read on
+            demand), "other" and their "total"
+        """
+        return self._search_rs.memory_usage()
 
 
 class MiniSearch:
@@ -88,29 +561,62 @@ class MiniSearch:
         """Create an in-memory registry of indexes"""
         self._conf = conf
         self._indexes: dict[str, Index] = {}
+        self._aliases: dict[str, str] = {}
 
     def add(self, index: str, dir: str, conf: str | None = None) -> tuple[bool, Index]:
         """
-        Get or create an index handle
+        Get or create an index handle, alias resolves to the index it points to
 
         Raises:
             IndexInitError: load/create index state failed
         """
+        index = self._aliases.get(index, index)
         if index not in self._indexes:
             self._indexes[index] = Index(dir, conf or self._conf)
             return (True, self._indexes[index])
 
         return (False, self._indexes[index])
 
+    def alias(self, alias: str, index: str) -> None:
+        """
+        Point "alias" to "index", e.g. alias("live", "products_v2"), alias which
+        already exists is swapped atomically, so a rebuilt index replaces the
+        serving one without the application changing names, searches already
+        running on the previous index finish on its handle
+
+        Raises:
+            KeyError: index doesn't exist
+            ValueError: alias is a name of an index
+        """
+        if index not in self._indexes:
+            raise KeyError(index)
+        if alias in self._indexes:
+            raise ValueError(f"'{alias}' is an index, it can't be used as an alias")
+        self._aliases[alias] = index
+
+    def aliases(self) -> dict[str, str]:
+        """Return every alias with the index it points to"""
+        return dict(self._aliases)
+
     def delete(self, index: str) -> None:
-        """Remove an index handle from the registry"""
-        if index in self._indexes:
+        """
+        Remove an index handle from the registry, or an alias if "index" is one
+
+        Raises:
+            ValueError: index is pointed to by an alias
+        """
+        if index in self._aliases:
+            del self._aliases[index]
+        elif index in self._indexes:
+            aliases = [alias for alias, name in self._aliases.items() if name == index]
+            if aliases:
+                raise ValueError(f"Index '{index}' is pointed to by aliases {aliases}")
             del self._indexes[index]
 
     def has_index(self, index: str) -> bool:
-        """Return True if the index handle exists"""
-        return index in self._indexes
+        """Return True if the index handle (or alias) exists"""
+        return self._aliases.get(index, index) in self._indexes
 
     def index(self, index: str) -> Index:
-        """Fetch an existing index handle"""
-        return self._indexes[index]
+        """Fetch an existing index handle, alias resolves to the index it points to"""
+        return self._indexes[self._aliases.get(index, index)]
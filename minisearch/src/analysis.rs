@@ -1,2 +1,5 @@
+pub mod folding;
+pub mod numeric;
 pub mod stemmer;
+pub mod stopwords;
 pub mod tokenizer;
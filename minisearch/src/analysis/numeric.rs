@@ -0,0 +1,57 @@
+// normalizes numbers to canonical decimal form, so "1,000", "1000" and "1000.00"
+// are indexed as the same token, "," is treated as thousands separator and "."
+// as decimal separator, returns None if word isn't a number
+pub fn normalize(word: &str) -> Option<String> {
+    let (integer, fraction) = match word.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (word, None),
+    };
+
+    if !is_integer(integer) {
+        return None;
+    }
+
+    if let Some(fraction) = fraction
+        && (fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()))
+    {
+        return None;
+    }
+
+    let integer = integer.replace(',', "");
+    let mut number = match integer.trim_start_matches('0') {
+        "" => String::from("0"),
+        integer => integer.to_string(),
+    };
+
+    if let Some(fraction) = fraction.map(|f| f.trim_end_matches('0'))
+        && !fraction.is_empty()
+    {
+        number.push('.');
+        number.push_str(fraction);
+    }
+
+    Some(number)
+}
+
+fn is_integer(integer: &str) -> bool {
+    // digits optionally grouped by thousands separator, e.g. "1000" or "1,000"
+    let mut groups = integer.split(',');
+    let first = match groups.next() {
+        Some(first) => first,
+        None => return false,
+    };
+
+    if first.is_empty() || !first.chars().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+
+    let mut grouped = false;
+    for group in groups {
+        if group.len() != 3 || !group.chars().all(|c| c.is_ascii_digit()) {
+            return false;
+        }
+        grouped = true;
+    }
+
+    !grouped || first.len() <= 3
+}
@@ -1,5 +1,33 @@
+use crate::config::Language;
 use std::collections::HashMap;
 
+mod french;
+mod german;
+mod russian;
+mod spanish;
+mod word;
+
+pub use french::FrenchStemmer;
+pub use german::GermanStemmer;
+pub use russian::RussianStemmer;
+pub use spanish::SpanishStemmer;
+
+// reduces words to their stems, so inflected forms of a word share one token
+pub trait Stemmer {
+    fn stem(&mut self, word: String) -> String;
+}
+
+// returns stemmer of given language, it's used for both documents and queries
+pub fn for_language(language: &Language) -> Box<dyn Stemmer + Send + Sync> {
+    match language {
+        Language::English => Box::new(SnowballStemmer::new()),
+        Language::German => Box::new(GermanStemmer),
+        Language::French => Box::new(FrenchStemmer),
+        Language::Spanish => Box::new(SpanishStemmer),
+        Language::Russian => Box::new(RussianStemmer),
+    }
+}
+
 static VOWELS: [char; 6] = ['a', 'e', 'i', 'o', 'u', 'y'];
 static DOUBLES: [&str; 9] = ["bb", "dd", "ff", "gg", "mm", "nn", "pp", "rr", "tt"];
 static LI_ENDINGS: [char; 10] = ['c', 'd', 'e', 'g', 'h', 'k', 'm', 'n', 'r', 't'];
@@ -97,8 +125,10 @@ impl SnowballStemmer {
             step_3_suffix_map: step_3_suffix_map,
         }
     }
+}
 
-    pub fn stem(&mut self, mut word: String) -> String {
+impl Stemmer for SnowballStemmer {
+    fn stem(&mut self, mut word: String) -> String {
         if word.len() <= 2 || EXCEPTION_WORDS.contains(&word.as_str()) || !word.is_ascii() {
             return word;
         }
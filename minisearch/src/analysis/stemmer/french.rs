@@ -0,0 +1,318 @@
+use crate::analysis::stemmer::Stemmer;
+use crate::analysis::stemmer::word::{Word, regions};
+
+static VOWELS: [char; 17] = [
+    'a', 'e', 'i', 'o', 'u', 'y', 'â', 'à', 'ë', 'é', 'ê', 'è', 'ï', 'î', 'ô', 'û', 'ù',
+];
+static KEEP_WITH_S: [char; 6] = ['a', 'i', 'o', 'u', 'è', 's'];
+
+static STEP_1_SUFFIXES: [&str; 43] = [
+    "ance",
+    "iqUe",
+    "isme",
+    "able",
+    "iste",
+    "eux",
+    "ances",
+    "iqUes",
+    "ismes",
+    "ables",
+    "istes",
+    "atrice",
+    "ateur",
+    "ation",
+    "atrices",
+    "ateurs",
+    "ations",
+    "logie",
+    "logies",
+    "usion",
+    "ution",
+    "usions",
+    "utions",
+    "ence",
+    "ences",
+    "ement",
+    "ements",
+    "ité",
+    "ités",
+    "if",
+    "ive",
+    "ifs",
+    "ives",
+    "eaux",
+    "aux",
+    "euse",
+    "euses",
+    "issement",
+    "issements",
+    "amment",
+    "emment",
+    "ment",
+    "ments",
+];
+static STEP_2A_SUFFIXES: [&str; 35] = [
+    "îmes", "ît", "îtes", "i", "ie", "ies", "ir", "ira", "irai", "iraIent", "irais", "irait",
+    "iras", "irent", "irez", "iriez", "irions", "irons", "iront", "is", "issaIent", "issais",
+    "issait", "issant", "issante", "issantes", "issants", "isse", "issent", "isses", "issez",
+    "issiez", "issions", "issons", "it",
+];
+static STEP_2B_SUFFIXES: [&str; 38] = [
+    "ions", "é", "ée", "ées", "és", "èrent", "er", "era", "erai", "eraIent", "erais", "erait",
+    "eras", "erez", "eriez", "erions", "erons", "eront", "ez", "iez", "âmes", "ât", "âtes", "a",
+    "ai", "aIent", "ais", "ait", "ant", "ante", "antes", "ants", "as", "asse", "assent", "asses",
+    "assiez", "assions",
+];
+static STEP_4_SUFFIXES: [&str; 7] = ["ion", "ier", "ière", "Ier", "Ière", "e", "ë"];
+static DOUBLES: [&str; 5] = ["enn", "onn", "ett", "ell", "eill"];
+
+fn is_vowel(c: char) -> bool {
+    VOWELS.contains(&c)
+}
+
+// french snowball stemmer
+pub struct FrenchStemmer;
+
+// outcome of step 1, "ment" like endings are removed, but verb suffixes are still tried
+#[derive(PartialEq)]
+enum Step1 {
+    Removed,
+    Altered,
+    Unchanged,
+}
+
+impl Stemmer for FrenchStemmer {
+    fn stem(&mut self, word: String) -> String {
+        if word.chars().count() <= 2 {
+            return word;
+        }
+
+        let mut word = Word::new(&word);
+        Self::prelude(&mut word);
+        let rv = Self::rv(&word.chars);
+        let (r1, r2) = regions(&word.chars, is_vowel);
+
+        let changed = match Self::step_1(&mut word, rv, r1, r2) {
+            Step1::Removed => true,
+            _ => Self::step_2a(&mut word, rv) || Self::step_2b(&mut word, rv, r2),
+        };
+        if changed {
+            // step 3
+            if word.ends_with("Y") {
+                word.replace("Y", "i");
+            } else if word.ends_with("ç") {
+                word.replace("ç", "c");
+            }
+        } else {
+            Self::step_4(&mut word, rv, r2);
+        }
+
+        // undouble
+        if DOUBLES.iter().any(|double| word.ends_with(double)) {
+            word.chars.pop();
+        }
+        // unaccent é or è followed only by non vowels
+        let consonants = word
+            .chars
+            .iter()
+            .rev()
+            .take_while(|c| !is_vowel(**c))
+            .count();
+        if consonants > 0 && consonants < word.len() {
+            let i = word.len() - consonants - 1;
+            if word.chars[i] == 'é' || word.chars[i] == 'è' {
+                word.chars[i] = 'e';
+            }
+        }
+
+        word.map(|c| match c {
+            'I' => 'i',
+            'U' => 'u',
+            'Y' => 'y',
+            c => c,
+        });
+        word.into_string()
+    }
+}
+
+impl FrenchStemmer {
+    fn prelude(word: &mut Word) {
+        // u and i between vowels, y next to a vowel and u after q are consonants
+        let chars = &mut word.chars;
+        for i in 0..chars.len() {
+            let previous = i.checked_sub(1).map(|i| chars[i]);
+            let next_vowel = chars.get(i + 1).is_some_and(|c| is_vowel(*c));
+            let upper = match chars[i] {
+                'u' | 'i' if previous.is_some_and(is_vowel) && next_vowel => true,
+                'y' => previous.is_some_and(is_vowel) || next_vowel,
+                'u' => previous == Some('q'),
+                _ => false,
+            };
+            if upper {
+                chars[i] = chars[i].to_ascii_uppercase();
+            }
+        }
+    }
+
+    fn rv(chars: &[char]) -> usize {
+        // region after the third letter if the word starts with two vowels or with
+        // "par", "col" or "tap", otherwise after the first vowel not at the start
+        let prefix = chars[..3].iter().collect::<String>();
+        if (is_vowel(chars[0]) && is_vowel(chars[1])) || ["par", "col", "tap"].contains(&&*prefix) {
+            return 3;
+        }
+        (1..chars.len())
+            .find(|i| is_vowel(chars[*i]))
+            .map_or(chars.len(), |i| i + 1)
+    }
+
+    fn step_1(word: &mut Word, rv: usize, r1: usize, r2: usize) -> Step1 {
+        let Some(suffix) = word.longest(&STEP_1_SUFFIXES, 0) else {
+            return Step1::Unchanged;
+        };
+        let start = word.start(suffix);
+        let (in_rv, in_r1, in_r2) = (start >= rv, start >= r1, start >= r2);
+
+        match suffix {
+            "ance" | "iqUe" | "isme" | "able" | "iste" | "eux" | "ances" | "iqUes" | "ismes"
+            | "ables" | "istes"
+                if in_r2 =>
+            {
+                word.delete(suffix)
+            }
+            "atrice" | "ateur" | "ation" | "atrices" | "ateurs" | "ations" if in_r2 => {
+                word.delete(suffix);
+                if word.ends_with("ic") {
+                    Self::delete_or_replace(word, "ic", r2, "iqU");
+                }
+            }
+            "logie" | "logies" if in_r2 => word.replace(suffix, "log"),
+            "usion" | "ution" | "usions" | "utions" if in_r2 => word.replace(suffix, "u"),
+            "ence" | "ences" if in_r2 => word.replace(suffix, "ent"),
+            "ement" | "ements" if in_rv => {
+                word.delete(suffix);
+                match word.longest(&["iv", "eus", "abl", "iqU", "ièr", "Ièr"], 0) {
+                    Some("iv") if word.start("iv") >= r2 => {
+                        word.delete("iv");
+                        if word.ends_with("at") && word.start("at") >= r2 {
+                            word.delete("at");
+                        }
+                    }
+                    Some("eus") if word.start("eus") >= r2 => word.delete("eus"),
+                    Some("eus") if word.start("eus") >= r1 => word.replace("eus", "eux"),
+                    Some(suffix @ ("abl" | "iqU")) if word.start(suffix) >= r2 => {
+                        word.delete(suffix)
+                    }
+                    Some(suffix @ ("ièr" | "Ièr")) if word.start(suffix) >= rv => {
+                        word.replace(suffix, "i")
+                    }
+                    _ => (),
+                }
+            }
+            "ité" | "ités" if in_r2 => {
+                word.delete(suffix);
+                match word.longest(&["abil", "ic", "iv"], 0) {
+                    Some("abil") => Self::delete_or_replace(word, "abil", r2, "abl"),
+                    Some("ic") => Self::delete_or_replace(word, "ic", r2, "iqU"),
+                    Some("iv") if word.start("iv") >= r2 => word.delete("iv"),
+                    _ => (),
+                }
+            }
+            "if" | "ive" | "ifs" | "ives" if in_r2 => {
+                word.delete(suffix);
+                if word.ends_with("at") && word.start("at") >= r2 {
+                    word.delete("at");
+                    if word.ends_with("ic") {
+                        Self::delete_or_replace(word, "ic", r2, "iqU");
+                    }
+                }
+            }
+            "eaux" => word.replace(suffix, "eau"),
+            "aux" if in_r1 => word.replace(suffix, "al"),
+            "euse" | "euses" if in_r2 => word.delete(suffix),
+            "euse" | "euses" if in_r1 => word.replace(suffix, "eux"),
+            "issement" | "issements"
+                if in_r1 && word.before(suffix, 0).is_some_and(|c| !is_vowel(c)) =>
+            {
+                word.delete(suffix)
+            }
+            "amment" if in_rv => {
+                word.replace(suffix, "ant");
+                return Step1::Altered;
+            }
+            "emment" if in_rv => {
+                word.replace(suffix, "ent");
+                return Step1::Altered;
+            }
+            "ment" | "ments" if word.before(suffix, rv).is_some_and(is_vowel) => {
+                word.delete(suffix);
+                return Step1::Altered;
+            }
+            _ => return Step1::Unchanged,
+        }
+        Step1::Removed
+    }
+
+    fn step_2a(word: &mut Word, rv: usize) -> bool {
+        // verb suffixes beginning with i, they have to follow a non vowel in RV
+        match word.longest(&STEP_2A_SUFFIXES, rv) {
+            Some(suffix) if word.before(suffix, rv).is_some_and(|c| !is_vowel(c)) => {
+                word.delete(suffix);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn step_2b(word: &mut Word, rv: usize, r2: usize) -> bool {
+        let Some(suffix) = word.longest(&STEP_2B_SUFFIXES, rv) else {
+            return false;
+        };
+
+        match suffix {
+            "ions" if word.start(suffix) < r2 => return false,
+            "ions" | "é" | "ée" | "ées" | "és" | "èrent" | "er" | "era" | "erai" | "eraIent"
+            | "erais" | "erait" | "eras" | "erez" | "eriez" | "erions" | "erons" | "eront"
+            | "ez" | "iez" => word.delete(suffix),
+            _ => {
+                word.delete(suffix);
+                if word.ends_with("e") && word.start("e") >= rv {
+                    word.delete("e");
+                }
+            }
+        }
+        true
+    }
+
+    fn step_4(word: &mut Word, rv: usize, r2: usize) {
+        // residual suffix
+        if word.ends_with("s")
+            && !word
+                .before("s", 0)
+                .is_some_and(|c| KEEP_WITH_S.contains(&c))
+        {
+            word.delete("s");
+        }
+
+        match word.longest(&STEP_4_SUFFIXES, rv) {
+            Some("ion")
+                if word.start("ion") >= r2
+                    && word.before("ion", rv).is_some_and(|c| c == 's' || c == 't') =>
+            {
+                word.delete("ion")
+            }
+            Some(suffix @ ("ier" | "ière" | "Ier" | "Ière")) => word.replace(suffix, "i"),
+            Some("e") => word.delete("e"),
+            Some("ë") if word.ends_with("guë") && word.start("guë") >= rv => word.delete("ë"),
+            _ => (),
+        }
+    }
+
+    fn delete_or_replace(word: &mut Word, suffix: &str, r2: usize, replacement: &str) {
+        if word.start(suffix) >= r2 {
+            word.delete(suffix);
+        } else {
+            word.replace(suffix, replacement);
+        }
+    }
+}
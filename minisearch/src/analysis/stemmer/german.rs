@@ -0,0 +1,143 @@
+use crate::analysis::stemmer::Stemmer;
+use crate::analysis::stemmer::word::{Word, regions};
+
+static VOWELS: [char; 9] = ['a', 'e', 'i', 'o', 'u', 'y', 'ä', 'ö', 'ü'];
+static S_ENDINGS: [char; 11] = ['b', 'd', 'f', 'g', 'h', 'k', 'l', 'm', 'n', 'r', 't'];
+static ST_ENDINGS: [char; 10] = ['b', 'd', 'f', 'g', 'h', 'k', 'l', 'm', 'n', 't'];
+
+static STEP_1_SUFFIXES: [&str; 7] = ["em", "ern", "er", "e", "en", "es", "s"];
+static STEP_2_SUFFIXES: [&str; 4] = ["en", "er", "est", "st"];
+static STEP_3_SUFFIXES: [&str; 8] = ["end", "ung", "ig", "ik", "isch", "lich", "heit", "keit"];
+
+fn is_vowel(c: char) -> bool {
+    VOWELS.contains(&c)
+}
+
+// german snowball stemmer
+pub struct GermanStemmer;
+
+impl Stemmer for GermanStemmer {
+    fn stem(&mut self, word: String) -> String {
+        if word.chars().count() <= 2 {
+            return word;
+        }
+
+        let mut word = Word::new(&word.replace('ß', "ss"));
+        // u and y between vowels are consonants
+        for i in 1..word.len().saturating_sub(1) {
+            let c = word.chars[i];
+            if (c == 'u' || c == 'y') && is_vowel(word.chars[i - 1]) && is_vowel(word.chars[i + 1])
+            {
+                word.chars[i] = c.to_ascii_uppercase();
+            }
+        }
+
+        let (r1, r2) = regions(&word.chars, is_vowel);
+        // region before R1 has at least 3 letters
+        let r1 = r1.max(3);
+
+        Self::step_1(&mut word, r1);
+        Self::step_2(&mut word, r1);
+        Self::step_3(&mut word, r1, r2);
+
+        word.map(|c| match c {
+            'U' | 'ü' => 'u',
+            'Y' => 'y',
+            'ä' => 'a',
+            'ö' => 'o',
+            c => c,
+        });
+        word.into_string()
+    }
+}
+
+impl GermanStemmer {
+    fn step_1(word: &mut Word, r1: usize) {
+        let Some(suffix) = word.longest(&STEP_1_SUFFIXES, 0) else {
+            return;
+        };
+        if word.start(suffix) < r1 {
+            return;
+        }
+
+        match suffix {
+            "s" => {
+                if word
+                    .before(suffix, 0)
+                    .is_some_and(|c| S_ENDINGS.contains(&c))
+                {
+                    word.delete(suffix);
+                }
+            }
+            "e" | "en" | "es" => {
+                word.delete(suffix);
+                if word.ends_with("niss") {
+                    word.delete("s");
+                }
+            }
+            _ => word.delete(suffix),
+        }
+    }
+
+    fn step_2(word: &mut Word, r1: usize) {
+        let Some(suffix) = word.longest(&STEP_2_SUFFIXES, 0) else {
+            return;
+        };
+        if word.start(suffix) < r1 {
+            return;
+        }
+
+        match suffix {
+            // st ending itself has to be preceded by at least 3 letters
+            "st" => {
+                if word
+                    .before(suffix, 3)
+                    .is_some_and(|c| ST_ENDINGS.contains(&c))
+                {
+                    word.delete(suffix);
+                }
+            }
+            _ => word.delete(suffix),
+        }
+    }
+
+    fn step_3(word: &mut Word, r1: usize, r2: usize) {
+        let Some(suffix) = word.longest(&STEP_3_SUFFIXES, 0) else {
+            return;
+        };
+        if word.start(suffix) < r2 {
+            return;
+        }
+
+        match suffix {
+            "end" | "ung" => {
+                word.delete(suffix);
+                if word.ends_with("ig")
+                    && word.start("ig") >= r2
+                    && word.before("ig", 0) != Some('e')
+                {
+                    word.delete("ig");
+                }
+            }
+            "ig" | "ik" | "isch" => {
+                if word.before(suffix, 0) != Some('e') {
+                    word.delete(suffix);
+                }
+            }
+            "lich" | "heit" => {
+                word.delete(suffix);
+                if let Some(suffix) = word.longest(&["er", "en"], r1) {
+                    word.delete(suffix);
+                }
+            }
+            _ => {
+                word.delete(suffix);
+                if let Some(suffix) = word.longest(&["lich", "ig"], 0)
+                    && word.start(suffix) >= r2
+                {
+                    word.delete(suffix);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use crate::analysis::stemmer::Stemmer;
+use crate::analysis::stemmer::word::{Word, regions};
+
+static VOWELS: [char; 9] = ['а', 'е', 'и', 'о', 'у', 'ы', 'э', 'ю', 'я'];
+
+// endings of the first groups have to follow а or я, which isn't removed
+static PERFECTIVE_GERUND_1: [&str; 3] = ["в", "вши", "вшись"];
+static PERFECTIVE_GERUND_2: [&str; 6] = ["ив", "ивши", "ившись", "ыв", "ывши", "ывшись"];
+static ADJECTIVE: [&str; 26] = [
+    "ее", "ие", "ые", "ое", "ими", "ыми", "ей", "ий", "ый", "ой", "ем", "им", "ым", "ом", "его",
+    "ого", "ему", "ому", "их", "ых", "ую", "юю", "ая", "яя", "ою", "ею",
+];
+static PARTICIPLE_1: [&str; 5] = ["ем", "нн", "вш", "ющ", "щ"];
+static PARTICIPLE_2: [&str; 3] = ["ивш", "ывш", "ующ"];
+static REFLEXIVE: [&str; 2] = ["ся", "сь"];
+static VERB_1: [&str; 17] = [
+    "ла", "на", "ете", "йте", "ли", "й", "л", "ем", "н", "ло", "но", "ет", "ют", "ны", "ть", "ешь",
+    "нно",
+];
+static VERB_2: [&str; 29] = [
+    "ила", "ыла", "ена", "ейте", "уйте", "ите", "или", "ыли", "ей", "уй", "ил", "ыл", "им", "ым",
+    "ен", "ило", "ыло", "ено", "ят", "ует", "уют", "ит", "ыт", "ены", "ить", "ыть", "ишь", "ую",
+    "ю",
+];
+static NOUN: [&str; 36] = [
+    "а", "ев", "ов", "ие", "ье", "е", "иями", "ями", "ами", "еи", "ии", "и", "ией", "ей", "ой",
+    "ий", "й", "иям", "ям", "ием", "ем", "ам", "ом", "о", "у", "ах", "иях", "ях", "ы", "ь", "ию",
+    "ью", "ю", "ия", "ья", "я",
+];
+static DERIVATIONAL: [&str; 2] = ["ост", "ость"];
+static SUPERLATIVE: [&str; 2] = ["ейш", "ейше"];
+
+fn is_vowel(c: char) -> bool {
+    VOWELS.contains(&c)
+}
+
+// russian snowball stemmer, all suffixes are searched for in RV
+pub struct RussianStemmer;
+
+impl Stemmer for RussianStemmer {
+    fn stem(&mut self, word: String) -> String {
+        if word.chars().count() <= 2 {
+            return word;
+        }
+
+        let mut word = Word::new(&word.replace('ё', "е"));
+        // RV is the region after the first vowel
+        let rv = word
+            .chars
+            .iter()
+            .position(|c| is_vowel(*c))
+            .map_or(word.len(), |i| i + 1);
+        let (_, r2) = regions(&word.chars, is_vowel);
+
+        // step 1
+        if !Self::delete_grouped(&mut word, &PERFECTIVE_GERUND_1, &PERFECTIVE_GERUND_2, rv) {
+            Self::delete(&mut word, &REFLEXIVE, rv);
+            if Self::delete(&mut word, &ADJECTIVE, rv) {
+                Self::delete_grouped(&mut word, &PARTICIPLE_1, &PARTICIPLE_2, rv);
+            } else if !Self::delete_grouped(&mut word, &VERB_1, &VERB_2, rv) {
+                Self::delete(&mut word, &NOUN, rv);
+            }
+        }
+
+        // step 2
+        Self::delete(&mut word, &["и"], rv);
+        // step 3
+        Self::delete(&mut word, &DERIVATIONAL, r2.max(rv));
+        // step 4
+        if Self::delete(&mut word, &SUPERLATIVE, rv) || word.ends_with("нн") {
+            if word.ends_with("нн") && word.start("нн") >= rv {
+                word.delete("н");
+            }
+        } else {
+            Self::delete(&mut word, &["ь"], rv);
+        }
+
+        word.into_string()
+    }
+}
+
+impl RussianStemmer {
+    fn delete(word: &mut Word, suffixes: &[&'static str], from: usize) -> bool {
+        // deletes the longest of suffixes found after given index
+        match word.longest(suffixes, from) {
+            Some(suffix) => {
+                word.delete(suffix);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn delete_grouped(
+        word: &mut Word,
+        first: &[&'static str],
+        second: &[&'static str],
+        rv: usize,
+    ) -> bool {
+        // the longest ending of both groups decides, ending of the first group is
+        // deleted only if it follows а or я
+        let suffixes = first.iter().chain(second).copied().collect::<Vec<_>>();
+        let Some(suffix) = word.longest(&suffixes, rv) else {
+            return false;
+        };
+        if first.contains(&suffix)
+            && !word
+                .before(suffix, rv)
+                .is_some_and(|c| c == 'а' || c == 'я')
+        {
+            return false;
+        }
+        word.delete(suffix);
+        true
+    }
+}
@@ -0,0 +1,201 @@
+use crate::analysis::stemmer::Stemmer;
+use crate::analysis::stemmer::word::{Word, regions};
+
+static VOWELS: [char; 11] = ['a', 'e', 'i', 'o', 'u', 'á', 'é', 'í', 'ó', 'ú', 'ü'];
+
+static PRONOUNS: [&str; 13] = [
+    "me", "se", "sela", "selo", "selas", "selos", "la", "le", "lo", "las", "les", "los", "nos",
+];
+static PRONOUN_ENDINGS: [&str; 11] = [
+    "iéndo", "ándo", "ár", "ér", "ír", "ando", "iendo", "ar", "er", "ir", "yendo",
+];
+static STEP_1_SUFFIXES: [&str; 46] = [
+    "anza", "anzas", "ico", "ica", "icos", "icas", "ismo", "ismos", "able", "ables", "ible",
+    "ibles", "ista", "istas", "oso", "osa", "osos", "osas", "amiento", "amientos", "imiento",
+    "imientos", "adora", "ador", "ación", "adoras", "adores", "aciones", "ante", "antes", "ancia",
+    "ancias", "logía", "logías", "ución", "uciones", "encia", "encias", "amente", "mente", "idad",
+    "idades", "iva", "ivo", "ivas", "ivos",
+];
+static STEP_2A_SUFFIXES: [&str; 12] = [
+    "ya", "ye", "yan", "yen", "yeron", "yendo", "yo", "yó", "yas", "yes", "yais", "yamos",
+];
+static STEP_2B_SUFFIXES: [&str; 96] = [
+    "en", "es", "éis", "emos", "arían", "arías", "arán", "arás", "aríais", "aría", "aréis",
+    "aríamos", "aremos", "ará", "aré", "erían", "erías", "erán", "erás", "eríais", "ería", "eréis",
+    "eríamos", "eremos", "erá", "eré", "irían", "irías", "irán", "irás", "iríais", "iría", "iréis",
+    "iríamos", "iremos", "irá", "iré", "aba", "ada", "ida", "ía", "ara", "iera", "ad", "ed", "id",
+    "ase", "iese", "aste", "iste", "an", "aban", "ían", "aran", "ieran", "asen", "iesen", "aron",
+    "ieron", "ado", "ido", "ando", "iendo", "ió", "ar", "er", "ir", "as", "abas", "adas", "idas",
+    "ías", "aras", "ieras", "ases", "ieses", "ís", "áis", "abais", "íais", "arais", "ierais",
+    "aseis", "ieseis", "asteis", "isteis", "ados", "idos", "amos", "ábamos", "íamos", "imos",
+    "áramos", "iéramos", "iésemos", "ásemos",
+];
+static STEP_3_SUFFIXES: [&str; 8] = ["os", "a", "o", "á", "í", "ó", "e", "é"];
+
+fn is_vowel(c: char) -> bool {
+    VOWELS.contains(&c)
+}
+
+// spanish snowball stemmer
+pub struct SpanishStemmer;
+
+impl Stemmer for SpanishStemmer {
+    fn stem(&mut self, word: String) -> String {
+        if word.chars().count() <= 2 {
+            return word;
+        }
+
+        let mut word = Word::new(&word);
+        let rv = Self::rv(&word.chars);
+        let (r1, r2) = regions(&word.chars, is_vowel);
+
+        Self::step_0(&mut word, rv);
+        if !Self::step_1(&mut word, r1, r2) && !Self::step_2a(&mut word, rv) {
+            Self::step_2b(&mut word, rv);
+        }
+        Self::step_3(&mut word, rv);
+
+        word.map(|c| match c {
+            'á' => 'a',
+            'é' => 'e',
+            'í' => 'i',
+            'ó' => 'o',
+            'ú' => 'u',
+            c => c,
+        });
+        word.into_string()
+    }
+}
+
+impl SpanishStemmer {
+    fn rv(chars: &[char]) -> usize {
+        // region after the next vowel if the second letter is a consonant, after
+        // the next consonant if the word starts with two vowels, otherwise after
+        // the third letter
+        let next = |from: usize, vowel: bool| {
+            (from..chars.len())
+                .find(|i| is_vowel(chars[*i]) == vowel)
+                .map(|i| i + 1)
+        };
+        let rv = match (is_vowel(chars[0]), is_vowel(chars[1])) {
+            (_, false) => next(2, true),
+            (true, true) => next(2, false),
+            (false, true) => Some(3),
+        };
+        rv.unwrap_or(chars.len())
+    }
+
+    fn step_0(word: &mut Word, rv: usize) {
+        // attached pronoun, it has to follow one of verb endings inside of RV
+        let Some(pronoun) = word.longest(&PRONOUNS, 0) else {
+            return;
+        };
+        let mut verb = Word::new(&word.chars[..word.start(pronoun)].iter().collect::<String>());
+        let Some(ending) = verb.longest(&PRONOUN_ENDINGS, 0) else {
+            return;
+        };
+        if verb.start(ending) < rv {
+            return;
+        }
+
+        match ending {
+            "iéndo" => verb.replace(ending, "iendo"),
+            "ándo" => verb.replace(ending, "ando"),
+            "ár" => verb.replace(ending, "ar"),
+            "ér" => verb.replace(ending, "er"),
+            "ír" => verb.replace(ending, "ir"),
+            // u before yendo doesn't have to be in RV
+            "yendo" if verb.before(ending, 0) != Some('u') => return,
+            _ => (),
+        }
+        *word = verb;
+    }
+
+    fn step_1(word: &mut Word, r1: usize, r2: usize) -> bool {
+        // returns whether suffix was removed
+        let Some(suffix) = word.longest(&STEP_1_SUFFIXES, 0) else {
+            return false;
+        };
+        let start = word.start(suffix);
+        if start < r2 && !(suffix == "amente" && start >= r1) {
+            return false;
+        }
+
+        match suffix {
+            "adora" | "ador" | "ación" | "adoras" | "adores" | "aciones" | "ante" | "antes"
+            | "ancia" | "ancias" => {
+                word.delete(suffix);
+                Self::delete_in(word, &["ic"], r2);
+            }
+            "logía" | "logías" => word.replace(suffix, "log"),
+            "ución" | "uciones" => word.replace(suffix, "u"),
+            "encia" | "encias" => word.replace(suffix, "ente"),
+            "amente" => {
+                word.delete(suffix);
+                if Self::delete_in(word, &["iv", "os", "ic", "ad"], r2) == Some("iv") {
+                    Self::delete_in(word, &["at"], r2);
+                }
+            }
+            "mente" => {
+                word.delete(suffix);
+                Self::delete_in(word, &["ante", "able", "ible"], r2);
+            }
+            "idad" | "idades" => {
+                word.delete(suffix);
+                Self::delete_in(word, &["abil", "ic", "iv"], r2);
+            }
+            "iva" | "ivo" | "ivas" | "ivos" => {
+                word.delete(suffix);
+                Self::delete_in(word, &["at"], r2);
+            }
+            _ => word.delete(suffix),
+        }
+        true
+    }
+
+    fn step_2a(word: &mut Word, rv: usize) -> bool {
+        // verb suffixes beginning with y, u before them doesn't have to be in RV
+        match word.longest(&STEP_2A_SUFFIXES, rv) {
+            Some(suffix) if word.before(suffix, 0) == Some('u') => {
+                word.delete(suffix);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn step_2b(word: &mut Word, rv: usize) {
+        let Some(suffix) = word.longest(&STEP_2B_SUFFIXES, rv) else {
+            return;
+        };
+
+        word.delete(suffix);
+        if matches!(suffix, "en" | "es" | "éis" | "emos") && word.ends_with("gu") {
+            word.delete("u");
+        }
+    }
+
+    fn step_3(word: &mut Word, rv: usize) {
+        let Some(suffix) = word.longest(&STEP_3_SUFFIXES, 0) else {
+            return;
+        };
+        if word.start(suffix) < rv {
+            return;
+        }
+
+        word.delete(suffix);
+        if matches!(suffix, "e" | "é") && word.ends_with("gu") && word.start("u") >= rv {
+            word.delete("u");
+        }
+    }
+
+    fn delete_in(word: &mut Word, suffixes: &[&'static str], r2: usize) -> Option<&'static str> {
+        // deletes the longest of suffixes if it's in R2
+        let suffix = word.longest(suffixes, 0)?;
+        if word.start(suffix) < r2 {
+            return None;
+        }
+        word.delete(suffix);
+        Some(suffix)
+    }
+}
@@ -0,0 +1,76 @@
+// word as a vector of chars, non english stemmers work on letters outside of ascii,
+// so suffixes are matched and removed by chars instead of bytes
+pub struct Word {
+    pub chars: Vec<char>,
+}
+
+impl Word {
+    pub fn new(word: &str) -> Self {
+        Self {
+            chars: word.chars().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        let len = suffix.chars().count();
+        len <= self.len()
+            && self.chars[self.len() - len..]
+                .iter()
+                .copied()
+                .eq(suffix.chars())
+    }
+
+    // index of the first char of suffix, word has to end with it
+    pub fn start(&self, suffix: &str) -> usize {
+        self.len() - suffix.chars().count()
+    }
+
+    // the longest of suffixes word ends with, which begins at or after given index
+    pub fn longest(&self, suffixes: &[&'static str], from: usize) -> Option<&'static str> {
+        suffixes
+            .iter()
+            .filter(|suffix| self.ends_with(suffix) && self.start(suffix) >= from)
+            .max_by_key(|suffix| suffix.chars().count())
+            .copied()
+    }
+
+    // char right before suffix if it's at or after given index
+    pub fn before(&self, suffix: &str, from: usize) -> Option<char> {
+        let start = self.start(suffix);
+        (start > from).then(|| self.chars[start - 1])
+    }
+
+    pub fn delete(&mut self, suffix: &str) {
+        let start = self.start(suffix);
+        self.chars.truncate(start);
+    }
+
+    pub fn replace(&mut self, suffix: &str, replacement: &str) {
+        self.delete(suffix);
+        self.chars.extend(replacement.chars());
+    }
+
+    pub fn map(&mut self, f: impl Fn(char) -> char) {
+        self.chars.iter_mut().for_each(|c| *c = f(*c));
+    }
+
+    pub fn into_string(self) -> String {
+        self.chars.into_iter().collect()
+    }
+}
+
+// standard R1 and R2 regions, R1 starts after the first non vowel following a vowel
+// and R2 is the same region found inside of R1
+pub fn regions(chars: &[char], is_vowel: impl Fn(char) -> bool) -> (usize, usize) {
+    let region = |from: usize| {
+        (from.max(1)..chars.len())
+            .find(|i| !is_vowel(chars[*i]) && is_vowel(chars[*i - 1]))
+            .map_or(chars.len(), |i| i + 1)
+    };
+    let r1 = region(0);
+    (r1, region(r1))
+}
@@ -0,0 +1,68 @@
+// built-in stop words lists selected by `stop_words = "<language>"` or by language
+// code with `stop_words_lang = "<code>"`, english one is
+// the default, lists are short on purpose, so only the most frequent function words
+// are dropped and queries made of common words still find something
+static ENGLISH: [&str; 35] = [
+    "a", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it", "no",
+    "not", "of", "on", "or", "s", "such", "t", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with", "www",
+];
+
+static GERMAN: [&str; 46] = [
+    "aber", "als", "am", "an", "auch", "auf", "aus", "bei", "bin", "bis", "das", "dass", "dem",
+    "den", "der", "des", "die", "ein", "eine", "einem", "einen", "einer", "es", "für", "hat", "im",
+    "in", "ist", "mit", "nach", "nicht", "noch", "oder", "sich", "sie", "sind", "so", "um", "und",
+    "vom", "von", "vor", "war", "wie", "zu", "zum",
+];
+
+static FRENCH: [&str; 41] = [
+    "au", "aux", "avec", "ce", "ces", "dans", "de", "des", "du", "elle", "en", "est", "et", "eux",
+    "il", "je", "la", "le", "les", "leur", "lui", "ma", "mais", "me", "mes", "ne", "nous", "on",
+    "ou", "par", "pas", "pour", "qu", "que", "qui", "sa", "se", "ses", "son", "sur", "un",
+];
+
+static SPANISH: [&str; 42] = [
+    "a", "al", "como", "con", "de", "del", "el", "en", "entre", "era", "es", "esta", "este", "fue",
+    "ha", "la", "las", "le", "lo", "los", "más", "me", "mi", "muy", "no", "o", "para", "pero",
+    "por", "que", "se", "sin", "sobre", "su", "sus", "también", "te", "u", "un", "una", "y", "ya",
+];
+
+static ITALIAN: [&str; 40] = [
+    "a", "al", "alla", "anche", "che", "chi", "ci", "come", "con", "da", "dal", "dei", "del",
+    "della", "di", "e", "è", "gli", "ha", "i", "il", "in", "la", "le", "lo", "ma", "mi", "ne",
+    "nel", "nella", "non", "o", "per", "più", "se", "si", "sono", "su", "un", "una",
+];
+
+static DUTCH: [&str; 40] = [
+    "aan", "al", "als", "bij", "dan", "dat", "de", "die", "dit", "een", "en", "er", "had", "heb",
+    "het", "hij", "hoe", "in", "is", "je", "maar", "met", "na", "naar", "niet", "nog", "of", "om",
+    "ook", "op", "over", "te", "tot", "uit", "van", "voor", "was", "wat", "wel", "zijn",
+];
+
+static PORTUGUESE: [&str; 40] = [
+    "a", "ao", "aos", "as", "com", "como", "da", "das", "de", "do", "dos", "e", "é", "ela", "ele",
+    "em", "entre", "era", "essa", "esse", "foi", "isso", "mais", "mas", "na", "nas", "no", "nos",
+    "não", "o", "os", "ou", "para", "pela", "pelo", "por", "que", "se", "seu", "um",
+];
+
+static RUSSIAN: [&str; 40] = [
+    "а", "без", "бы", "в", "во", "вы", "да", "для", "до", "его", "ее", "если", "же", "за", "и",
+    "из", "или", "их", "к", "как", "ли", "мы", "на", "не", "но", "о", "об", "он", "она", "они",
+    "от", "по", "с", "со", "так", "то", "у", "что", "это", "я",
+];
+
+// returns words of a preset with given name or ISO 639-1 code, "none" disables stop words
+pub fn preset(name: &str) -> Option<&'static [&'static str]> {
+    match name.to_lowercase().as_str() {
+        "none" => Some(&[]),
+        "english" | "en" => Some(&ENGLISH),
+        "german" | "de" => Some(&GERMAN),
+        "french" | "fr" => Some(&FRENCH),
+        "spanish" | "es" => Some(&SPANISH),
+        "italian" | "it" => Some(&ITALIAN),
+        "dutch" | "nl" => Some(&DUTCH),
+        "portuguese" | "pt" => Some(&PORTUGUESE),
+        "russian" | "ru" => Some(&RUSSIAN),
+        _ => None,
+    }
+}
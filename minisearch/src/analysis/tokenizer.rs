@@ -1,69 +1,492 @@
 use std::sync::Arc;
 
-use crate::query::parser::Query;
-use crate::{analysis::stemmer::SnowballStemmer, config::Config};
+use crate::analysis::stemmer::{self, Stemmer};
+use crate::analysis::{folding, numeric};
+use crate::config::{Config, StopWordsFallback};
+use crate::query::parser::Clause;
+use crate::utils::lru::LRUCache;
+use crate::utils::memory::HeapSize;
 use hashbrown::HashMap;
+use pyo3::prelude::*;
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug)]
 pub struct Token {
     pub text: String,
     pub fuzz: u8,
+    pub auto_fuzz: bool,
+    pub gap: u32, // number of placeholder positions between this and previous token
+    pub synonyms: Vec<String>, // matched exactly in the same position as the token
+    pub term: usize, // index of clause term the token comes from
 }
 
 pub struct TokenizedQuery {
     pub tokens: Vec<Token>,
     pub slop: u8,
+    pub in_order: bool,
+    pub match_all: bool, // clause made only of stop words matches every document
+    pub field: Option<String>, // field the clause is scoped to
 }
 
 pub struct Tokenizer {
-    stemmer: SnowballStemmer,
+    stemmer: Box<dyn Stemmer + Send + Sync>,
+    // python callable replacing word splitting, stop words and stemming, it receives
+    // text and returns (token, position) pairs, first raised exception is kept and
+    // text which failed is tokenized to nothing
+    custom: Option<Py<PyAny>>,
+    error: Option<PyErr>,
+    truncated: bool, // some words were past max positions since it was last taken
+    stems: LRUCache<String, String>, // normalized forms of recent words
     config: Arc<Config>,
 }
 
 impl Tokenizer {
+    const VALUE_POSITION_GAP: u32 = 1024;
+    const MIN_COMPOUND_PART: usize = 3;
+
     pub fn new(config: Arc<Config>) -> Self {
         Self {
-            stemmer: SnowballStemmer::new(),
+            stemmer: stemmer::for_language(&config.language),
+            custom: None,
+            error: None,
+            truncated: false,
+            stems: LRUCache::new(config.stem_cache_size),
             config: config,
         }
     }
 
-    pub fn tokenize_doc(&mut self, doc: &mut str) -> (u32, HashMap<String, Vec<u32>>) {
+    pub fn set_custom(&mut self, callback: Option<Py<PyAny>>) {
+        self.custom = callback;
+    }
+
+    pub fn take_error(&mut self) -> Option<PyErr> {
+        self.error.take()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.stems.heap_size()
+    }
+
+    pub fn take_truncated(&mut self) -> bool {
+        std::mem::take(&mut self.truncated)
+    }
+
+    fn max_positions(&self) -> u32 {
+        self.config.max_positions.unwrap_or(u32::MAX)
+    }
+
+    fn custom_tokens(&mut self, text: &str) -> Option<Vec<(String, u32)>> {
+        // returns None if custom tokenizer isn't set
+        let callback = self.custom.as_ref()?;
+        if self.error.is_some() {
+            return Some(vec![]);
+        }
+
+        let tokens = Python::with_gil(|py| {
+            callback
+                .call1(py, (text,))?
+                .extract::<Vec<(String, u32)>>(py)
+        });
+        match tokens {
+            Ok(tokens) => Some(tokens),
+            Err(err) => {
+                self.error = Some(err);
+                Some(vec![])
+            }
+        }
+    }
+
+    fn ngram_tokens(&self, text: &str) -> Option<Vec<(String, u32)>> {
+        // returns None if character n-grams aren't enabled, every run of letters and
+        // digits is split into overlapping grams, run shorter than gram is a single
+        // token, positions continue across runs, but no gram spans two of them
+        let n = self.config.char_ngrams?;
+        let mut tokens = vec![];
+        for run in text.split(|c: char| !c.is_alphanumeric()) {
+            let mut run = run.to_lowercase();
+            if self.config.ascii_folding {
+                run = folding::fold(run);
+            }
+            let chars = run.chars().collect::<Vec<char>>();
+            if chars.is_empty() {
+                continue;
+            }
+            for gram in chars.windows(n.min(chars.len())) {
+                tokens.push((gram.iter().collect(), tokens.len() as u32));
+            }
+        }
+        Some(tokens)
+    }
+
+    fn text_tokens(&mut self, text: &str) -> Option<Vec<(String, u32)>> {
+        // tokens with positions of custom tokenizer or character n-grams, returns
+        // None if text is split into words
+        self.custom_tokens(text).or_else(|| self.ngram_tokens(text))
+    }
+
+    pub fn tokenize_doc(
+        &mut self,
+        doc: &mut str,
+    ) -> (u32, HashMap<String, Vec<u32>>, HashMap<String, String>) {
+        // returns also surface forms, first original word from which each token was created
         let mut tokens: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut surfaces: HashMap<String, String> = HashMap::new();
+
+        let (_, len) = self.tokenize_into(doc, 0, &mut tokens, &mut surfaces);
+        (len, tokens, surfaces)
+    }
+
+    fn tokenize_into(
+        &mut self,
+        doc: &str,
+        start: u32,
+        tokens: &mut HashMap<String, Vec<u32>>,
+        surfaces: &mut HashMap<String, String>,
+    ) -> (u32, u32) {
+        // positions start at given one, returns position following the last word and
+        // number of indexed words, words past max positions are dropped
+        let limit = self.max_positions();
+        if let Some(custom) = self.text_tokens(doc) {
+            let (mut end, mut len) = (start, 0);
+            for (token, position) in custom.iter() {
+                match start.checked_add(*position).filter(|p| *p < limit) {
+                    Some(position) => {
+                        tokens.entry_ref(token).or_default().push(position);
+                        end = end.max(position + 1);
+                        len += 1;
+                    }
+                    None => self.truncated = true,
+                }
+            }
+            // positions returned by callback don't have to be in order
+            for positions in tokens.values_mut() {
+                positions.sort_unstable();
+                positions.dedup();
+            }
+            return (end, len);
+        }
 
-        let mut i = 0;
-        for word in doc.unicode_words() {
-            let word = word.to_owned().to_ascii_lowercase();
-            if self.config.stop_words.contains(word.as_str()) {
+        let (mut i, mut len) = (start, 0);
+        for surface in doc.unicode_words() {
+            if i >= limit {
+                self.truncated = true;
+                break;
+            }
+            let word = surface.to_lowercase();
+            if self.config.stop_words.contains(word.as_str())
+                && self.config.stop_words_fallback != StopWordsFallback::Terms
+            {
+                if self.config.stop_words_placeholders {
+                    i += 1;
+                }
                 continue;
             }
-            let word = self.stemmer.stem(word);
+            let mut grams = self.edge_ngrams(&word);
+            grams.extend(self.compound_parts(&word));
+            let word = self.normalize(word);
+            if !surfaces.contains_key(&word) {
+                surfaces.insert(word.clone(), surface.to_owned());
+            }
             tokens.entry_ref(&word).or_default().push(i);
+            // prefixes and compound parts take position of their word, but don't count
+            // into its length, normalized one may be the same as the word or as other one
+            for gram in grams {
+                let positions = tokens.entry_ref(&self.normalize(gram)).or_default();
+                if positions.last() != Some(&i) {
+                    positions.push(i);
+                }
+            }
             i += 1;
+            len += 1;
+        }
+
+        (i, len)
+    }
+
+    pub fn tokenize_query(&mut self, clause: &Clause) -> TokenizedQuery {
+        let (mut query, stopped) = self.tokenize_clause(clause, false);
+        if !query.tokens.is_empty() || !stopped {
+            return query;
         }
 
-        return (i, tokens);
+        match self.config.stop_words_fallback {
+            StopWordsFallback::None => query,
+            StopWordsFallback::Terms => self.tokenize_clause(clause, true).0,
+            StopWordsFallback::All => {
+                query.match_all = true;
+                query
+            }
+        }
     }
 
-    pub fn tokenize_query(&mut self, query: Query) -> TokenizedQuery {
-        let mut tokens: Vec<Token> = Vec::with_capacity(query.terms.len());
+    fn tokenize_clause(
+        &mut self,
+        clause: &Clause,
+        keep_stop_words: bool,
+    ) -> (TokenizedQuery, bool) {
+        // returns also whether any stop word was skipped
+        let mut tokens: Vec<Token> = Vec::with_capacity(clause.terms.len());
 
-        for term in query.terms {
-            if self.config.stop_words.contains(term.text) {
+        let mut stopped = false;
+        let mut gap = 0;
+        for (i, term) in clause.terms.iter().enumerate() {
+            if let Some(custom) = self.text_tokens(&term.text) {
+                self.push_custom_tokens(&mut tokens, custom, i, clause);
                 continue;
             }
 
+            // literal terms are split into words in the same way as documents
+            let words = match term.literal {
+                true => term.text.unicode_words().collect(),
+                false => vec![term.text.as_str()],
+            };
+
+            for word in words {
+                if !keep_stop_words && self.config.stop_words.contains(word) {
+                    // indexed stop words occupy position as well
+                    if self.config.stop_words_placeholders
+                        || self.config.stop_words_fallback == StopWordsFallback::Terms
+                    {
+                        gap += 1;
+                    }
+                    stopped = true;
+                    continue;
+                }
+
+                // alternatives of programmatically built terms are matched as synonyms
+                let mut synonyms = self.config.synonyms.get(word).cloned().unwrap_or_default();
+                synonyms.extend(term.alternatives.iter().cloned());
+                let synonyms = synonyms
+                    .into_iter()
+                    .map(|synonym| self.normalize(synonym))
+                    .collect();
+
+                let text = self.normalize(word.to_string());
+                let token = Token {
+                    text,
+                    fuzz: term.fuzz,
+                    auto_fuzz: term.auto_fuzz,
+                    gap,
+                    synonyms,
+                    term: i,
+                };
+                let token = match &clause.field {
+                    Some(field) => Self::scope_token(field, token),
+                    None => token,
+                };
+                tokens.push(token);
+                gap = 0;
+            }
+        }
+
+        let query = TokenizedQuery {
+            tokens: tokens,
+            slop: clause.slop,
+            in_order: clause.in_order,
+            match_all: false,
+            field: clause.field.clone(),
+        };
+        (query, stopped)
+    }
+
+    fn push_custom_tokens(
+        &mut self,
+        tokens: &mut Vec<Token>,
+        mut custom: Vec<(String, u32)>,
+        i: usize,
+        clause: &Clause,
+    ) {
+        let term = &clause.terms[i];
+        // term is tokenized by custom tokenizer as a whole, gaps between its tokens are
+        // given by their positions, alternatives are tokenized by it as well
+        custom.sort_by_key(|(_, position)| *position);
+        let mut synonyms = vec![];
+        for alternative in term.alternatives.iter() {
+            if let Some(alternative) = self.text_tokens(alternative) {
+                synonyms.extend(alternative.into_iter().map(|(token, _)| token));
+            }
+        }
+
+        let mut previous = None;
+        for (text, position) in custom {
             let token = Token {
-                text: self.stemmer.stem(term.text.to_string()),
+                text,
                 fuzz: term.fuzz,
+                auto_fuzz: term.auto_fuzz,
+                gap: previous.map_or(0, |p: u32| position.saturating_sub(p + 1)),
+                synonyms: synonyms.clone(),
+                term: i,
             };
-            tokens.push(token);
+            tokens.push(match &clause.field {
+                Some(field) => Self::scope_token(field, token),
+                None => token,
+            });
+            previous = Some(position);
         }
+    }
 
-        TokenizedQuery {
-            tokens: tokens,
-            slop: query.slop,
+    pub fn adapt_auto_fuzz(
+        &self,
+        query: &mut TokenizedQuery,
+        df: impl Fn(&str) -> u64,
+        docs_num: u64,
+    ) {
+        // length based auto fuzz is lowered to 0 for frequent terms, which are
+        // most likely spelled correctly, and raised for terms missing in index
+        let frequency = match self.config.auto_fuzz_frequency {
+            Some(frequency) => frequency,
+            None => return,
+        };
+
+        for token in query.tokens.iter_mut().filter(|t| t.auto_fuzz) {
+            let df = df(&token.text);
+            token.fuzz = match df {
+                0 if token.fuzz > 0 => (token.fuzz + 1).min(self.config.max_fuzziness),
+                _ if df as f64 >= frequency * docs_num as f64 => 0,
+                _ => token.fuzz,
+            };
+        }
+    }
+
+    pub fn tokenize_field(
+        &mut self,
+        field: &str,
+        values: &[String],
+    ) -> (u32, HashMap<String, Vec<u32>>, HashMap<String, String>) {
+        // field tokens are indexed separately from document content tokens, positions
+        // of field values are separated by gap greater than any slop, so phrases
+        // never match across values
+        let mut tokens: HashMap<String, Vec<u32>> = HashMap::new();
+        let mut surfaces: HashMap<String, String> = HashMap::new();
+
+        let (mut start, mut len) = (0, 0);
+        for value in values {
+            let (end, value_len) = self.tokenize_into(value, start, &mut tokens, &mut surfaces);
+            start = end.saturating_add(Self::VALUE_POSITION_GAP);
+            len += value_len;
+        }
+
+        (
+            len,
+            tokens
+                .into_iter()
+                .map(|(token, positions)| (Self::field_token(field, &token), positions))
+                .collect(),
+            surfaces
+                .into_iter()
+                .map(|(token, surface)| (Self::field_token(field, &token), surface))
+                .collect(),
+        )
+    }
+
+    pub fn is_field_name(field: &str) -> bool {
+        // only names which can be used in field scoped query are allowed
+        let mut chars = field.chars();
+        matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn scope_token(field: &str, token: Token) -> Token {
+        Token {
+            text: Self::field_token(field, &token.text),
+            synonyms: token
+                .synonyms
+                .iter()
+                .map(|synonym| Self::field_token(field, synonym))
+                .collect(),
+            ..token
         }
     }
+
+    pub fn field_token(field: &str, token: &str) -> String {
+        // whitespace never occurs in document tokens, so it can't collide with them
+        format!("{field} {token}")
+    }
+
+    pub fn tokenize_term(&mut self, term: &str) -> Option<String> {
+        if let Some(custom) = self.text_tokens(term) {
+            return custom
+                .into_iter()
+                .min_by_key(|(_, p)| *p)
+                .map(|(token, _)| token);
+        }
+
+        let term = term.to_lowercase();
+        if self.config.stop_words.contains(term.as_str())
+            && self.config.stop_words_fallback != StopWordsFallback::Terms
+        {
+            return None;
+        }
+
+        Some(self.normalize(term))
+    }
+
+    pub fn first_number(&self, doc: &str) -> Option<f64> {
+        doc.unicode_words()
+            .find_map(|word| numeric::normalize(word)?.parse::<f64>().ok())
+    }
+
+    fn edge_ngrams(&self, word: &str) -> Vec<String> {
+        // prefixes of word with length in configured range, word itself is excluded
+        let Some((min, max)) = self.config.edge_ngrams else {
+            return vec![];
+        };
+        word.char_indices()
+            .skip(min)
+            .take(max + 1 - min)
+            .map(|(end, _)| word[..end].to_string())
+            .collect()
+    }
+
+    fn compound_parts(&self, word: &str) -> Vec<String> {
+        // dictionary words found inside of word, overlapping ones too, word itself is
+        // excluded
+        if self.config.compound_words.is_empty() {
+            return vec![];
+        }
+
+        let chars = word.chars().collect::<Vec<char>>();
+        let mut parts = vec![];
+        for start in 0..chars.len() {
+            for end in start + Self::MIN_COMPOUND_PART..=chars.len() {
+                if end - start == chars.len() {
+                    continue;
+                }
+                let part = chars[start..end].iter().collect::<String>();
+                if self.config.compound_words.contains(&part) {
+                    parts.push(part);
+                }
+            }
+        }
+        parts
+    }
+
+    fn normalize(&mut self, word: String) -> String {
+        // normalized form depends only on the word, so it's cached by it
+        if self.config.stem_cache_size == 0 {
+            return self.normalize_word(word);
+        }
+        if let Some(normalized) = self.stems.get(&word) {
+            return normalized.clone();
+        }
+        let normalized = self.normalize_word(word.clone());
+        self.stems.insert(word, normalized.clone());
+        normalized
+    }
+
+    fn normalize_word(&mut self, word: String) -> String {
+        // stop words are checked before folding, so they are matched with diacritics
+        let word = match self.config.ascii_folding {
+            true => folding::fold(word),
+            false => word,
+        };
+        // numbers are not stemmed
+        if self.config.normalize_numbers
+            && let Some(number) = numeric::normalize(&word)
+        {
+            return number;
+        }
+
+        self.stemmer.stem(word)
+    }
 }
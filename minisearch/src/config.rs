@@ -1,9 +1,17 @@
-use serde::Deserialize;
-use std::{collections::HashSet, fs, io, path::PathBuf};
+use serde::{Deserialize, Deserializer, de};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io, mem,
+    path::PathBuf,
+};
 use thiserror::Error;
 use toml::{self, de::Error};
 
+use crate::analysis::stopwords;
+use crate::analysis::tokenizer::Tokenizer;
 use crate::errors::TomlDeserializeException;
+use crate::utils::automaton::MAX_DISTANCE;
+use pyo3::exceptions::PyValueError;
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -11,6 +19,32 @@ pub enum ConfigError {
     TomlDeError(#[from] Error),
     #[error("config: io error: {0}")]
     Io(#[from] io::Error),
+    #[error("config: max_fuzziness must be less or equal to {1}, but it is {0}")]
+    MaxFuzziness(u8, u8),
+    #[error("config: coordination must be a non negative number, but it is {0}")]
+    Coordination(f64),
+    #[error("config: proximity_weight must be a non negative number, but it is {0}")]
+    ProximityWeight(f64),
+    #[error("config: weight of field '{0}' must be a positive number, but it is {1}")]
+    FieldWeight(String, f64),
+    #[error("config: num_threads must be a positive number")]
+    NumThreads,
+    #[error("config: edge_ngrams must be a positive range, but it is [{0}, {1}]")]
+    EdgeNgrams(usize, usize),
+    #[error("config: char_ngrams must be a positive number")]
+    CharNgrams,
+    #[error("config: max_positions must be a positive number")]
+    MaxPositions,
+    #[error("config: unknown stop_words_lang \"{0}\"")]
+    StopWordsLang(String),
+    #[error("config: search_defaults fuzziness must be less or equal to {1}, but it is {0}")]
+    DefaultFuzziness(u8, u8),
+    #[error("config: search_defaults timeout must be greater than 0, but it is {0}")]
+    DefaultTimeout(f64),
+    #[error("config: search_defaults fields contain invalid field name \"{0}\"")]
+    DefaultField(String),
+    #[error("config: coalesce_on_open requires coalesce_segments_below to be set")]
+    CoalesceOnOpen,
 }
 
 impl From<ConfigError> for pyo3::PyErr {
@@ -18,10 +52,94 @@ impl From<ConfigError> for pyo3::PyErr {
         match err {
             ConfigError::TomlDeError(err) => TomlDeserializeException::new_err(err.to_string()),
             ConfigError::Io(err) => err.into(),
+            ConfigError::MaxFuzziness(..)
+            | ConfigError::Coordination(..)
+            | ConfigError::ProximityWeight(..)
+            | ConfigError::FieldWeight(..)
+            | ConfigError::NumThreads
+            | ConfigError::EdgeNgrams(..)
+            | ConfigError::CharNgrams
+            | ConfigError::MaxPositions
+            | ConfigError::StopWordsLang(..)
+            | ConfigError::DefaultFuzziness(..)
+            | ConfigError::DefaultTimeout(..)
+            | ConfigError::DefaultField(..)
+            | ConfigError::CoalesceOnOpen => PyValueError::new_err(err.to_string()),
         }
     }
 }
 
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentsStorage {
+    Memory, // all documents metadata is kept in memory
+    Disk,   // only segments keys tables are kept on disk, metadata is read on demand
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StopWordsFallback {
+    None,  // query made only of stop words matches nothing
+    Terms, // stop words are indexed too and such query is matched with them
+    All,   // query made only of stop words matches every document
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodeErrorPolicy {
+    Raise,   // loading index fails with decode error
+    Rebuild, // warning is logged and tokens are recovered from index and stored documents
+    Reset,   // warning is logged and unreadable state is started from scratch
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    Truncate, // words past the limit aren't indexed and document is flagged as truncated
+    Reject,   // adding the document fails
+}
+
+// values of search options which weren't set, neither by argument nor in options
+// passed to search, there is no highlighting to set default for
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct SearchDefaults {
+    pub top_k: u32,
+    pub fuzziness: u8,
+    pub timeout: Option<f64>,
+    pub fields: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    English,
+    German,
+    French,
+    Spanish,
+    Russian,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StopWords {
+    Preset(String),         // name of built-in list, e.g. "english" or "none"
+    Words(HashSet<String>), // the full list given inline
+}
+
+fn stop_words<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<String>, D::Error> {
+    match StopWords::deserialize(deserializer)? {
+        StopWords::Preset(name) => match stopwords::preset(&name) {
+            Some(words) => Ok(words.iter().map(|word| word.to_string()).collect()),
+            None => Err(de::Error::custom(format!(
+                "unknown stop words preset \"{}\"",
+                name
+            ))),
+        },
+        StopWords::Words(words) => Ok(words),
+    }
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(default)]
 pub struct Config {
@@ -30,6 +148,20 @@ pub struct Config {
     pub documents_buffer_size: u64,
     pub documents_save_after_seconds: u64,
     pub merge_deleted_ratio: f64,
+    pub segment_bloom_bits: u64,
+    pub documents_storage: DocumentsStorage,
+    // if set segments smaller than this many bytes are rewritten into the current
+    // segment by merge, when there is more than one of them, e.g. after many short
+    // sessions
+    pub coalesce_segments_below: Option<u64>,
+    // whether small segments are rewritten also when index is opened, before it
+    // serves any query, it's meant only for the writer process, readers opening the
+    // same directory must leave it off, so they never modify it
+    pub coalesce_on_open: bool,
+    // if set content of documents up to this many bytes is stored compressed in
+    // their meta record instead of data file, so reading it needs no extra I/O,
+    // meta file then counts into segment size
+    pub inline_documents_size: Option<usize>,
     // search metadata config
     pub metadata_save_after_operations: u32,
     pub metadata_save_after_seconds: u64,
@@ -37,8 +169,126 @@ pub struct Config {
     pub index_buffer_size: u64,
     pub index_save_after_operations: u64,
     pub index_save_after_seconds: u64,
+    // number of index shards, tokens are partitioned between them by id and every
+    // shard has its own log files, it can't be changed once index is written
+    pub index_shards: usize,
+    // if set at most this many threads load, replay and flush shards at once,
+    // 1 processes them one by one in the calling thread, by default every shard
+    // gets its own thread
+    pub num_threads: Option<usize>,
+    // if set only this many postings with the highest tf are scanned for tokens found
+    // in more documents, which bounds latency at the cost of recall for common terms
+    pub max_docs_per_term: Option<u64>,
+    // if enabled merge builds copies of posting lists in descending impact order, which
+    // let single term and OR queries stop reading postings once top k is known
+    pub impact_ordered_postings: bool,
+    // if enabled positions of postings are read from index files only by phrase and
+    // proximity matching, which saves memory, but makes such queries slower
+    pub lazy_positions: bool,
     // additional config
+    // language of stemmer used for both documents and queries, it changes indexed
+    // tokens, so it can't be changed for already written index
+    pub language: Language,
+    // either the full list of stop words or name of built-in preset, e.g. "german",
+    // "none" disables them
+    #[serde(deserialize_with = "stop_words")]
     pub stop_words: HashSet<String>,
+    // if set built-in stop words list of this language replaces stop_words, it is
+    // selected by ISO 639-1 code or name, e.g. "de" or "german"
+    pub stop_words_lang: Option<String>,
+    // file with additional stop words, one word per line
+    pub stop_words_file: Option<PathBuf>,
+    // individual words added to or removed from configured list, e.g. to keep
+    // "it" searchable as a name of department
+    pub stop_words_add: Vec<String>,
+    pub stop_words_remove: Vec<String>,
+    // if enabled stop words are not indexed but still consume a position,
+    // so phrase slop takes the gap left by them into account
+    pub stop_words_placeholders: bool,
+    // how query (clause) consisting only of stop words is matched, "terms" changes
+    // how documents are indexed, so it can't be enabled for already written index
+    pub stop_words_fallback: StopWordsFallback,
+    // if set prefixes of words with length in this range are indexed at the position
+    // of their word, e.g. [2, 4] makes "search" found by "se", "sea" and "sear", so
+    // incomplete query matches without fuzzy expansion, every word adds at most
+    // max - min + 1 tokens, it changes indexed tokens, so it can't be changed for
+    // already written index
+    pub edge_ngrams: Option<(usize, usize)>,
+    // if set text is split into overlapping grams of this many characters instead
+    // of words, e.g. for languages without spaces or substring search, grams aren't
+    // stemmed nor checked against stop words and query term matches as phrase of
+    // its grams, it can't be changed for already written index
+    pub char_ngrams: Option<usize>,
+    // if enabled letters with diacritics are folded to ascii before stemming, e.g.
+    // "café" is indexed and searched as "cafe", it changes indexed tokens, so it
+    // can't be changed for already written index
+    pub ascii_folding: bool,
+    // number of recently normalized words whose stems are cached, which saves
+    // stemming the same words again during bulk indexing, 0 disables the cache
+    pub stem_cache_size: usize,
+    // if set words at positions past this limit aren't indexed, which bounds memory
+    // and index size taken by huge documents, positions are u32, so without the
+    // limit document can't have more than u32::MAX of them
+    pub max_positions: Option<u32>,
+    // what happens with document (or its field) exceeding max_positions
+    pub on_position_overflow: OverflowPolicy,
+    // if disabled fuzzy trie isn't built, which saves memory and load time, and
+    // queries with fuzzy terms are rejected
+    pub enable_fuzzy: bool,
+    // soft limit on number of words kept in fuzzy trie, least frequent words
+    // are evicted from it but remain searchable with exact match
+    pub fuzzy_vocabulary_cap: Option<u64>,
+    // if set terms with auto fuzz ("~") found in at least this fraction of
+    // documents are matched exactly, terms missing in index get higher fuzz
+    pub auto_fuzz_frequency: Option<f64>,
+    // auto fuzz ("~") of a term is the number of these lengths it is longer than,
+    // e.g. with [2, 5] terms up to 2 characters are exact and longer than 5 get 2
+    pub auto_fuzz_lengths: Vec<usize>,
+    // the highest fuzziness of a term, higher explicit fuzz is rejected and auto
+    // fuzz is capped, lower value limits cost of fuzzy expansion
+    pub max_fuzziness: u8,
+    // score of disjunction (OR or min_should_match) is multiplied by fraction of its
+    // terms that matched raised to this power, so documents matching more distinct
+    // terms rank higher, 0 disables coordination
+    pub coordination: f64,
+    // score of phrase matched with gaps is divided by (slop + 1) raised to this power,
+    // lower value makes term frequency matter more than proximity, 0 ignores gaps
+    pub proximity_weight: f64,
+    // score of field scoped clause is multiplied by weight of its field, e.g. title = 3.0,
+    // fields which aren't listed have weight 1
+    pub field_weights: HashMap<String, f64>,
+    // if set text queries with at least one result are recorded for query suggestions,
+    // at most this many distinct queries are kept
+    pub query_log_size: Option<usize>,
+    // if enabled numbers are indexed in canonical form, e.g. "1,000" as "1000"
+    pub normalize_numbers: bool,
+    // name of attribute under which first number found in document is stored
+    pub numbers_attribute: Option<String>,
+    // query terms are expanded with their synonyms, e.g. car = ["automobile", "auto"],
+    // synonym matches the same position as the term it was expanded from
+    pub synonyms: HashMap<String, Vec<String>>,
+    // file with comma separated groups of equivalent words, one group per line,
+    // every word of a group is expanded with all other words of that group
+    pub synonyms_file: Option<PathBuf>,
+    // synonyms with more than one word on either side, e.g. "new york" = ["nyc"],
+    // split from synonyms when config is loaded, clause containing the words is
+    // matched also as phrases with them replaced by their synonyms
+    #[serde(skip)]
+    pub phrase_synonyms: HashMap<Vec<String>, Vec<String>>,
+    // dictionary of words which are indexed also when found inside of a longer word,
+    // at its position, e.g. with "dampf" and "schiff" "donaudampfschiff" is found by
+    // "schiff", it changes indexed tokens, so it can't be changed for already written
+    // index
+    pub compound_words: HashSet<String>,
+    // file with additional compound dictionary words, one word per line
+    pub compound_words_file: Option<PathBuf>,
+    // what happens when tokens or display terms files can't be decoded, e.g. after
+    // they were truncated, resetting them leaves indexed tokens unsearchable
+    pub on_decode_error: DecodeErrorPolicy,
+    // search config
+    // defaults of search options, e.g. [search_defaults] top_k = 10, so they can be
+    // tuned without changing every search call
+    pub search_defaults: SearchDefaults,
 }
 
 impl Default for Config {
@@ -49,6 +299,11 @@ impl Default for Config {
             documents_buffer_size: 1024 * 1024,
             documents_save_after_seconds: 5,
             merge_deleted_ratio: 0.3,
+            segment_bloom_bits: 1 << 20,
+            documents_storage: DocumentsStorage::Memory,
+            coalesce_segments_below: None,
+            coalesce_on_open: false,
+            inline_documents_size: None,
             // search metadata config
             metadata_save_after_operations: 100_000,
             metadata_save_after_seconds: 10,
@@ -56,22 +311,55 @@ impl Default for Config {
             index_buffer_size: 1024 * 1024,
             index_save_after_operations: 100_000,
             index_save_after_seconds: 5,
+            index_shards: 1,
+            num_threads: None,
+            max_docs_per_term: None,
+            impact_ordered_postings: false,
+            lazy_positions: false,
             // additional config
-            stop_words: [
-                "a", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is",
-                "it", "no", "not", "of", "on", "or", "s", "such", "t", "that", "the", "their",
-                "then", "there", "these", "they", "this", "to", "was", "will", "with", "www",
-            ]
-            .map(|word| word.to_string())
-            .into_iter()
-            .collect(),
+            language: Language::English,
+            stop_words: stopwords::preset("english")
+                .unwrap_or_default()
+                .iter()
+                .map(|word| word.to_string())
+                .collect(),
+            stop_words_lang: None,
+            stop_words_file: None,
+            stop_words_add: vec![],
+            stop_words_remove: vec![],
+            stop_words_placeholders: false,
+            stop_words_fallback: StopWordsFallback::None,
+            edge_ngrams: None,
+            char_ngrams: None,
+            ascii_folding: false,
+            stem_cache_size: 10_000,
+            max_positions: None,
+            on_position_overflow: OverflowPolicy::Truncate,
+            enable_fuzzy: true,
+            fuzzy_vocabulary_cap: None,
+            auto_fuzz_frequency: None,
+            auto_fuzz_lengths: vec![2, 5],
+            max_fuzziness: MAX_DISTANCE,
+            coordination: 0.0,
+            proximity_weight: 1.0,
+            field_weights: HashMap::new(),
+            query_log_size: None,
+            normalize_numbers: false,
+            numbers_attribute: None,
+            synonyms: HashMap::new(),
+            synonyms_file: None,
+            phrase_synonyms: HashMap::new(),
+            compound_words: HashSet::new(),
+            compound_words_file: None,
+            on_decode_error: DecodeErrorPolicy::Raise,
+            search_defaults: SearchDefaults::default(),
         }
     }
 }
 
 impl Config {
     pub fn load(path: Option<PathBuf>) -> Result<Self, ConfigError> {
-        let config = match path {
+        let mut config: Self = match path {
             Some(path) => {
                 let config: String = fs::read_to_string(path)?;
                 toml::from_str(&config)?
@@ -79,6 +367,192 @@ impl Config {
             None => Self::default(),
         };
 
+        if config.max_fuzziness > MAX_DISTANCE {
+            return Err(ConfigError::MaxFuzziness(
+                config.max_fuzziness,
+                MAX_DISTANCE,
+            ));
+        }
+
+        if !config.coordination.is_finite() || config.coordination < 0.0 {
+            return Err(ConfigError::Coordination(config.coordination));
+        }
+
+        if !config.proximity_weight.is_finite() || config.proximity_weight < 0.0 {
+            return Err(ConfigError::ProximityWeight(config.proximity_weight));
+        }
+
+        if let Some((field, weight)) = config
+            .field_weights
+            .iter()
+            .find(|(_, weight)| !weight.is_finite() || **weight <= 0.0)
+        {
+            return Err(ConfigError::FieldWeight(field.clone(), *weight));
+        }
+
+        if config.search_defaults.fuzziness > config.max_fuzziness {
+            return Err(ConfigError::DefaultFuzziness(
+                config.search_defaults.fuzziness,
+                config.max_fuzziness,
+            ));
+        }
+
+        if let Some(timeout) = config.search_defaults.timeout
+            && (!timeout.is_finite() || timeout <= 0.0)
+        {
+            return Err(ConfigError::DefaultTimeout(timeout));
+        }
+
+        if let Some(field) = config
+            .search_defaults
+            .fields
+            .iter()
+            .flatten()
+            .find(|field| !Tokenizer::is_field_name(field))
+        {
+            return Err(ConfigError::DefaultField(field.clone()));
+        }
+
+        if config.coalesce_on_open && config.coalesce_segments_below.is_none() {
+            return Err(ConfigError::CoalesceOnOpen);
+        }
+
+        if config.num_threads == Some(0) {
+            return Err(ConfigError::NumThreads);
+        }
+
+        if let Some((min, max)) = config.edge_ngrams
+            && (min == 0 || min > max)
+        {
+            return Err(ConfigError::EdgeNgrams(min, max));
+        }
+
+        if config.char_ngrams == Some(0) {
+            return Err(ConfigError::CharNgrams);
+        }
+
+        if config.max_positions == Some(0) {
+            return Err(ConfigError::MaxPositions);
+        }
+
+        if let Some(lang) = &config.stop_words_lang {
+            match stopwords::preset(lang) {
+                Some(words) => {
+                    config.stop_words = words.iter().map(|word| word.to_string()).collect()
+                }
+                None => return Err(ConfigError::StopWordsLang(lang.clone())),
+            }
+        }
+
+        if let Some(path) = &config.stop_words_file {
+            let words = fs::read_to_string(path)?;
+            config.add_stop_words(&words);
+        }
+
+        // words are compared with lowercased document words
+        config
+            .stop_words
+            .extend(config.stop_words_add.iter().map(|word| word.to_lowercase()));
+        for word in config.stop_words_remove.iter() {
+            config.stop_words.remove(&word.to_lowercase());
+        }
+
+        if let Some(path) = &config.synonyms_file {
+            let groups = fs::read_to_string(path)?;
+            config.add_synonym_groups(&groups);
+        }
+
+        if let Some(path) = &config.compound_words_file {
+            let words = fs::read_to_string(path)?;
+            config.compound_words.extend(
+                words
+                    .lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|word| word.to_string()),
+            );
+        }
+        // parts are looked up in lowercased document words
+        config.compound_words = config
+            .compound_words
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect();
+
+        // query terms are looked up in lowercase form
+        config.synonyms = config
+            .synonyms
+            .into_iter()
+            .map(|(word, synonyms)| {
+                (
+                    word.to_lowercase(),
+                    synonyms.iter().map(|s| s.to_lowercase()).collect(),
+                )
+            })
+            .collect();
+        config.split_phrase_synonyms();
+
         Ok(config)
     }
+
+    fn add_stop_words(&mut self, words: &str) {
+        // words are compared with lowercased document words
+        self.stop_words.extend(
+            words
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|word| word.to_lowercase()),
+        );
+    }
+
+    fn split_phrase_synonyms(&mut self) {
+        // single word synonyms of single words stay token alternatives, all the
+        // others are matched as phrases
+        let words = |text: &str| {
+            text.split_whitespace()
+                .map(|word| word.to_string())
+                .collect::<Vec<String>>()
+        };
+        for (word, synonyms) in mem::take(&mut self.synonyms) {
+            let key = words(&word);
+            if key.is_empty() {
+                continue;
+            }
+
+            let (phrases, single): (Vec<String>, Vec<String>) = synonyms
+                .into_iter()
+                .map(|synonym| words(&synonym).join(" "))
+                .filter(|synonym| !synonym.is_empty())
+                .partition(|synonym| key.len() > 1 || synonym.contains(' '));
+            if !phrases.is_empty() {
+                self.phrase_synonyms.insert(key.clone(), phrases);
+            }
+            if key.len() == 1 && !single.is_empty() {
+                self.synonyms.insert(key[0].clone(), single);
+            }
+        }
+    }
+
+    fn add_synonym_groups(&mut self, groups: &str) {
+        for line in groups
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+        {
+            let words = line
+                .split(',')
+                .map(|word| word.trim())
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<&str>>();
+
+            for word in words.iter() {
+                let synonyms = self.synonyms.entry(word.to_string()).or_default();
+                for synonym in words.iter().filter(|s| *s != word) {
+                    if !synonyms.iter().any(|s| s == synonym) {
+                        synonyms.push(synonym.to_string());
+                    }
+                }
+            }
+        }
+    }
 }
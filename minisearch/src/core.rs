@@ -1,2 +1,6 @@
+pub mod export;
 pub mod index;
+pub mod options;
+pub mod postings;
 pub mod search;
+pub mod shadow;
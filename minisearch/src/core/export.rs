@@ -0,0 +1,86 @@
+use crate::core::index::IndexManager;
+use crate::storage::documents::DocumentsManager;
+use crate::utils::hasher::TokenHasher;
+use hashbrown::HashSet;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use ulid::Ulid;
+
+// maximal number of rows of vocabulary growth, documents are sampled evenly
+const GROWTH_SAMPLES: usize = 1000;
+
+fn quote(value: &str) -> String {
+    // field tokens contain space and terms may contain any other character
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+pub fn export_stats(
+    dir: &Path,
+    index_manager: &IndexManager,
+    documents_manager: &DocumentsManager,
+    hasher: &TokenHasher,
+) -> io::Result<()> {
+    // writes csv files: terms.csv with document and total frequency of every term,
+    // doc_lengths.csv with histogram of document lengths and vocabulary_growth.csv
+    // with number of distinct terms after given number of documents in order they
+    // were added, documents pending deletion are skipped
+    fs::create_dir_all(dir)?;
+    let deleted = &documents_manager.deleted_docs_buffer;
+
+    let mut terms = BufWriter::new(File::create(dir.join("terms.csv"))?);
+    writeln!(terms, "term,display,df,tf")?;
+    let mut tokens = index_manager.iter().collect::<Vec<_>>();
+    tokens.sort_unstable_by_key(|(token, _)| **token);
+    for (token, postings) in tokens {
+        let (mut df, mut tf) = (0, 0);
+        for posting in postings.iter() {
+            if deleted.contains_key(&Ulid(posting.doc_id)) {
+                continue;
+            }
+            df += 1;
+            tf += posting.tf as u64;
+        }
+
+        let term = match hasher.unhash(*token) {
+            Some(term) if df > 0 => term,
+            _ => continue,
+        };
+        let display = hasher.display(*token).map_or(String::new(), |d| quote(d));
+        writeln!(terms, "{},{},{},{}", quote(term), display, df, tf)?;
+    }
+    terms.flush()?;
+
+    let ids = documents_manager
+        .ids()
+        .into_iter()
+        .filter(|id| !deleted.contains_key(id))
+        .collect::<Vec<Ulid>>();
+    let step = ids.len().div_ceil(GROWTH_SAMPLES).max(1);
+
+    let mut lengths: BTreeMap<u32, u64> = BTreeMap::new();
+    let mut vocabulary = HashSet::new();
+    let mut growth = BufWriter::new(File::create(dir.join("vocabulary_growth.csv"))?);
+    writeln!(growth, "documents,vocabulary")?;
+    for (i, id) in ids.iter().enumerate() {
+        if let Some(doc) = documents_manager.get(id) {
+            *lengths.entry(doc.len).or_default() += 1;
+            vocabulary.extend(doc.tokens.iter().copied());
+        }
+
+        if (i + 1) % step == 0 || i + 1 == ids.len() {
+            writeln!(growth, "{},{}", i + 1, vocabulary.len())?;
+        }
+    }
+    growth.flush()?;
+
+    let mut histogram = BufWriter::new(File::create(dir.join("doc_lengths.csv"))?);
+    writeln!(histogram, "length,documents")?;
+    for (length, documents) in lengths {
+        writeln!(histogram, "{},{}", length, documents)?;
+    }
+    histogram.flush()?;
+
+    Ok(())
+}
@@ -1,20 +1,29 @@
 use crate::config::Config;
 use crate::errors::{
-    BincodeDecodeError, BincodePersistenceError, TryFromSliceException, UnknownLogOperation,
+    BincodeDecodeError, BincodePersistenceError, DuplicatePosting, TryFromSliceException,
+    UnknownLogOperation,
 };
+use crate::query::scoring::quantize_impact;
 use crate::utils::hasher::TokenHasher;
+use crate::utils::memory::HeapSize;
 use crate::utils::trie::Trie;
 
 use std::array::TryFromSliceError;
 use std::borrow::Cow;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::time::{SystemTime, SystemTimeError};
-use std::{io, path::PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use std::{panic, thread};
 
 use bincode::config::Configuration;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
 use bincode::enc::EncoderImpl;
 use bincode::enc::write::SizeWriter;
 use bincode::error::{DecodeError, EncodeError};
@@ -23,7 +32,7 @@ use hashbrown::hash_map::Entry;
 use hashbrown::{HashMap, HashSet};
 use memmap2::Mmap;
 use nohash_hasher::BuildNoHashHasher;
-use pyo3::exceptions::PySystemError;
+use pyo3::exceptions::{PySystemError, PyValueError};
 use std::fmt::Debug;
 use thiserror::Error;
 
@@ -80,6 +89,12 @@ pub enum IndexManagerError {
     LogsReaderError(#[from] LogsReaderError),
     #[error("index manager: bincode persistence error: {0}")]
     BincodePersistenceError(#[from] BincodePersistenceError),
+    #[error(
+        "index manager: posting of document {0} for token {1} isn't newer than the last one, document was already added"
+    )]
+    DuplicatePosting(Ulid, u32),
+    #[error("index manager: index was written with {0} shards, but index_shards is set to {1}")]
+    ShardsMismatch(usize, usize),
 }
 
 impl From<IndexManagerError> for pyo3::PyErr {
@@ -89,6 +104,8 @@ impl From<IndexManagerError> for pyo3::PyErr {
             IndexManagerError::Time(err) => PySystemError::new_err(err.to_string()),
             IndexManagerError::LogsReaderError(err) => err.into(),
             IndexManagerError::BincodePersistenceError(err) => err.into(),
+            IndexManagerError::DuplicatePosting(..) => DuplicatePosting::new_err(err.to_string()),
+            IndexManagerError::ShardsMismatch(..) => PyValueError::new_err(err.to_string()),
         }
     }
 }
@@ -159,10 +176,14 @@ impl<'a> IndexLogImpl<'a> {
     }
 }
 
-fn decode_log<'a>(bytes: &[u8]) -> Result<IndexLogImpl<'a>, FromBytesError> {
+fn decode_log<'a>(bytes: &[u8], stored: Option<u64>) -> Result<IndexLogImpl<'a>, FromBytesError> {
+    // if offset of log is given positions of added posting are left in index file
     let operation = LogOperation::from_u8(u8::from_be_bytes(bytes[..1].try_into()?))?;
     match operation {
-        LogOperation::ADD => Ok(IndexLogImpl::Add(AddLog::from_bytes(bytes)?)),
+        LogOperation::ADD => match stored {
+            Some(offset) => Ok(IndexLogImpl::Add(AddLog::from_stored_bytes(bytes, offset)?)),
+            None => Ok(IndexLogImpl::Add(AddLog::from_bytes(bytes)?)),
+        },
         LogOperation::DELETE => Ok(IndexLogImpl::Delete(DeleteLog::from_bytes(bytes)?)),
     }
 }
@@ -242,6 +263,26 @@ impl<'a> IndexLog for AddLog<'a> {
 }
 
 impl<'a> AddLog<'a> {
+    fn from_stored_bytes(bytes: &[u8], offset: u64) -> Result<Self, FromBytesError> {
+        // positions are encoded as their number followed by values, so only document
+        // id and number of positions are decoded
+        let header = LogHeader::from_bytes(bytes[..LogHeader::ENCODED_SIZE].try_into()?)?;
+        let ((doc_id, tf), _): ((u128, u64), usize) = bincode::decode_from_slice(
+            &bytes[LogHeader::ENCODED_SIZE..],
+            bincode::config::standard(),
+        )?;
+
+        Ok(Self {
+            header,
+            posting: Cow::Owned(Posting {
+                doc_id,
+                tf: tf as u32,
+                positions: Positions::Stored(offset + LogHeader::ENCODED_SIZE as u64),
+                impact: u8::MAX,
+            }),
+        })
+    }
+
     fn new(token: u32, postings_num: u32, posting: &'a Posting) -> Self {
         Self {
             header: LogHeader {
@@ -334,7 +375,6 @@ impl Buffer {
 }
 
 enum ReadDirection {
-    #[allow(dead_code)]
     FORWARD,
     BACKWARD,
 }
@@ -399,6 +439,7 @@ struct LogsReader<'a> {
     _marker: PhantomData<&'a ()>,
     mmap: Mmap,
     meta_reader: MetaReader,
+    lazy: bool, // positions of added postings aren't decoded
 }
 
 impl<'a> LogsReader<'a> {
@@ -409,6 +450,25 @@ impl<'a> LogsReader<'a> {
             _marker: PhantomData,
             mmap: mmap,
             meta_reader: MetaReader::new(index_dir.join("meta"), direction)?,
+            lazy: false,
+        })
+    }
+
+    fn from_offset(index_dir: &Path, offset: u64) -> Result<Self, io::Error> {
+        // reads logs forward starting from given meta file offset, meta is mapped
+        // before index so every visible meta points to already written index log
+        let mut meta_reader = MetaReader::new(index_dir.join("meta"), ReadDirection::FORWARD)?;
+        meta_reader.offset = offset as i64;
+        // skip partially written meta at the end of file
+        meta_reader.file_size -= meta_reader.file_size % LogMeta::ENCODED_SIZE as u64;
+
+        let file = File::open(index_dir.join("index"))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self {
+            _marker: PhantomData,
+            mmap,
+            meta_reader,
+            lazy: false,
         })
     }
 }
@@ -426,7 +486,8 @@ impl<'a> Iterator for LogsReader<'a> {
         };
 
         let bytes = &self.mmap[meta.offset as usize..(meta.offset as usize + meta.size as usize)];
-        let log = match decode_log(bytes) {
+        let stored = self.lazy.then_some(meta.offset);
+        let log = match decode_log(bytes, stored) {
             Ok(log) => log,
             Err(e) => return Some(Err(LogsReaderError::FromBytesError(e))),
         };
@@ -438,6 +499,9 @@ impl<'a> Iterator for LogsReader<'a> {
 struct LogsManager {
     last_save: u64,
     buffer: Buffer,
+    meta_read: u64, // bytes of meta file already applied to in-memory index
+    // index file mapped at the last load or replay, stored positions are read from it
+    positions: Option<Mmap>,
     config: Arc<Config>,
 }
 
@@ -453,6 +517,8 @@ impl LogsManager {
                 index: Vec::new(),
                 meta: Vec::new(),
             },
+            meta_read: 0,
+            positions: None,
             config: config,
         })
     }
@@ -467,24 +533,26 @@ impl LogsManager {
             || cur_ts >= self.last_save + self.config.index_save_after_seconds
         {
             self.last_save = cur_ts;
-            self.buffer.flush()?;
+            self.flush()?;
         }
 
         Ok(())
     }
 
     fn load(
-        &self,
+        &mut self,
         direction: ReadDirection,
     ) -> Result<HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>, LogsReaderError> {
-        let reader = LogsReader::new(&self.buffer.dir, direction)?;
+        self.meta_read = File::open(self.buffer.dir.join("meta"))?.metadata()?.len();
+        let mut reader = LogsReader::new(&self.buffer.dir, direction)?;
+        reader.lazy = self.config.lazy_positions;
 
         let mut index: HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>> = HashMap::default();
         let mut tokens_cur_index: HashMap<u32, usize, BuildNoHashHasher<u32>> = HashMap::default();
         let mut deleted: HashSet<u128> = HashSet::default();
         let mut empty_postings = vec![];
 
-        for res in reader {
+        for res in reader.by_ref() {
             let (meta, log) = res?;
 
             let idx = tokens_cur_index
@@ -517,59 +585,576 @@ impl LogsManager {
         for token in empty_postings {
             index.remove(&token);
         }
+        if reader.lazy {
+            self.positions = Some(reader.mmap);
+        }
 
         Ok(index)
     }
 
+    fn replay(
+        &mut self,
+        index: &mut HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+    ) -> Result<(), LogsReaderError> {
+        // applies logs written by other process since the last load
+        let mut reader = LogsReader::from_offset(&self.buffer.dir, self.meta_read)?;
+        reader.lazy = self.config.lazy_positions;
+        let meta_size = reader.meta_reader.file_size;
+
+        for res in reader.by_ref() {
+            let (meta, log) = res?;
+            let token = log.header().token;
+
+            match log {
+                IndexLogImpl::Add(log) => {
+                    let postings = index.entry(token).or_default();
+                    let posting = log.posting.into_owned();
+                    if let Err(idx) = postings.binary_search_by(|p| p.doc_id.cmp(&posting.doc_id)) {
+                        postings.insert(idx, posting);
+                    }
+                }
+                IndexLogImpl::Delete(_) => {
+                    if let Some(postings) = index.get_mut(&token) {
+                        postings.retain(|p| p.doc_id != meta.id);
+                        if postings.is_empty() {
+                            index.remove(&token);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.meta_read = self.meta_read.max(meta_size);
+        if reader.lazy {
+            // index file only grows, so new mapping covers all stored postings
+            self.positions = Some(reader.mmap);
+        }
+        Ok(())
+    }
+
     fn flush(&mut self) -> Result<(), io::Error> {
+        self.meta_read += self.buffer.meta.len() as u64;
         self.buffer.flush()
     }
+
+    fn check_buffer(&self) -> Result<(), io::Error> {
+        // log files buffer is appended to on flush
+        for file in ["index", "meta"] {
+            File::options()
+                .append(true)
+                .open(self.buffer.dir.join(file))?;
+        }
+        Ok(())
+    }
+
+    fn check_tail(&self) -> Result<(), LogsReaderError> {
+        // the last log has to be within index file and decodable, partially written
+        // meta at the end of file is skipped as in replay
+        let mut meta = File::open(self.buffer.dir.join("meta"))?;
+        let size = meta.metadata()?.len();
+        let size = size - size % LogMeta::ENCODED_SIZE as u64;
+        if size == 0 {
+            return Ok(());
+        }
+
+        let mut bytes = [0; LogMeta::ENCODED_SIZE];
+        meta.seek(SeekFrom::Start(size - LogMeta::ENCODED_SIZE as u64))?;
+        meta.read_exact(&mut bytes)?;
+        let log_meta = LogMeta::from_bytes(&bytes)?;
+
+        let mut bytes = vec![0; log_meta.size as usize];
+        let mut index = File::open(self.buffer.dir.join("index"))?;
+        index.seek(SeekFrom::Start(log_meta.offset))?;
+        index.read_exact(&mut bytes)?;
+        if bytes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty index log").into());
+        }
+        decode_log(&bytes, None)?;
+        Ok(())
+    }
 }
 
-#[derive(Decode, Encode, PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone)]
+pub enum Positions {
+    Loaded(Vec<u32>),
+    Stored(u64), // offset of encoded posting in index file of its shard
+}
+
+#[derive(PartialEq, Debug, Clone)]
 pub struct Posting {
     pub doc_id: u128,
-    pub positions: Vec<u32>,
+    pub tf: u32,
+    // positions are left in index file if lazy_positions is enabled, they are read
+    // through postings view or index manager only by matching which needs them
+    pub positions: Positions,
+    // quantized tf component of bm25 refreshed at merge and used only for pruning, it
+    // isn't persisted and unknown impact is the maximal one
+    pub impact: u8,
 }
 
 impl Posting {
+    pub fn new(doc_id: u128, positions: Vec<u32>) -> Self {
+        Self {
+            doc_id,
+            tf: positions.len() as u32,
+            positions: Positions::Loaded(positions),
+            impact: u8::MAX,
+        }
+    }
+
     fn default() -> Self {
+        Self::new(0, Vec::with_capacity(0))
+    }
+}
+
+impl Encode for Posting {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.doc_id.encode(encoder)?;
+        match &self.positions {
+            Positions::Loaded(positions) => positions.encode(encoder),
+            Positions::Stored(_) => Err(EncodeError::Other("posting positions aren't loaded")),
+        }
+    }
+}
+
+impl<Context> Decode<Context> for Posting {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(Self::new(
+            Decode::decode(decoder)?,
+            Decode::decode(decoder)?,
+        ))
+    }
+}
+
+bincode::impl_borrow_decode!(Posting);
+
+impl HeapSize for Posting {
+    fn heap_size(&self) -> usize {
+        match &self.positions {
+            Positions::Loaded(positions) => positions.heap_size(),
+            Positions::Stored(_) => 0,
+        }
+    }
+}
+
+fn read_positions<'a>(file: Option<&Mmap>, posting: &'a Posting) -> Cow<'a, [u32]> {
+    // posting can't be stored without mapped index file and index file only grows, so
+    // it's always decodable, otherwise posting has no positions and doesn't match
+    let offset = match &posting.positions {
+        Positions::Loaded(positions) => return Cow::Borrowed(positions),
+        Positions::Stored(offset) => *offset as usize,
+    };
+
+    file.and_then(|file| file.get(offset..))
+        .and_then(|bytes| {
+            bincode::decode_from_slice::<(u128, Vec<u32>), _>(bytes, bincode::config::standard())
+                .ok()
+        })
+        .map_or(Cow::Owned(vec![]), |((_, positions), _)| {
+            Cow::Owned(positions)
+        })
+}
+
+// postings are split into blocks of this size, the highest impact of every block lets
+// intersection skip whole blocks which can't make it into top k
+pub const BLOCK_SIZE: usize = 128;
+
+// posting reference in impact ordered list, quantized impact is tf component of bm25
+#[derive(Clone, Copy, Debug)]
+pub struct Impact {
+    pub quantized: u8,
+    pub doc_id: u128,
+}
+
+impl HeapSize for Impact {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+// view over index which replaces posting lists longer than cap with their impact copies
+#[derive(Clone, Copy)]
+pub struct PostingsView<'a> {
+    shards: &'a [HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>],
+    logs_managers: &'a [LogsManager],
+    impacts: Option<&'a HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>>,
+    blocks: Option<&'a HashMap<u32, Vec<u8>, BuildNoHashHasher<u32>>>,
+}
+
+impl<'a> PostingsView<'a> {
+    pub fn new(index: &'a HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>) -> Self {
         Self {
-            doc_id: 0,
-            positions: Vec::with_capacity(0),
+            shards: std::slice::from_ref(index),
+            logs_managers: &[],
+            impacts: None,
+            blocks: None,
         }
     }
+
+    pub fn block(&self, token: &u32, doc_idx: u32) -> Option<(u8, u128)> {
+        // the highest impact and the last document of block containing the posting,
+        // posting list without blocks (e.g. its impact copy) is a single unknown block
+        let postings = self.get(token)?;
+        let block = doc_idx as usize / BLOCK_SIZE;
+        let end = ((block + 1) * BLOCK_SIZE).min(postings.len());
+
+        match self.blocks.and_then(|blocks| blocks.get(token)?.get(block)) {
+            Some(max)
+                if !self
+                    .impacts
+                    .is_some_and(|impacts| impacts.contains_key(token)) =>
+            {
+                Some((*max, postings.get(end.checked_sub(1)?)?.doc_id))
+            }
+            _ => Some((u8::MAX, postings.last()?.doc_id)),
+        }
+    }
+
+    pub fn get(&self, token: &u32) -> Option<&'a Vec<Posting>> {
+        if let Some(impacts) = self.impacts
+            && let Some(postings) = impacts.get(token)
+        {
+            return Some(postings);
+        }
+
+        self.shards[*token as usize % self.shards.len()].get(token)
+    }
+
+    pub fn positions(&self, token: u32, posting: &'a Posting) -> Cow<'a, [u32]> {
+        // impact copy of posting list is stored in the same shard as the list
+        let file = self
+            .logs_managers
+            .get(token as usize % self.shards.len())
+            .and_then(|logs_manager| logs_manager.positions.as_ref());
+        read_positions(file, posting)
+    }
+}
+
+fn each_shard<T, E, F>(
+    threads: Option<usize>,
+    logs_managers: &mut [LogsManager],
+    shards: &mut [HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>],
+    f: F,
+) -> Result<Vec<T>, E>
+where
+    T: Send,
+    E: Send,
+    F: Fn(
+            &mut LogsManager,
+            &mut HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+        ) -> Result<T, E>
+        + Sync,
+{
+    // shards are split between at most given number of threads, every shard gets its
+    // own thread by default, with a single thread shards are processed in place
+    let threads = threads
+        .unwrap_or(shards.len())
+        .clamp(1, shards.len().max(1));
+    if threads == 1 {
+        return logs_managers
+            .iter_mut()
+            .zip(shards.iter_mut())
+            .map(|(logs_manager, shard)| f(logs_manager, shard))
+            .collect();
+    }
+
+    let chunk = shards.len().div_ceil(threads);
+    thread::scope(|scope| {
+        let handles = logs_managers
+            .chunks_mut(chunk)
+            .zip(shards.chunks_mut(chunk))
+            .map(|(logs_managers, shards)| {
+                scope.spawn(|| {
+                    logs_managers
+                        .iter_mut()
+                        .zip(shards.iter_mut())
+                        .map(|(logs_manager, shard)| f(logs_manager, shard))
+                        .collect::<Result<Vec<T>, E>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::with_capacity(handles.len() * chunk);
+        for handle in handles {
+            let result = handle
+                .join()
+                .unwrap_or_else(|err| panic::resume_unwind(err));
+            results.extend(result?);
+        }
+        Ok(results)
+    })
 }
 
 pub struct IndexManager {
-    logs_manager: LogsManager,
-    pub index: HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+    // every shard holds tokens with the same id modulo number of shards and has its
+    // own log files, so shards can be loaded, replayed and flushed in parallel
+    logs_managers: Vec<LogsManager>,
+    shards: Vec<HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>>,
+    // postings with the highest tf of tokens found in more than max_docs_per_term
+    // documents, kept in document order so they can be intersected like full lists
+    impacts: HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+    stale_impacts: HashSet<u32>, // modified tokens which impact copies have to be rebuilt
+    // postings in descending impact order, built at merge and dropped for modified tokens
+    impact_ordered: HashMap<u32, Vec<Impact>, BuildNoHashHasher<u32>>,
+    // the highest impact of every block of postings, built at merge and updated on insert
+    block_max: HashMap<u32, Vec<u8>, BuildNoHashHasher<u32>>,
+    pub impact_avg_doc_len: f64, // average document length posting impacts were quantized with
+    config: Arc<Config>,
 }
 
 impl IndexManager {
+    pub fn memory_usage(&self) -> (usize, usize, usize) {
+        // estimated bytes of posting lists, their impact structures and log buffers
+        let impacts = self.impacts.heap_size()
+            + self.stale_impacts.heap_size()
+            + self.impact_ordered.heap_size()
+            + self.block_max.heap_size();
+        let buffers = self
+            .logs_managers
+            .iter()
+            .map(|m| m.buffer.index.capacity() + m.buffer.meta.capacity())
+            .sum();
+        (self.shards.heap_size(), impacts, buffers)
+    }
+
     pub fn load(dir: &PathBuf, config: Arc<Config>) -> Result<Self, IndexManagerError> {
         let index_dir = dir.join("index");
-        let (index, meta) = (index_dir.join("index"), index_dir.join("meta"));
-        if !fs::exists(&index_dir)? || !fs::exists(&index)? || !fs::exists(&meta)? {
-            fs::create_dir_all(&index_dir)?;
-            File::create(&index)?;
-            File::create(&meta)?;
+        let shards_num = config.index_shards.max(1);
+        Self::check_shards(&index_dir, shards_num)?;
+
+        let mut logs_managers = Vec::with_capacity(shards_num);
+        for shard in 0..shards_num {
+            // the first shard is stored directly in index directory, so index created
+            // without sharding remains readable
+            let shard_dir = match shard {
+                0 => index_dir.clone(),
+                _ => index_dir.join(shard.to_string()),
+            };
+            let (index, meta) = (shard_dir.join("index"), shard_dir.join("meta"));
+            if !fs::exists(&shard_dir)? || !fs::exists(&index)? || !fs::exists(&meta)? {
+                fs::create_dir_all(&shard_dir)?;
+                File::create(&index)?;
+                File::create(&meta)?;
+            }
+            logs_managers.push(LogsManager::new(shard_dir, Arc::clone(&config))?);
         }
 
-        let logs_manager = LogsManager::new(index_dir, config)?;
+        let mut shards = vec![HashMap::default(); shards_num];
+        each_shard(
+            config.num_threads,
+            &mut logs_managers,
+            &mut shards,
+            |logs_manager, shard| {
+                *shard = logs_manager.load(ReadDirection::BACKWARD)?;
+                Ok::<(), LogsReaderError>(())
+            },
+        )?;
 
         Ok(Self {
-            index: logs_manager.load(ReadDirection::BACKWARD)?,
-            logs_manager: logs_manager,
+            stale_impacts: shards
+                .iter()
+                .flat_map(|shard| shard.keys().copied())
+                .collect(),
+            shards,
+            logs_managers,
+            impacts: HashMap::default(),
+            impact_ordered: HashMap::default(),
+            block_max: HashMap::default(),
+            impact_avg_doc_len: 1.0,
+            config,
         })
     }
 
-    pub fn insert(&mut self, token: u32, posting: Posting) -> Result<(), BincodePersistenceError> {
-        let postings = self.index.entry(token).or_default();
+    fn check_shards(index_dir: &Path, shards_num: usize) -> Result<(), IndexManagerError> {
+        // tokens are assigned to shards by their id, so index can't be read with
+        // different number of shards than it was written with
+        if !fs::exists(index_dir.join("meta"))? {
+            return Ok(());
+        }
+
+        let mut existing = 1;
+        while fs::exists(index_dir.join(existing.to_string()))? {
+            existing += 1;
+        }
+
+        let written = fs::metadata(index_dir.join("meta"))?.len() > 0 || existing > 1;
+        if written && existing != shards_num {
+            return Err(IndexManagerError::ShardsMismatch(existing, shards_num));
+        }
+        Ok(())
+    }
+
+    fn shard(&self, token: u32) -> usize {
+        token as usize % self.shards.len()
+    }
+
+    pub fn get(&self, token: &u32) -> Option<&Vec<Posting>> {
+        self.shards[self.shard(*token)].get(token)
+    }
+
+    pub fn positions<'a>(&self, token: u32, posting: &'a Posting) -> Cow<'a, [u32]> {
+        let file = self.logs_managers[self.shard(token)].positions.as_ref();
+        read_positions(file, posting)
+    }
+
+    pub fn postings_after(&self, token: u32, after: Option<u128>) -> &[Posting] {
+        // postings of documents added after given one, all postings without it
+        let postings = match self.get(&token) {
+            Some(postings) => postings.as_slice(),
+            None => return &[],
+        };
+        match after {
+            Some(after) => &postings[postings.partition_point(|p| p.doc_id <= after)..],
+            None => postings,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u32, &Vec<Posting>)> {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    pub fn refresh_impacts(
+        &mut self,
+        doc_length: impl Fn(u128) -> Option<u32> + Sync,
+        avg_doc_len: f64,
+    ) {
+        // impacts are quantized with current average document length, postings of
+        // missing documents keep the maximal impact
+        let _ = each_shard(
+            self.config.num_threads,
+            &mut self.logs_managers,
+            &mut self.shards,
+            |_, shard| {
+                for postings in shard.values_mut() {
+                    for posting in postings.iter_mut() {
+                        posting.impact = match doc_length(posting.doc_id) {
+                            Some(len) => quantize_impact(posting.tf as u64, len, avg_doc_len),
+                            None => u8::MAX,
+                        };
+                    }
+                }
+                Ok::<(), ()>(())
+            },
+        );
+        self.impact_avg_doc_len = avg_doc_len;
+        self.stale_impacts.extend(self.impacts.keys().copied());
+        self.block_max = self
+            .iter()
+            .map(|(token, postings)| {
+                let blocks = postings
+                    .chunks(BLOCK_SIZE)
+                    .map(|block| block.iter().map(|p| p.impact).max().unwrap_or(u8::MAX))
+                    .collect();
+                (*token, blocks)
+            })
+            .collect();
+    }
+
+    pub fn build_impact_ordered(&mut self) {
+        // uses impacts stored in postings, so they have to be refreshed first
+        self.impact_ordered = self
+            .iter()
+            .map(|(token, postings)| {
+                let mut impacts = postings
+                    .iter()
+                    .map(|posting| Impact {
+                        quantized: posting.impact,
+                        doc_id: posting.doc_id,
+                    })
+                    .collect::<Vec<Impact>>();
+                impacts.sort_unstable_by(|x, y| {
+                    y.quantized
+                        .cmp(&x.quantized)
+                        .then_with(|| x.doc_id.cmp(&y.doc_id))
+                });
+                (*token, impacts)
+            })
+            .collect();
+    }
+
+    pub fn impact_ordered(&self, token: u32) -> Option<&[Impact]> {
+        self.impact_ordered
+            .get(&token)
+            .map(|impacts| impacts.as_slice())
+    }
+
+    pub fn postings(&mut self) -> PostingsView<'_> {
+        // long posting lists are replaced with impact copies only if cap is configured
+        let cap = match self.config.max_docs_per_term {
+            Some(cap) => cap as usize,
+            None => {
+                return PostingsView {
+                    shards: &self.shards,
+                    logs_managers: &self.logs_managers,
+                    impacts: None,
+                    blocks: Some(&self.block_max),
+                };
+            }
+        };
+
+        for token in std::mem::take(&mut self.stale_impacts) {
+            match self.get(&token) {
+                Some(postings) if postings.len() > cap => {
+                    let top = Self::top_impacts(postings, cap);
+                    self.impacts.insert(token, top);
+                }
+                _ => {
+                    self.impacts.remove(&token);
+                }
+            }
+        }
+
+        PostingsView {
+            shards: &self.shards,
+            logs_managers: &self.logs_managers,
+            impacts: Some(&self.impacts),
+            blocks: Some(&self.block_max),
+        }
+    }
+
+    fn top_impacts(postings: &[Posting], cap: usize) -> Vec<Posting> {
+        let mut top = postings.iter().collect::<Vec<&Posting>>();
+        top.select_nth_unstable_by(cap - 1, |x, y| {
+            y.tf.cmp(&x.tf).then_with(|| x.doc_id.cmp(&y.doc_id))
+        });
+        top.truncate(cap);
+        top.sort_unstable_by_key(|posting| posting.doc_id);
+        top.into_iter().cloned().collect()
+    }
+
+    pub fn contains(&self, token: u32, doc_id: u128) -> bool {
+        match self.get(&token) {
+            Some(postings) => postings.binary_search_by(|p| p.doc_id.cmp(&doc_id)).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn check_insert(&self, token: u32, doc_id: u128) -> Result<(), IndexManagerError> {
+        // postings are sorted by document id, which intersection binary search relies on
+        match self.get(&token).and_then(|postings| postings.last()) {
+            Some(last) if last.doc_id >= doc_id => {
+                Err(IndexManagerError::DuplicatePosting(Ulid(doc_id), token))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn insert(&mut self, token: u32, posting: Posting) -> Result<(), IndexManagerError> {
+        self.check_insert(token, posting.doc_id)?;
+        let shard = self.shard(token);
+        let postings = self.shards[shard].entry(token).or_default();
         let log = AddLog::new(token, postings.len() as u32 + 1, &posting);
-        self.logs_manager.write(posting.doc_id, log)?;
+        self.logs_managers[shard].write(posting.doc_id, log)?;
 
         postings.push(posting);
+        // impact of appended posting is unknown until next merge
+        let block = (postings.len() - 1) / BLOCK_SIZE;
+        if let Some(blocks) = self.block_max.get_mut(&token) {
+            blocks.resize(block + 1, u8::MAX);
+            blocks[block] = u8::MAX;
+        }
+        self.stale_impacts.insert(token);
+        self.impact_ordered.remove(&token);
         Ok(())
     }
 
@@ -581,7 +1166,8 @@ impl IndexManager {
         hasher: &mut TokenHasher,
     ) -> Result<(), BincodePersistenceError> {
         for token in tokens {
-            let postings = match self.index.get_mut(token) {
+            let shard = self.shard(*token);
+            let postings = match self.shards[shard].get_mut(token) {
                 Some(postings) => postings,
                 _ => continue,
             };
@@ -592,8 +1178,7 @@ impl IndexManager {
             postings.retain(|doc| {
                 if document_ids.contains(&Ulid(doc.doc_id)) {
                     deleted += 1;
-                    if let Err(err) = self
-                        .logs_manager
+                    if let Err(err) = self.logs_managers[shard]
                         .write(doc.doc_id, DeleteLog::new(*token, (len - deleted) as u32))
                     {
                         error.replace(err);
@@ -607,9 +1192,12 @@ impl IndexManager {
             if let Some(err) = error {
                 return Err(err);
             }
+            self.stale_impacts.insert(*token);
+            self.impact_ordered.remove(token);
+            self.block_max.remove(token);
 
             if postings.len() == 0 {
-                self.index.remove(token);
+                self.shards[shard].remove(token);
                 if let Some(token) = hasher.delete(*token)? {
                     fuzzy_trie.delete(token);
                 }
@@ -619,7 +1207,111 @@ impl IndexManager {
         Ok(())
     }
 
+    pub fn sweep(
+        &mut self,
+        fuzzy_trie: &mut Trie,
+        hasher: &mut TokenHasher,
+    ) -> Result<(usize, usize), BincodePersistenceError> {
+        // removes empty postings and tokens without postings, returns number of
+        // removed postings and tokens
+        let empty = self
+            .iter()
+            .filter(|(_, postings)| postings.is_empty())
+            .map(|(token, _)| *token)
+            .collect::<Vec<u32>>();
+
+        for token in empty.iter() {
+            let shard = self.shard(*token);
+            self.shards[shard].remove(token);
+            self.stale_impacts.insert(*token);
+            self.impact_ordered.remove(token);
+            self.block_max.remove(token);
+        }
+
+        let orphans = hasher
+            .ids()
+            .filter(|token| self.get(token).is_none())
+            .copied()
+            .collect::<Vec<u32>>();
+
+        let mut tokens_num = 0;
+        for token in orphans {
+            if let Some(token) = hasher.delete(token)? {
+                fuzzy_trie.delete(token);
+                tokens_num += 1;
+            }
+        }
+
+        Ok((empty.len(), tokens_num))
+    }
+
+    pub fn reload(&mut self) -> Result<(), LogsReaderError> {
+        each_shard(
+            self.config.num_threads,
+            &mut self.logs_managers,
+            &mut self.shards,
+            |logs_manager, shard| logs_manager.replay(shard),
+        )?;
+        // replayed logs may modify any token
+        self.stale_impacts
+            .extend(self.shards.iter().flat_map(|shard| shard.keys().copied()));
+        self.stale_impacts.extend(self.impacts.keys().copied());
+        self.impact_ordered.clear();
+        self.block_max.clear();
+        Ok(())
+    }
+
+    pub fn check_buffers(&self) -> Result<(), io::Error> {
+        self.logs_managers.iter().try_for_each(|m| m.check_buffer())
+    }
+
+    pub fn check_logs(&self) -> Result<(), LogsReaderError> {
+        self.logs_managers.iter().try_for_each(|m| m.check_tail())
+    }
+
     pub fn flush(&mut self) -> Result<(), io::Error> {
-        self.logs_manager.flush()
+        each_shard(
+            self.config.num_threads,
+            &mut self.logs_managers,
+            &mut self.shards,
+            |logs_manager, _| logs_manager.flush(),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc_ids(index: &IndexManager, token: u32) -> Vec<u128> {
+        index
+            .get(&token)
+            .map(|postings| postings.iter().map(|p| p.doc_id).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn duplicate_posting_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("minisearch-index-{}", std::process::id()));
+        let mut index = IndexManager::load(&dir, Arc::new(Config::default())).unwrap();
+        index.insert(1, Posting::new(5, vec![0])).unwrap();
+        index.insert(1, Posting::new(7, vec![1, 2])).unwrap();
+        index.insert(2, Posting::new(7, vec![0])).unwrap();
+        let logged = index.logs_managers[0].buffer.index.len();
+
+        // the same document again and older document are rejected before anything is written
+        for doc_id in [7, 5, 3] {
+            assert!(matches!(
+                index.insert(1, Posting::new(doc_id, vec![3])),
+                Err(IndexManagerError::DuplicatePosting(id, 1)) if id == Ulid(doc_id)
+            ));
+        }
+        assert!(index.check_insert(1, 8).is_ok());
+        assert!(index.check_insert(3, 1).is_ok());
+        assert_eq!(doc_ids(&index, 1), vec![5, 7]);
+        assert_eq!(doc_ids(&index, 2), vec![7]);
+        assert_eq!(index.logs_managers[0].buffer.index.len(), logged);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
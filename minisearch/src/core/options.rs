@@ -0,0 +1,287 @@
+use crate::analysis::tokenizer::Tokenizer;
+use crate::config::Config;
+use crate::utils::automaton::MAX_DISTANCE;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+// which of documents with equal score is ranked higher
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TieBreak {
+    #[default]
+    Oldest,
+    Newest,
+    Shuffled(u64), // order of ties is pseudo random, but the same for the same seed
+}
+
+impl TieBreak {
+    pub fn cmp(&self, x: u128, y: u128) -> Ordering {
+        // greater document is preferred, ids are ULIDs so they grow with creation time
+        match self {
+            Self::Oldest => y.cmp(&x),
+            Self::Newest => x.cmp(&y),
+            Self::Shuffled(seed) => Self::mix(x, *seed)
+                .cmp(&Self::mix(y, *seed))
+                .then_with(|| y.cmp(&x)),
+        }
+    }
+
+    fn mix(id: u128, seed: u64) -> u64 {
+        // splitmix64 finalizer of id folded with seed
+        let mut z = (id as u64) ^ ((id >> 64) as u64).rotate_left(32) ^ seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[pyclass(name = "SearchOptions", get_all, set_all)]
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    pub top_k: Option<u32>,    // 0 returns all results
+    pub timeout: Option<f64>,  // seconds, partial results are returned after it elapses
+    pub fuzziness: Option<u8>, // used for terms without explicit "~"
+    pub recency_boost: Option<f64>,
+    pub score_script: Option<String>,
+    pub restrict_ids: Option<Vec<String>>, // only these documents can be matched
+    pub exclude_ids: Option<Vec<String>>,  // these documents are never matched
+    pub profile: Option<String>,           // "flame" returns time spent per search component
+    pub min_should_match: Option<u32>,     // number of clause terms that have to match
+    pub position_boost: Option<f64>,       // half-distance (in positions) of early match boost
+    pub diversify: Option<f64>,            // mmr lambda, trade-off of relevance and diversity
+    pub tie_break: Option<String>,         // "oldest" (default) or "newest" first on equal score
+    pub min_len: Option<u32>,              // shortest document (in tokens) which can be matched
+    pub max_len: Option<u32>,              // longest document (in tokens) which can be matched
+    pub seed: Option<u64>,                 // shuffles results with equal score reproducibly
+    pub length_norm: Option<bool>,         // false scores documents regardless of their length
+    pub max_content_bytes: Option<usize>,  // content of results is cut to this size
+    pub min_score: Option<f64>,            // documents scoring less are never returned
+    pub fields: Option<Vec<String>>,       // fields terms without field are searched in too
+}
+
+#[pymethods]
+impl SearchOptions {
+    #[new]
+    #[pyo3(signature = (top_k=None, timeout=None, fuzziness=None, recency_boost=None, score_script=None, restrict_ids=None, exclude_ids=None, profile=None, min_should_match=None, position_boost=None, diversify=None, tie_break=None, min_len=None, max_len=None, seed=None, length_norm=None, max_content_bytes=None, min_score=None, fields=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        top_k: Option<u32>,
+        timeout: Option<f64>,
+        fuzziness: Option<u8>,
+        recency_boost: Option<f64>,
+        score_script: Option<String>,
+        restrict_ids: Option<Vec<String>>,
+        exclude_ids: Option<Vec<String>>,
+        profile: Option<String>,
+        min_should_match: Option<u32>,
+        position_boost: Option<f64>,
+        diversify: Option<f64>,
+        tie_break: Option<String>,
+        min_len: Option<u32>,
+        max_len: Option<u32>,
+        seed: Option<u64>,
+        length_norm: Option<bool>,
+        max_content_bytes: Option<usize>,
+        min_score: Option<f64>,
+        fields: Option<Vec<String>>,
+    ) -> PyResult<Self> {
+        let options = Self {
+            top_k,
+            timeout,
+            fuzziness,
+            recency_boost,
+            score_script,
+            restrict_ids,
+            exclude_ids,
+            profile,
+            min_should_match,
+            position_boost,
+            diversify,
+            tie_break,
+            min_len,
+            max_len,
+            seed,
+            length_norm,
+            max_content_bytes,
+            min_score,
+            fields,
+        };
+        options.validate()?;
+        Ok(options)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+impl SearchOptions {
+    pub fn with_defaults(mut self, config: &Config) -> PyResult<Self> {
+        // options which weren't set are taken from [search_defaults] of index config
+        let defaults = &config.search_defaults;
+        self.top_k = self.top_k.or(Some(defaults.top_k));
+        self.fuzziness = self.fuzziness.or(Some(defaults.fuzziness));
+        self.timeout = self.timeout.or(defaults.timeout);
+        if self.fields.is_none() {
+            self.fields = defaults.fields.clone();
+        }
+
+        if self.fuzziness() > config.max_fuzziness {
+            return Err(PyValueError::new_err(format!(
+                "Fuzziness must be less or equal to max_fuzziness {} of index config, but it is {}.",
+                config.max_fuzziness,
+                self.fuzziness()
+            )));
+        }
+        self.validate()?;
+        Ok(self)
+    }
+
+    pub fn validate(&self) -> PyResult<()> {
+        // fields are mutable from python so options are validated again before search
+        if let Some(halflife) = self.recency_boost
+            && (!halflife.is_finite() || halflife <= 0.0)
+        {
+            return Err(PyValueError::new_err(format!(
+                "recency_boost half-life must be a finite number greater than 0, but it is {}",
+                halflife
+            )));
+        }
+
+        if let Some(halfdistance) = self.position_boost
+            && (!halfdistance.is_finite() || halfdistance <= 0.0)
+        {
+            return Err(PyValueError::new_err(format!(
+                "position_boost half-distance must be a finite number greater than 0, but it is {}",
+                halfdistance
+            )));
+        }
+
+        if let Some(lambda) = self.diversify
+            && !(0.0..=1.0).contains(&lambda)
+        {
+            return Err(PyValueError::new_err(format!(
+                "diversify lambda must be between 0 and 1, but it is {}",
+                lambda
+            )));
+        }
+
+        if let Some(timeout) = self.timeout
+            && (!timeout.is_finite() || timeout <= 0.0)
+        {
+            return Err(PyValueError::new_err(format!(
+                "timeout must be a finite number greater than 0, but it is {}",
+                timeout
+            )));
+        }
+
+        if let Some(profile) = &self.profile
+            && profile != "flame"
+        {
+            return Err(PyValueError::new_err(format!(
+                "Unknown profile '{}', only 'flame' is supported",
+                profile
+            )));
+        }
+
+        if let Some(tie_break) = &self.tie_break
+            && !matches!(tie_break.as_str(), "oldest" | "newest")
+        {
+            return Err(PyValueError::new_err(format!(
+                "Unknown tie_break '{}', only 'oldest' and 'newest' are supported",
+                tie_break
+            )));
+        }
+
+        if self.seed.is_some() && self.tie_break.is_some() {
+            return Err(PyValueError::new_err(
+                "seed shuffles results with equal score, so it can't be combined with tie_break",
+            ));
+        }
+
+        if let (Some(min_len), Some(max_len)) = (self.min_len, self.max_len)
+            && min_len > max_len
+        {
+            return Err(PyValueError::new_err(format!(
+                "min_len must be less or equal to max_len, but {} > {}",
+                min_len, max_len
+            )));
+        }
+
+        if self.min_should_match == Some(0) {
+            return Err(PyValueError::new_err(
+                "min_should_match must be greater than 0",
+            ));
+        }
+
+        if let Some(min_score) = self.min_score
+            && !min_score.is_finite()
+        {
+            return Err(PyValueError::new_err(format!(
+                "min_score must be a finite number, but it is {}",
+                min_score
+            )));
+        }
+
+        if self.max_content_bytes == Some(0) {
+            return Err(PyValueError::new_err(
+                "max_content_bytes must be greater than 0",
+            ));
+        }
+
+        if let Some(field) = self
+            .fields
+            .iter()
+            .flatten()
+            .find(|field| !Tokenizer::is_field_name(field))
+        {
+            return Err(PyValueError::new_err(format!(
+                "Invalid field name '{}', it must contain only ascii letters, digits or underscores and can't start with a digit",
+                field
+            )));
+        }
+
+        // index config may lower the limit further, it's checked with defaults
+        if self.fuzziness() > MAX_DISTANCE {
+            return Err(PyValueError::new_err(format!(
+                "Fuzziness must be less or equal to {}, but it is {}.",
+                MAX_DISTANCE,
+                self.fuzziness()
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn top_k(&self) -> u32 {
+        self.top_k.unwrap_or(0)
+    }
+
+    pub fn fuzziness(&self) -> u8 {
+        self.fuzziness.unwrap_or(0)
+    }
+
+    pub fn doc_len(&self) -> RangeInclusive<u32> {
+        self.min_len.unwrap_or(0)..=self.max_len.unwrap_or(u32::MAX)
+    }
+
+    pub fn length_norm(&self) -> bool {
+        // bm25 normalizes term frequency by document length unless disabled
+        self.length_norm != Some(false)
+    }
+
+    pub fn min_score(&self) -> f64 {
+        self.min_score.unwrap_or(f64::NEG_INFINITY)
+    }
+
+    pub fn tie_break(&self) -> TieBreak {
+        if let Some(seed) = self.seed {
+            return TieBreak::Shuffled(seed);
+        }
+        match self.tie_break.as_deref() {
+            Some("newest") => TieBreak::Newest,
+            _ => TieBreak::Oldest,
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::core::search::Search;
+use pyo3::prelude::*;
+
+// lazy iterator over postings of a single token in document order, position in list
+// is kept as the last returned document, so index may be modified while iterating
+#[pyclass(name = "Postings")]
+pub struct PostingsIterator {
+    search: Py<Search>,
+    token: Option<u32>,
+    last: Option<u128>,
+}
+
+impl PostingsIterator {
+    pub fn new(search: Py<Search>, token: Option<u32>) -> Self {
+        Self {
+            search,
+            token,
+            last: None,
+        }
+    }
+}
+
+#[pymethods]
+impl PostingsIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<(String, usize, Vec<u32>)>> {
+        // returns document id, term frequency and positions of the term in document
+        let token = match self.token {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let search = self.search.try_borrow(py)?;
+        Ok(search
+            .next_posting(token, self.last)
+            .map(|(doc_id, positions)| {
+                self.last = Some(doc_id.0);
+                (doc_id.to_string(), positions.len(), positions)
+            }))
+    }
+}
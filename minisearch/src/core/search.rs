@@ -1,24 +1,41 @@
 use crate::analysis::tokenizer::Tokenizer;
-use crate::config::Config;
-use crate::core::index::{IndexManager, Posting};
-use crate::errors::{BincodePersistenceError, UlidDecodeError, UlidMonotonicError};
-use crate::matching::intersect::PostingListIntersection;
-use crate::matching::mis::MinimalIntervalSemanticMatch;
+use crate::config::{Config, DecodeErrorPolicy, OverflowPolicy};
+use crate::core::export;
+use crate::core::index::{IndexManager, Posting, PostingsView};
+use crate::core::options::{SearchOptions, TieBreak};
+use crate::core::postings::PostingsIterator;
+use crate::core::shadow::Shadow;
+use crate::errors::{
+    BincodePersistenceError, InvalidEncoding, UlidDecodeError, UlidMonotonicError,
+};
+use crate::matching::boolean::{IdFilter, MatchContext, Matcher};
+use crate::matching::diversity::{self, DIVERSITY_POOL};
+use crate::matching::impact::{self, ImpactList};
+use crate::matching::percolator::Percolator;
+use crate::matching::visibility::{PyVisibility, VisibilityHook};
+use crate::query::builder::QueryInput;
+use crate::query::expression::Expr;
 use crate::query::parser::Query;
-use crate::query::scoring::{bm25, max_bm25};
+use crate::query::scoring::{CorpusStats, FieldStats};
+use crate::storage::attributes::{Attributes, AttributesManager};
+use crate::storage::digests::ContentDigests;
 use crate::storage::documents::{Document, DocumentsManager};
+use crate::storage::querylog::QueryLog;
 use crate::utils::hasher::TokenHasher;
+use crate::utils::logging;
+use crate::utils::profiler::{Profiler, Span};
 use crate::utils::trie::Trie;
 use bincode::{Decode, Encode};
 use hashbrown::HashSet;
-use pyo3::exceptions::PyKeyError;
+use pyo3::exceptions::{PyKeyError, PySystemError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyString, PyWeakrefMethods, PyWeakrefReference};
 use std::cmp::{Ordering, Reverse};
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap as StdHashMap};
 use std::fs::{self, File};
 use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use std::vec::Vec;
 use thiserror::Error;
 use ulid::{Generator, MonotonicError, Ulid};
@@ -40,9 +57,35 @@ impl From<UlidError> for pyo3::PyErr {
     }
 }
 
+// number of postings of a token tried when its text is recovered from documents
+const RECOVERY_ATTEMPTS: usize = 3;
+
+// indexes open in this process by canonical path of their directory, handles are
+// weak, so index is closed once python drops its last reference
+type OpenIndexes = StdHashMap<PathBuf, (Py<PyWeakrefReference>, Option<PathBuf>)>;
+static OPEN: LazyLock<Mutex<OpenIndexes>> = LazyLock::new(|| Mutex::new(StdHashMap::new()));
+
 #[derive(Decode, Encode, PartialEq, Debug, Clone)]
 struct SearchMetaData {
     avg_doc_len: f64,
+    stats: CorpusStats, // persisted so every process scores with the same idf
+    boosts: (f32, f32), // lowest and highest document boost ever added
+    fields: FieldStats,
+}
+
+impl SearchMetaData {
+    fn new(avg_doc_len: f64, stats: CorpusStats) -> Self {
+        Self {
+            avg_doc_len,
+            stats,
+            boosts: (1.0, 1.0),
+            fields: FieldStats::default(),
+        }
+    }
+
+    fn boosted(&self) -> bool {
+        self.boosts != (1.0, 1.0)
+    }
 }
 
 struct SearchMeta {
@@ -62,21 +105,48 @@ impl SearchMeta {
             last_save: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)?
                 .as_secs(),
-            data: SearchMetaData { avg_doc_len: 1.0 },
+            data: SearchMetaData::new(1.0, CorpusStats::default()),
         })
     }
 
-    fn load(path: PathBuf, config: Arc<Config>) -> Result<Self, BincodePersistenceError> {
+    fn load(
+        path: PathBuf,
+        config: Arc<Config>,
+        build_stats: impl FnOnce() -> CorpusStats,
+    ) -> Result<Self, BincodePersistenceError> {
         if !fs::exists(&path)? {
             File::create(&path)?;
-            return Ok(Self::new(path, config)?);
+            let mut meta = Self::new(path, config)?;
+            meta.data.stats = build_stats();
+            return Ok(meta);
         }
 
-        let mut file = File::open(&path)?;
-        let data: SearchMetaData = if file.metadata()?.len() > 0 {
-            bincode::decode_from_std_read(&mut file, bincode::config::standard())?
+        let bytes = fs::read(&path)?;
+        let data: SearchMetaData = if bytes.is_empty() {
+            SearchMetaData::new(1.0, build_stats())
+        } else if let Ok((data, _)) =
+            bincode::decode_from_slice(&bytes, bincode::config::standard())
+        {
+            data
+        } else if let Ok(((avg_doc_len, stats, boosts), _)) =
+            bincode::decode_from_slice(&bytes, bincode::config::standard())
+        {
+            // meta saved before field lengths were introduced, fields of existing
+            // documents are scored like document content
+            SearchMetaData {
+                boosts,
+                ..SearchMetaData::new(avg_doc_len, stats)
+            }
+        } else if let Ok(((avg_doc_len, stats), _)) =
+            bincode::decode_from_slice(&bytes, bincode::config::standard())
+        {
+            // meta saved before document boosts were introduced
+            SearchMetaData::new(avg_doc_len, stats)
         } else {
-            SearchMetaData { avg_doc_len: 1.0 }
+            // meta saved before statistics were persisted, rebuild them from index
+            let (avg_doc_len, _): (f64, usize) =
+                bincode::decode_from_slice(&bytes, bincode::config::standard())?;
+            SearchMetaData::new(avg_doc_len, build_stats())
         };
 
         Ok(Self {
@@ -96,8 +166,14 @@ impl SearchMeta {
         docs_num_after: usize,
         new_doc_len: i64,
     ) -> Result<(), BincodePersistenceError> {
-        self.data.avg_doc_len = (self.data.avg_doc_len * docs_num as f64 + new_doc_len as f64)
-            / (docs_num_after as f64);
+        // average of empty index starts again from its initial value
+        self.data.avg_doc_len = match docs_num_after {
+            0 => 1.0,
+            _ => {
+                (self.data.avg_doc_len * docs_num as f64 + new_doc_len as f64)
+                    / (docs_num_after as f64)
+            }
+        };
 
         let cur_ts = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)?
@@ -108,14 +184,34 @@ impl SearchMeta {
         if self.operations >= self.config.metadata_save_after_operations
             || cur_ts >= self.last_save + self.config.metadata_save_after_seconds
         {
-            self.flush()?;
-            self.operations = 0;
-            self.last_save = cur_ts;
+            self.save()?;
         };
 
         Ok(())
     }
 
+    fn save(&mut self) -> Result<(), BincodePersistenceError> {
+        self.flush()?;
+        self.operations = 0;
+        self.last_save = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), BincodePersistenceError> {
+        let mut file = File::open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(());
+        }
+
+        // file may be partially written at the moment, keep current data in that case
+        if let Ok(data) = bincode::decode_from_std_read(&mut file, bincode::config::standard()) {
+            self.data = data;
+        }
+        Ok(())
+    }
+
     fn flush(&self) -> Result<(), BincodePersistenceError> {
         let mut file = File::create(&self.path)?;
         bincode::encode_into_std_write(&self.data, &mut file, bincode::config::standard())?;
@@ -123,128 +219,543 @@ impl SearchMeta {
     }
 }
 
+// field holds a single text or list of values, e.g. tags
+#[derive(FromPyObject)]
+pub enum FieldValue {
+    Text(String),
+    Values(Vec<String>),
+}
+
+impl FieldValue {
+    fn values(&self) -> &[String] {
+        match self {
+            Self::Text(text) => std::slice::from_ref(text),
+            Self::Values(values) => values,
+        }
+    }
+}
+
+// document content is text or utf-8 encoded bytes
+#[derive(FromPyObject)]
+pub enum DocInput<'py> {
+    Text(Bound<'py, PyString>),
+    Bytes(Bound<'py, PyBytes>),
+}
+
+impl DocInput<'_> {
+    fn decode(&self, errors: &str) -> PyResult<String> {
+        // "strict" rejects content which can't be stored as utf-8, e.g. text with lone
+        // surrogates of undecodable file names, "replace" substitutes it with U+FFFD
+        let lossy = match errors {
+            "strict" => false,
+            "replace" => true,
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown errors '{}', only 'strict' and 'replace' are supported",
+                    errors
+                )));
+            }
+        };
+
+        match self {
+            Self::Text(text) => match text.to_str() {
+                Ok(text) => Ok(text.to_string()),
+                Err(_) if lossy => {
+                    // one replacement character per lone surrogate
+                    let units = text.call_method1("encode", ("utf-16-le", "surrogatepass"))?;
+                    let units = units
+                        .downcast::<PyBytes>()?
+                        .as_bytes()
+                        .chunks_exact(2)
+                        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                        .collect::<Vec<_>>();
+                    Ok(String::from_utf16_lossy(&units))
+                }
+                Err(err) => Err(InvalidEncoding::new_err(format!(
+                    "Document can't be encoded as utf-8: {}",
+                    err
+                ))),
+            },
+            Self::Bytes(bytes) => match std::str::from_utf8(bytes.as_bytes()) {
+                Ok(text) => Ok(text.to_string()),
+                Err(_) if lossy => Ok(String::from_utf8_lossy(bytes.as_bytes()).into_owned()),
+                Err(err) => Err(InvalidEncoding::new_err(format!(
+                    "Document isn't valid utf-8: {}",
+                    err
+                ))),
+            },
+        }
+    }
+}
+
 #[pyclass(name = "Result", get_all)]
 pub struct PySearchResult {
     pub score: f64,
     pub document: Document,
 }
 
+// search returns profile report and query analysis next to results only if they
+// were requested
+#[derive(IntoPyObject)]
+pub enum SearchOutput {
+    Results(Vec<PySearchResult>),
+    Profiled(Vec<PySearchResult>, Py<PyDict>),
+    Analyzed(Vec<PySearchResult>, Vec<Py<PyDict>>),
+    ProfiledAnalyzed(Vec<PySearchResult>, Py<PyDict>, Vec<Py<PyDict>>),
+}
+
 pub struct SearchResult {
     pub doc_id: Ulid,
     pub score: f64,
+    pub tie_break: TieBreak,
 }
 
+// greater result is ranked higher, documents with equal score are ordered by id
 impl Ord for SearchResult {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.score.total_cmp(&other.score)
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| self.tie_break.cmp(self.doc_id.0, other.doc_id.0))
     }
 }
 
 impl PartialOrd for SearchResult {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.score.total_cmp(&other.score))
+        Some(self.cmp(other))
     }
 }
 
 impl PartialEq for SearchResult {
     fn eq(&self, other: &Self) -> bool {
-        self.score == other.score
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for SearchResult {}
 
-#[pyclass(name = "Search")]
+#[pyclass(name = "Search", weakref)]
 pub struct Search {
     index_manager: IndexManager,
     documents_manager: DocumentsManager,
+    attributes_manager: AttributesManager,
+    digests: ContentDigests,
+    query_log: Option<QueryLog>,
     ulid_generator: Generator,
+    latest_id: Ulid, // generated ids must be greater than already stored ones
     tokenizer: Tokenizer,
     hasher: TokenHasher,
     fuzzy_trie: Trie,
+    fuzzy_floor: u64, // highest document frequency of word evicted from fuzzy trie
     meta: SearchMeta,
+    percolator: Percolator,
+    shadow: Option<Shadow>, // experimental options searches can be shadowed with
+    config: Arc<Config>,
 }
 
 #[pymethods]
 impl Search {
     #[new]
-    fn new(dir: PathBuf, config: Option<PathBuf>) -> PyResult<Self> {
-        let mut fuzzy_trie = Trie::new();
-        for i in 0..3 {
-            fuzzy_trie.init_automaton(i);
+    #[pyo3(signature = (dir, config=None))]
+    fn new(py: Python<'_>, dir: PathBuf, config: Option<PathBuf>) -> PyResult<Py<Self>> {
+        // directory already open in this process is shared, so handles don't keep
+        // divergent states of the same files, it's released with its last handle
+        let loaded = Config::load(config.clone())?;
+        fs::create_dir_all(&dir)?;
+        let key = fs::canonicalize(&dir)?;
+        if let Some((search, opened_with)) = Self::find_open(py, &key) {
+            if opened_with != config {
+                return Err(PyValueError::new_err(format!(
+                    "Index in {} is already open with config {:?}",
+                    key.display(),
+                    opened_with
+                )));
+            }
+            return Ok(search);
         }
 
-        let config = Arc::new(Config::load(config)?);
+        // loading may call python, so registry isn't locked meanwhile
+        let search = Py::new(py, Self::open(dir, loaded)?)?;
+        let handle = PyWeakrefReference::new(search.bind(py))?.unbind();
+        OPEN.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(key, (handle, config));
+        Ok(search)
+    }
+
+    #[pyo3(signature = (doc, attributes=None, fields=None, boost=1.0, errors="strict", payload=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn add(
+        &mut self,
+        py: Python<'_>,
+        doc: DocInput<'_>,
+        attributes: Option<Attributes>,
+        fields: Option<StdHashMap<String, FieldValue>>,
+        boost: f32,
+        errors: &str,
+        payload: Option<StdHashMap<String, String>>,
+    ) -> PyResult<String> {
+        let doc = doc.decode(errors)?;
+        let doc_id = self.next_id()?;
+        self.insert(py, doc_id, doc, attributes, fields, boost, payload)?;
+        Ok(doc_id.to_string())
+    }
 
-        let hasher = TokenHasher::load(&dir, Arc::clone(&config))?;
-        for token in hasher.tokens() {
-            fuzzy_trie.add(token);
+    #[pyo3(signature = (doc, attributes=None, fields=None, boost=1.0, errors="strict", payload=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn add_idempotent(
+        &mut self,
+        py: Python<'_>,
+        doc: DocInput<'_>,
+        attributes: Option<Attributes>,
+        fields: Option<StdHashMap<String, FieldValue>>,
+        boost: f32,
+        errors: &str,
+        payload: Option<StdHashMap<String, String>>,
+    ) -> PyResult<String> {
+        // adding the same content again returns id of already existing document
+        let doc = doc.decode(errors)?;
+        let digest = ContentDigests::digest(&doc);
+        if let Some(id) = self.digests.get(digest)
+            && self.documents_manager.contains(&id)
+        {
+            return Ok(id.to_string());
         }
 
-        Ok(Self {
-            index_manager: IndexManager::load(&dir, Arc::clone(&config))?,
-            meta: SearchMeta::load(dir.join("meta"), Arc::clone(&config))?,
-            hasher: hasher,
-            documents_manager: DocumentsManager::load(dir, Arc::clone(&config))?,
-            ulid_generator: Generator::new(),
-            tokenizer: Tokenizer::new(Arc::clone(&config)),
-            fuzzy_trie: fuzzy_trie,
-        })
+        let doc_id = self.next_id()?;
+        self.insert(py, doc_id, doc, attributes, fields, boost, payload)?;
+        self.digests.insert(digest, doc_id);
+        Ok(doc_id.to_string())
     }
 
-    fn add(&mut self, mut doc: String) -> PyResult<String> {
-        let doc_id = match self.ulid_generator.generate() {
-            Ok(id) => id,
-            Err(err) => return Err(UlidError::UlidMonotonicError(err).into()),
+    #[pyo3(signature = (target, transform=None))]
+    fn reindex_into(
+        slf: PyRef<'_, Self>,
+        target: &Bound<'_, Self>,
+        transform: Option<Py<PyAny>>,
+    ) -> PyResult<usize> {
+        // copies live documents into empty index in order they were added, documents
+        // keep their ids, transform receives document and its attributes and returns
+        // None to skip it or dict with "doc", "attributes", "fields", "boost" or
+        // "payload" to replace, indexed fields aren't stored, so they have to be
+        // returned by transform for documents added with them (empty dict drops them),
+        // payload is kept
+        if slf.as_ptr() == target.as_ptr() {
+            return Err(PyValueError::new_err(
+                "Index can't be reindexed into itself",
+            ));
+        }
+        let py = slf.py();
+        let mut target = target.try_borrow_mut()?;
+        if !target.documents_manager.is_empty() {
+            return Err(PyValueError::new_err(
+                "Index can be reindexed only into an empty index",
+            ));
+        }
+
+        let missing_fields = |id: Ulid| {
+            PyValueError::new_err(format!(
+                "Document {} was added with fields, which aren't stored, transform has to return them as \"fields\"",
+                id
+            ))
         };
+        // without transform nothing would restore fields, so nothing is copied
+        let documents_manager = &slf.documents_manager;
+        if transform.is_none()
+            && let Some(id) = documents_manager.ids().into_iter().find(|id| {
+                !documents_manager.deleted_docs_buffer.contains_key(id)
+                    && documents_manager
+                        .get(id)
+                        .is_some_and(|doc| !doc.fields.is_empty())
+            })
+        {
+            return Err(missing_fields(id));
+        }
 
-        let (tokens_num, tokens_map) = self.tokenizer.tokenize_doc(&mut doc);
+        let mut copied = 0;
+        for id in slf.documents_manager.ids() {
+            if slf.documents_manager.deleted_docs_buffer.contains_key(&id) {
+                continue;
+            }
+            let mut doc = match slf.documents_manager.get(&id) {
+                Some(doc) => doc.into_owned(),
+                None => continue,
+            };
+            let content = doc.content()?;
+            let idempotent = slf.digests.get(ContentDigests::digest(&content)) == Some(id);
+            let (mut attributes, mut boost) = (slf.attributes_manager.get(&id).cloned(), doc.boost);
+            let (mut content, mut fields, mut payload) = (content, None, Some(doc.payload()?));
+            let has_fields = !doc.fields.is_empty();
 
-        self.meta.update_avg_doc_len(
-            self.documents_manager.docs.len(),
-            self.documents_manager.docs.len() + 1,
-            tokens_num as i64,
-        )?;
+            if let Some(transform) = &transform {
+                let replaced = transform.call1(py, (doc, attributes.clone()))?;
+                if replaced.is_none(py) {
+                    continue;
+                }
+                let replaced = replaced.downcast_bound::<PyDict>(py)?;
+                if let Some(value) = replaced.get_item("doc")? {
+                    content = value.extract()?;
+                }
+                if let Some(value) = replaced.get_item("attributes")? {
+                    attributes = value.extract()?;
+                }
+                if let Some(value) = replaced.get_item("fields")? {
+                    fields = value.extract()?;
+                }
+                if let Some(value) = replaced.get_item("boost")? {
+                    boost = value.extract()?;
+                }
+                if let Some(value) = replaced.get_item("payload")? {
+                    payload = value.extract()?;
+                }
+            }
 
-        let mut tokens = Vec::with_capacity(tokens_map.len());
-        for (token, positions) in tokens_map {
-            if !self.hasher.contains(&token) {
-                self.fuzzy_trie.add(&token);
+            if has_fields && fields.is_none() {
+                return Err(missing_fields(id));
             }
 
-            let token = self.hasher.add(token)?;
-            let posting = Posting {
-                doc_id: doc_id.0,
-                positions: positions,
+            target.latest_id = target.latest_id.max(id);
+            target.insert(py, id, content.clone(), attributes, fields, boost, payload)?;
+            // documents added idempotently stay deduplicated by their new content
+            if idempotent {
+                target.digests.insert(ContentDigests::digest(&content), id);
+            }
+            copied += 1;
+        }
+
+        target.flush()?;
+        Ok(copied)
+    }
+
+    fn register_query(&mut self, name: String, query: String) -> PyResult<()> {
+        self.percolator.register(name, query, &self.config)
+    }
+
+    fn unregister_query(&mut self, name: String) -> bool {
+        self.percolator.unregister(&name)
+    }
+
+    #[pyo3(signature = (callback=None))]
+    fn on_match(&mut self, callback: Option<Py<PyAny>>) {
+        self.percolator.callback = callback;
+    }
+
+    #[pyo3(signature = (options=None))]
+    fn set_shadow(&mut self, options: Option<SearchOptions>) -> PyResult<()> {
+        // registering experiment again resets its recorded stats
+        let options = options
+            .map(|options| options.with_defaults(&self.config))
+            .transpose()?;
+        self.shadow = options.map(Shadow::new);
+        Ok(())
+    }
+
+    fn shadow_stats(&self) -> PyResult<StdHashMap<&'static str, f64>> {
+        match &self.shadow {
+            Some(shadow) => Ok(shadow.stats()),
+            None => Err(PyValueError::new_err(
+                "No experiment is registered, register it with set_shadow",
+            )),
+        }
+    }
+
+    #[pyo3(signature = (callback=None))]
+    fn set_tokenizer(&mut self, callback: Option<Py<PyAny>>) {
+        if callback.is_some() {
+            logging::warning(
+                "custom tokenizer is called from python for every document and query, \
+                 indexing and searching are much slower than with the native one",
+            );
+        }
+        self.tokenizer.set_custom(callback);
+    }
+
+    #[pyo3(signature = (id, max_content_bytes=None))]
+    fn get(&self, id: String, max_content_bytes: Option<usize>) -> PyResult<Document> {
+        let id = match Ulid::from_string(&id) {
+            Ok(val) => val,
+            Err(e) => return Err(UlidError::UlidDecodeError(e).into()),
+        };
+        if max_content_bytes == Some(0) {
+            return Err(PyValueError::new_err(
+                "max_content_bytes must be greater than 0",
+            ));
+        }
+
+        match self.documents_manager.get(&id) {
+            Some(doc) => {
+                let mut doc = doc.into_owned();
+                doc.max_content_bytes = max_content_bytes;
+                Ok(doc)
+            }
+            None => Err(PyKeyError::new_err(format!(
+                "Document with id: {} does not exist",
+                id,
+            ))),
+        }
+    }
+
+    #[pyo3(signature = (query, fuzziness=0))]
+    fn parse<'py>(
+        &self,
+        py: Python<'py>,
+        query: &str,
+        fuzziness: u8,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        // parsed query tree, terms are reported as written in query, i.e. not stemmed
+        Query::parse(query, fuzziness, &self.config)?.to_dict(py)
+    }
+
+    #[pyo3(signature = (prefix, limit=10))]
+    fn suggest_queries(&self, prefix: &str, limit: usize) -> PyResult<Vec<String>> {
+        match &self.query_log {
+            Some(query_log) => Ok(query_log.suggest(prefix, limit, Self::now()?)),
+            None => Err(PyValueError::new_err(
+                "Query log is disabled, set query_log_size in config to enable it",
+            )),
+        }
+    }
+
+    fn health(&self) -> StdHashMap<&'static str, bool> {
+        // cheap structural checks, no document or posting is read
+        let checks = [
+            (
+                "buffers",
+                self.documents_manager.check_buffer().is_ok()
+                    && self.index_manager.check_buffers().is_ok(),
+            ),
+            ("segments", self.documents_manager.check_segments().is_ok()),
+            ("logs", self.index_manager.check_logs().is_ok()),
+            ("tokens", self.hasher.is_consistent()),
+        ];
+        let healthy = checks.iter().all(|(_, ok)| *ok);
+
+        checks.into_iter().chain([("healthy", healthy)]).collect()
+    }
+
+    fn export_stats(&self, path: PathBuf) -> PyResult<()> {
+        export::export_stats(
+            &path,
+            &self.index_manager,
+            &self.documents_manager,
+            &self.hasher,
+        )?;
+        Ok(())
+    }
+
+    #[pyo3(signature = (term, field=None))]
+    fn postings(
+        mut slf: PyRefMut<'_, Self>,
+        term: &str,
+        field: Option<&str>,
+    ) -> PyResult<PostingsIterator> {
+        // term is normalized the same way as query terms, stop word has no postings
+        let token = slf.tokenizer.tokenize_term(term).and_then(|token| {
+            let token = match field {
+                Some(field) => Tokenizer::field_token(field, &token),
+                None => token,
             };
-            self.index_manager.insert(token, posting)?;
+            slf.hasher.hash(&token)
+        });
+        if let Some(err) = slf.tokenizer.take_error() {
+            return Err(err);
+        }
+        Ok(PostingsIterator::new(slf.into(), token))
+    }
 
-            tokens.push(token);
+    fn display_term(&mut self, term: String) -> PyResult<Option<String>> {
+        let token = self.tokenizer.tokenize_term(&term);
+        if let Some(err) = self.tokenizer.take_error() {
+            return Err(err);
         }
+        Ok(token
+            .and_then(|token| self.hasher.hash(&token))
+            .and_then(|token| self.hasher.display(token).cloned()))
+    }
 
-        self.documents_manager
-            .write(doc_id, tokens_num, tokens, &doc)?;
+    fn analyze(&mut self, mut text: String) -> PyResult<Vec<(String, u32)>> {
+        // tokens with positions exactly as text would be indexed, ordered by position
+        let (_, tokens, _) = self.tokenizer.tokenize_doc(&mut text);
+        // words past max positions are simply missing
+        self.tokenizer.take_truncated();
+        if let Some(err) = self.tokenizer.take_error() {
+            return Err(err);
+        }
 
-        Ok(doc_id.to_string())
+        let mut analyzed = tokens
+            .into_iter()
+            .flat_map(|(token, positions)| {
+                positions
+                    .into_iter()
+                    .map(move |position| (token.clone(), position))
+            })
+            .collect::<Vec<(String, u32)>>();
+        // prefixes sharing position of their word come before it
+        analyzed.sort_unstable_by(|(x, i), (y, j)| {
+            i.cmp(j)
+                .then_with(|| x.len().cmp(&y.len()))
+                .then_with(|| x.cmp(y))
+        });
+        Ok(analyzed)
     }
 
-    fn get(&self, id: String) -> PyResult<Document> {
+    fn read_raw<'py>(&self, py: Python<'py>, id: String) -> PyResult<Bound<'py, PyBytes>> {
         let id = match Ulid::from_string(&id) {
             Ok(val) => val,
             Err(e) => return Err(UlidError::UlidDecodeError(e).into()),
         };
 
-        let doc = match self.documents_manager.docs.get(&id) {
-            Some(doc) => doc,
-            None => {
-                return Err(PyKeyError::new_err(format!(
-                    "Document with id: {} does not exist",
-                    id,
-                )));
-            }
+        match self.documents_manager.get(&id) {
+            Some(doc) => Ok(PyBytes::new(py, &doc.read_raw()?)),
+            None => Err(PyKeyError::new_err(format!(
+                "Document with id: {} does not exist",
+                id,
+            ))),
+        }
+    }
+
+    fn attributes(&self, id: String) -> PyResult<Attributes> {
+        let id = match Ulid::from_string(&id) {
+            Ok(val) => val,
+            Err(e) => return Err(UlidError::UlidDecodeError(e).into()),
         };
 
-        Ok(doc.clone())
+        if !self.documents_manager.contains(&id) {
+            return Err(PyKeyError::new_err(format!(
+                "Document with id: {} does not exist",
+                id,
+            )));
+        }
+
+        Ok(self
+            .attributes_manager
+            .get(&id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn update_attributes(&mut self, id: String, attributes: Attributes) -> PyResult<()> {
+        // given attributes are merged into existing ones, only new attributes log is
+        // written, postings and stored content aren't touched
+        let id = match Ulid::from_string(&id) {
+            Ok(val) => val,
+            Err(e) => return Err(UlidError::UlidDecodeError(e).into()),
+        };
+
+        if !self.documents_manager.contains(&id) {
+            return Err(PyKeyError::new_err(format!(
+                "Document with id: {} does not exist",
+                id,
+            )));
+        }
+
+        let mut merged = self
+            .attributes_manager
+            .get(&id)
+            .cloned()
+            .unwrap_or_default();
+        merged.extend(attributes);
+        self.attributes_manager.write(id, merged)?;
+        Ok(())
     }
 
     fn delete(&mut self, id: String) -> PyResult<bool> {
@@ -254,8 +765,9 @@ impl Search {
         };
 
         self.documents_manager.delete(id)?;
+        self.attributes_manager.delete(id)?;
 
-        if self.documents_manager.deleted_docs_buffer.len() <= self.documents_manager.docs.len() / 20 // delete if greater then 5% of all documents
+        if self.documents_manager.deleted_docs_buffer.len() <= self.documents_manager.len() / 20 // delete if greater then 5% of all documents
             || self.documents_manager.deleted_docs_buffer.len() <= 1000
         {
             return Ok(true);
@@ -264,128 +776,1035 @@ impl Search {
         self.force_delete()
     }
 
-    fn search(&mut self, mut query: String, top_k: u32) -> PyResult<Vec<PySearchResult>> {
-        let query = Query::parse(&mut query)?;
+    #[pyo3(signature = (query, top_k=None, recency_boost=None, score_script=None, options=None, restrict_ids=None, exclude_ids=None, visibility=None, profile=None, min_should_match=None, position_boost=None, diversify=None, min_len=None, max_len=None, seed=None, length_norm=None, max_content_bytes=None, min_score=None, shadow=false, analyze_query=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &mut self,
+        py: Python<'_>,
+        query: QueryInput,
+        top_k: Option<u32>,
+        recency_boost: Option<f64>,
+        score_script: Option<String>,
+        options: Option<SearchOptions>,
+        restrict_ids: Option<Vec<String>>,
+        exclude_ids: Option<Vec<String>>,
+        visibility: Option<Py<PyAny>>,
+        profile: Option<String>,
+        min_should_match: Option<u32>,
+        position_boost: Option<f64>,
+        diversify: Option<f64>,
+        min_len: Option<u32>,
+        max_len: Option<u32>,
+        seed: Option<u64>,
+        length_norm: Option<bool>,
+        max_content_bytes: Option<usize>,
+        min_score: Option<f64>,
+        shadow: bool,
+        analyze_query: bool,
+    ) -> PyResult<SearchOutput> {
+        // explicitly passed arguments override ones from options, which default to
+        // search defaults from config
+        let mut options = options.unwrap_or_default();
+        if top_k.is_some() {
+            options.top_k = top_k;
+        }
+        if recency_boost.is_some() {
+            options.recency_boost = recency_boost;
+        }
+        if score_script.is_some() {
+            options.score_script = score_script;
+        }
+        if restrict_ids.is_some() {
+            options.restrict_ids = restrict_ids;
+        }
+        if exclude_ids.is_some() {
+            options.exclude_ids = exclude_ids;
+        }
+        if profile.is_some() {
+            options.profile = profile;
+        }
+        if min_should_match.is_some() {
+            options.min_should_match = min_should_match;
+        }
+        if position_boost.is_some() {
+            options.position_boost = position_boost;
+        }
+        if diversify.is_some() {
+            options.diversify = diversify;
+        }
+        if min_len.is_some() {
+            options.min_len = min_len;
+        }
+        if max_len.is_some() {
+            options.max_len = max_len;
+        }
+        if seed.is_some() {
+            options.seed = seed;
+        }
+        if length_norm.is_some() {
+            options.length_norm = length_norm;
+        }
+        if max_content_bytes.is_some() {
+            options.max_content_bytes = max_content_bytes;
+        }
+        if min_score.is_some() {
+            options.min_score = min_score;
+        }
+        let options = options.with_defaults(&self.config)?;
+
+        let text = match (&query, &self.query_log) {
+            (QueryInput::Text(text), Some(_)) => Some(text.clone()),
+            _ => None,
+        };
 
-        let slop = query.slop;
-        let query = self.tokenizer.tokenize_query(query);
+        if shadow && self.shadow.is_none() {
+            return Err(PyValueError::new_err(
+                "No experiment is registered, register it with set_shadow",
+            ));
+        }
+        let experiment = match shadow {
+            true => Some((query.clone(), visibility.as_ref().map(|v| v.clone_ref(py)))),
+            false => None,
+        };
 
-        let mut intersection = match PostingListIntersection::new(
-            query,
-            &self.index_manager.index,
-            &self.hasher,
-            &self.fuzzy_trie,
-        ) {
-            Some(iter) => iter,
-            _ => return Ok(vec![]),
+        let analysis = match analyze_query {
+            true => Some(self.analyze_query(py, &query, &options)?),
+            false => None,
         };
 
-        let mut results: BinaryHeap<Reverse<SearchResult>> =
-            BinaryHeap::with_capacity(top_k as usize);
-
-        while let Some(pointers) = intersection.next() {
-            let (doc_id, mut score) = (pointers[0][0].doc_id, 0.0);
-            if self
-                .documents_manager
-                .deleted_docs_buffer
-                .contains_key(&doc_id)
+        let profiler = Profiler::new(options.profile.is_some());
+        let started = Instant::now();
+        let results = {
+            let _timer = profiler.scope(Span::Search);
+            self.execute(query, &options, visibility, &profiler)?
+        };
+
+        // experiment runs after live search, so it doesn't delay it more than
+        // necessary, its failure is only recorded
+        if let Some((query, visibility)) = experiment {
+            let live_time = started.elapsed();
+            let started = Instant::now();
+            let shadow = self.shadow.as_ref().map(|shadow| shadow.options(&options));
+            let experiment = shadow
+                .map(|shadow| self.execute(query, &shadow, visibility, &Profiler::default()))
+                .and_then(Result::ok);
+            let ids = |results: &[PySearchResult]| {
+                results
+                    .iter()
+                    .map(|result| Ulid::from_bytes(result.document.id))
+                    .collect::<Vec<Ulid>>()
+            };
+            let experiment = experiment.as_deref().map(ids);
+            if let Some(shadow) = &mut self.shadow {
+                shadow.record(
+                    &ids(&results),
+                    experiment.as_deref(),
+                    live_time,
+                    started.elapsed(),
+                );
+            }
+        }
+
+        // only queries which found something are worth suggesting
+        if let (Some(text), Some(query_log)) = (text, &mut self.query_log)
+            && !results.is_empty()
+        {
+            query_log.record(&text, Self::now()?);
+        }
+
+        let profile = match options.profile {
+            Some(_) => Some(profiler.report(py)?.unbind()),
+            None => None,
+        };
+        Ok(match (profile, analysis) {
+            (Some(profile), Some(analysis)) => {
+                SearchOutput::ProfiledAnalyzed(results, profile, analysis)
+            }
+            (Some(profile), None) => SearchOutput::Profiled(results, profile),
+            (None, Some(analysis)) => SearchOutput::Analyzed(results, analysis),
+            (None, None) => SearchOutput::Results(results),
+        })
+    }
+
+    #[pyo3(signature = (query, snapshot_ids, top_k=None, options=None))]
+    fn diff_results<'py>(
+        &mut self,
+        py: Python<'py>,
+        query: QueryInput,
+        snapshot_ids: Vec<String>,
+        top_k: Option<u32>,
+        options: Option<SearchOptions>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        // compares current results with previous ones, ranks are positions in results
+        // starting at 0, top k defaults to the length of snapshot, query isn't recorded
+        // in query log, so monitoring doesn't affect suggestions
+        let snapshot = Self::parse_ids(&snapshot_ids)?;
+        let mut options = options.unwrap_or_default();
+        options.top_k = top_k.or(options.top_k).or(Some(snapshot.len() as u32));
+        let options = options.with_defaults(&self.config)?;
+
+        let current = self
+            .execute(query, &options, None, &Profiler::default())?
+            .into_iter()
+            .map(|result| Ulid::from_bytes(result.document.id))
+            .collect::<Vec<Ulid>>();
+        let mut previous: StdHashMap<Ulid, usize> = StdHashMap::new();
+        for (rank, id) in snapshot.iter().enumerate() {
+            previous.entry(*id).or_insert(rank);
+        }
+        let ranks: StdHashMap<Ulid, usize> = current
+            .iter()
+            .enumerate()
+            .map(|(rank, id)| (*id, rank))
+            .collect();
+
+        let (mut added, mut moved) = (vec![], vec![]);
+        for (rank, id) in current.iter().enumerate() {
+            match previous.get(id) {
+                None => added.push((id.to_string(), rank)),
+                Some(old) if *old != rank => moved.push((id.to_string(), *old, rank)),
+                Some(_) => (),
+            }
+        }
+        let dropped = snapshot
+            .iter()
+            .enumerate()
+            .filter(|(rank, id)| !ranks.contains_key(*id) && previous.get(*id) == Some(rank))
+            .map(|(rank, id)| (id.to_string(), rank))
+            .collect::<Vec<_>>();
+
+        let diff = PyDict::new(py);
+        diff.set_item("added", added)?;
+        diff.set_item("dropped", dropped)?;
+        diff.set_item("moved", moved)?;
+        Ok(diff)
+    }
+
+    fn flush(&mut self) -> PyResult<()> {
+        self.force_delete()?;
+        self.documents_manager.flush()?;
+        self.index_manager.flush()?;
+        self.documents_manager.clear_pending_deletes()?;
+        self.hasher.flush()?;
+        self.meta.flush()?;
+        self.attributes_manager.flush()?;
+        self.digests.flush()?;
+        if let Some(query_log) = &self.query_log {
+            query_log.flush()?;
+        }
+        Ok(())
+    }
+
+    fn reload(&mut self) -> PyResult<()> {
+        // catch up with data flushed by other process writing to the same directory
+        let (added, removed) = self.hasher.reload()?;
+        for token in added.iter() {
+            self.fuzzy_trie.add(token);
+        }
+        for token in removed {
+            self.fuzzy_trie.delete(token);
+        }
+        self.prune_fuzzy_trie();
+
+        self.index_manager.reload()?;
+        self.documents_manager.reload()?;
+        self.attributes_manager.reload()?;
+        self.meta.reload()?;
+        self.digests.reload()?;
+        if let Some(query_log) = &mut self.query_log {
+            query_log.reload()?;
+        }
+        Ok(())
+    }
+
+    #[pyo3(signature = (progress=None))]
+    fn merge(&mut self, py: Python<'_>, progress: Option<Py<PyAny>>) -> PyResult<bool> {
+        // progress receives processed and total bytes of merged segments, returning
+        // False cancels the merge, first exception raised by it cancels it as well
+        // and is raised once documents are consistent again
+        let mut error = None;
+        let mut report = |processed: u64, total: u64| {
+            let Some(progress) = &progress else {
+                return true;
+            };
+            let proceed = progress
+                .call1(py, (processed, total))
+                .and_then(|proceed| proceed.extract::<Option<bool>>(py));
+            match proceed {
+                Ok(proceed) => proceed != Some(false),
+                Err(err) => {
+                    error.get_or_insert(err);
+                    false
+                }
+            }
+        };
+
+        // flush data before merge
+        let _ = self.flush();
+        let completed = self.documents_manager.merge(&mut report)?;
+        if let Some(err) = error {
+            return Err(err);
+        }
+        if !completed {
+            return Ok(false);
+        }
+        self.attributes_manager.compact()?;
+        let documents_manager = &self.documents_manager;
+        self.digests.retain(|id| documents_manager.contains(id));
+        self.sweep()?;
+
+        let documents_manager = &self.documents_manager;
+        self.index_manager.refresh_impacts(
+            |doc_id| Some(documents_manager.get(&Ulid(doc_id))?.tokens.len() as u32),
+            self.meta.data.avg_doc_len,
+        );
+        if self.config.impact_ordered_postings {
+            self.index_manager.build_impact_ordered();
+        }
+        Ok(true)
+    }
+
+    fn memory_usage(&self) -> StdHashMap<&'static str, usize> {
+        // estimated heap bytes per component, memory mapped files aren't included
+        let (index, impacts, index_buffers) = self.index_manager.memory_usage();
+        let (documents, documents_buffers, caches) = self.documents_manager.memory_usage();
+        let (attributes, attributes_buffer) = self.attributes_manager.memory_usage();
+        let other = self.digests.memory_usage()
+            + self.query_log.as_ref().map_or(0, |log| log.memory_usage());
+
+        let mut usage = StdHashMap::from([
+            ("index", index),
+            ("impacts", impacts),
+            ("documents", documents),
+            ("attributes", attributes),
+            ("trie", self.fuzzy_trie.memory_usage()),
+            ("tokens", self.hasher.memory_usage()),
+            (
+                "buffers",
+                index_buffers + documents_buffers + attributes_buffer,
+            ),
+            ("caches", caches + self.tokenizer.memory_usage()),
+            ("other", other),
+        ]);
+        usage.insert("total", usage.values().sum());
+        usage
+    }
+
+    fn sweep(&mut self) -> PyResult<StdHashMap<&'static str, usize>> {
+        let (postings, tokens) = self
+            .index_manager
+            .sweep(&mut self.fuzzy_trie, &mut self.hasher)?;
+
+        Ok(StdHashMap::from([
+            ("postings", postings),
+            ("tokens", tokens),
+        ]))
+    }
+}
+
+impl Search {
+    fn next_id(&mut self) -> PyResult<Ulid> {
+        let mut doc_id = match self.ulid_generator.generate() {
+            Ok(id) => id,
+            Err(err) => return Err(UlidError::UlidMonotonicError(err).into()),
+        };
+        // new generator after restart may return lower id within the same millisecond
+        if doc_id <= self.latest_id {
+            doc_id = match self.latest_id.increment() {
+                Some(id) => id,
+                None => return Err(UlidError::UlidMonotonicError(MonotonicError::Overflow).into()),
+            };
+        }
+        self.latest_id = doc_id;
+        Ok(doc_id)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &mut self,
+        py: Python<'_>,
+        doc_id: Ulid,
+        mut doc: String,
+        mut attributes: Option<Attributes>,
+        fields: Option<StdHashMap<String, FieldValue>>,
+        boost: f32,
+        payload: Option<StdHashMap<String, String>>,
+    ) -> PyResult<()> {
+        // indexes document under given id, which has to be higher than ids of already
+        // indexed documents
+        if !boost.is_finite() || boost <= 0.0 {
+            return Err(PyValueError::new_err(format!(
+                "Invalid boost {}, it must be a positive number",
+                boost
+            )));
+        }
+
+        let fields = fields.unwrap_or_default();
+        if let Some(field) = fields.keys().find(|f| !Tokenizer::is_field_name(f)) {
+            return Err(PyValueError::new_err(format!(
+                "Invalid field name '{}', it must contain only ascii letters, digits or underscores and can't start with a digit",
+                field
+            )));
+        }
+
+        let (mut tokens_num, mut tokens_map, mut surfaces) = self.tokenizer.tokenize_doc(&mut doc);
+
+        // fields are only indexed, stored document content is "doc"
+        let mut field_lens = Vec::with_capacity(fields.len());
+        for (field, value) in fields.iter() {
+            let (len, field_tokens, field_surfaces) =
+                self.tokenizer.tokenize_field(field, value.values());
+            tokens_num += len;
+            field_lens.push((field.clone(), len));
+            tokens_map.extend(field_tokens);
+            surfaces.extend(field_surfaces);
+        }
+        let truncated = self.tokenizer.take_truncated();
+        if let Some(err) = self.tokenizer.take_error() {
+            return Err(err);
+        }
+        if truncated && self.config.on_position_overflow == OverflowPolicy::Reject {
+            return Err(PyValueError::new_err(format!(
+                "Document exceeds max positions {}",
+                self.config.max_positions.unwrap_or(u32::MAX)
+            )));
+        }
+
+        // payload is stored with content, but never tokenized
+        let payload = match payload {
+            Some(payload) if !payload.is_empty() => Document::encode_payload(&payload)?,
+            _ => vec![],
+        };
+
+        // reject duplicated document before any state is modified
+        for token in tokens_map.keys() {
+            if let Some(token) = self.hasher.hash(token) {
+                self.index_manager.check_insert(token, doc_id.0)?;
+            }
+        }
+
+        let percolate = self.percolator.is_active();
+        let mut percolated = vec![];
+
+        let mut tokens = Vec::with_capacity(tokens_map.len());
+        for (token, positions) in tokens_map {
+            if !self.hasher.contains(&token) {
+                self.fuzzy_trie.add(&token);
+            }
+
+            let surface = surfaces.remove(&token);
+            let token = self.hasher.add(token)?;
+            if let Some(surface) = surface {
+                self.hasher.set_display(token, surface);
+            }
+
+            if percolate {
+                percolated.push((token, positions.clone()));
+            }
+
+            self.index_manager
+                .insert(token, Posting::new(doc_id.0, positions))?;
+
+            tokens.push(token);
+        }
+
+        self.meta.data.stats.add(&tokens);
+        self.meta.data.fields.add(&field_lens);
+        if self.config.fuzzy_vocabulary_cap.is_some() {
+            self.readmit_fuzzy_words(&tokens);
+            self.prune_fuzzy_trie();
+        }
+
+        self.meta.update_avg_doc_len(
+            self.documents_manager.len(),
+            self.documents_manager.len() + 1,
+            tokens_num as i64,
+        )?;
+
+        self.documents_manager.write(
+            doc_id, tokens_num, tokens, boost, field_lens, truncated, &doc, &payload,
+        )?;
+        let (min_boost, max_boost) = self.meta.data.boosts;
+        self.meta.data.boosts = (min_boost.min(boost), max_boost.max(boost));
+
+        if let Some(name) = &self.config.numbers_attribute
+            && let Some(number) = self.tokenizer.first_number(&doc)
+        {
+            // explicitly passed attribute takes precedence
+            attributes
+                .get_or_insert_default()
+                .entry(name.clone())
+                .or_insert(number);
+        }
+
+        if let Some(attributes) = attributes {
+            self.attributes_manager.write(doc_id, attributes)?;
+        }
+
+        if percolate {
+            let index = Percolator::document_index(doc_id.0, percolated);
+            let (filter, profiler) = (IdFilter::default(), Profiler::default());
+            let ctx = MatchContext {
+                index: PostingsView::new(&index),
+                hasher: &self.hasher,
+                fuzzy_trie: &self.fuzzy_trie,
+                documents_manager: &self.documents_manager,
+                stats: &self.meta.data.stats,
+                avg_doc_len: self.meta.data.avg_doc_len,
+                impact_avg_doc_len: self.meta.data.avg_doc_len,
+                recency: None,
+                position_boost: None,
+                max_boost: self.meta.data.boosts.1 as f64,
+                filter: &filter,
+                profiler: &profiler,
+                min_should_match: None,
+                coordination: self.config.coordination,
+                proximity: self.config.proximity_weight,
+                fields: &self.meta.data.fields,
+                field_weights: &self.config.field_weights,
+                doc_len: 0..=u32::MAX,
+                length_norm: true,
+            };
+            let matched = self.percolator.matches(&mut self.tokenizer, &ctx)?;
+            if let Some(err) = self.tokenizer.take_error() {
+                return Err(err);
+            }
+
+            // document is already added, so callback errors are only propagated
+            if !matched.is_empty()
+                && let Some(callback) = &self.percolator.callback
             {
-                continue;
+                callback.call1(py, (doc_id.to_string(), matched))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open(dir: PathBuf, config: Config) -> PyResult<Self> {
+        let config = Arc::new(config);
+        let mut fuzzy_trie = match config.enable_fuzzy {
+            true => Trie::new(config.max_fuzziness),
+            false => Trie::disabled(),
+        };
+
+        let mut hasher = TokenHasher::load(&dir, Arc::clone(&config))?;
+        let index_manager = IndexManager::load(&dir, Arc::clone(&config))?;
+        let mut documents_manager = DocumentsManager::load(dir.clone(), Arc::clone(&config))?;
+        let mut tokenizer = Tokenizer::new(Arc::clone(&config));
+        if hasher.unreadable && config.on_decode_error == DecodeErrorPolicy::Rebuild {
+            let tokens = Self::recover_tokens(&index_manager, &documents_manager, &mut tokenizer);
+            let missing = index_manager
+                .iter()
+                .filter(|(token, _)| !tokens.contains_key(*token))
+                .count();
+            if missing > 0 {
+                logging::warning(&format!(
+                    "{} tokens couldn't be recovered, e.g. tokens of fields which aren't stored",
+                    missing
+                ));
+            }
+            hasher.restore(index_manager.iter().map(|(token, _)| *token), tokens)?;
+        }
+
+        if config.enable_fuzzy {
+            for token in hasher.tokens() {
+                fuzzy_trie.add(token);
+            }
+        }
+
+        // pending deletes which postings were already removed from index are done
+        documents_manager.deleted_docs_buffer.retain(|id, doc| {
+            doc.tokens
+                .iter()
+                .any(|token| index_manager.contains(*token, id.0))
+        });
+        let latest_id = documents_manager.latest_id();
+        let meta = SearchMeta::load(dir.join("meta"), Arc::clone(&config), || {
+            CorpusStats::build(documents_manager.len() as u64, index_manager.iter())
+        })?;
+
+        let mut search = Self {
+            index_manager,
+            meta,
+            attributes_manager: AttributesManager::load(&dir, Arc::clone(&config))?,
+            digests: ContentDigests::load(&dir)?,
+            query_log: config
+                .query_log_size
+                .map(|size| QueryLog::load(&dir, size))
+                .transpose()?,
+            hasher,
+            documents_manager,
+            ulid_generator: Generator::new(),
+            latest_id,
+            tokenizer,
+            fuzzy_trie,
+            fuzzy_floor: 0,
+            percolator: Percolator::new(),
+            shadow: None,
+            config,
+        };
+        search.prune_fuzzy_trie();
+
+        Ok(search)
+    }
+
+    fn find_open(py: Python<'_>, key: &PathBuf) -> Option<(Py<Self>, Option<PathBuf>)> {
+        // returns live handle of directory and config it was opened with
+        let mut open = OPEN.lock().unwrap_or_else(|err| err.into_inner());
+        open.retain(|_, (handle, _)| handle.bind(py).upgrade().is_some());
+        let (handle, config) = open.get(key)?;
+        let search = handle.bind(py).upgrade_as::<Self>().ok()??;
+        Some((search.unbind(), config.clone()))
+    }
+
+    pub fn next_posting(&self, token: u32, after: Option<u128>) -> Option<(Ulid, Vec<u32>)> {
+        // the first posting of live document added after given one
+        self.index_manager
+            .postings_after(token, after)
+            .iter()
+            .map(|posting| (Ulid(posting.doc_id), posting))
+            .find(|(id, _)| !self.documents_manager.deleted_docs_buffer.contains_key(id))
+            .map(|(id, posting)| {
+                (
+                    id,
+                    self.index_manager.positions(token, posting).into_owned(),
+                )
+            })
+    }
+
+    fn recover_tokens(
+        index_manager: &IndexManager,
+        documents_manager: &DocumentsManager,
+        tokenizer: &mut Tokenizer,
+    ) -> StdHashMap<u32, (String, Option<String>)> {
+        // index logs keep only token ids, their text is found by tokenizing stored
+        // documents again, token is the one at the same positions in all of its first
+        // postings, field tokens aren't stored so they aren't recovered
+        let mut documents = StdHashMap::new();
+        let mut tokens: StdHashMap<u32, (String, Option<String>)> = StdHashMap::new();
+        for (token, postings) in index_manager.iter() {
+            let mut recovered: Option<(String, Option<String>)> = None;
+            for posting in postings.iter().take(RECOVERY_ATTEMPTS) {
+                let tokenized = documents.entry(posting.doc_id).or_insert_with(|| {
+                    let mut content = documents_manager
+                        .get(&Ulid(posting.doc_id))?
+                        .into_owned()
+                        .content()
+                        .ok()?;
+                    let (_, positions, mut surfaces) = tokenizer.tokenize_doc(&mut content);
+                    Some(
+                        positions
+                            .into_iter()
+                            .map(|(text, positions)| {
+                                let surface = surfaces.remove(&text);
+                                (positions, (text, surface))
+                            })
+                            .collect::<StdHashMap<Vec<u32>, (String, Option<String>)>>(),
+                    )
+                });
+
+                let Some(tokenized) = tokenized else { continue };
+                let positions = index_manager.positions(*token, posting);
+                match (tokenized.get(positions.as_ref()), &recovered) {
+                    (Some(text), None) => recovered = Some(text.clone()),
+                    (Some(text), Some(other)) if text.0 == other.0 => (),
+                    _ => {
+                        recovered = None;
+                        break;
+                    }
+                }
             }
 
-            let max_score = max_bm25(
-                &self.documents_manager,
-                self.meta.data.avg_doc_len,
-                pointers,
+            if let Some(recovered) = recovered {
+                tokens.insert(*token, recovered);
+            }
+        }
+
+        // truncation of recovered documents was already recorded when they were added
+        tokenizer.take_truncated();
+
+        // field token at the same positions as document token is indistinguishable from it
+        let mut ids: StdHashMap<&str, usize> = StdHashMap::new();
+        for (text, _) in tokens.values() {
+            *ids.entry(text.as_str()).or_default() += 1;
+        }
+        let ambiguous = ids
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(text, _)| text.to_string())
+            .collect::<HashSet<String>>();
+        tokens.retain(|_, (text, _)| !ambiguous.contains(text));
+
+        tokens
+    }
+
+    // every term of query with tokens it was analyzed to, e.g. for "searched for"
+    // feedback, terms removed as stop words have no tokens
+    fn analyze_query(
+        &mut self,
+        py: Python<'_>,
+        query: &QueryInput,
+        options: &SearchOptions,
+    ) -> PyResult<Vec<Py<PyDict>>> {
+        let query = match query {
+            QueryInput::Text(query) => Query::parse(query, options.fuzziness(), &self.config)?,
+            QueryInput::Built(builder) => builder.query.clone(),
+        };
+
+        let mut analysis = vec![];
+        for clause in query.clauses() {
+            let mut tokenized = self.tokenizer.tokenize_query(clause);
+            if let Some(err) = self.tokenizer.take_error() {
+                return Err(err);
+            }
+            let (hasher, stats) = (&self.hasher, &self.meta.data.stats);
+            self.tokenizer.adapt_auto_fuzz(
+                &mut tokenized,
+                |text| hasher.hash(text).map_or(0, |token| stats.df(token)),
+                stats.docs_num,
             );
 
-            if top_k != 0
-                && results.len() == top_k as usize
-                && let Some(peek) = results.peek()
-                && peek.0.score >= max_score
-            {
-                // skip minimal interval sematic match for non compatative documents
-                continue;
+            // tokens of field scoped clause are reported without field prefix
+            let unscoped = |token: &str| match &clause.field {
+                Some(_) => token.split_once(' ').map_or(token, |(_, t)| t).to_string(),
+                None => token.to_string(),
+            };
+            for (i, term) in clause.terms.iter().enumerate() {
+                let (mut tokens, mut synonyms, mut expansions) = (vec![], vec![], vec![]);
+                for token in tokenized.tokens.iter().filter(|t| t.term == i) {
+                    tokens.push(unscoped(&token.text));
+                    for synonym in token.synonyms.iter().map(|s| unscoped(s)) {
+                        if !synonyms.contains(&synonym) {
+                            synonyms.push(synonym);
+                        }
+                    }
+                    if token.fuzz == 0 {
+                        continue;
+                    }
+                    // the same expansions fuzzy matching would search for
+                    for (distance, expansion) in self.fuzzy_trie.search(token.fuzz, &token.text)? {
+                        if distance == 0
+                            || expansion.len() <= token.fuzz as usize
+                            || token.text.len() <= token.fuzz as usize
+                            || self.hasher.hash(&expansion).is_none()
+                        {
+                            continue;
+                        }
+                        let expansion = unscoped(&expansion);
+                        if !expansions.contains(&expansion) {
+                            expansions.push(expansion);
+                        }
+                    }
+                }
+
+                let item = PyDict::new(py);
+                item.set_item("term", &term.text)?;
+                item.set_item("field", &clause.field)?;
+                item.set_item("tokens", tokens)?;
+                item.set_item("synonyms", synonyms)?;
+                item.set_item("expansions", expansions)?;
+                analysis.push(item.unbind());
             }
+        }
+        Ok(analysis)
+    }
 
-            for mis_result in
-                MinimalIntervalSemanticMatch::new(&self.index_manager.index, pointers, slop as i32)
-            {
-                let doc = match self.documents_manager.docs.get(&doc_id) {
-                    Some(doc) => doc,
-                    None => continue,
-                };
+    // runs search, components are timed with profiler only if it is enabled
+    fn execute(
+        &mut self,
+        query: QueryInput,
+        options: &SearchOptions,
+        visibility: Option<Py<PyAny>>,
+        profiler: &Profiler,
+    ) -> PyResult<Vec<PySearchResult>> {
+        let (top_k, recency_boost) = (options.top_k(), options.recency_boost);
+        let (tie_break, min_score) = (options.tie_break(), options.min_score());
+        // timeout too long to be represented means there is no deadline
+        let deadline = options
+            .timeout
+            .and_then(|timeout| Duration::try_from_secs_f64(timeout).ok())
+            .and_then(|timeout| Instant::now().checked_add(timeout));
+
+        let script = match &options.score_script {
+            Some(script) => Some(Expr::compile(script)?),
+            None => None,
+        };
+
+        let visibility = visibility.map(PyVisibility::new);
+        let filter = IdFilter::new(
+            options
+                .restrict_ids
+                .as_deref()
+                .map(Self::parse_ids)
+                .transpose()?,
+            Self::parse_ids(options.exclude_ids.as_deref().unwrap_or_default())?,
+            visibility.as_ref().map(|v| v as &dyn VisibilityHook),
+        );
+
+        let query = {
+            let _timer = profiler.scope(Span::Parse);
+            match query {
+                QueryInput::Text(query) => Query::parse(&query, options.fuzziness(), &self.config)?,
+                QueryInput::Built(builder) => builder.query,
+            }
+        };
+        let query = match &options.fields {
+            Some(fields) => query.with_fields(fields),
+            None => query,
+        };
+
+        // single terms and their disjunctions can be answered from impact ordered postings
+        if top_k != 0
+            && script.is_none()
+            && recency_boost.is_none()
+            && options.position_boost.is_none()
+            && options.diversify.is_none()
+            && options.length_norm()
+            && !self.meta.data.boosted()
+            && self.config.coordination == 0.0
+            && filter.is_empty()
+            && options.min_len.is_none()
+            && options.max_len.is_none()
+            && self.config.max_docs_per_term.is_none()
+            && let Some(lists) = {
+                let _timer = profiler.scope(Span::Expansion);
+                ImpactList::collect(
+                    &query,
+                    &mut self.tokenizer,
+                    &self.hasher,
+                    &self.index_manager,
+                )
+            }
+        {
+            if let Some(err) = self.tokenizer.take_error() {
+                return Err(err);
+            }
 
-                score = bm25(
-                    self.documents_manager.docs.len() as u64,
-                    doc.tokens.len() as u32,
+            let results = {
+                let _timer = profiler.scope(Span::Matching);
+                impact::top_k(
+                    &lists,
+                    top_k as usize,
+                    min_score,
+                    &self.documents_manager,
+                    &self.meta.data.stats,
                     self.meta.data.avg_doc_len,
-                    &self.index_manager.index,
-                    mis_result,
+                    self.index_manager.impact_avg_doc_len,
+                    tie_break,
                 )
-                .max(score);
+            };
+
+            let _timer = profiler.scope(Span::Materialization);
+            return Ok(self.materialize(results.into_iter(), options));
+        }
+
+        let now = Self::now()?;
+        let impact_avg_doc_len = self.index_manager.impact_avg_doc_len;
+        let ctx = MatchContext {
+            index: self.index_manager.postings(),
+            hasher: &self.hasher,
+            fuzzy_trie: &self.fuzzy_trie,
+            documents_manager: &self.documents_manager,
+            stats: &self.meta.data.stats,
+            avg_doc_len: self.meta.data.avg_doc_len,
+            impact_avg_doc_len,
+            recency: recency_boost.map(|halflife| (now, halflife)),
+            position_boost: options.position_boost,
+            max_boost: self.meta.data.boosts.1 as f64,
+            filter: &filter,
+            profiler,
+            min_should_match: options.min_should_match.map(|min| min as usize),
+            coordination: self.config.coordination,
+            proximity: self.config.proximity_weight,
+            fields: &self.meta.data.fields,
+            field_weights: &self.config.field_weights,
+            doc_len: options.doc_len(),
+            length_norm: options.length_norm(),
+        };
+        let mut matcher = {
+            let _timer = profiler.scope(Span::Expansion);
+            Matcher::build(&query, &mut self.tokenizer, &ctx)?
+        };
+        if let Some(err) = self.tokenizer.take_error() {
+            return Err(err);
+        }
+
+        // diversified results are picked from a larger pool of the best candidates
+        let pool = match options.diversify {
+            Some(_) => top_k * DIVERSITY_POOL,
+            None => top_k,
+        };
+        let mut results: BinaryHeap<Reverse<SearchResult>> =
+            BinaryHeap::with_capacity(pool as usize);
+
+        let matching = profiler.scope(Span::Matching);
+        loop {
+            if let Some(deadline) = deadline
+                && Instant::now() >= deadline
+            {
+                break;
             }
 
+            // score script may change scores arbitrarily so bm25 upper bound can't be used,
+            // documents are matched in id order, so with newest first (or shuffled) the
+            // later document with equal score may replace the lowest one and mustn't be
+            // skipped, document scoring exactly min score is still returned
+            let threshold = match results.peek() {
+                Some(peek) if script.is_none() && pool != 0 && results.len() == pool as usize => {
+                    match tie_break {
+                        TieBreak::Oldest => peek.0.score,
+                        TieBreak::Newest | TieBreak::Shuffled(_) => peek.0.score.next_down(),
+                    }
+                }
+                _ => f64::NEG_INFINITY,
+            };
+            let threshold = match script {
+                Some(_) => threshold,
+                None => threshold.max(min_score.next_down()),
+            };
+
+            let (doc_id, mut score) = match matcher.next(threshold) {
+                Some(res) => res,
+                None => break,
+            };
+
             if score > 0.0 {
-                if top_k == 0 || results.len() < top_k as usize {
-                    results.push(Reverse(SearchResult {
-                        doc_id: doc_id,
-                        score: score,
-                    }));
+                if let Some(script) = &script {
+                    let _timer = profiler.scope(Span::Scoring);
+                    score = script.eval(score, self.attributes_manager.get(&doc_id));
+                    // e.g. log of missing attribute or division by it, such score
+                    // isn't comparable and would be ranked above every real one
+                    if !score.is_finite() || score <= 0.0 {
+                        continue;
+                    }
+                }
+                if score < min_score {
+                    continue;
+                }
+
+                let result = SearchResult {
+                    doc_id,
+                    score,
+                    tie_break,
+                };
+                if pool == 0 || results.len() < pool as usize {
+                    results.push(Reverse(result));
                 } else if let Some(peek) = results.peek()
-                    && peek.0.score < score
+                    && peek.0 < result
                 {
                     let _ = results.pop();
-                    results.push(Reverse(SearchResult {
-                        doc_id: doc_id,
-                        score: score,
-                    }));
+                    results.push(Reverse(result));
                 }
             }
         }
 
-        Ok(results
-            .into_sorted_vec()
+        drop(matching);
+
+        if let Some(e) = visibility.as_ref().and_then(|v| v.take_error()) {
+            return Err(e);
+        }
+
+        // the highest ranked results come first
+        let mut results = results.into_vec();
+        results.sort_unstable();
+
+        let results = results.into_iter().map(|r| (r.0.doc_id, r.0.score));
+        let results = match options.diversify {
+            Some(lambda) => {
+                let _timer = profiler.scope(Span::Scoring);
+                diversity::mmr(
+                    results.collect(),
+                    top_k as usize,
+                    lambda,
+                    &self.documents_manager,
+                    &self.meta.data.stats,
+                )
+            }
+            None => results.collect(),
+        };
+
+        let _timer = profiler.scope(Span::Materialization);
+        Ok(self.materialize(results.into_iter(), options))
+    }
+
+    fn materialize(
+        &self,
+        results: impl Iterator<Item = (Ulid, f64)>,
+        options: &SearchOptions,
+    ) -> Vec<PySearchResult> {
+        // documents are looked up together, so disk storage can batch their reads
+        let (ids, scores): (Vec<Ulid>, Vec<f64>) = results.unzip();
+        self.documents_manager
+            .get_many(&ids)
             .into_iter()
-            .filter_map(|r| {
-                if let Some(doc) = self.documents_manager.docs.get(&r.0.doc_id) {
-                    Some(PySearchResult {
-                        document: doc.clone(),
-                        score: r.0.score,
-                    })
-                } else {
-                    None
-                }
+            .zip(scores)
+            .filter_map(|(doc, score)| {
+                doc.map(|doc| {
+                    let mut document = doc.into_owned();
+                    document.max_content_bytes = options.max_content_bytes;
+                    PySearchResult { document, score }
+                })
             })
-            .collect())
+            .collect()
     }
 
-    fn flush(&mut self) -> PyResult<()> {
-        self.force_delete()?;
-        self.documents_manager.flush()?;
-        self.index_manager.flush()?;
-        self.hasher.flush()?;
-        self.meta.flush()?;
-        Ok(())
+    fn now() -> PyResult<u64> {
+        // current timestamp in milliseconds
+        Ok(SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|e| PySystemError::new_err(e.to_string()))?
+            .as_millis() as u64)
     }
 
-    fn merge(&mut self) -> PyResult<()> {
-        // flush data before merge
-        let _ = self.flush();
-        self.documents_manager.merge()?;
-        Ok(())
+    fn parse_ids(ids: &[String]) -> PyResult<Vec<Ulid>> {
+        ids.iter()
+            .map(|id| Ulid::from_string(id).map_err(|e| UlidError::UlidDecodeError(e).into()))
+            .collect()
+    }
+
+    fn prune_fuzzy_trie(&mut self) {
+        let cap = match self.config.fuzzy_vocabulary_cap {
+            Some(cap) => cap as usize,
+            None => return,
+        };
+
+        let (hasher, stats) = (&self.hasher, &self.meta.data.stats);
+        if let Some(floor) = self.fuzzy_trie.prune(cap, |word| {
+            hasher.hash(word).map_or(0, |token| stats.df(token))
+        }) {
+            self.fuzzy_floor = floor;
+        }
+    }
+
+    fn readmit_fuzzy_words(&mut self, tokens: &[u32]) {
+        // evicted words which became more frequent than evicted ones are added back
+        for token in tokens {
+            if self.meta.data.stats.df(*token) <= self.fuzzy_floor {
+                continue;
+            }
+
+            if let Some(word) = self.hasher.unhash(*token)
+                && !self.fuzzy_trie.contains(word)
+            {
+                self.fuzzy_trie.add(word);
+            }
+        }
     }
-}
 
-impl Search {
     fn force_delete(&mut self) -> PyResult<bool> {
         let (mut deleted_len_sum, deleted_docs_num) =
             (0, self.documents_manager.deleted_docs_buffer.len());
+        if deleted_docs_num == 0 {
+            return Ok(true);
+        }
 
         let (mut tokens, mut document_ids) =
             (HashSet::new(), HashSet::with_capacity(deleted_docs_num));
 
         for (id, doc) in self.documents_manager.deleted_docs_buffer.drain() {
+            self.meta.data.stats.remove(&doc.tokens);
+            self.meta.data.fields.remove(&doc.fields);
             tokens.extend(doc.tokens);
             document_ids.insert(id);
             deleted_len_sum += doc.len;
@@ -393,8 +1812,8 @@ impl Search {
 
         // update avg len
         self.meta.update_avg_doc_len(
-            self.documents_manager.docs.len() + deleted_docs_num,
-            self.documents_manager.docs.len(),
+            self.documents_manager.len() + deleted_docs_num,
+            self.documents_manager.len(),
             -1 * deleted_len_sum as i64,
         )?;
 
@@ -405,6 +1824,10 @@ impl Search {
             &mut self.hasher,
         )?;
 
+        // statistics are persisted right away, so other processes and reopened index
+        // don't score with idf and average length of removed documents
+        self.meta.save()?;
+
         Ok(true)
     }
 }
@@ -0,0 +1,82 @@
+use crate::core::options::SearchOptions;
+use std::collections::HashMap as StdHashMap;
+use std::time::Duration;
+use ulid::Ulid;
+
+// experimental search options executed next to live search on demand, only live
+// results are returned and comparison of both runs is aggregated in memory
+pub struct Shadow {
+    options: SearchOptions,
+    queries: u64,
+    errors: u64,    // experimental runs which failed, e.g. on invalid score script
+    overlap: f64,   // sum of fractions of results returned by both runs
+    top_match: u64, // runs with the same first result
+    live: Duration,
+    experiment: Duration,
+}
+
+impl Shadow {
+    pub fn new(options: SearchOptions) -> Self {
+        Self {
+            options,
+            queries: 0,
+            errors: 0,
+            overlap: 0.0,
+            top_match: 0,
+            live: Duration::ZERO,
+            experiment: Duration::ZERO,
+        }
+    }
+
+    pub fn options(&self, live: &SearchOptions) -> SearchOptions {
+        // both runs are compared at the same depth and over the same documents
+        let mut options = self.options.clone();
+        options.top_k = live.top_k;
+        options.restrict_ids = live.restrict_ids.clone();
+        options.exclude_ids = live.exclude_ids.clone();
+        options.profile = None;
+        options
+    }
+
+    pub fn record(
+        &mut self,
+        live: &[Ulid],
+        experiment: Option<&[Ulid]>,
+        live_time: Duration,
+        experiment_time: Duration,
+    ) {
+        self.queries += 1;
+        self.live += live_time;
+        self.experiment += experiment_time;
+        let Some(experiment) = experiment else {
+            self.errors += 1;
+            return;
+        };
+
+        let common = live.iter().filter(|id| experiment.contains(id)).count();
+        self.overlap += match live.len().max(experiment.len()) {
+            0 => 1.0,
+            len => common as f64 / len as f64,
+        };
+        if live.first() == experiment.first() {
+            self.top_match += 1;
+        }
+    }
+
+    pub fn stats(&self) -> StdHashMap<&'static str, f64> {
+        // means over compared runs, times over all runs
+        let compared = (self.queries - self.errors).max(1) as f64;
+        let queries = self.queries.max(1) as f64;
+        StdHashMap::from([
+            ("queries", self.queries as f64),
+            ("errors", self.errors as f64),
+            ("overlap", self.overlap / compared),
+            ("top_match", self.top_match as f64 / compared),
+            ("live_seconds", self.live.as_secs_f64() / queries),
+            (
+                "experiment_seconds",
+                self.experiment.as_secs_f64() / queries,
+            ),
+        ])
+    }
+}
@@ -11,6 +11,9 @@ create_exception!(crate, BincodeDecodeError, pyo3::exceptions::PyException);
 create_exception!(crate, UlidMonotonicError, pyo3::exceptions::PyException);
 create_exception!(crate, UlidDecodeError, pyo3::exceptions::PyException);
 create_exception!(crate, CompressException, pyo3::exceptions::PyException);
+create_exception!(crate, DocumentCorrupted, pyo3::exceptions::PyException);
+create_exception!(crate, DuplicatePosting, pyo3::exceptions::PyException);
+create_exception!(crate, InvalidEncoding, pyo3::exceptions::PyValueError);
 create_exception!(
     crate,
     TomlDeserializeException,
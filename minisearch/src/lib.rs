@@ -11,12 +11,22 @@ use pyo3::prelude::*;
 
 #[pymodule]
 mod rust {
+    #[pymodule_export]
+    use crate::core::options::SearchOptions;
+    #[pymodule_export]
+    use crate::core::postings::PostingsIterator;
     #[pymodule_export]
     use crate::core::search::PySearchResult;
     #[pymodule_export]
     use crate::core::search::Search;
     #[pymodule_export]
+    use crate::query::builder::QueryBuilder;
+    #[pymodule_export]
     use crate::storage::documents::Document;
+    #[pymodule_export]
+    use crate::utils::automaton::levenshtein;
+    #[pymodule_export]
+    use crate::utils::automaton::within_distance;
 
     // errors
     #[pymodule_export]
@@ -26,6 +36,14 @@ mod rust {
     #[pymodule_export]
     use crate::errors::CompressException;
     #[pymodule_export]
+    use crate::errors::DocumentCorrupted;
+    #[pymodule_export]
+    use crate::errors::DuplicatePosting;
+    #[pymodule_export]
+    use crate::errors::InvalidEncoding;
+    #[pymodule_export]
+    use crate::errors::TomlDeserializeException;
+    #[pymodule_export]
     use crate::errors::TryFromSliceException;
     #[pymodule_export]
     use crate::errors::UlidDecodeError;
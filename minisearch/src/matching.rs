@@ -1,2 +1,7 @@
+pub mod boolean;
+pub mod diversity;
+pub mod impact;
 pub mod intersect;
 pub mod mis;
+pub mod percolator;
+pub mod visibility;
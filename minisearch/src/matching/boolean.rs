@@ -0,0 +1,815 @@
+use crate::analysis::tokenizer::{Token, TokenizedQuery, Tokenizer};
+use crate::core::index::PostingsView;
+use crate::matching::intersect::PostingListIntersection;
+use crate::matching::mis::{MinimalIntervalSemanticMatch, MisResult, exact_phrase, term_intervals};
+use crate::matching::visibility::VisibilityHook;
+use crate::query::parser::Query;
+use crate::query::scoring::{
+    CorpusStats, FieldStats, Idfs, block_bm25, bm25, coord, max_bm25, position_boost,
+    recency_decay, unbounded_bm25,
+};
+use crate::storage::documents::DocumentsManager;
+use crate::utils::hasher::TokenHasher;
+use crate::utils::profiler::{Profiler, Span};
+use crate::utils::trie::Trie;
+use hashbrown::{HashMap, HashSet};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap as StdHashMap;
+use std::ops::RangeInclusive;
+use ulid::Ulid;
+
+// caller provided document ids which matching is restricted to or excluded from
+#[derive(Default)]
+pub struct IdFilter<'a> {
+    restrict: Option<Vec<Ulid>>, // sorted, so intersection can skip to the next allowed id
+    exclude: HashSet<Ulid>,
+    hook: Option<&'a dyn VisibilityHook>,
+    verdicts: RefCell<HashMap<Ulid, bool>>, // hook results, document may be reached by many clauses
+}
+
+enum Visibility {
+    Visible,
+    Hidden,
+    SkipTo(Ulid),
+    Exhausted,
+}
+
+impl<'a> IdFilter<'a> {
+    pub fn new(
+        restrict: Option<Vec<Ulid>>,
+        exclude: Vec<Ulid>,
+        hook: Option<&'a dyn VisibilityHook>,
+    ) -> Self {
+        Self {
+            restrict: restrict.map(|mut ids| {
+                ids.sort_unstable();
+                ids.dedup();
+                ids
+            }),
+            exclude: exclude.into_iter().collect(),
+            hook,
+            verdicts: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.restrict.is_none() && self.exclude.is_empty() && self.hook.is_none()
+    }
+
+    fn visible(&self, doc_id: &Ulid) -> bool {
+        let hook = match self.hook {
+            Some(hook) => hook,
+            None => return true,
+        };
+
+        *self
+            .verdicts
+            .borrow_mut()
+            .entry(*doc_id)
+            .or_insert_with(|| hook.visible(doc_id))
+    }
+
+    fn check(&self, doc_id: &Ulid) -> Visibility {
+        if let Some(restrict) = &self.restrict
+            && let Err(idx) = restrict.binary_search(doc_id)
+        {
+            return match restrict.get(idx) {
+                Some(next) => Visibility::SkipTo(*next),
+                None => Visibility::Exhausted,
+            };
+        }
+
+        match self.exclude.contains(doc_id) {
+            true => Visibility::Hidden,
+            false => Visibility::Visible,
+        }
+    }
+}
+
+pub struct MatchContext<'a> {
+    pub index: PostingsView<'a>,
+    pub hasher: &'a TokenHasher,
+    pub fuzzy_trie: &'a Trie,
+    pub documents_manager: &'a DocumentsManager,
+    pub stats: &'a CorpusStats,
+    pub avg_doc_len: f64,
+    pub impact_avg_doc_len: f64, // average document length posting impacts were quantized with
+    pub recency: Option<(u64, f64)>, // current timestamp in milliseconds and half-life
+    pub position_boost: Option<f64>, // half-distance of boost of matches near document start
+    pub max_boost: f64,          // highest document boost, bounds score before document is read
+    pub filter: &'a IdFilter<'a>,
+    pub profiler: &'a Profiler,
+    pub min_should_match: Option<usize>, // clauses with more tokens match if enough of them match
+    pub coordination: f64,               // weight of fraction of matched disjunction terms in score
+    pub proximity: f64,                  // weight of gaps between matched tokens in score
+    pub fields: &'a FieldStats,
+    pub field_weights: &'a StdHashMap<String, f64>,
+    pub doc_len: RangeInclusive<u32>, // lengths (in tokens) of documents which can match
+    pub length_norm: bool,            // whether tf is normalized by document (or field) length
+}
+
+impl<'a> MatchContext<'a> {
+    fn decay(&self, doc_id: &Ulid) -> f64 {
+        match self.recency {
+            Some((now, halflife)) => recency_decay(doc_id, now, halflife),
+            None => 1.0,
+        }
+    }
+
+    fn within_len(&self, doc_id: &Ulid) -> bool {
+        // document is read only if length is limited
+        *self.doc_len.start() == 0 && *self.doc_len.end() == u32::MAX
+            || self
+                .documents_manager
+                .get(doc_id)
+                .is_some_and(|doc| self.doc_len.contains(&doc.len))
+    }
+
+    fn boost(&self, position: u32) -> f64 {
+        match self.position_boost {
+            Some(halfdistance) => position_boost(position, halfdistance),
+            None => 1.0,
+        }
+    }
+}
+
+// field scoped clause is scored with weight of its field and its tf is normalized by
+// length of the field, average length is unknown if no document stored field lengths
+struct FieldNorm {
+    name: String,
+    weight: f64,
+    avg_len: Option<f64>,
+}
+
+pub struct ClauseMatcher<'a> {
+    intersection: PostingListIntersection<'a>,
+    idfs: Idfs<'a>,
+    field: Option<FieldNorm>,
+    slop: u8,
+    gaps: Vec<u32>,
+    in_order: bool,
+    ctx: &'a MatchContext<'a>,
+}
+
+impl<'a> ClauseMatcher<'a> {
+    fn weight(&self) -> f64 {
+        self.field.as_ref().map_or(1.0, |field| field.weight)
+    }
+
+    fn field_len(&self) -> Option<(&str, f64)> {
+        // field name and its average length, if tf is normalized by field length
+        let field = self.field.as_ref()?;
+        Some((field.name.as_str(), field.avg_len?))
+    }
+
+    fn skip_blocks(&self, threshold: f64) -> Option<Ulid> {
+        // returns the last document of skipped blocks, recency decay is at most 1,
+        // impacts are quantized with document length, so they don't bound field scores
+        // nor scores without length normalization
+        if threshold == f64::NEG_INFINITY || self.field_len().is_some() || !self.ctx.length_norm {
+            return None;
+        }
+
+        let _timer = self.ctx.profiler.scope(Span::Scoring);
+        let (bound, end) = self.intersection.block_bound(|pointer, block_max| {
+            block_bm25(
+                &self.idfs,
+                self.ctx.avg_doc_len,
+                self.ctx.impact_avg_doc_len,
+                pointer,
+                block_max,
+            )
+        })?;
+
+        match threshold >= bound * self.weight() * self.ctx.boost(0) * self.ctx.max_boost {
+            true => Some(end),
+            false => None,
+        }
+    }
+
+    fn next(&mut self, threshold: f64, target: Option<&Ulid>) -> Option<(Ulid, f64)> {
+        let mut target = target.copied();
+        loop {
+            let pointers = {
+                let _timer = self.ctx.profiler.scope(Span::Intersection);
+                match target.take() {
+                    Some(target) => self.intersection.seek(&target)?,
+                    None => self.intersection.next()?,
+                }
+            };
+
+            let doc_id = pointers[0][0].doc_id;
+            if let Some(end) = self.skip_blocks(threshold) {
+                // no document up to the end of the nearest block can exceed threshold
+                target = Some(Ulid(end.0.checked_add(1)?));
+                continue;
+            }
+
+            match self.ctx.filter.check(&doc_id) {
+                Visibility::Visible => (),
+                Visibility::Hidden => continue,
+                Visibility::SkipTo(next) => {
+                    target = Some(next);
+                    continue;
+                }
+                Visibility::Exhausted => return None,
+            }
+
+            if self
+                .ctx
+                .documents_manager
+                .deleted_docs_buffer
+                .contains_key(&doc_id)
+            {
+                continue;
+            }
+
+            let pointers = self.intersection.current();
+            let decay = self.ctx.decay(&doc_id);
+            // match at the first position has the highest boost
+            let factor = decay * self.weight() * self.ctx.boost(0) * self.ctx.max_boost;
+            let max_score = {
+                let _timer = self.ctx.profiler.scope(Span::Scoring);
+                match self.field_len() {
+                    Some(_) => unbounded_bm25(&self.idfs, pointers) * factor,
+                    None if !self.ctx.length_norm => unbounded_bm25(&self.idfs, pointers) * factor,
+                    None => {
+                        max_bm25(
+                            self.ctx.documents_manager,
+                            &self.idfs,
+                            self.ctx.avg_doc_len,
+                            self.ctx.impact_avg_doc_len,
+                            pointers,
+                        ) * factor
+                    }
+                }
+            };
+
+            if threshold >= max_score {
+                // skip minimal interval sematic match for non compatative documents
+                continue;
+            }
+
+            if !self.ctx.filter.visible(&doc_id) {
+                continue;
+            }
+
+            let doc = match self.ctx.documents_manager.get(&doc_id) {
+                Some(doc) if self.ctx.doc_len.contains(&doc.len) => doc,
+                _ => continue,
+            };
+
+            let (doc_len, avg_len) = match self.field_len() {
+                // document of average length isn't normalized, which equals bm25 with b = 0
+                _ if !self.ctx.length_norm => (1, 1.0),
+                // documents added before field lengths were stored have average length
+                Some((name, avg_len)) => (doc.field_len(name).unwrap_or(avg_len as u32), avg_len),
+                None => (doc.tokens.len() as u32, self.ctx.avg_doc_len),
+            };
+
+            let mut score: Option<f64> = None;
+            let intervals = {
+                let _timer = self.ctx.profiler.scope(Span::Mis);
+                match pointers.len() == 1 && self.ctx.position_boost.is_none() {
+                    // single token matches at its every position without gaps, so its
+                    // positions aren't read at all
+                    true => term_intervals(&pointers[0]),
+                    // exact phrase is verified with position arithmetic
+                    false if self.slop == 0 && self.in_order => {
+                        exact_phrase(self.ctx.index, pointers, &self.gaps)
+                    }
+                    false => MinimalIntervalSemanticMatch::new(
+                        self.ctx.index,
+                        pointers,
+                        self.slop as i32,
+                        self.gaps.clone(),
+                        self.in_order,
+                    )
+                    .collect::<Vec<MisResult>>(),
+                }
+            };
+            for mis_result in intervals {
+                let _timer = self.ctx.profiler.scope(Span::Scoring);
+                // boost is given by the earliest position of matched interval
+                let boost = match mis_result.indexes.iter().map(|idx| idx.token_idx).min() {
+                    Some(position) => self.ctx.boost(position),
+                    None => 1.0,
+                };
+                let mis_score =
+                    boost * bm25(&self.idfs, doc_len, avg_len, mis_result, self.ctx.proximity);
+                score = Some(score.map_or(mis_score, |s| s.max(mis_score)));
+            }
+
+            // document contains all tokens, but not within allowed slop
+            if let Some(score) = score {
+                return Some((doc_id, score * self.weight() * decay * doc.boost as f64));
+            }
+        }
+    }
+}
+
+// iterates all live documents, ids are taken once when matcher is built
+pub struct AllMatcher<'a> {
+    ids: Vec<Ulid>,
+    pos: usize,
+    ctx: &'a MatchContext<'a>,
+}
+
+impl<'a> AllMatcher<'a> {
+    fn next(&mut self, threshold: f64, target: Option<&Ulid>) -> Option<(Ulid, f64)> {
+        let mut target = target.copied();
+        loop {
+            if let Some(target) = target.take() {
+                self.pos = self.pos.max(self.ids.partition_point(|id| *id < target));
+            }
+
+            let doc_id = *self.ids.get(self.pos)?;
+            self.pos += 1;
+            match self.ctx.filter.check(&doc_id) {
+                Visibility::Visible => (),
+                Visibility::Hidden => continue,
+                Visibility::SkipTo(next) => {
+                    target = Some(next);
+                    continue;
+                }
+                Visibility::Exhausted => return None,
+            }
+
+            // every document has the same score, so only recency decay can order them
+            let score = self.ctx.decay(&doc_id);
+            if threshold >= score
+                || !self.ctx.filter.visible(&doc_id)
+                || !self.ctx.within_len(&doc_id)
+            {
+                continue;
+            }
+
+            return Some((doc_id, score));
+        }
+    }
+}
+
+// head of child matcher, last document returned by it
+type Head = Option<(Ulid, f64)>;
+
+pub enum Matcher<'a> {
+    Empty,
+    Clause(ClauseMatcher<'a>),
+    All(AllMatcher<'a>),
+    And {
+        required: Vec<Matcher<'a>>,
+        excluded: Vec<Matcher<'a>>,
+        optional: Vec<Matcher<'a>>,
+        heads: Vec<Head>,
+        excluded_heads: Vec<Head>,
+        optional_heads: Vec<Head>,
+        started: bool,
+    },
+    Or {
+        children: Vec<Matcher<'a>>,
+        heads: Vec<Head>,
+        total: usize, // number of disjunction terms, including ones missing in index
+        current: Option<Ulid>,
+        started: bool,
+        coordination: f64,
+        best: bool, // score is the best score of matched children instead of their sum
+    },
+    AtLeast {
+        children: Vec<Matcher<'a>>,
+        heads: Vec<Head>,
+        min: usize, // number of children that has to match the same document
+        total: usize,
+        current: Option<Ulid>,
+        started: bool,
+        coordination: f64,
+    },
+}
+
+impl<'a> Matcher<'a> {
+    pub fn build(
+        query: &Query,
+        tokenizer: &mut Tokenizer,
+        ctx: &'a MatchContext<'a>,
+    ) -> PyResult<Self> {
+        match query {
+            Query::Clause(clause) => {
+                let mut query = tokenizer.tokenize_query(clause);
+                if query.match_all {
+                    return Self::build(&Query::All, tokenizer, ctx);
+                }
+                if query.tokens.is_empty() {
+                    return Ok(Self::Empty);
+                }
+                tokenizer.adapt_auto_fuzz(
+                    &mut query,
+                    |text| ctx.hasher.hash(text).map_or(0, |token| ctx.stats.df(token)),
+                    ctx.stats.docs_num,
+                );
+
+                match ctx.min_should_match {
+                    Some(min) if query.tokens.len() > min => Self::at_least(query, min, ctx),
+                    _ => Self::clause(query, ctx),
+                }
+            }
+            Query::And(queries) => {
+                let (mut required, mut excluded, mut optional) = (vec![], vec![], vec![]);
+                for query in queries {
+                    match query {
+                        Query::Not(query) => excluded.push(Self::build(query, tokenizer, ctx)?),
+                        Query::Optional(query) => {
+                            optional.push(Self::build(query, tokenizer, ctx)?)
+                        }
+                        query => required.push(Self::build(query, tokenizer, ctx)?),
+                    }
+                }
+
+                if required.is_empty() {
+                    return Err(Self::negation_error());
+                }
+
+                if required.iter().any(|m| matches!(m, Self::Empty)) {
+                    return Ok(Self::Empty);
+                }
+
+                excluded.retain(|m| !matches!(m, Self::Empty));
+                optional.retain(|m| !matches!(m, Self::Empty));
+                Ok(Self::And {
+                    heads: vec![None; required.len()],
+                    excluded_heads: vec![None; excluded.len()],
+                    optional_heads: vec![None; optional.len()],
+                    required,
+                    excluded,
+                    optional,
+                    started: false,
+                })
+            }
+            Query::Or(queries) => {
+                let mut children = vec![];
+                for query in queries {
+                    match Self::build(query, tokenizer, ctx)? {
+                        Self::Empty => continue,
+                        matcher => children.push(matcher),
+                    }
+                }
+
+                // single child is matched on its own, unless missing terms lower its score
+                Ok(match children.len() {
+                    0 => Self::Empty,
+                    1 if queries.len() == 1 || ctx.coordination == 0.0 => children.remove(0),
+                    _ => Self::Or {
+                        heads: vec![None; children.len()],
+                        children,
+                        total: queries.len(),
+                        current: None,
+                        started: false,
+                        coordination: ctx.coordination,
+                        best: false,
+                    },
+                })
+            }
+            Query::Any(queries) => {
+                // alternatives aren't separate terms, so coordination doesn't apply
+                let mut children = vec![];
+                for query in queries {
+                    match Self::build(query, tokenizer, ctx)? {
+                        Self::Empty => continue,
+                        matcher => children.push(matcher),
+                    }
+                }
+
+                Ok(match children.len() {
+                    0 => Self::Empty,
+                    1 => children.remove(0),
+                    _ => Self::Or {
+                        heads: vec![None; children.len()],
+                        total: children.len(),
+                        children,
+                        current: None,
+                        started: false,
+                        coordination: 0.0,
+                        best: true,
+                    },
+                })
+            }
+            Query::Not(_) => Err(Self::negation_error()),
+            Query::Optional(query) => Self::build(query, tokenizer, ctx),
+            Query::All => Ok(Self::All(AllMatcher {
+                ids: ctx.documents_manager.ids(),
+                pos: 0,
+                ctx,
+            })),
+        }
+    }
+
+    fn clause(query: TokenizedQuery, ctx: &'a MatchContext<'a>) -> PyResult<Self> {
+        let (slop, in_order) = (query.slop, query.in_order);
+        let gaps = query.tokens.iter().map(|t| t.gap).collect();
+        let field = query.field.clone().map(|name| FieldNorm {
+            weight: ctx.field_weights.get(&name).copied().unwrap_or(1.0),
+            avg_len: ctx.fields.avg_len(&name),
+            name,
+        });
+        match PostingListIntersection::new(query, ctx.index, ctx.hasher, ctx.fuzzy_trie)? {
+            Some(intersection) => Ok(Self::Clause(ClauseMatcher {
+                idfs: Idfs::new(ctx.stats, intersection.tokens()),
+                field,
+                intersection,
+                slop,
+                gaps,
+                in_order,
+                ctx,
+            })),
+            None => Ok(Self::Empty),
+        }
+    }
+
+    fn at_least(query: TokenizedQuery, min: usize, ctx: &'a MatchContext<'a>) -> PyResult<Self> {
+        // every token is matched on its own, so positions of tokens don't matter
+        let (slop, total, field) = (query.slop, query.tokens.len(), query.field);
+        let children = query
+            .tokens
+            .into_iter()
+            .map(|token| {
+                let query = TokenizedQuery {
+                    tokens: vec![Token { gap: 0, ..token }],
+                    slop,
+                    in_order: true,
+                    match_all: false,
+                    field: field.clone(),
+                };
+                Self::clause(query, ctx)
+            })
+            .filter(|m| !matches!(m, Ok(Self::Empty)))
+            .collect::<PyResult<Vec<Self>>>()?;
+
+        if children.len() < min {
+            return Ok(Self::Empty);
+        }
+
+        Ok(Self::AtLeast {
+            heads: vec![None; children.len()],
+            children,
+            min,
+            total,
+            current: None,
+            started: false,
+            coordination: ctx.coordination,
+        })
+    }
+
+    pub fn validate(query: &Query) -> PyResult<()> {
+        // checks query tree without building matchers, uses the same rules as build
+        match query {
+            Query::Clause(_) => Ok(()),
+            Query::And(queries) => {
+                let mut positive = false;
+                for query in queries {
+                    match query {
+                        Query::Not(query) | Query::Optional(query) => Self::validate(query)?,
+                        query => {
+                            positive = true;
+                            Self::validate(query)?
+                        }
+                    }
+                }
+
+                match positive {
+                    true => Ok(()),
+                    false => Err(Self::negation_error()),
+                }
+            }
+            Query::Or(queries) | Query::Any(queries) => queries.iter().try_for_each(Self::validate),
+            Query::Not(_) => Err(Self::negation_error()),
+            Query::Optional(query) => Self::validate(query),
+            Query::All => Ok(()),
+        }
+    }
+
+    fn negation_error() -> PyErr {
+        PyValueError::new_err(
+            "NOT must be combined with positive query using AND, e.g. 'a AND NOT b'",
+        )
+    }
+
+    pub fn next(&mut self, threshold: f64) -> Option<(Ulid, f64)> {
+        // returns next matching document with its score in documents order, threshold
+        // is a score which document has to exceed and is used only by root clause
+        match self {
+            Self::Empty => None,
+            Self::Clause(matcher) => matcher.next(threshold, None),
+            Self::All(matcher) => matcher.next(threshold, None),
+            _ => self.advance(None),
+        }
+    }
+
+    fn seek(&mut self, target: &Ulid) -> Option<(Ulid, f64)> {
+        match self {
+            Self::Empty => None,
+            Self::Clause(matcher) => matcher.next(f64::NEG_INFINITY, Some(target)),
+            Self::All(matcher) => matcher.next(f64::NEG_INFINITY, Some(target)),
+            _ => self.advance(Some(target)),
+        }
+    }
+
+    fn advance(&mut self, target: Option<&Ulid>) -> Option<(Ulid, f64)> {
+        match self {
+            Self::And {
+                required,
+                excluded,
+                optional,
+                heads,
+                excluded_heads,
+                optional_heads,
+                started,
+            } => {
+                if !*started {
+                    *started = true;
+                    for (head, matcher) in heads.iter_mut().zip(required.iter_mut()) {
+                        *head = Self::step(matcher, target);
+                    }
+                } else {
+                    match target {
+                        Some(target) => {
+                            for (head, matcher) in heads.iter_mut().zip(required.iter_mut()) {
+                                if let Some((doc_id, _)) = head
+                                    && *doc_id < *target
+                                {
+                                    *head = matcher.seek(target);
+                                }
+                            }
+                        }
+                        None => heads[0] = required[0].next(f64::NEG_INFINITY),
+                    }
+                }
+
+                loop {
+                    let mut max = None;
+                    for head in heads.iter() {
+                        let (doc_id, _) = (*head)?;
+                        max = max.max(Some(doc_id));
+                    }
+                    let max = max?;
+
+                    let mut aligned = true;
+                    for (head, matcher) in heads.iter_mut().zip(required.iter_mut()) {
+                        if let Some((doc_id, _)) = head
+                            && *doc_id < max
+                        {
+                            *head = matcher.seek(&max);
+                            aligned = false;
+                        }
+                    }
+
+                    if !aligned {
+                        continue;
+                    }
+
+                    let mut is_excluded = false;
+                    for (head, matcher) in excluded_heads.iter_mut().zip(excluded.iter_mut()) {
+                        match head {
+                            Some((doc_id, _)) if *doc_id >= max => (),
+                            _ => *head = matcher.seek(&max),
+                        }
+
+                        if let Some((doc_id, _)) = head
+                            && *doc_id == max
+                        {
+                            is_excluded = true;
+                        }
+                    }
+
+                    if is_excluded {
+                        heads[0] = required[0].next(f64::NEG_INFINITY);
+                        continue;
+                    }
+
+                    // optional children only contribute to the score of matched document
+                    let mut score: f64 = heads.iter().flatten().map(|(_, score)| score).sum();
+                    for (head, matcher) in optional_heads.iter_mut().zip(optional.iter_mut()) {
+                        match head {
+                            Some((doc_id, _)) if *doc_id >= max => (),
+                            _ => *head = matcher.seek(&max),
+                        }
+
+                        if let Some((doc_id, optional_score)) = head
+                            && *doc_id == max
+                        {
+                            score += *optional_score;
+                        }
+                    }
+
+                    return Some((max, score));
+                }
+            }
+            Self::Or {
+                children,
+                heads,
+                total,
+                current,
+                started,
+                coordination,
+                best,
+            } => {
+                for (head, matcher) in heads.iter_mut().zip(children.iter_mut()) {
+                    if !*started {
+                        *head = Self::step(matcher, target);
+                        continue;
+                    }
+
+                    let doc_id = match head {
+                        Some((doc_id, _)) => *doc_id,
+                        None => continue,
+                    };
+
+                    match target {
+                        Some(target) if doc_id < *target => *head = matcher.seek(target),
+                        None if Some(doc_id) == *current => *head = matcher.next(f64::NEG_INFINITY),
+                        _ => (),
+                    }
+                }
+                *started = true;
+
+                let min = heads.iter().flatten().map(|(doc_id, _)| *doc_id).min()?;
+                current.replace(min);
+
+                // score of disjunction is a sum of scores of all matched children
+                let (matched, score) = heads
+                    .iter()
+                    .flatten()
+                    .filter(|(doc_id, _)| *doc_id == min)
+                    .fold((0, 0.0), |(matched, sum), (_, score)| match best {
+                        true => (matched + 1, f64::max(sum, *score)),
+                        false => (matched + 1, sum + score),
+                    });
+                Some((min, score * coord(matched, *total, *coordination)))
+            }
+            Self::AtLeast {
+                children,
+                heads,
+                min,
+                total,
+                current,
+                started,
+                coordination,
+            } => {
+                for (head, matcher) in heads.iter_mut().zip(children.iter_mut()) {
+                    if !*started {
+                        *head = Self::step(matcher, target);
+                        continue;
+                    }
+
+                    let doc_id = match head {
+                        Some((doc_id, _)) => *doc_id,
+                        None => continue,
+                    };
+
+                    match target {
+                        Some(target) if doc_id < *target => *head = matcher.seek(target),
+                        None if Some(doc_id) == *current => *head = matcher.next(f64::NEG_INFINITY),
+                        _ => (),
+                    }
+                }
+                *started = true;
+
+                loop {
+                    if heads.iter().flatten().count() < *min {
+                        return None;
+                    }
+
+                    let doc = heads.iter().flatten().map(|(doc_id, _)| *doc_id).min()?;
+                    let (matched, score) = heads
+                        .iter()
+                        .flatten()
+                        .filter(|(doc_id, _)| *doc_id == doc)
+                        .fold((0, 0.0), |(matched, sum), (_, score)| {
+                            (matched + 1, sum + score)
+                        });
+
+                    if matched >= *min {
+                        current.replace(doc);
+                        return Some((doc, score * coord(matched, *total, *coordination)));
+                    }
+
+                    // document is matched by too few children, all of them move past it
+                    for (head, matcher) in heads.iter_mut().zip(children.iter_mut()) {
+                        if let Some((doc_id, _)) = head
+                            && *doc_id == doc
+                        {
+                            *head = matcher.next(f64::NEG_INFINITY);
+                        }
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn step(matcher: &mut Matcher<'a>, target: Option<&Ulid>) -> Head {
+        match target {
+            Some(target) => matcher.seek(target),
+            None => matcher.next(f64::NEG_INFINITY),
+        }
+    }
+}
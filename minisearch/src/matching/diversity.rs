@@ -0,0 +1,106 @@
+use crate::query::scoring::{CorpusStats, idf};
+use crate::storage::documents::DocumentsManager;
+use hashbrown::HashMap;
+use nohash_hasher::BuildNoHashHasher;
+use ulid::Ulid;
+
+// number of candidates per requested result diversified results are selected from
+pub const DIVERSITY_POOL: u32 = 4;
+
+// document tokens weighted by idf, documents are compared by cosine similarity
+struct TermVector {
+    weights: HashMap<u32, f64, BuildNoHashHasher<u32>>,
+    norm: f64,
+}
+
+impl TermVector {
+    fn new(tokens: &[u32], stats: &CorpusStats) -> Self {
+        let weights = tokens
+            .iter()
+            .map(|token| (*token, idf(stats.docs_num, stats.df(*token))))
+            .collect::<HashMap<u32, f64, BuildNoHashHasher<u32>>>();
+        let norm = weights.values().map(|w| w * w).sum::<f64>().sqrt();
+
+        Self { weights, norm }
+    }
+
+    fn similarity(&self, other: &Self) -> f64 {
+        if self.norm == 0.0 || other.norm == 0.0 {
+            return 0.0;
+        }
+
+        let (small, large) = match self.weights.len() <= other.weights.len() {
+            true => (self, other),
+            false => (other, self),
+        };
+        let dot = small
+            .weights
+            .iter()
+            .filter_map(|(token, w)| Some(w * large.weights.get(token)?))
+            .sum::<f64>();
+        dot / (self.norm * other.norm)
+    }
+}
+
+struct Candidate {
+    doc_id: Ulid,
+    score: f64,
+    relevance: f64, // score normalized to the highest one
+    vector: TermVector,
+    similarity: f64, // highest similarity to already picked results
+}
+
+pub fn mmr(
+    candidates: Vec<(Ulid, f64)>,
+    k: usize,
+    lambda: f64,
+    documents_manager: &DocumentsManager,
+    stats: &CorpusStats,
+) -> Vec<(Ulid, f64)> {
+    // maximal marginal relevance, results are picked greedily by relevance lowered by
+    // similarity to the most similar already picked result, candidates are sorted by
+    // score and keep their original scores
+    let max_score = candidates.first().map_or(0.0, |c| c.1);
+    let mut candidates = candidates
+        .into_iter()
+        .filter_map(|(doc_id, score)| {
+            let doc = documents_manager.get(&doc_id)?;
+            Some(Candidate {
+                doc_id,
+                score,
+                relevance: match max_score > 0.0 {
+                    true => score / max_score,
+                    false => 0.0,
+                },
+                vector: TermVector::new(&doc.tokens, stats),
+                similarity: 0.0,
+            })
+        })
+        .collect::<Vec<Candidate>>();
+
+    let k = match k {
+        0 => candidates.len(),
+        k => k.min(candidates.len()),
+    };
+
+    let mut results = Vec::with_capacity(k);
+    while results.len() < k {
+        // the first of equally good candidates is picked, so the one with higher score
+        let mut best = (0, f64::NEG_INFINITY);
+        for (idx, candidate) in candidates.iter().enumerate() {
+            let value = lambda * candidate.relevance - (1.0 - lambda) * candidate.similarity;
+            if value > best.1 {
+                best = (idx, value);
+            }
+        }
+
+        let picked = candidates.remove(best.0);
+        for candidate in candidates.iter_mut() {
+            let similarity = picked.vector.similarity(&candidate.vector);
+            candidate.similarity = candidate.similarity.max(similarity);
+        }
+        results.push((picked.doc_id, picked.score));
+    }
+
+    results
+}
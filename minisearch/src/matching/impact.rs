@@ -0,0 +1,229 @@
+use crate::analysis::tokenizer::Tokenizer;
+use crate::core::index::{Impact, IndexManager, Posting};
+use crate::core::options::TieBreak;
+use crate::query::parser::Query;
+use crate::query::scoring::{CorpusStats, idf, impact_bound, term_bm25};
+use crate::storage::documents::DocumentsManager;
+use crate::utils::hasher::TokenHasher;
+use hashbrown::HashSet;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use ulid::Ulid;
+
+// impact ordered postings of a single exact query token
+pub struct ImpactList<'a> {
+    token: u32,
+    impacts: &'a [Impact],
+    postings: &'a [Posting],
+}
+
+struct Candidate {
+    score: f64,
+    doc_id: u128,
+    tie_break: TieBreak,
+}
+
+// documents with equal score are ordered by id
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .total_cmp(&other.score)
+            .then_with(|| self.tie_break.cmp(self.doc_id, other.doc_id))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl<'a> ImpactList<'a> {
+    pub fn collect(
+        query: &Query,
+        tokenizer: &mut Tokenizer,
+        hasher: &TokenHasher,
+        index_manager: &'a IndexManager,
+    ) -> Option<Vec<Self>> {
+        // only single exact terms and disjunctions of them can be evaluated with impact
+        // ordered postings, None is returned for other queries or outdated postings
+        let clauses = match query {
+            Query::Clause(clause) => vec![clause],
+            Query::Or(queries) => queries
+                .iter()
+                .map(|query| match query {
+                    Query::Clause(clause) => Some(clause),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?,
+            _ => return None,
+        };
+
+        let mut lists = Vec::with_capacity(clauses.len());
+        for clause in clauses {
+            // impacts are quantized with document length, but field scoped clause is
+            // normalized by length of its field
+            if clause.field.is_some() {
+                return None;
+            }
+
+            let mut query = tokenizer.tokenize_query(clause);
+            let token = match query.tokens.len() {
+                0 if query.match_all => return None,
+                0 => continue,
+                1 => query.tokens.remove(0),
+                _ => return None,
+            };
+
+            if token.fuzz != 0 || token.auto_fuzz || !token.synonyms.is_empty() {
+                return None;
+            }
+
+            let token = match hasher.hash(&token.text) {
+                Some(token) => token,
+                None => continue,
+            };
+
+            let postings = match index_manager.get(&token) {
+                Some(postings) => postings,
+                None => continue,
+            };
+
+            lists.push(Self {
+                token,
+                impacts: index_manager.impact_ordered(token)?,
+                postings,
+            });
+        }
+
+        Some(lists)
+    }
+
+    fn score(&self, doc_id: u128, stats: &CorpusStats, doc_length: u32, avg: f64) -> f64 {
+        match self.postings.binary_search_by(|p| p.doc_id.cmp(&doc_id)) {
+            Ok(idx) => term_bm25(
+                self.postings[idx].tf as u64,
+                stats.docs_num,
+                stats.df(self.token),
+                doc_length,
+                avg,
+                0,
+            ),
+            Err(_) => 0.0,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn top_k(
+    lists: &[ImpactList],
+    k: usize,
+    min_score: f64,
+    documents_manager: &DocumentsManager,
+    stats: &CorpusStats,
+    avg_doc_len: f64,
+    impact_avg_doc_len: f64,
+    tie_break: TieBreak,
+) -> Vec<(Ulid, f64)> {
+    // threshold algorithm, lists are read in descending impact order and every newly
+    // seen document is scored with all lists, reading stops once upper bound of score
+    // of any unseen document can't exceed the lowest score in top k or reach min score
+    let idfs = lists
+        .iter()
+        .map(|list| idf(stats.docs_num, stats.df(list.token)))
+        .collect::<Vec<f64>>();
+    // tf component grows at most proportionally to average document length
+    let slack = (avg_doc_len / impact_avg_doc_len).max(1.0);
+
+    let mut positions = vec![0; lists.len()];
+    let mut seen = HashSet::new();
+    let mut results: BinaryHeap<Reverse<Candidate>> = BinaryHeap::with_capacity(k);
+
+    loop {
+        let bound: f64 = lists
+            .iter()
+            .zip(positions.iter())
+            .zip(idfs.iter())
+            .filter_map(|((list, pos), idf)| {
+                Some(impact_bound(list.impacts.get(*pos)?.quantized) * idf * slack)
+            })
+            .sum();
+
+        let exhausted = lists
+            .iter()
+            .zip(positions.iter())
+            .all(|(list, pos)| *pos >= list.impacts.len());
+        if exhausted {
+            break;
+        }
+
+        // unseen document with score equal to the bound could still win the tie
+        if results.len() == k
+            && let Some(lowest) = results.peek()
+            && lowest.0.score > bound
+        {
+            break;
+        }
+        if bound < min_score {
+            break;
+        }
+
+        for i in 0..lists.len() {
+            let impact = match lists[i].impacts.get(positions[i]) {
+                Some(impact) => *impact,
+                None => continue,
+            };
+            positions[i] += 1;
+
+            if !seen.insert(impact.doc_id) {
+                continue;
+            }
+
+            let doc_id = Ulid(impact.doc_id);
+            if documents_manager.deleted_docs_buffer.contains_key(&doc_id) {
+                continue;
+            }
+
+            let doc_length = match documents_manager.get(&doc_id) {
+                Some(doc) => doc.tokens.len() as u32,
+                None => continue,
+            };
+
+            let candidate = Candidate {
+                score: lists
+                    .iter()
+                    .map(|list| list.score(impact.doc_id, stats, doc_length, avg_doc_len))
+                    .sum(),
+                doc_id: impact.doc_id,
+                tie_break,
+            };
+
+            if candidate.score < min_score {
+                continue;
+            }
+            if results.len() < k {
+                results.push(Reverse(candidate));
+            } else if let Some(lowest) = results.peek()
+                && lowest.0 < candidate
+            {
+                let _ = results.pop();
+                results.push(Reverse(candidate));
+            }
+        }
+    }
+
+    let mut results = results.into_vec();
+    results.sort_by(|x, y| y.0.cmp(&x.0));
+    results
+        .into_iter()
+        .map(|r| (Ulid(r.0.doc_id), r.0.score))
+        .collect()
+}
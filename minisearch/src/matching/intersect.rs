@@ -1,9 +1,7 @@
 use crate::analysis::tokenizer::TokenizedQuery;
-use crate::core::index::Posting;
+use crate::core::index::PostingsView;
 use crate::utils::hasher::TokenHasher;
-use crate::utils::trie::Trie;
-use hashbrown::HashMap;
-use nohash_hasher::BuildNoHashHasher;
+use crate::utils::trie::{Trie, TrieError};
 use std::cmp::{Ordering, Reverse, max};
 use std::collections::BinaryHeap;
 use ulid::Ulid;
@@ -16,11 +14,12 @@ pub struct TokenDocPointer {
     pub distance: u16,
     pub postings_len: u64,
     pub tf: u64,
+    pub impact: u8,
 }
 
 pub struct PostingListIntersection<'a> {
     query: TokenizedQuery,
-    index: &'a HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+    index: PostingsView<'a>,
     docs: Vec<Vec<TokenDocPointer>>,
     pointers: Vec<BinaryHeap<Reverse<TokenDocPointer>>>,
 }
@@ -48,17 +47,34 @@ impl Eq for TokenDocPointer {}
 impl<'a> PostingListIntersection<'a> {
     pub fn new(
         query: TokenizedQuery,
-        index: &'a HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+        index: PostingsView<'a>,
         hasher: &TokenHasher,
         fuzzy_trie: &Trie,
-    ) -> Option<Self> {
+    ) -> Result<Option<Self>, TrieError> {
         let docs: Vec<Vec<TokenDocPointer>> = Vec::with_capacity(query.tokens.len());
         let mut pointers: Vec<BinaryHeap<Reverse<TokenDocPointer>>> =
             vec![BinaryHeap::new(); query.tokens.len()];
 
         for (i, query_token) in query.tokens.iter().enumerate() {
-            for (distance, token) in fuzzy_trie.search(query_token.fuzz, &query_token.text) {
-                if query_token.text != token
+            if query_token.auto_fuzz && !fuzzy_trie.is_enabled() {
+                return Err(TrieError::Disabled(query_token.text.clone()));
+            }
+
+            let mut tokens = fuzzy_trie.search(query_token.fuzz, &query_token.text)?;
+            // exact token may be evicted from fuzzy trie, but it's still present in hasher
+            if !tokens.iter().any(|(distance, _)| *distance == 0) {
+                tokens.push((0, query_token.text.clone()));
+            }
+            // synonyms are matched exactly and scored as the token itself
+            for synonym in query_token.synonyms.iter() {
+                if !tokens.iter().any(|(_, token)| token == synonym) {
+                    tokens.push((0, synonym.clone()));
+                }
+            }
+
+            for (distance, token) in tokens {
+                // only exact token and synonyms have distance 0
+                if distance != 0
                     && (token.len() <= query_token.fuzz as usize
                         || query_token.text.len() <= query_token.fuzz as usize)
                 {
@@ -80,27 +96,36 @@ impl<'a> PostingListIntersection<'a> {
                     doc_idx: 0,
                     token: token,
                     distance: distance,
-                    tf: postings[0].positions.len() as u64,
+                    tf: postings[0].tf as u64,
+                    impact: postings[0].impact,
                     postings_len: postings.len() as u64,
                 };
                 pointers[i].push(Reverse(pointer));
             }
 
             if pointers[i].is_empty() {
-                return None;
+                return Ok(None);
             }
         }
 
-        Some(Self {
+        Ok(Some(Self {
             query: query,
             index: index,
             docs: docs,
             pointers: pointers,
-        })
+        }))
+    }
+
+    pub fn tokens(&self) -> impl Iterator<Item = u32> + '_ {
+        // tokens of all query tokens expansions which still have postings to read
+        self.pointers
+            .iter()
+            .flat_map(|pointer| pointer.iter().map(|p| p.0.token))
+            .chain(self.docs.iter().flatten().map(|p| p.token))
     }
 
     fn next_docs(
-        index: &HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+        index: PostingsView,
         pointer: &mut BinaryHeap<Reverse<TokenDocPointer>>,
     ) -> Vec<TokenDocPointer> {
         let mut doc_ids = Vec::<TokenDocPointer>::new();
@@ -120,7 +145,8 @@ impl<'a> PostingListIntersection<'a> {
                     doc_idx: p.0.doc_idx + 1,
                     token: p.0.token.clone(),
                     distance: p.0.distance,
-                    tf: postings[p.0.doc_idx as usize + 1].positions.len() as u64,
+                    tf: postings[p.0.doc_idx as usize + 1].tf as u64,
+                    impact: postings[p.0.doc_idx as usize + 1].impact,
                     postings_len: postings.len() as u64,
                 }))
             }
@@ -132,7 +158,7 @@ impl<'a> PostingListIntersection<'a> {
     }
 
     fn geq_docs(
-        index: &HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+        index: PostingsView,
         pointer: &mut BinaryHeap<Reverse<TokenDocPointer>>,
         target_doc: &Ulid,
     ) -> Vec<TokenDocPointer> {
@@ -157,7 +183,8 @@ impl<'a> PostingListIntersection<'a> {
                     doc_idx: new_idx as u32,
                     token: doc.0.token.clone(),
                     distance: doc.0.distance,
-                    tf: postings[new_idx].positions.len() as u64,
+                    tf: postings[new_idx].tf as u64,
+                    impact: postings[new_idx].impact,
                     postings_len: postings.len() as u64,
                 }))
             }
@@ -166,11 +193,45 @@ impl<'a> PostingListIntersection<'a> {
         return Self::next_docs(index, pointer);
     }
 
+    pub fn block_bound(&self, bound: impl Fn(&TokenDocPointer, u8) -> f64) -> Option<(f64, Ulid)> {
+        // upper bound of score of current and following documents up to the end of the
+        // nearest block, every token can match them only within its current block
+        let (mut score, mut end) = (0.0, u128::MAX);
+        for (docs, pointers) in self.docs.iter().zip(self.pointers.iter()) {
+            let mut max: f64 = 0.0;
+            for pointer in docs.iter().chain(pointers.iter().map(|p| &p.0)) {
+                let (block_max, block_end) = self.index.block(&pointer.token, pointer.doc_idx)?;
+                max = max.max(bound(pointer, block_max));
+                end = end.min(block_end);
+            }
+            score += max;
+        }
+
+        Some((score, Ulid(end)))
+    }
+
+    pub fn current(&self) -> &Vec<Vec<TokenDocPointer>> {
+        // pointers of every query token to the last returned document
+        &self.docs
+    }
+
     pub fn next(&mut self) -> Option<&Vec<Vec<TokenDocPointer>>> {
+        self.advance(None)
+    }
+
+    pub fn seek(&mut self, target_doc: &Ulid) -> Option<&Vec<Vec<TokenDocPointer>>> {
+        // moves to the first matching document greater or equal to target document
+        self.advance(Some(target_doc))
+    }
+
+    fn advance(&mut self, target: Option<&Ulid>) -> Option<&Vec<Vec<TokenDocPointer>>> {
         let mut same = true;
 
         for i in 0..self.query.tokens.len() {
-            let docs = Self::next_docs(self.index, &mut self.pointers[i]);
+            let docs = match target {
+                Some(target) => Self::geq_docs(self.index, &mut self.pointers[i], target),
+                None => Self::next_docs(self.index, &mut self.pointers[i]),
+            };
 
             if docs.is_empty() {
                 return None;
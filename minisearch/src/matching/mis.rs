@@ -1,16 +1,23 @@
-use crate::core::index::Posting;
+use crate::core::index::PostingsView;
 use crate::matching::intersect::TokenDocPointer;
 use core::cmp::{Ordering, Reverse};
-use hashbrown::HashMap;
-use nohash_hasher::BuildNoHashHasher;
+use std::borrow::Cow;
 use std::collections::BinaryHeap;
-use std::slice::Iter;
 
 struct TokenPositions<'a> {
     token: u32,
     distance: u16,
     tf: u64,
-    positions: Iter<'a, u32>,
+    positions: Cow<'a, [u32]>, // positions read from index file are owned
+    next: usize,
+}
+
+impl<'a> TokenPositions<'a> {
+    fn next(&mut self) -> Option<u32> {
+        let position = self.positions.get(self.next).copied();
+        self.next += 1;
+        position
+    }
 }
 
 struct TokenMeta {
@@ -58,6 +65,92 @@ pub struct MisResult {
     pub indexes: Vec<MisTokenIdx>,
 }
 
+pub fn term_intervals(pointers: &[TokenDocPointer]) -> Vec<MisResult> {
+    // intervals of single token group, every token forms interval without slop and
+    // term frequency is known from its posting
+    pointers
+        .iter()
+        .map(|pointer| MisResult {
+            slop: 0,
+            indexes: vec![MisTokenIdx {
+                token: pointer.token,
+                token_idx: 0,
+                tf: pointer.tf,
+                distance: pointer.distance,
+            }],
+        })
+        .collect()
+}
+
+pub fn exact_phrase(
+    index: PostingsView,
+    pointers: &Vec<Vec<TokenDocPointer>>,
+    gaps: &[u32],
+) -> Vec<MisResult> {
+    // intervals of phrase with slop 0, every next group has to be exactly at position
+    // of the previous one plus its gap, so positions of groups are only merged forward
+    let mut groups = Vec::with_capacity(pointers.len());
+    for group in pointers {
+        let mut positions = vec![];
+        for (idx, pointer) in group.iter().enumerate() {
+            if let Some(postings) = index.get(&pointer.token) {
+                let posting = &postings[pointer.doc_idx as usize];
+                positions.extend(
+                    index
+                        .positions(pointer.token, posting)
+                        .iter()
+                        .map(|p| (*p, idx)),
+                );
+            }
+        }
+
+        if positions.is_empty() {
+            return vec![];
+        }
+        positions.sort_unstable();
+        groups.push(positions);
+    }
+
+    let mut offsets = vec![0; groups.len()];
+    for idx in 1..groups.len() {
+        offsets[idx] = offsets[idx - 1] + 1 + gaps.get(idx).copied().unwrap_or(0);
+    }
+
+    let mut results = vec![];
+    let mut cursors = vec![0; groups.len()];
+    'start: for (start, idx) in groups[0].iter() {
+        let mut window = vec![(*start, *idx)];
+        for group in 1..groups.len() {
+            let target = start + offsets[group];
+            let positions = &groups[group];
+            while cursors[group] < positions.len() && positions[cursors[group]].0 < target {
+                cursors[group] += 1;
+            }
+
+            match positions.get(cursors[group]) {
+                Some((position, idx)) if *position == target => window.push((*position, *idx)),
+                _ => continue 'start,
+            }
+        }
+
+        results.push(MisResult {
+            slop: 0,
+            indexes: window
+                .into_iter()
+                .zip(pointers.iter())
+                .map(|((position, idx), group)| MisTokenIdx {
+                    token: group[idx].token,
+                    token_idx: position,
+                    tf: group[idx].tf,
+                    distance: group[idx].distance,
+                })
+                .collect(),
+        });
+    }
+
+    results
+}
+
 struct TokenGroupIterator<'a> {
     heap: BinaryHeap<Reverse<TokenPosition>>,
     tokens: Vec<TokenPositions<'a>>,
@@ -65,9 +158,11 @@ struct TokenGroupIterator<'a> {
 
 pub struct MinimalIntervalSemanticMatch<'a> {
     min_slop: i32,
+    gaps: Vec<u32>, // expected placeholder gaps between consecutive token groups
     iterators: Vec<TokenGroupIterator<'a>>,
     window: Vec<u32>, // window of token indexes
     slops: Vec<i32>,
+    in_order: bool, // if false token groups may match in any order
     end: bool,
 }
 
@@ -79,21 +174,19 @@ impl<'a> TokenGroupIterator<'a> {
         }
     }
 
-    fn add_token_positions(&mut self, mut positions: Iter<'a, u32>, token: u32, distance: u16) {
-        match positions.next() {
-            Some(val) => {
-                self.heap.push(Reverse(TokenPosition {
-                    position: *val,
-                    idx: self.tokens.len(),
-                }));
-                self.tokens.push(TokenPositions {
-                    token: token,
-                    distance: distance,
-                    tf: positions.len() as u64 + 1,
-                    positions: positions,
-                });
-            }
-            _ => (),
+    fn add_token_positions(&mut self, positions: Cow<'a, [u32]>, token: u32, distance: u16) {
+        if let Some(val) = positions.first() {
+            self.heap.push(Reverse(TokenPosition {
+                position: *val,
+                idx: self.tokens.len(),
+            }));
+            self.tokens.push(TokenPositions {
+                token,
+                distance,
+                tf: positions.len() as u64,
+                positions,
+                next: 1,
+            });
         }
     }
 
@@ -102,10 +195,10 @@ impl<'a> TokenGroupIterator<'a> {
             && pos.0.position <= target
         {
             let pos = self.heap.pop().unwrap();
-            while let Some(val) = self.tokens[pos.0.idx].positions.next() {
-                if *val > target {
+            while let Some(val) = self.tokens[pos.0.idx].next() {
+                if val > target {
                     self.heap.push(Reverse(TokenPosition {
-                        position: *val,
+                        position: val,
                         idx: pos.0.idx,
                     }));
                     break;
@@ -118,9 +211,9 @@ impl<'a> TokenGroupIterator<'a> {
 
     fn next(&mut self) -> Option<u32> {
         if let Some(pos) = self.heap.pop() {
-            if let Some(val) = self.tokens[pos.0.idx].positions.next() {
+            if let Some(val) = self.tokens[pos.0.idx].next() {
                 self.heap.push(Reverse(TokenPosition {
-                    position: *val,
+                    position: val,
                     idx: pos.0.idx,
                 }));
             }
@@ -153,16 +246,20 @@ impl<'a> TokenGroupIterator<'a> {
 
 impl<'a> MinimalIntervalSemanticMatch<'a> {
     pub fn new(
-        index: &'a HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
+        index: PostingsView<'a>,
         pointers: &Vec<Vec<TokenDocPointer>>,
         min_slop: i32,
+        gaps: Vec<u32>,
+        in_order: bool,
     ) -> Self {
         let mut iterators: Vec<TokenGroupIterator> = Vec::with_capacity(pointers.len());
         for group in pointers {
             let mut iterator = TokenGroupIterator::new();
             for pointer in group {
                 let positions = match index.get(&pointer.token) {
-                    Some(postings) => postings[pointer.doc_idx as usize].positions.iter(),
+                    Some(postings) => {
+                        index.positions(pointer.token, &postings[pointer.doc_idx as usize])
+                    }
                     None => continue,
                 };
 
@@ -187,18 +284,89 @@ impl<'a> MinimalIntervalSemanticMatch<'a> {
 
         Self {
             min_slop: min_slop,
+            gaps,
             iterators: iterators,
             window: window,
             slops: slops,
+            in_order,
             end: end,
         }
     }
+
+    fn next_unordered(&mut self) -> Option<MisResult> {
+        // window is formed by current positions of all groups, its slop is number of
+        // positions between them which aren't taken by matched tokens, window is then
+        // moved by advancing the group with the lowest position
+        while !self.end {
+            for (idx, iterator) in self.iterators.iter().enumerate() {
+                match iterator.peek() {
+                    Some(pos) => self.window[idx] = pos,
+                    None => return None,
+                }
+            }
+
+            let (mut first, mut last) = (0, 0);
+            for idx in 1..self.window.len() {
+                if self.window[idx] < self.window[first] {
+                    first = idx;
+                }
+                if self.window[idx] > self.window[last] {
+                    last = idx;
+                }
+            }
+
+            let mut positions = self.window.clone();
+            positions.sort_unstable();
+            positions.dedup();
+            // groups can share token, but every group has to match different position
+            let slop = match positions.len() == self.window.len() {
+                true => {
+                    (self.window[last] - self.window[first]) as i32 + 1 - self.window.len() as i32
+                }
+                false => i32::MAX,
+            };
+
+            let mut result = None;
+            if slop <= self.min_slop {
+                let indexes = self
+                    .iterators
+                    .iter()
+                    .zip(self.window.iter())
+                    .filter_map(|(iterator, token_idx)| {
+                        let meta = iterator.last_meta()?;
+                        Some(MisTokenIdx {
+                            token: meta.token,
+                            token_idx: *token_idx,
+                            tf: meta.tf,
+                            distance: meta.distance,
+                        })
+                    })
+                    .collect::<Vec<MisTokenIdx>>();
+
+                let _ = result.insert(MisResult { slop, indexes });
+            }
+
+            if self.iterators[first].next().is_none() {
+                self.end = true;
+            }
+
+            if result.is_some() {
+                return result;
+            }
+        }
+
+        None
+    }
 }
 
 impl<'a> Iterator for MinimalIntervalSemanticMatch<'a> {
     type Item = MisResult;
 
     fn next(&mut self) -> Option<MisResult> {
+        if !self.in_order {
+            return self.next_unordered();
+        }
+
         let mut idx = 1;
         while !self.end {
             while idx <= self.iterators.len() - 1 {
@@ -208,8 +376,9 @@ impl<'a> Iterator for MinimalIntervalSemanticMatch<'a> {
                 };
 
                 self.window[idx] = val;
+                let gap = self.gaps.get(idx).copied().unwrap_or(0) as i32;
                 let slop = self.slops[idx - 1]
-                    + (self.window[idx - 1] as i32 - (self.window[idx] as i32 - 1)).abs();
+                    + (self.window[idx - 1] as i32 - (self.window[idx] as i32 - 1 - gap)).abs();
 
                 if slop > self.min_slop {
                     break;
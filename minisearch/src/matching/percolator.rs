@@ -0,0 +1,70 @@
+use crate::analysis::tokenizer::Tokenizer;
+use crate::config::Config;
+use crate::core::index::Posting;
+use crate::matching::boolean::{MatchContext, Matcher};
+use crate::query::parser::Query;
+use hashbrown::HashMap;
+use nohash_hasher::BuildNoHashHasher;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+#[derive(Default)]
+pub struct Percolator {
+    queries: HashMap<String, Query>, // registered query name -> parsed query
+    pub callback: Option<Py<PyAny>>,
+}
+
+impl Percolator {
+    pub fn new() -> Self {
+        Self {
+            queries: HashMap::new(),
+            callback: None,
+        }
+    }
+
+    pub fn register(&mut self, name: String, query: String, config: &Config) -> PyResult<()> {
+        let query = Query::parse(&query, 0, config)?;
+        if matches!(query, Query::All) {
+            return Err(PyValueError::new_err(
+                "Match-all query can't be registered, it would match every document",
+            ));
+        }
+        Matcher::validate(&query)?;
+        self.queries.insert(name, query);
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.queries.remove(name).is_some()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.callback.is_some() && !self.queries.is_empty()
+    }
+
+    pub fn document_index(
+        doc_id: u128,
+        tokens: Vec<(u32, Vec<u32>)>,
+    ) -> HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>> {
+        // registered queries are evaluated against index built only from added document
+        tokens
+            .into_iter()
+            .map(|(token, positions)| (token, vec![Posting::new(doc_id, positions)]))
+            .collect()
+    }
+
+    pub fn matches(&self, tokenizer: &mut Tokenizer, ctx: &MatchContext) -> PyResult<Vec<String>> {
+        let mut matched = vec![];
+        for (name, query) in self.queries.iter() {
+            if Matcher::build(query, tokenizer, ctx)?
+                .next(f64::NEG_INFINITY)
+                .is_some()
+            {
+                matched.push(name.clone());
+            }
+        }
+
+        matched.sort();
+        Ok(matched)
+    }
+}
@@ -0,0 +1,52 @@
+use pyo3::prelude::*;
+use std::cell::RefCell;
+use ulid::Ulid;
+
+// decides whether document can be matched, e.g. based on permissions of user
+// who is searching, it's consulted only for documents which passed cheaper
+// filters and score upper bound check, and at most once per document and search
+pub trait VisibilityHook {
+    fn visible(&self, doc_id: &Ulid) -> bool;
+}
+
+// python callable receiving document id and returning whether it can be matched,
+// first raised exception hides all remaining documents and is returned after search
+pub struct PyVisibility {
+    callback: Py<PyAny>,
+    error: RefCell<Option<PyErr>>,
+}
+
+impl PyVisibility {
+    pub fn new(callback: Py<PyAny>) -> Self {
+        Self {
+            callback,
+            error: RefCell::new(None),
+        }
+    }
+
+    pub fn take_error(&self) -> Option<PyErr> {
+        self.error.borrow_mut().take()
+    }
+}
+
+impl VisibilityHook for PyVisibility {
+    fn visible(&self, doc_id: &Ulid) -> bool {
+        if self.error.borrow().is_some() {
+            return false;
+        }
+
+        let visible = Python::with_gil(|py| {
+            self.callback
+                .call1(py, (doc_id.to_string(),))?
+                .is_truthy(py)
+        });
+
+        match visible {
+            Ok(visible) => visible,
+            Err(e) => {
+                *self.error.borrow_mut() = Some(e);
+                false
+            }
+        }
+    }
+}
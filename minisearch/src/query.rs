@@ -1,2 +1,4 @@
+pub mod builder;
+pub mod expression;
 pub mod parser;
 pub mod scoring;
@@ -0,0 +1,189 @@
+use crate::query::parser::{Clause, Query, Term};
+use crate::utils::automaton::MAX_DISTANCE;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use unicode_segmentation::UnicodeSegmentation;
+
+// builds the same query tree as parser, so query doesn't have to be escaped
+#[pyclass(name = "QueryBuilder", frozen)]
+#[derive(Clone, Debug)]
+pub struct QueryBuilder {
+    pub query: Query,
+}
+
+#[derive(FromPyObject, Clone)]
+pub enum QueryInput {
+    Text(String),
+    Built(QueryBuilder),
+}
+
+// element of span, single word or words matched interchangeably at its position
+#[derive(FromPyObject)]
+pub enum SpanTerm {
+    Word(String),
+    Alternatives(Vec<String>),
+}
+
+#[pymethods]
+impl QueryBuilder {
+    #[staticmethod]
+    #[pyo3(signature = (text, fuzziness=0, field=None))]
+    fn term(text: &str, fuzziness: u8, field: Option<String>) -> PyResult<Self> {
+        Self::phrase(text, 0, fuzziness, field)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (text, slop=0, fuzziness=0, field=None))]
+    fn phrase(text: &str, slop: u8, fuzziness: u8, field: Option<String>) -> PyResult<Self> {
+        // text is split into words in the same way as documents
+        if fuzziness > MAX_DISTANCE {
+            return Err(PyValueError::new_err(format!(
+                "Fuzziness must be less or equal to {}, but it is {}.",
+                MAX_DISTANCE, fuzziness
+            )));
+        }
+
+        let terms = text
+            .unicode_words()
+            .map(|word| Term {
+                text: word.to_lowercase(),
+                fuzz: fuzziness,
+                auto_fuzz: false,
+                literal: false,
+                alternatives: vec![],
+            })
+            .collect::<Vec<Term>>();
+
+        if terms.is_empty() {
+            return Err(PyValueError::new_err(format!(
+                "Text '{}' doesn't contain any words",
+                text
+            )));
+        }
+
+        Ok(Self {
+            query: Query::Clause(Clause {
+                terms,
+                slop,
+                field,
+                in_order: true,
+            }),
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (terms, slop=0, in_order=true, field=None))]
+    fn span_near(
+        terms: Vec<SpanTerm>,
+        slop: u8,
+        in_order: bool,
+        field: Option<String>,
+    ) -> PyResult<Self> {
+        // terms have to be found within slop, unordered span is matched in any order
+        let terms = terms
+            .into_iter()
+            .map(|term| {
+                let words = match term {
+                    SpanTerm::Word(word) => vec![word],
+                    SpanTerm::Alternatives(words) => words,
+                };
+                let mut words = words
+                    .iter()
+                    .map(|word| Self::span_word(word))
+                    .collect::<PyResult<Vec<String>>>()?;
+
+                if words.is_empty() {
+                    return Err(PyValueError::new_err(
+                        "Span term must contain at least one alternative",
+                    ));
+                }
+
+                Ok(Term {
+                    text: words.remove(0),
+                    fuzz: 0,
+                    auto_fuzz: false,
+                    literal: false,
+                    alternatives: words,
+                })
+            })
+            .collect::<PyResult<Vec<Term>>>()?;
+
+        if terms.is_empty() {
+            return Err(PyValueError::new_err("At least one span term is required"));
+        }
+
+        Ok(Self {
+            query: Query::Clause(Clause {
+                terms,
+                slop,
+                field,
+                in_order,
+            }),
+        })
+    }
+
+    #[staticmethod]
+    fn all() -> Self {
+        // matches every document, can be combined with exclude to express pure negation
+        Self { query: Query::All }
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (*queries))]
+    fn all_of(queries: Vec<QueryBuilder>) -> PyResult<Self> {
+        Self::combine(queries, Query::And)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (*queries))]
+    fn any_of(queries: Vec<QueryBuilder>) -> PyResult<Self> {
+        Self::combine(queries, Query::Or)
+    }
+
+    fn exclude(&self, query: &QueryBuilder) -> Self {
+        self.extend(Query::Not(Box::new(query.query.clone())))
+    }
+
+    fn optional(&self, query: &QueryBuilder) -> Self {
+        // optional query doesn't filter documents, it only contributes to the score
+        self.extend(Query::Optional(Box::new(query.query.clone())))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("QueryBuilder({:?})", self.query)
+    }
+}
+
+impl QueryBuilder {
+    fn span_word(text: &str) -> PyResult<String> {
+        let mut words = text.unicode_words();
+        match (words.next(), words.next()) {
+            (Some(word), None) => Ok(word.to_lowercase()),
+            _ => Err(PyValueError::new_err(format!(
+                "Span term '{}' must be a single word",
+                text
+            ))),
+        }
+    }
+
+    fn combine(mut queries: Vec<QueryBuilder>, op: fn(Vec<Query>) -> Query) -> PyResult<Self> {
+        match queries.len() {
+            0 => Err(PyValueError::new_err("At least one query is required")),
+            1 => Ok(queries.remove(0)),
+            _ => Ok(Self {
+                query: op(queries.into_iter().map(|q| q.query).collect()),
+            }),
+        }
+    }
+
+    fn extend(&self, query: Query) -> Self {
+        let queries = match &self.query {
+            Query::And(queries) => queries.iter().cloned().chain([query]).collect(),
+            positive => vec![positive.clone(), query],
+        };
+
+        Self {
+            query: Query::And(queries),
+        }
+    }
+}
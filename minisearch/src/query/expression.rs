@@ -0,0 +1,196 @@
+use chumsky::prelude::*;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::storage::attributes::Attributes;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Func {
+    Log,
+    Log10,
+    Sqrt,
+    Exp,
+    Abs,
+    Min,
+    Max,
+    Pow,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<(Self, usize)> {
+        // returns function with its number of arguments
+        match name {
+            "log" | "ln" => Some((Self::Log, 1)),
+            "log10" => Some((Self::Log10, 1)),
+            "sqrt" => Some((Self::Sqrt, 1)),
+            "exp" => Some((Self::Exp, 1)),
+            "abs" => Some((Self::Abs, 1)),
+            "min" => Some((Self::Min, 2)),
+            "max" => Some((Self::Max, 2)),
+            "pow" => Some((Self::Pow, 2)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Num(f64),
+    Score,
+    Attr(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+impl Expr {
+    pub fn compile(expression: &str) -> Result<Expr, PyErr> {
+        let result = Self::parser().parse(expression);
+        if result.has_errors() {
+            let errors = result
+                .errors()
+                .map(|e| format!("{:?}", e))
+                .collect::<Vec<String>>()
+                .join("\n");
+
+            return Err(PyValueError::new_err(format!(
+                "Following score script is invalid: '{}'\n, {}",
+                expression, errors
+            )));
+        }
+
+        match result.into_output() {
+            Some(res) => Ok(res),
+            None => Err(PyValueError::new_err(
+                "Failed to parse score script, the output is empty",
+            )),
+        }
+    }
+
+    pub fn eval(&self, score: f64, attributes: Option<&Attributes>) -> f64 {
+        match self {
+            Self::Num(v) => *v,
+            Self::Score => score,
+            Self::Attr(name) => match attributes.and_then(|a| a.get(name)) {
+                Some(v) => *v,
+                None => 0.0, // missing attributes are treated as 0
+            },
+            Self::Neg(e) => -e.eval(score, attributes),
+            Self::Binary(l, op, r) => {
+                let (l, r) = (l.eval(score, attributes), r.eval(score, attributes));
+                match op {
+                    Op::Add => l + r,
+                    Op::Sub => l - r,
+                    Op::Mul => l * r,
+                    Op::Div => l / r,
+                }
+            }
+            Self::Call(func, args) => {
+                let a = args[0].eval(score, attributes);
+                match func {
+                    Func::Log => a.ln(),
+                    Func::Log10 => a.log10(),
+                    Func::Sqrt => a.sqrt(),
+                    Func::Exp => a.exp(),
+                    Func::Abs => a.abs(),
+                    Func::Min => a.min(args[1].eval(score, attributes)),
+                    Func::Max => a.max(args[1].eval(score, attributes)),
+                    Func::Pow => a.powf(args[1].eval(score, attributes)),
+                }
+            }
+        }
+    }
+
+    fn parser<'a>() -> impl Parser<'a, &'a str, Expr, extra::Err<Rich<'a, char>>> {
+        recursive(|expr| {
+            // NUMBER = DIGITS then optional fraction
+            let number = text::int(10)
+                .then(just('.').then(text::digits(10)).or_not())
+                .to_slice()
+                .map(|s: &str| Expr::Num(s.parse().unwrap()));
+
+            // ATTR = "attr." + IDENT
+            let attr = text::ascii::keyword("attr")
+                .ignore_then(just('.'))
+                .ignore_then(text::ascii::ident())
+                .map(|name: &str| Expr::Attr(name.to_string()));
+
+            // CALL = IDENT + "(" + arguments seperated by comma + ")"
+            let call = text::ascii::ident()
+                .then(
+                    expr.clone()
+                        .separated_by(just(','))
+                        .at_least(1)
+                        .collect::<Vec<Expr>>()
+                        .delimited_by(just('('), just(')')),
+                )
+                .validate(|(name, args): (&str, Vec<Expr>), e, emitter| {
+                    match Func::from_name(name) {
+                        Some((func, argc)) if argc == args.len() => Expr::Call(func, args),
+                        Some((_, argc)) => {
+                            emitter.emit(Rich::custom(
+                                e.span(),
+                                format!(
+                                    "Function '{}' expects {} arguments, but got {}.",
+                                    name,
+                                    argc,
+                                    args.len()
+                                ),
+                            ));
+                            Expr::Num(0.0)
+                        }
+                        None => {
+                            emitter.emit(Rich::custom(
+                                e.span(),
+                                format!("Unknown function '{}'.", name),
+                            ));
+                            Expr::Num(0.0)
+                        }
+                    }
+                });
+
+            let score = text::ascii::keyword("score").to(Expr::Score);
+
+            let atom = number
+                .or(attr)
+                .or(score)
+                .or(call)
+                .or(expr.delimited_by(just('('), just(')')))
+                .padded();
+
+            let unary = just('-')
+                .padded()
+                .repeated()
+                .foldr(atom, |_, e| Expr::Neg(Box::new(e)));
+
+            let product = unary.clone().foldl(
+                just('*')
+                    .to(Op::Mul)
+                    .or(just('/').to(Op::Div))
+                    .padded()
+                    .then(unary)
+                    .repeated(),
+                |l, (op, r)| Expr::Binary(Box::new(l), op, Box::new(r)),
+            );
+
+            product.clone().foldl(
+                just('+')
+                    .to(Op::Add)
+                    .or(just('-').to(Op::Sub))
+                    .padded()
+                    .then(product)
+                    .repeated(),
+                |l, (op, r)| Expr::Binary(Box::new(l), op, Box::new(r)),
+            )
+        })
+        .then_ignore(end())
+    }
+}
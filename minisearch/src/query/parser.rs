@@ -1,29 +1,65 @@
+use crate::config::Config;
 use chumsky::prelude::*;
 use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
 use std::str::FromStr;
 
+// clause with several phrase synonyms is matched as at most this many phrases
+const MAX_SYNONYM_VARIANTS: usize = 16;
+
 enum Fuzz {
     Strict(u8),
     Auto,
 }
 
+// element of terms sequence, field scoped terms and phrases form separate clauses,
+// "+" and "-" prefixed ones mark required and prohibited clauses
+enum Item {
+    Clause(Clause),
+    Phrase(Clause),
+    Bare(Term),
+    Required(Clause),
+    Prohibited(Clause),
+}
+
 #[derive(Clone, Debug)]
-pub struct Term<'a> {
-    pub text: &'a str,
+pub struct Term {
+    pub text: String,
     pub fuzz: u8,
+    pub auto_fuzz: bool, // "~" without number, fuzz may be adapted to term frequency
+    pub literal: bool,   // escaped or verbatim term, split into words like document text
+    pub alternatives: Vec<String>, // matched exactly in the same position as the term
 }
 
+// phrase or sequence of bare terms, matched as a phrase with given slop
 #[derive(Clone, Debug)]
-pub struct Query<'a> {
-    pub terms: Vec<Term<'a>>,
+pub struct Clause {
+    pub terms: Vec<Term>,
     pub slop: u8,
+    pub field: Option<String>, // None matches document content
+    pub in_order: bool,        // if false terms may match in any order within slop
+}
+
+#[derive(Clone, Debug)]
+pub enum Query {
+    Clause(Clause),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    Optional(Box<Query>), // inside AND, matched query only contributes to the score
+    Any(Vec<Query>),      // alternative forms of one clause, scored by the best matched one
+    All,                  // matches every live document
 }
 
-impl<'a> Query<'a> {
-    pub fn parse(query: &'a mut str) -> Result<Query<'a>, PyErr> {
-        query.make_ascii_lowercase();
-        let result = Self::parser().parse(query);
+impl Query {
+    pub fn parse(query: &str, default_fuzz: u8, config: &Config) -> Result<Query, PyErr> {
+        // default fuzziness is used for terms without explicit "~"
+        if matches!(query.trim(), "" | "*") {
+            return Ok(Query::All);
+        }
+
+        let result = Self::parser(default_fuzz, config).parse(query);
         if result.has_errors() {
             let errors = result
                 .errors()
@@ -38,29 +74,266 @@ impl<'a> Query<'a> {
         }
 
         match result.into_output() {
-            Some(res) => Ok(res),
+            Some(res) if config.phrase_synonyms.is_empty() => Ok(res),
+            Some(res) => Ok(res.expand_phrase_synonyms(config)),
             None => Err(PyValueError::new_err(
                 "Failed to parse query, the output is empty",
             )),
         }
     }
 
-    fn map_auto_fuzz(len: usize) -> u8 {
-        match len {
-            _ if len <= 2 => 0,
-            _ if len <= 5 => 1,
-            _ => 2,
+    fn expand_phrase_synonyms(self, config: &Config) -> Query {
+        match self {
+            Self::Clause(clause) => {
+                let mut variants = Self::synonym_variants(clause, config);
+                match variants.len() {
+                    1 => Self::Clause(variants.remove(0)),
+                    _ => Self::Any(variants.into_iter().map(Self::Clause).collect()),
+                }
+            }
+            Self::And(queries) => Self::And(
+                queries
+                    .into_iter()
+                    .map(|q| q.expand_phrase_synonyms(config))
+                    .collect(),
+            ),
+            Self::Or(queries) => Self::Or(
+                queries
+                    .into_iter()
+                    .map(|q| q.expand_phrase_synonyms(config))
+                    .collect(),
+            ),
+            Self::Not(query) => Self::Not(Box::new(query.expand_phrase_synonyms(config))),
+            Self::Optional(query) => Self::Optional(Box::new(query.expand_phrase_synonyms(config))),
+            query => query,
+        }
+    }
+
+    fn synonym_variants(clause: Clause, config: &Config) -> Vec<Clause> {
+        // the longest sequence of words with phrase synonyms starting at each term is
+        // replaced, clause itself is the first variant, number of variants is capped
+        let longest = config
+            .phrase_synonyms
+            .keys()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0);
+        let words = clause
+            .terms
+            .iter()
+            .map(|term| match term.literal {
+                true => None,
+                false => Some(term.text.to_lowercase()),
+            })
+            .collect::<Vec<Option<String>>>();
+
+        let mut variants: Vec<Vec<Term>> = vec![vec![]];
+        let mut i = 0;
+        while i < clause.terms.len() {
+            let matched = (1..=longest.min(clause.terms.len() - i))
+                .rev()
+                .find_map(|len| {
+                    let key = words[i..i + len]
+                        .iter()
+                        .cloned()
+                        .collect::<Option<Vec<_>>>()?;
+                    config
+                        .phrase_synonyms
+                        .get(&key)
+                        .map(|synonyms| (len, synonyms))
+                });
+            let Some((len, synonyms)) = matched else {
+                variants
+                    .iter_mut()
+                    .for_each(|v| v.push(clause.terms[i].clone()));
+                i += 1;
+                continue;
+            };
+
+            let original = &clause.terms[i..i + len];
+            let mut expanded = vec![];
+            for (j, variant) in variants.iter().enumerate() {
+                expanded.push([variant.as_slice(), original].concat());
+                for synonym in synonyms.iter() {
+                    // every variant keeps at least its original words
+                    if expanded.len() + variants.len() - j > MAX_SYNONYM_VARIANTS {
+                        break;
+                    }
+                    let mut variant = variant.clone();
+                    variant.extend(synonym.split(' ').map(|word| Term {
+                        text: word.to_string(),
+                        fuzz: 0,
+                        auto_fuzz: false,
+                        literal: false,
+                        alternatives: vec![],
+                    }));
+                    expanded.push(variant);
+                }
+            }
+            variants = expanded;
+            i += len;
+        }
+
+        variants
+            .into_iter()
+            .map(|terms| Clause {
+                terms,
+                ..clause.clone()
+            })
+            .collect()
+    }
+
+    fn map_auto_fuzz(len: usize, config: &Config) -> u8 {
+        let fuzz = config
+            .auto_fuzz_lengths
+            .iter()
+            .filter(|l| len > **l)
+            .count();
+        (fuzz as u8).min(config.max_fuzziness)
+    }
+
+    fn is_operator(word: &str) -> bool {
+        matches!(word, "AND" | "OR" | "NOT")
+    }
+
+    fn unescape(word: &str) -> (String, bool) {
+        // character following backslash is taken literally
+        if !word.contains('\\') {
+            return (word.to_string(), false);
+        }
+
+        let (mut text, mut chars) = (String::with_capacity(word.len()), word.chars());
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => text.extend(chars.next()),
+                c => text.push(c),
+            }
+        }
+        (text, true)
+    }
+
+    fn group_items(items: Vec<Item>) -> Query {
+        if items
+            .iter()
+            .any(|item| matches!(item, Item::Required(_) | Item::Prohibited(_)))
+        {
+            return Self::group_modified(items);
+        }
+
+        // consecutive bare terms are matched as a phrase, clauses are combined with AND,
+        // if sequence contains a phrase, slop applies only within it and every bare
+        // term is a separate clause, e.g. "error handling"~1 rust tokio
+        let mixed = items.iter().any(|item| matches!(item, Item::Phrase(_)));
+        let (mut clauses, mut terms) = (vec![], vec![]);
+        for item in items {
+            match item {
+                Item::Bare(term) if mixed => clauses.push(Query::Clause(Clause {
+                    terms: vec![term],
+                    slop: 0,
+                    field: None,
+                    in_order: true,
+                })),
+                Item::Bare(term) => terms.push(term),
+                Item::Required(clause)
+                | Item::Prohibited(clause)
+                | Item::Clause(clause)
+                | Item::Phrase(clause) => {
+                    if !terms.is_empty() {
+                        clauses.push(Query::Clause(Clause {
+                            terms: std::mem::take(&mut terms),
+                            slop: 0,
+                            field: None,
+                            in_order: true,
+                        }));
+                    }
+                    clauses.push(Query::Clause(clause));
+                }
+            }
+        }
+
+        if !terms.is_empty() {
+            clauses.push(Query::Clause(Clause {
+                terms,
+                slop: 0,
+                field: None,
+                in_order: true,
+            }));
+        }
+
+        match clauses.len() {
+            1 => clauses.remove(0),
+            _ => Query::And(clauses),
+        }
+    }
+
+    fn group_modified(items: Vec<Item>) -> Query {
+        // every item is a separate clause, unprefixed ones are optional, if there is
+        // no required clause at least one of optional clauses has to match
+        let (mut required, mut prohibited, mut optional) = (vec![], vec![], vec![]);
+        for item in items {
+            match item {
+                Item::Required(clause) => required.push(Query::Clause(clause)),
+                Item::Prohibited(clause) => {
+                    prohibited.push(Query::Not(Box::new(Query::Clause(clause))))
+                }
+                Item::Clause(clause) | Item::Phrase(clause) => optional.push(Query::Clause(clause)),
+                Item::Bare(term) => optional.push(Query::Clause(Clause {
+                    terms: vec![term],
+                    slop: 0,
+                    field: None,
+                    in_order: true,
+                })),
+            }
+        }
+
+        if required.is_empty() && !optional.is_empty() {
+            required.push(match optional.len() {
+                1 => optional.remove(0),
+                _ => Query::Or(std::mem::take(&mut optional)),
+            });
+        }
+
+        let mut queries = required;
+        queries.extend(prohibited);
+        queries.extend(optional.into_iter().map(|q| Query::Optional(Box::new(q))));
+        match queries.len() {
+            1 => queries.remove(0),
+            _ => Query::And(queries),
         }
     }
 
-    fn parser() -> impl Parser<'a, &'a str, Query<'a>, extra::Err<Rich<'a, char>>> {
-        // TOKEN = any string that do not contain whitespaces, double quotes or tildas
-        let token = any()
-            .filter(|c: &char| !char::is_whitespace(*c) && *c != '"' && *c != '~')
+    fn parser<'a>(
+        default_fuzz: u8,
+        config: &'a Config,
+    ) -> impl Parser<'a, &'a str, Query, extra::Err<Rich<'a, char>>> {
+        // WORD = any string that do not contain whitespaces, double quotes, tildas, backticks or
+        // parentheses, parentheses are allowed only inside phrases, any character can be escaped
+        // with backslash
+        let escaped = just('\\').then(any()).ignored();
+        let phrase_word = any()
+            .filter(|c: &char| !char::is_whitespace(*c) && !matches!(*c, '"' | '~' | '`' | '\\'))
+            .ignored()
+            .or(escaped)
+            .repeated()
+            .at_least(1)
+            .to_slice()
+            .map(Self::unescape);
+        let word = any()
+            .filter(|c: &char| {
+                !char::is_whitespace(*c) && !matches!(*c, '"' | '~' | '`' | '(' | ')' | '\\')
+            })
+            .ignored()
+            .or(escaped)
             .repeated()
             .at_least(1)
             .to_slice();
 
+        // VERBATIM = backtick then any characters except backtick then backtick
+        let verbatim = just('`')
+            .ignore_then(none_of('`').repeated().at_least(1).to_slice())
+            .then_ignore(just('`'))
+            .map(|text: &str| (text.to_string(), true));
+
         let number = text::digits(10)
             .at_least(1)
             .at_most(2)
@@ -75,14 +348,13 @@ impl<'a> Query<'a> {
             }))
             .validate(|x, e, emitter| {
                 match x {
-                    Fuzz::Strict(v) => {
-                        if v > 2 {
-                            emitter.emit(Rich::custom(
-                                e.span(),
-                                format!("Fuzziness must be less or equal to 2, but it is {}.", v),
-                            ))
-                        }
-                    }
+                    Fuzz::Strict(v) if v > config.max_fuzziness => emitter.emit(Rich::custom(
+                        e.span(),
+                        format!(
+                            "Fuzziness must be less or equal to {}, but it is {}.",
+                            config.max_fuzziness, v
+                        ),
+                    )),
                     _ => (),
                 };
                 x
@@ -91,52 +363,211 @@ impl<'a> Query<'a> {
         // SLOP = "~" + DIGITS
         let slop = just('~').ignore_then(number);
 
-        // TERM = TOKEN then FUZZ.optional()
-        let term = token.then(fuzz.or_not());
+        // TERM = WORD then FUZZ.optional(), operators are terms only inside phrases
+        let to_term = move |((text, literal), fuzz): ((String, bool), Option<Fuzz>)| Term {
+            fuzz: match fuzz {
+                Some(Fuzz::Strict(v)) => v,
+                Some(Fuzz::Auto) => Self::map_auto_fuzz(text.len(), config),
+                None => default_fuzz,
+            },
+            auto_fuzz: matches!(fuzz, Some(Fuzz::Auto)),
+            text: text.to_lowercase(),
+            literal,
+            alternatives: vec![],
+        };
+        let phrase_term = verbatim
+            .or(phrase_word)
+            .then(fuzz.or_not())
+            .map(to_term)
+            .boxed();
+        let term = verbatim
+            .or(word
+                .filter(|w: &&str| !Self::is_operator(w))
+                .map(Self::unescape))
+            .then(fuzz.or_not())
+            .map(to_term)
+            .boxed();
 
-        // PHRASE = quote then repeated terms seperated by whitespace then quote
+        // PHRASE = quote then repeated terms seperated by whitespace then quote then SLOP.optional()
         let ws = text::whitespace().at_least(1);
-        let terms = term
-            .separated_by(ws)
-            .at_least(1)
-            .collect::<Vec<_>>()
-            .map(|v| {
-                v.into_iter()
-                    .map(|val: (&str, Option<Fuzz>)| Term {
-                        fuzz: match &val.1 {
-                            Some(x) => match x {
-                                Fuzz::Strict(v) => *v,
-                                Fuzz::Auto => Self::map_auto_fuzz(val.0.len()),
-                            },
-                            None => 0,
-                        },
-                        text: val.0,
-                    })
-                    .collect()
-            });
-
         let phrase = just('"')
-            .ignore_then(terms)
+            .ignore_then(
+                phrase_term
+                    .separated_by(ws)
+                    .at_least(1)
+                    .collect::<Vec<Term>>(),
+            )
             .then_ignore(just('"'))
-            .then(slop.or_not());
-
-        // QUERY = (PHRASE then SLOP) or repeated terms seperated by whitespace
-        let query = text::whitespace()
-            .ignore_then(phrase)
-            .map(|val| Query {
-                terms: val.0,
-                slop: match val.1 {
-                    Some(v) => v,
-                    _ => 0,
-                },
-            })
-            .or(terms.map(|terms| Query {
+            .then(slop.or_not())
+            .map(|(terms, slop)| Clause {
                 terms: terms,
-                slop: 0,
-            }))
-            .then_ignore(text::whitespace())
-            .then_ignore(end());
+                slop: slop.unwrap_or(0),
+                field: None,
+                in_order: true,
+            })
+            .boxed();
 
-        query
+        // SCOPED = IDENT + ":" then PHRASE or TERM, e.g. title:foo or body:"bar baz"~2
+        let single = term.clone().map(|term| Clause {
+            terms: vec![term],
+            slop: 0,
+            field: None,
+            in_order: true,
+        });
+        let field = text::ascii::ident().then_ignore(just(':'));
+        let scoped = field
+            .then(phrase.clone().or(single.clone()))
+            .map(|(field, mut clause): (&str, Clause)| {
+                clause.field = Some(field.to_string());
+                clause
+            })
+            .boxed();
+        let bare = field
+            .then(any().filter(|c: &char| !char::is_whitespace(*c)))
+            .not()
+            .ignore_then(term);
+
+        // MODIFIED = "+" or "-" then SCOPED, PHRASE or TERM, e.g. +foo or -"bar baz"
+        let modified = just('+')
+            .or(just('-'))
+            .then(scoped.clone().or(phrase.clone()).or(single))
+            .map(|(modifier, clause)| match modifier {
+                '+' => Item::Required(clause),
+                _ => Item::Prohibited(clause),
+            });
+
+        // CLAUSE = repeated MODIFIED, SCOPED, PHRASE or terms seperated by whitespace
+        let clause = modified
+            .or(scoped.map(Item::Clause))
+            .or(phrase.map(Item::Phrase))
+            .or(bare.map(Item::Bare))
+            .separated_by(ws)
+            .at_least(1)
+            .collect::<Vec<Item>>()
+            .map(Self::group_items)
+            .boxed();
+
+        let operator = move |name: &'static str| word.filter(move |w: &&str| *w == name).padded();
+
+        // QUERY = OR, where NOT binds tighter than AND and AND binds tighter than OR
+        let query = recursive(|query| {
+            let atom = clause
+                .or(query.delimited_by(just('(').padded(), just(')').padded()))
+                .padded();
+
+            let not = operator("NOT")
+                .repeated()
+                .foldr(atom, |_, q| Query::Not(Box::new(q)));
+
+            let and = not
+                .separated_by(operator("AND"))
+                .at_least(1)
+                .collect::<Vec<Query>>()
+                .map(|mut q| {
+                    if q.len() == 1 {
+                        q.remove(0)
+                    } else {
+                        Query::And(q)
+                    }
+                });
+
+            and.separated_by(operator("OR"))
+                .at_least(1)
+                .collect::<Vec<Query>>()
+                .map(|mut q| {
+                    if q.len() == 1 {
+                        q.remove(0)
+                    } else {
+                        Query::Or(q)
+                    }
+                })
+        });
+
+        query.padded().then_ignore(end())
+    }
+
+    pub fn with_fields(self, fields: &[String]) -> Query {
+        // clauses without field match given fields as well, as alternatives scored by
+        // the best matched one
+        match self {
+            Self::Clause(clause) if clause.field.is_none() && !fields.is_empty() => {
+                let mut alternatives = Vec::with_capacity(fields.len() + 1);
+                for field in fields {
+                    let mut scoped = clause.clone();
+                    scoped.field = Some(field.clone());
+                    alternatives.push(Self::Clause(scoped));
+                }
+                alternatives.insert(0, Self::Clause(clause));
+                Self::Any(alternatives)
+            }
+            Self::And(queries) => Self::And(Self::all_with_fields(queries, fields)),
+            Self::Or(queries) => Self::Or(Self::all_with_fields(queries, fields)),
+            Self::Any(queries) => Self::Any(Self::all_with_fields(queries, fields)),
+            Self::Not(query) => Self::Not(Box::new(query.with_fields(fields))),
+            Self::Optional(query) => Self::Optional(Box::new(query.with_fields(fields))),
+            query => query,
+        }
+    }
+
+    fn all_with_fields(queries: Vec<Query>, fields: &[String]) -> Vec<Query> {
+        queries.into_iter().map(|q| q.with_fields(fields)).collect()
+    }
+
+    pub fn clauses(&self) -> Vec<&Clause> {
+        // all clauses in query tree, including the negated ones
+        match self {
+            Self::Clause(clause) => vec![clause],
+            Self::And(queries) | Self::Or(queries) | Self::Any(queries) => {
+                queries.iter().flat_map(|q| q.clauses()).collect()
+            }
+            Self::Not(query) | Self::Optional(query) => query.clauses(),
+            Self::All => vec![],
+        }
+    }
+
+    pub fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        // every node is a dict with "type" and its operands, e.g. "queries" of AND
+        let node = PyDict::new(py);
+        match self {
+            Self::Clause(clause) => {
+                let terms = PyList::empty(py);
+                for term in clause.terms.iter() {
+                    let item = PyDict::new(py);
+                    item.set_item("text", &term.text)?;
+                    item.set_item("fuzz", term.fuzz)?;
+                    item.set_item("auto_fuzz", term.auto_fuzz)?;
+                    item.set_item("literal", term.literal)?;
+                    terms.append(item)?;
+                }
+                node.set_item("type", "clause")?;
+                node.set_item("terms", terms)?;
+                node.set_item("slop", clause.slop)?;
+                node.set_item("field", &clause.field)?;
+            }
+            Self::And(queries) | Self::Or(queries) | Self::Any(queries) => {
+                let children = PyList::empty(py);
+                for query in queries {
+                    children.append(query.to_dict(py)?)?;
+                }
+                let kind = match self {
+                    Self::And(_) => "and",
+                    Self::Or(_) => "or",
+                    _ => "any",
+                };
+                node.set_item("type", kind)?;
+                node.set_item("queries", children)?;
+            }
+            Self::Not(query) | Self::Optional(query) => {
+                let kind = if matches!(self, Self::Not(_)) {
+                    "not"
+                } else {
+                    "optional"
+                };
+                node.set_item("type", kind)?;
+                node.set_item("query", query.to_dict(py)?)?;
+            }
+            Self::All => node.set_item("type", "all")?,
+        }
+        Ok(node)
     }
 }
@@ -2,13 +2,130 @@ use crate::core::index::Posting;
 use crate::matching::intersect::TokenDocPointer;
 use crate::matching::mis::MisResult;
 use crate::storage::documents::DocumentsManager;
+use bincode::{Decode, Encode};
 use hashbrown::HashMap;
 use nohash_hasher::BuildNoHashHasher;
+use std::collections::HashMap as StdHashMap;
+use ulid::Ulid;
 
 static K: f64 = 1.5;
 static B: f64 = 0.75;
 static EPS: f64 = 0.5;
 static FUZZINESS_PENALTY: f64 = 0.8;
+static IMPACT_LEVELS: f64 = 256.0;
+
+#[derive(Decode, Encode, PartialEq, Debug, Clone, Default)]
+pub struct CorpusStats {
+    pub docs_num: u64,
+    dfs: StdHashMap<u32, u64>, // number of documents containing token
+}
+
+impl CorpusStats {
+    pub fn build<'a>(
+        docs_num: u64,
+        index: impl Iterator<Item = (&'a u32, &'a Vec<Posting>)>,
+    ) -> Self {
+        Self {
+            docs_num,
+            dfs: index
+                .map(|(token, postings)| (*token, postings.len() as u64))
+                .collect(),
+        }
+    }
+
+    pub fn df(&self, token: u32) -> u64 {
+        self.dfs.get(&token).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, tokens: &[u32]) {
+        self.docs_num += 1;
+        for token in tokens {
+            *self.dfs.entry(*token).or_insert(0) += 1;
+        }
+    }
+
+    pub fn remove(&mut self, tokens: &[u32]) {
+        self.docs_num = self.docs_num.saturating_sub(1);
+        for token in tokens {
+            if let Some(df) = self.dfs.get_mut(token) {
+                *df -= 1;
+                if *df == 0 {
+                    self.dfs.remove(token);
+                }
+            }
+        }
+    }
+}
+
+// number of documents with a field and sum of lengths of the field in them, tf of field
+// scoped clause is normalized by average length of its field instead of document
+#[derive(Decode, Encode, PartialEq, Debug, Clone, Default)]
+pub struct FieldStats {
+    fields: StdHashMap<String, (u64, u64)>,
+}
+
+impl FieldStats {
+    pub fn add(&mut self, fields: &[(String, u32)]) {
+        for (field, len) in fields {
+            let (docs, lens) = self.fields.entry(field.clone()).or_default();
+            *docs += 1;
+            *lens += *len as u64;
+        }
+    }
+
+    pub fn remove(&mut self, fields: &[(String, u32)]) {
+        for (field, len) in fields {
+            if let Some((docs, lens)) = self.fields.get_mut(field) {
+                *docs = docs.saturating_sub(1);
+                *lens = lens.saturating_sub(*len as u64);
+                if *docs == 0 {
+                    self.fields.remove(field);
+                }
+            }
+        }
+    }
+
+    pub fn avg_len(&self, field: &str) -> Option<f64> {
+        // None for fields of documents added before field lengths were stored
+        let (docs, lens) = self.fields.get(field)?;
+        Some((*lens as f64 / *docs as f64).max(1.0))
+    }
+}
+
+pub fn idf(docs_num: u64, token_docs_num: u64) -> f64 {
+    ((docs_num.saturating_sub(token_docs_num) as f64 + EPS) / (token_docs_num as f64 + EPS) + 1.0)
+        .ln()
+}
+
+// idf of query tokens (with their fuzzy expansions and synonyms) computed once when
+// matcher is built, instead of for every scored interval, corpus statistics don't
+// change during a query
+pub struct Idfs<'a> {
+    stats: &'a CorpusStats,
+    idfs: HashMap<u32, f64, BuildNoHashHasher<u32>>,
+}
+
+impl<'a> Idfs<'a> {
+    pub fn new(stats: &'a CorpusStats, tokens: impl Iterator<Item = u32>) -> Self {
+        Self {
+            idfs: tokens
+                .map(|token| (token, idf(stats.docs_num, stats.df(token))))
+                .collect(),
+            stats,
+        }
+    }
+
+    pub fn get(&self, token: u32) -> f64 {
+        match self.idfs.get(&token) {
+            Some(idf) => *idf,
+            None => idf(self.stats.docs_num, self.stats.df(token)),
+        }
+    }
+}
+
+fn tf_norm(tf: u64, doc_length: u32, avg_doc_length: f64) -> f64 {
+    (tf as f64 * (K + 1.0)) / (tf as f64 + K * (1.0 - B + B * (doc_length as f64 / avg_doc_length)))
+}
 
 pub fn term_bm25(
     tf: u64,
@@ -18,64 +135,140 @@ pub fn term_bm25(
     avg_doc_length: f64,
     distance: u16,
 ) -> f64 {
-    let idf =
-        (((docs_num - token_docs_num) as f64 + EPS) / (token_docs_num as f64 + EPS) + 1.0).ln();
+    idf_bm25(
+        idf(docs_num, token_docs_num),
+        tf,
+        doc_length,
+        avg_doc_length,
+        distance,
+    )
+}
 
-    let bm25 = idf
-        * ((tf as f64 * (K + 1.0))
-            / (tf as f64 + K * (1.0 - B + B * (doc_length as f64 / avg_doc_length))));
+fn idf_bm25(idf: f64, tf: u64, doc_length: u32, avg_doc_length: f64, distance: u16) -> f64 {
+    idf * tf_norm(tf, doc_length, avg_doc_length) * FUZZINESS_PENALTY.powi(distance as i32)
+}
+
+pub fn quantize_impact(tf: u64, doc_length: u32, avg_doc_length: f64) -> u8 {
+    // tf component of bm25 is lower than K + 1, so it's mapped to 256 levels
+    ((tf_norm(tf, doc_length, avg_doc_length) / (K + 1.0) * IMPACT_LEVELS) as u64).min(255) as u8
+}
 
-    bm25 * FUZZINESS_PENALTY.powi(distance as i32)
+pub fn impact_bound(quantized: u8) -> f64 {
+    // upper bound of tf component of bm25 which was quantized to given level
+    (quantized as f64 + 1.0) / IMPACT_LEVELS * (K + 1.0)
 }
 
 pub fn bm25(
-    docs_num: u64,
+    idfs: &Idfs,
     doc_length: u32,
     avg_doc_length: f64,
-    index: &HashMap<u32, Vec<Posting>, BuildNoHashHasher<u32>>,
     mis_result: MisResult,
+    proximity: f64,
 ) -> f64 {
     let mut score = 0.0;
     for mis_idx in mis_result.indexes {
-        score += term_bm25(
+        score += idf_bm25(
+            idfs.get(mis_idx.token),
             mis_idx.tf,
-            docs_num,
-            index.get(&mis_idx.token).unwrap_or(&vec![]).len() as u64,
             doc_length,
             avg_doc_length,
             mis_idx.distance,
         );
     }
 
-    score / (mis_result.slop + 1) as f64
+    // score of interval with gaps is divided by their size raised to proximity weight,
+    // 1 divides it by slop + 1 and 0 doesn't penalize gaps at all
+    score / ((mis_result.slop + 1) as f64).powf(proximity)
 }
 
 pub fn max_bm25(
     docs_manager: &DocumentsManager,
+    idfs: &Idfs,
     avg_doc_length: f64,
+    impact_avg_doc_length: f64,
     pointers: &Vec<Vec<TokenDocPointer>>,
 ) -> f64 {
-    let mut score: f64 = 0.0;
-    let docs_num = docs_manager.docs.len() as u64;
-    let doc_length = match docs_manager.docs.get(&pointers[0][0].doc_id) {
-        Some(doc) => doc.tokens.len() as u32,
-        None => return 0.0,
-    };
+    // quantized impacts are upper bounds of tf component, which grows at most
+    // proportionally to average document length, document length is read only
+    // for postings which impact is unknown
+    let slack = (avg_doc_length / impact_avg_doc_length).max(1.0);
+    let mut doc_length = None;
 
+    let mut score: f64 = 0.0;
     for pointer in pointers {
         let mut max: f64 = 0.0;
         for token_doc_pointer in pointer {
-            max = max.max(term_bm25(
-                token_doc_pointer.tf,
-                docs_num,
-                token_doc_pointer.postings_len,
-                doc_length,
-                avg_doc_length,
-                token_doc_pointer.distance,
-            ));
+            let idf = idfs.get(token_doc_pointer.token);
+            let tf_norm = match token_doc_pointer.impact {
+                u8::MAX => {
+                    let doc_length = match doc_length {
+                        Some(len) => len,
+                        None => match docs_manager.get(&token_doc_pointer.doc_id) {
+                            Some(doc) => *doc_length.insert(doc.tokens.len() as u32),
+                            None => return 0.0,
+                        },
+                    };
+                    tf_norm(token_doc_pointer.tf, doc_length, avg_doc_length)
+                }
+                impact => impact_bound(impact) * slack,
+            };
+
+            max =
+                max.max(idf * tf_norm * FUZZINESS_PENALTY.powi(token_doc_pointer.distance as i32));
         }
         score += max;
     }
 
     score
 }
+
+pub fn unbounded_bm25(idfs: &Idfs, pointers: &[Vec<TokenDocPointer>]) -> f64 {
+    // upper bound for postings which impacts don't apply to, tf component of bm25 is
+    // lower than K + 1 for any length normalization
+    pointers
+        .iter()
+        .map(|pointer| {
+            pointer
+                .iter()
+                .map(|p| idfs.get(p.token) * (K + 1.0) * FUZZINESS_PENALTY.powi(p.distance as i32))
+                .fold(0.0, f64::max)
+        })
+        .sum()
+}
+
+pub fn block_bm25(
+    idfs: &Idfs,
+    avg_doc_length: f64,
+    impact_avg_doc_length: f64,
+    pointer: &TokenDocPointer,
+    block_max: u8,
+) -> f64 {
+    // upper bound of token score in any document of posting block with given highest
+    // impact, the maximal impact stands for unknown one which tf component is below K + 1
+    let tf_norm = match block_max {
+        u8::MAX => K + 1.0,
+        impact => impact_bound(impact) * (avg_doc_length / impact_avg_doc_length).max(1.0),
+    };
+    idfs.get(pointer.token) * tf_norm * FUZZINESS_PENALTY.powi(pointer.distance as i32)
+}
+
+pub fn coord(matched: usize, total: usize, weight: f64) -> f64 {
+    // coordination factor rewarding documents which match more of disjunction terms
+    if weight == 0.0 || total == 0 {
+        return 1.0;
+    }
+    (matched as f64 / total as f64).powf(weight)
+}
+
+pub fn position_boost(position: u32, halfdistance: f64) -> f64 {
+    // match at the beginning of document (or field) doubles the score, the boost
+    // halves every "halfdistance" positions, so it's always in range (1, 2]
+    1.0 + 0.5f64.powf(position as f64 / halfdistance)
+}
+
+pub fn recency_decay(doc_id: &Ulid, now: u64, halflife: f64) -> f64 {
+    // exponential decay of document age (in seconds), documents ids are ULIDs
+    // so creation timestamp (in milliseconds) is already encoded in them
+    let age = now.saturating_sub(doc_id.timestamp_ms()) as f64 / 1000.0;
+    0.5f64.powf(age / halflife)
+}
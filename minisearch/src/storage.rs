@@ -1 +1,4 @@
+pub mod attributes;
+pub mod digests;
 pub mod documents;
+pub mod querylog;
@@ -0,0 +1,175 @@
+use bincode::{Decode, Encode};
+use hashbrown::HashMap;
+use std::collections::HashMap as StdHashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use ulid::Ulid;
+
+use crate::config::Config;
+use crate::errors::BincodePersistenceError;
+use crate::utils::memory::HeapSize;
+
+pub type Attributes = StdHashMap<String, f64>;
+
+#[derive(Decode, Encode, PartialEq, Debug, Clone)]
+struct AttributesLog {
+    id: [u8; 16],
+    attributes: Option<Attributes>, // None marks deleted attributes
+}
+
+pub struct AttributesManager {
+    path: PathBuf,
+    pub attributes: HashMap<Ulid, Attributes>,
+    buffer: Vec<u8>,
+    last_save: u64,
+    config: Arc<Config>,
+}
+
+impl AttributesManager {
+    pub fn memory_usage(&self) -> (usize, usize) {
+        // estimated bytes of attributes and their write buffer
+        (self.attributes.heap_size(), self.buffer.capacity())
+    }
+
+    pub fn load(dir: &PathBuf, config: Arc<Config>) -> Result<Self, BincodePersistenceError> {
+        let path = dir.join("attributes");
+
+        if !fs::exists(&path)? {
+            fs::create_dir_all(dir)?;
+            File::create(&path)?;
+        }
+
+        Ok(Self {
+            attributes: Self::read(&path)?,
+            path,
+            buffer: Vec::new(),
+            last_save: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)?
+                .as_secs(),
+            config,
+        })
+    }
+
+    fn read(path: &PathBuf) -> Result<HashMap<Ulid, Attributes>, BincodePersistenceError> {
+        let mut attributes = HashMap::new();
+        let mut file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        while file.stream_position()? + 8 <= file_size {
+            let mut size = [0u8; 8];
+            file.read_exact(&mut size)?;
+            let size = u64::from_be_bytes(size);
+            // skip log which is still being written
+            if file.stream_position()? + size > file_size {
+                break;
+            }
+
+            let mut log = vec![0u8; size as usize];
+            file.read_exact(&mut log)?;
+            let (log, _): (AttributesLog, usize) =
+                bincode::decode_from_slice(&log, bincode::config::standard())?;
+
+            // later logs always override previous ones
+            match log.attributes {
+                Some(attrs) => attributes.insert(Ulid::from_bytes(log.id), attrs),
+                None => attributes.remove(&Ulid::from_bytes(log.id)),
+            };
+        }
+
+        Ok(attributes)
+    }
+
+    pub fn reload(&mut self) -> Result<(), BincodePersistenceError> {
+        // file may be rewritten by compaction, so it's always read from the start
+        self.attributes = Self::read(&self.path)?;
+        Ok(())
+    }
+
+    pub fn get(&self, id: &Ulid) -> Option<&Attributes> {
+        self.attributes.get(id)
+    }
+
+    pub fn write(
+        &mut self,
+        id: Ulid,
+        attributes: Attributes,
+    ) -> Result<(), BincodePersistenceError> {
+        Self::write_log(
+            &mut self.buffer,
+            &AttributesLog {
+                id: id.to_bytes(),
+                attributes: Some(attributes.clone()),
+            },
+        )?;
+        self.attributes.insert(id, attributes);
+        self.save()
+    }
+
+    pub fn delete(&mut self, id: Ulid) -> Result<(), BincodePersistenceError> {
+        if self.attributes.remove(&id).is_none() {
+            return Ok(());
+        }
+
+        Self::write_log(
+            &mut self.buffer,
+            &AttributesLog {
+                id: id.to_bytes(),
+                attributes: None,
+            },
+        )?;
+        self.save()
+    }
+
+    pub fn flush(&mut self) -> Result<(), BincodePersistenceError> {
+        let mut file = File::options().append(true).open(&self.path)?;
+        file.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    pub fn compact(&mut self) -> Result<(), BincodePersistenceError> {
+        // rewrite attributes file keeping only latest attributes of live documents
+        let mut buffer = Vec::new();
+        for (id, attributes) in self.attributes.iter() {
+            Self::write_log(
+                &mut buffer,
+                &AttributesLog {
+                    id: id.to_bytes(),
+                    attributes: Some(attributes.clone()),
+                },
+            )?;
+        }
+
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buffer)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn write_log(buffer: &mut Vec<u8>, log: &AttributesLog) -> Result<(), BincodePersistenceError> {
+        let log = bincode::encode_to_vec(log, bincode::config::standard())?;
+        buffer.extend((log.len() as u64).to_be_bytes());
+        buffer.extend(log);
+        Ok(())
+    }
+
+    fn save(&mut self) -> Result<(), BincodePersistenceError> {
+        let cur_ts = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+
+        if self.buffer.len() as u64 > self.config.documents_buffer_size
+            || cur_ts >= self.last_save + self.config.documents_save_after_seconds
+        {
+            self.last_save = cur_ts;
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use bincode::{Decode, Encode};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use ulid::Ulid;
+
+use crate::errors::BincodePersistenceError;
+use crate::utils::memory::HeapSize;
+
+static FNV_OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+static FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+#[derive(Decode, Encode, PartialEq, Debug, Clone, Default)]
+struct DigestsData {
+    ids: HashMap<u128, u128>, // content digest -> document id
+}
+
+// maps content digests to ids of documents added with idempotent add, documents
+// ids stay time ordered ulids since postings lists are sorted by them
+pub struct ContentDigests {
+    path: PathBuf,
+    data: DigestsData,
+}
+
+impl ContentDigests {
+    pub fn memory_usage(&self) -> usize {
+        self.data.ids.heap_size()
+    }
+
+    pub fn load(dir: &PathBuf) -> Result<Self, BincodePersistenceError> {
+        let path = dir.join("digests");
+        if !fs::exists(&path)? {
+            fs::create_dir_all(dir)?;
+            File::create(&path)?;
+        }
+
+        Ok(Self {
+            data: Self::read(&path)?.unwrap_or_default(),
+            path,
+        })
+    }
+
+    fn read(path: &PathBuf) -> Result<Option<DigestsData>, BincodePersistenceError> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(None);
+        }
+
+        // file may be partially written by other process, it's treated as missing
+        Ok(bincode::decode_from_std_read(&mut file, bincode::config::standard()).ok())
+    }
+
+    pub fn digest(content: &str) -> u128 {
+        // 128 bit fnv-1a, stable across processes and versions
+        content.bytes().fold(FNV_OFFSET, |hash, byte| {
+            (hash ^ byte as u128).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    pub fn get(&self, digest: u128) -> Option<Ulid> {
+        self.data.ids.get(&digest).map(|id| Ulid(*id))
+    }
+
+    pub fn insert(&mut self, digest: u128, id: Ulid) {
+        self.data.ids.insert(digest, id.0);
+    }
+
+    pub fn retain(&mut self, exists: impl Fn(&Ulid) -> bool) {
+        self.data.ids.retain(|_, id| exists(&Ulid(*id)));
+    }
+
+    pub fn flush(&self) -> Result<(), BincodePersistenceError> {
+        let mut file = File::create(&self.path)?;
+        bincode::encode_into_std_write(&self.data, &mut file, bincode::config::standard())?;
+        Ok(())
+    }
+
+    pub fn reload(&mut self) -> Result<(), BincodePersistenceError> {
+        if let Some(data) = Self::read(&self.path)? {
+            self.data = data;
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,23 @@
 use bincode::config::Configuration;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
 use bincode::enc::EncoderImpl;
 use bincode::enc::write::SizeWriter;
 use bincode::error::{DecodeError, EncodeError};
 use bincode::{Decode, Encode};
 use hashbrown::{HashMap, HashSet};
 use lz4_flex::block::{
-    CompressError, compress_into, decompress_size_prepended, get_maximum_output_size,
+    CompressError, compress_into, compress_prepend_size, decompress_size_prepended,
+    get_maximum_output_size,
 };
-use pyo3::exceptions::{PySystemError, PyValueError};
+use memmap2::Mmap;
+use pyo3::exceptions::PySystemError;
 use pyo3::prelude::*;
+use std::borrow::Cow;
+use std::collections::HashMap as StdHashMap;
 use std::fs::remove_dir_all;
 use std::io::{self, prelude::*};
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::SystemTimeError;
 use std::{
     fs::{self, File},
@@ -21,9 +27,12 @@ use std::{
 use thiserror::Error;
 use ulid::Ulid;
 
-use crate::config::Config;
-use crate::errors::{BincodeDecodeError, BincodeEncodeError, CompressException};
-use crate::utils::fileext::FileExt;
+use crate::config::{Config, DocumentsStorage};
+use crate::errors::{BincodeDecodeError, BincodeEncodeError, CompressException, DocumentCorrupted};
+use crate::utils::bloom::BloomFilter;
+use crate::utils::fileext::{FileExt, ReopeningFile, read_exact_at_path};
+use crate::utils::lz4;
+use crate::utils::memory::HeapSize;
 
 #[derive(Error, Debug)]
 pub enum DocumentBufferError {
@@ -52,6 +61,8 @@ pub enum DocumentsManagerError {
     Time(#[from] SystemTimeError),
     #[error("documents manager: bincode decode failed: {0}")]
     BincodeDecodeError(#[from] DecodeError),
+    #[error("documents manager: bincode encode failed: {0}")]
+    BincodeEncodeError(#[from] EncodeError),
     #[error("documents manager: document buffer error: {0}")]
     DocumentBufferError(#[from] DocumentBufferError),
 }
@@ -64,31 +75,204 @@ impl From<DocumentsManagerError> for pyo3::PyErr {
             DocumentsManagerError::BincodeDecodeError(err) => {
                 BincodeDecodeError::new_err(err.to_string())
             }
+            DocumentsManagerError::BincodeEncodeError(err) => {
+                BincodeEncodeError::new_err(err.to_string())
+            }
             DocumentsManagerError::DocumentBufferError(err) => err.into(),
         }
     }
 }
 
 #[pyclass(name = "Document")]
-#[derive(Decode, Encode, PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone)]
 pub struct Document {
     pub id: [u8; 16], // binary representation of ULID
     data: Option<String>,
     pub location: DocLocation,
     pub len: u32,
     pub tokens: Vec<u32>,
+    pub boost: f32,                 // static weight multiplied into document score
+    pub fields: Vec<(String, u32)>, // length of every field the document was added with
+    pub truncated: bool,            // some words weren't indexed, they were past max positions
+    pub inline: Option<Vec<u8>>,    // compressed content stored in meta record of small document
+    pub payload_size: u32,          // size of compressed payload at the end of content record
+    pub max_content_bytes: Option<usize>, // content is cut to this size, it's never stored
+}
+
+impl Encode for Document {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.id.encode(encoder)?;
+        self.data.encode(encoder)?;
+        self.location.encode(encoder)?;
+        self.len.encode(encoder)?;
+        self.tokens.encode(encoder)?;
+        self.boost.encode(encoder)?;
+        self.fields.encode(encoder)?;
+        self.truncated.encode(encoder)?;
+        self.inline.encode(encoder)?;
+        self.payload_size.encode(encoder)
+    }
 }
 
+impl<Context> Decode<Context> for Document {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let mut doc = Self {
+            id: Decode::decode(decoder)?,
+            data: Decode::decode(decoder)?,
+            location: Decode::decode(decoder)?,
+            len: Decode::decode(decoder)?,
+            tokens: Decode::decode(decoder)?,
+            boost: 1.0,
+            fields: vec![],
+            truncated: false,
+            inline: None,
+            payload_size: 0,
+            max_content_bytes: None,
+        };
+        // documents are always decoded from their own record, ones written before
+        // boost, field lengths, truncation, inline content or payload were stored end
+        // right after the previous one
+        match f32::decode(decoder) {
+            Ok(boost) => doc.boost = boost,
+            Err(DecodeError::UnexpectedEnd { .. }) => return Ok(doc),
+            Err(err) => return Err(err),
+        }
+        match Vec::decode(decoder) {
+            Ok(fields) => doc.fields = fields,
+            Err(DecodeError::UnexpectedEnd { .. }) => return Ok(doc),
+            Err(err) => return Err(err),
+        }
+        match bool::decode(decoder) {
+            Ok(truncated) => doc.truncated = truncated,
+            Err(DecodeError::UnexpectedEnd { .. }) => return Ok(doc),
+            Err(err) => return Err(err),
+        }
+        match Option::decode(decoder) {
+            Ok(inline) => doc.inline = inline,
+            Err(DecodeError::UnexpectedEnd { .. }) => return Ok(doc),
+            Err(err) => return Err(err),
+        }
+        match u32::decode(decoder) {
+            Ok(payload_size) => doc.payload_size = payload_size,
+            Err(DecodeError::UnexpectedEnd { .. }) => {}
+            Err(err) => return Err(err),
+        }
+        Ok(doc)
+    }
+}
+
+bincode::impl_borrow_decode!(Document);
+
 impl Document {
-    fn new(id: [u8; 16], location: DocLocation, len: u32, tokens: Vec<u32>) -> Self {
+    fn new(
+        id: [u8; 16],
+        location: DocLocation,
+        len: u32,
+        tokens: Vec<u32>,
+        boost: f32,
+        fields: Vec<(String, u32)>,
+        truncated: bool,
+    ) -> Self {
         Self {
             id: id,
             data: None,
             location: location,
             len: len,
             tokens: tokens,
+            boost,
+            fields,
+            truncated,
+            inline: None,
+            payload_size: 0,
+            max_content_bytes: None,
         }
     }
+
+    pub fn field_len(&self, field: &str) -> Option<u32> {
+        self.fields
+            .iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, len)| *len)
+    }
+
+    pub fn read_raw(&self) -> Result<Vec<u8>, io::Error> {
+        // compressed content as stored in segment data file or meta record
+        let size = self.location.size - self.payload_size as usize;
+        self.read_record(0, size)
+    }
+
+    fn read_record(&self, from: usize, size: usize) -> Result<Vec<u8>, io::Error> {
+        // part of content record, which is compressed content followed by payload
+        if let Some(inline) = &self.inline {
+            return Ok(inline[from..from + size].to_vec());
+        }
+
+        let mut buf = vec![0u8; size];
+        read_exact_at_path(
+            &self.location.segment.join("data"),
+            &mut buf,
+            self.location.offset + from as u64,
+        )?;
+        Ok(buf)
+    }
+
+    pub fn encode_payload(
+        payload: &StdHashMap<String, String>,
+    ) -> Result<Vec<u8>, DocumentBufferError> {
+        // fields are sorted, so the same payload is always stored in the same way
+        let mut fields = payload.iter().collect::<Vec<(&String, &String)>>();
+        fields.sort_unstable();
+        let encoded = bincode::encode_to_vec(fields, bincode::config::standard())?;
+        Ok(compress_prepend_size(&encoded))
+    }
+
+    fn decompressed_prefix(&self, limit: usize) -> PyResult<Vec<u8>> {
+        // only beginning of content record is read and decompressed, it's read again
+        // twice as long if it wasn't enough, e.g. for long run of literals
+        let content_size = self.location.size - self.payload_size as usize;
+        let mut size = lz4::prefix_bound(limit).min(content_size);
+        loop {
+            let block = self.read_record(0, size)?;
+            let data = lz4::decompress_prefix(&block, limit).map_err(|err| {
+                self.corrupted(format!("failed to decompress document content: {}", err))
+            })?;
+            let full = limit.min(lz4::decompressed_size(&block).unwrap_or(0));
+            if data.len() >= full || size == content_size {
+                return Ok(data);
+            }
+            size = (size * 2).min(content_size);
+        }
+    }
+
+    fn content_size(&self) -> PyResult<usize> {
+        // size of uncompressed content is prepended to it, so it's known without
+        // decompressing it
+        if let Some(data) = &self.data {
+            return Ok(data.len());
+        }
+        let block = self.read_record(0, 4.min(self.location.size))?;
+        lz4::decompressed_size(&block)
+            .map_err(|err| self.corrupted(format!("failed to read document content size: {}", err)))
+    }
+
+    fn decompressed(&self) -> PyResult<Vec<u8>> {
+        match decompress_size_prepended(&self.read_raw()?) {
+            Ok(data) => Ok(data),
+            Err(err) => {
+                Err(self.corrupted(format!("failed to decompress document content: {}", err)))
+            }
+        }
+    }
+
+    fn corrupted(&self, reason: String) -> PyErr {
+        DocumentCorrupted::new_err(format!(
+            "Document {} is corrupted (segment: {}, offset: {}): {}",
+            Ulid::from_bytes(self.id),
+            self.location.segment.display(),
+            self.location.offset,
+            reason
+        ))
+    }
 }
 
 #[pymethods]
@@ -100,28 +284,35 @@ impl Document {
 
     #[getter(content)]
     pub fn content(&mut self) -> PyResult<String> {
+        // capped content ends at the last whole character which fits into the cap and
+        // isn't cached, so the whole content is never decompressed for it
+        if let Some(limit) = self.max_content_bytes {
+            let mut data = match &self.data {
+                Some(val) => val.as_bytes()[..limit.min(val.len())].to_vec(),
+                None => self.decompressed_prefix(limit)?,
+            };
+            if let Err(err) = std::str::from_utf8(&data) {
+                if err.error_len().is_some() {
+                    return Err(
+                        self.corrupted(format!("document content is not valid utf-8: {}", err))
+                    );
+                }
+                data.truncate(err.valid_up_to());
+            }
+            return Ok(String::from_utf8(data).unwrap());
+        }
+
         let content = match &self.data {
             Some(val) => val.clone(),
             None => {
-                let DocLocation {
-                    segment,
-                    offset,
-                    size,
-                } = &self.location;
-
-                let data = File::open(segment.join("data"))?;
-                let mut buf = vec![0u8; *size];
-                data.read_exact_at(&mut buf, *offset)?;
-                let data = match decompress_size_prepended(&buf) {
+                let data = match String::from_utf8(self.decompressed()?) {
                     Ok(data) => data,
                     Err(err) => {
-                        return Err(PyValueError::new_err(format!(
-                            "Failed to decompress document content: {}",
-                            err
-                        )));
+                        return Err(
+                            self.corrupted(format!("document content is not valid utf-8: {}", err))
+                        );
                     }
                 };
-                let data = String::from_utf8(data)?;
                 self.data.replace(data.clone());
                 data
             }
@@ -129,13 +320,78 @@ impl Document {
 
         Ok(content)
     }
+
+    #[getter(content_bytes)]
+    pub fn content_bytes(&self) -> PyResult<Vec<u8>> {
+        // stored content without utf-8 decoding, readable even if it doesn't round-trip
+        match (&self.data, self.max_content_bytes) {
+            (Some(val), limit) => {
+                Ok(val.as_bytes()[..limit.unwrap_or(val.len()).min(val.len())].to_vec())
+            }
+            (None, Some(limit)) => self.decompressed_prefix(limit),
+            (None, None) => self.decompressed(),
+        }
+    }
+
+    #[getter(is_truncated)]
+    pub fn is_truncated(&self) -> PyResult<bool> {
+        // whether content is longer than max_content_bytes it was returned with, all
+        // of it is still stored, unlike positions_truncated it isn't about indexing
+        match self.max_content_bytes {
+            Some(limit) => Ok(self.content_size()? > limit),
+            None => Ok(false),
+        }
+    }
+
+    #[getter(payload)]
+    pub fn payload(&self) -> PyResult<StdHashMap<String, String>> {
+        // stored fields which were never tokenized
+        if self.payload_size == 0 {
+            return Ok(StdHashMap::new());
+        }
+
+        let size = self.payload_size as usize;
+        let record = self.read_record(self.location.size - size, size)?;
+        let encoded = decompress_size_prepended(&record).map_err(|err| {
+            self.corrupted(format!("failed to decompress document payload: {}", err))
+        })?;
+        match bincode::decode_from_slice::<Vec<(String, String)>, _>(
+            &encoded,
+            bincode::config::standard(),
+        ) {
+            Ok((fields, _)) => Ok(fields.into_iter().collect()),
+            Err(err) => Err(self.corrupted(format!("failed to decode document payload: {}", err))),
+        }
+    }
+
+    #[getter(boost)]
+    pub fn boost(&self) -> PyResult<f32> {
+        Ok(self.boost)
+    }
+
+    #[getter(positions_truncated)]
+    pub fn positions_truncated(&self) -> PyResult<bool> {
+        // whether words past max positions weren't indexed, it's set when document is
+        // added, unlike is_truncated which tells whether returned content was cut
+        Ok(self.truncated)
+    }
+}
+
+impl HeapSize for Document {
+    fn heap_size(&self) -> usize {
+        self.data.heap_size()
+            + self.location.segment.heap_size()
+            + self.tokens.heap_size()
+            + self.fields.heap_size()
+            + self.inline.heap_size()
+    }
 }
 
 #[derive(Decode, Encode, PartialEq, Debug, Clone)]
 pub struct DocLocation {
     pub segment: PathBuf,
-    pub offset: u64,
-    pub size: usize,
+    pub offset: u64, // inline document has offset of data file end at the time it was written
+    pub size: usize, // size of compressed content and payload, inline one isn't in data file
 }
 
 #[derive(Debug, Clone)]
@@ -143,24 +399,147 @@ struct Segment {
     name: u128,
     size: u64,
     deleted: u64,
+    bloom: BloomFilter, // filter over ids of documents stored in segment
+    meta_read: u64,     // bytes of meta file already loaded into memory
+    del_read: u64,      // bytes of del file already loaded into memory
+    // meta records not yet covered by offsets file, None if they aren't tracked
+    block: Option<OffsetsBlock>,
+}
+
+// range of ids of consecutive meta records, once it holds OFFSETS_BLOCK records it
+// is appended to segment offsets file as 16 bytes of min id, 16 bytes of max id and
+// 8 bytes of meta offset following its last record, all big endian
+#[derive(Debug, Clone, Default)]
+struct OffsetsBlock {
+    min: u128,
+    max: u128,
+    len: u32,
+}
+
+impl OffsetsBlock {
+    const ENTRY_SIZE: usize = 40;
+    const RECORDS: u32 = 64;
+
+    fn add(&mut self, id: u128) {
+        (self.min, self.max) = match self.len {
+            0 => (id, id),
+            _ => (self.min.min(id), self.max.max(id)),
+        };
+        self.len += 1;
+    }
+
+    fn encode_into_vec(&self, end: u64, vec: &mut Vec<u8>) {
+        vec.extend(self.min.to_be_bytes());
+        vec.extend(self.max.to_be_bytes());
+        vec.extend(end.to_be_bytes());
+    }
+
+    fn read(segment: &Path, meta_size: u64) -> Result<Vec<(u128, u128, u64)>, io::Error> {
+        // entries pointing behind meta file end were written without their records
+        let path = segment.join("offsets");
+        if !fs::exists(&path)? {
+            return Ok(vec![]);
+        }
+
+        Ok(fs::read(path)?
+            .chunks_exact(Self::ENTRY_SIZE)
+            .map(|entry| {
+                (
+                    u128::from_be_bytes(entry[..16].try_into().unwrap()),
+                    u128::from_be_bytes(entry[16..32].try_into().unwrap()),
+                    u64::from_be_bytes(entry[32..].try_into().unwrap()),
+                )
+            })
+            .take_while(|(_, _, end)| *end <= meta_size)
+            .collect())
+    }
+}
+
+// live documents of merged segment waiting to be read with a single read of data file,
+// their content forms one range, which may include deleted documents shorter than
+// READ_GAP, reading through them is cheaper than seeking, especially on spinning disks
+#[derive(Default)]
+struct ReadAhead {
+    docs: Vec<Document>,
+    range: Option<(u64, u64)>,
+}
+
+impl ReadAhead {
+    const READ_SIZE: u64 = 1024 * 1024;
+    const READ_GAP: u64 = 64 * 1024;
+
+    fn fits(&self, doc: &Document) -> bool {
+        // inline documents have no content in data file
+        let Some((start, end)) = self.range else {
+            return true;
+        };
+        let offset = doc.location.offset;
+        doc.inline.is_some()
+            || (offset >= end
+                && offset - end <= Self::READ_GAP
+                && offset + doc.location.size as u64 - start <= Self::READ_SIZE)
+    }
+
+    fn push(&mut self, doc: Document) {
+        if doc.inline.is_none() {
+            let end = doc.location.offset + doc.location.size as u64;
+            self.range = match self.range {
+                Some((start, _)) => Some((start, end)),
+                None => Some((doc.location.offset, end)),
+            };
+        }
+        self.docs.push(doc);
+    }
+
+    fn end(&self) -> u64 {
+        self.range.map_or(0, |(_, end)| end)
+    }
+
+    fn read(&mut self, data: &impl FileExt) -> Result<(Vec<u8>, u64, Vec<Document>), io::Error> {
+        // returns content of the range with offset it starts at and its documents
+        let (start, end) = self.range.take().unwrap_or_default();
+        let mut content = vec![0u8; (end - start) as usize];
+        data.read_exact_at(&mut content, start)?;
+        Ok((content, start, std::mem::take(&mut self.docs)))
+    }
+}
+
+// segment read from disk, bloom filter is None if it has to be rebuilt
+struct SegmentInfo {
+    name: u128,
+    size: u64,
+    deleted: u64,
+    del_size: u64,
+    bloom: Option<BloomFilter>,
 }
 
+// segments read from disk with ids of their deleted documents
+type StoredSegments = Vec<(PathBuf, SegmentInfo, HashSet<Ulid>)>;
+
 struct Buffer {
     segment_size: Option<u64>,
+    meta_size: Option<u64>,
     documents: Vec<u8>,
     meta: Vec<u8>,
+    offsets: Vec<u8>,
 }
 
 impl Buffer {
     fn new() -> Self {
         Self {
             segment_size: None,
+            meta_size: None,
             documents: vec![],
             meta: vec![],
+            offsets: vec![],
         }
     }
 
-    fn write_document(&mut self, doc: &str) -> Result<(usize, usize), DocumentBufferError> {
+    fn write_document(
+        &mut self,
+        doc: &str,
+        payload: &[u8],
+    ) -> Result<(usize, usize), DocumentBufferError> {
         // preappend document length
         self.documents.extend((doc.len() as u32).to_le_bytes());
         let offset = self.documents.len();
@@ -169,12 +548,14 @@ impl Buffer {
             .resize(offset + get_maximum_output_size(doc.len()), 0);
         let compressed_size = compress_into(doc.as_bytes(), &mut self.documents[offset..])?;
         self.documents.truncate(offset + compressed_size);
+        // already compressed payload follows content
+        self.documents.extend_from_slice(payload);
 
         // 4 bytes for extra preappended document length
-        Ok((offset - 4, compressed_size + 4))
+        Ok((offset - 4, compressed_size + 4 + payload.len()))
     }
 
-    fn write_meta(&mut self, doc: &Document) -> Result<(), DocumentBufferError> {
+    fn write_meta(&mut self, doc: &Document) -> Result<usize, DocumentBufferError> {
         let config = bincode::config::standard();
         let size = {
             let mut size_writer =
@@ -183,19 +564,35 @@ impl Buffer {
             size_writer.into_writer().bytes_written
         };
 
+        let meta_offset = self.meta.len();
         self.meta.extend((size as u64).to_be_bytes());
         let offset = self.meta.len();
         self.meta.resize(offset + size, 0);
 
         let size = bincode::encode_into_slice(&doc, &mut self.meta[offset..], config)?;
         self.meta.truncate(offset + size);
-        Ok(())
+
+        // return offset of meta record in buffer
+        Ok(meta_offset)
     }
 
     fn reset(&mut self) {
         self.documents.clear();
         self.meta.clear();
+        self.offsets.clear();
         self.segment_size.take();
+        self.meta_size.take();
+    }
+
+    fn meta_size(&mut self, segment: &Path) -> Result<u64, io::Error> {
+        match self.meta_size {
+            Some(size) => Ok(size),
+            None => {
+                let size = File::open(segment.join("meta"))?.metadata()?.len();
+                self.meta_size.replace(size);
+                Ok(size)
+            }
+        }
     }
 
     fn segment_size(&mut self, segment: &PathBuf) -> Result<u64, io::Error> {
@@ -212,22 +609,321 @@ impl Buffer {
     }
 }
 
+fn read_meta_record(
+    meta: &impl FileExt,
+    offset: u64,
+) -> Result<(Document, u64), DocumentsManagerError> {
+    // returns document with size of its record
+    let mut size = [0u8; 8];
+    meta.read_exact_at(&mut size, offset)?;
+    let size = u64::from_be_bytes(size);
+    let mut doc = vec![0u8; size as usize];
+    meta.read_exact_at(&mut doc, offset + 8)?;
+    let (doc, _): (Document, usize) =
+        bincode::decode_from_slice(&doc, bincode::config::standard())?;
+    Ok((doc, 8 + size))
+}
+
+// Sorted on disk table mapping document id to offset of its meta record,
+// each entry is 16 bytes of big endian id followed by 8 bytes of offset
+struct KeysTable {
+    meta: ReopeningFile,
+    keys: Option<Mmap>,
+    blocks: Vec<KeysBlock>, // documents covered by offsets file, read on first access
+    pending: HashMap<Ulid, u64>, // documents not yet written into sealed table
+    deleted: HashSet<Ulid>,
+}
+
+// meta records between start and end offsets with ids in min..=max range
+struct KeysBlock {
+    min: u128,
+    max: u128,
+    start: u64,
+    end: u64,
+    keys: OnceLock<Vec<(u128, u64)>>, // sorted ids with offsets of their meta records
+}
+
+impl KeysTable {
+    const ENTRY_SIZE: usize = 24;
+
+    fn new(segment: &Path, deleted: HashSet<Ulid>) -> Result<Self, io::Error> {
+        Ok(Self {
+            meta: ReopeningFile::open(segment.join("meta"))?,
+            keys: None,
+            blocks: vec![],
+            pending: HashMap::new(),
+            deleted,
+        })
+    }
+
+    fn open(segment: &Path, deleted: HashSet<Ulid>) -> Result<Self, io::Error> {
+        let mut table = Self::new(segment, deleted)?;
+        let keys = File::open(segment.join("keys"))?;
+        if keys.metadata()?.len() > 0 {
+            table.keys.replace(unsafe { Mmap::map(&keys)? });
+        }
+        Ok(table)
+    }
+
+    fn len(&self) -> usize {
+        let sealed = match &self.keys {
+            Some(keys) => keys.len() / Self::ENTRY_SIZE,
+            None => 0,
+        };
+        sealed + self.blocks.len() * OffsetsBlock::RECORDS as usize + self.pending.len()
+    }
+
+    fn add_blocks(&mut self, entries: Vec<(u128, u128, u64)>) {
+        let mut start = 0;
+        for (min, max, end) in entries {
+            self.blocks.push(KeysBlock {
+                min,
+                max,
+                start,
+                end,
+                keys: OnceLock::new(),
+            });
+            start = end;
+        }
+    }
+
+    fn block_keys<'a>(&self, block: &'a KeysBlock) -> &'a Vec<(u128, u64)> {
+        // read errors are treated as missing documents, like in get
+        block.keys.get_or_init(|| {
+            let (mut keys, mut offset) = (vec![], block.start);
+            while offset < block.end {
+                match read_meta_record(&self.meta, offset) {
+                    Ok((doc, size)) => {
+                        keys.push((Ulid::from_bytes(doc.id).0, offset));
+                        offset += size;
+                    }
+                    Err(_) => break,
+                }
+            }
+            keys.sort_unstable_by_key(|x| x.0);
+            keys
+        })
+    }
+
+    fn entry(keys: &[u8], idx: usize) -> (u128, u64) {
+        let entry = &keys[idx * Self::ENTRY_SIZE..(idx + 1) * Self::ENTRY_SIZE];
+        (
+            u128::from_be_bytes(entry[..16].try_into().unwrap()),
+            u64::from_be_bytes(entry[16..].try_into().unwrap()),
+        )
+    }
+
+    fn offset(&self, id: &Ulid) -> Option<u64> {
+        if self.deleted.contains(id) {
+            return None;
+        }
+
+        if let Some(offset) = self.pending.get(id) {
+            return Some(*offset);
+        }
+
+        let (mut lo, mut hi) = match &self.keys {
+            Some(keys) => (0, keys.len() / Self::ENTRY_SIZE),
+            None => (0, 0),
+        };
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let (key, offset) = Self::entry(self.keys.as_ref().unwrap(), mid);
+            match key.cmp(&id.0) {
+                std::cmp::Ordering::Equal => return Some(offset),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+
+        self.blocks
+            .iter()
+            .filter(|block| block.min <= id.0 && id.0 <= block.max)
+            .find_map(|block| {
+                let keys = self.block_keys(block);
+                let idx = keys.binary_search_by_key(&id.0, |x| x.0).ok()?;
+                Some(keys[idx].1)
+            })
+    }
+
+    fn ids(&self) -> impl Iterator<Item = Ulid> + '_ {
+        let sealed = match &self.keys {
+            Some(keys) => keys.len() / Self::ENTRY_SIZE,
+            None => 0,
+        };
+
+        (0..sealed)
+            .map(|idx| Ulid(Self::entry(self.keys.as_ref().unwrap(), idx).0))
+            .chain(
+                self.blocks
+                    .iter()
+                    .flat_map(|block| self.block_keys(block).iter().map(|x| Ulid(x.0))),
+            )
+            .chain(self.pending.keys().copied())
+            .filter(|id| !self.deleted.contains(id))
+    }
+
+    fn get(&self, id: &Ulid) -> Option<Document> {
+        let offset = self.offset(id)?;
+        read_meta_record(&self.meta, offset)
+            .ok()
+            .map(|(doc, _)| doc)
+    }
+
+    fn last_id(&self) -> Option<u128> {
+        // greatest id in table including deleted ones, sealed keys are sorted
+        let sealed = match &self.keys {
+            Some(keys) if !keys.is_empty() => {
+                Some(Self::entry(keys, keys.len() / Self::ENTRY_SIZE - 1).0)
+            }
+            _ => None,
+        };
+        self.pending
+            .keys()
+            .map(|id| id.0)
+            .chain(self.blocks.iter().map(|block| block.max))
+            .chain(sealed)
+            .max()
+    }
+
+    fn get_many(&self, offsets: &[u64]) -> Vec<Option<Document>> {
+        // meta records at given offsets, sizes of all records are read together first
+        // and then records themselves, so it takes two batches instead of two reads
+        // per document
+        let mut sizes = offsets
+            .iter()
+            .map(|offset| (*offset, vec![0u8; 8]))
+            .collect::<Vec<(u64, Vec<u8>)>>();
+        let found = self.meta.read_batch_at(&mut sizes);
+
+        let mut records = sizes
+            .into_iter()
+            .zip(found)
+            .map(|((offset, size), found)| {
+                let size = match found {
+                    Ok(()) => u64::from_be_bytes(size.try_into().unwrap()),
+                    Err(_) => 0,
+                };
+                (offset + 8, vec![0u8; size as usize])
+            })
+            .collect::<Vec<(u64, Vec<u8>)>>();
+        let read = self.meta.read_batch_at(&mut records);
+
+        records
+            .into_iter()
+            .zip(read)
+            .map(|((_, record), read)| {
+                // empty record is a failed read of its size
+                if read.is_err() || record.is_empty() {
+                    return None;
+                }
+                bincode::decode_from_slice::<Document, _>(&record, bincode::config::standard())
+                    .ok()
+                    .map(|(doc, _)| doc)
+            })
+            .collect()
+    }
+
+    fn seal(&mut self, segment: &Path) -> Result<(), io::Error> {
+        // merges pending entries with already sealed ones into new sorted table
+        let mut entries = Vec::with_capacity(self.len());
+        if let Some(keys) = &self.keys {
+            for idx in 0..keys.len() / Self::ENTRY_SIZE {
+                entries.push(Self::entry(keys, idx));
+            }
+        }
+        for block in std::mem::take(&mut self.blocks) {
+            entries.extend(self.block_keys(&block));
+        }
+        entries.extend(self.pending.drain().map(|(id, offset)| (id.0, offset)));
+        entries.sort_unstable_by_key(|x| x.0);
+
+        let mut buf = Vec::with_capacity(entries.len() * Self::ENTRY_SIZE);
+        for (id, offset) in entries {
+            buf.extend(id.to_be_bytes());
+            buf.extend(offset.to_be_bytes());
+        }
+
+        let (path, tmp) = (segment.join("keys"), segment.join("keys.tmp"));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+
+        self.keys = match buf.len() {
+            0 => None,
+            _ => Some(unsafe { Mmap::map(&File::open(&path)?)? }),
+        };
+        self.meta = ReopeningFile::open(segment.join("meta"))?;
+        Ok(())
+    }
+}
+
+pub struct DiskDocuments {
+    tables: HashMap<PathBuf, KeysTable>,
+    unflushed: HashMap<Ulid, (Document, u64)>, // documents still in buffer with their meta offset
+    len: usize,
+}
+
+pub enum Documents {
+    Memory(HashMap<Ulid, Document>),
+    Disk(DiskDocuments),
+}
+
 pub struct DocumentsManager {
     pub dir: PathBuf,
-    pub docs: HashMap<Ulid, Document>,
+    docs: Documents,
     pub deleted_docs_buffer: HashMap<Ulid, Document>,
     buffer: Buffer,
     segments: HashMap<PathBuf, Segment>,
     cur_segment: PathBuf,
     last_save: u64,
+    latest_id: Ulid, // greatest id ever written, including deleted documents
     config: Arc<Config>,
 }
 
 impl DocumentsManager {
+    pub fn memory_usage(&self) -> (usize, usize, usize) {
+        // estimated bytes of documents map (with segments bloom filters), write buffer
+        // and keys of on disk tables read on demand
+        let (mut documents, mut caches) = (0, 0);
+        match &self.docs {
+            Documents::Memory(docs) => documents += docs.heap_size(),
+            Documents::Disk(disk) => {
+                documents += disk.unflushed.heap_size();
+                for table in disk.tables.values() {
+                    documents += table.pending.heap_size() + table.deleted.heap_size();
+                    documents += table.blocks.capacity() * size_of::<KeysBlock>();
+                    caches += table
+                        .blocks
+                        .iter()
+                        .filter_map(|block| block.keys.get())
+                        .map(|keys| keys.heap_size())
+                        .sum::<usize>();
+                }
+            }
+        }
+        documents += self.deleted_docs_buffer.heap_size();
+        documents += self
+            .segments
+            .values()
+            .map(|segment| segment.bloom.heap_size())
+            .sum::<usize>();
+
+        let buffers = self.buffer.documents.capacity()
+            + self.buffer.meta.capacity()
+            + self.buffer.offsets.capacity();
+        (documents, buffers, caches)
+    }
+
     pub fn load(dir: PathBuf, config: Arc<Config>) -> Result<Self, DocumentsManagerError> {
         let (mut documents, mut segments_map) = (HashMap::new(), HashMap::new());
+        let (mut tables, mut disk_len) = (HashMap::new(), 0);
+        let mut latest_id = 0;
+        let on_disk = config.documents_storage == DocumentsStorage::Disk;
 
-        let cur_segment = match Self::segments(&dir)? {
+        let inline = config.inline_documents_size.is_some();
+        let cur_segment = match Self::segments(&dir, config.segment_bloom_bits, inline)? {
             Some(segments) => {
                 let cur_segment = segments
                     .iter()
@@ -238,11 +934,41 @@ impl DocumentsManager {
 
                 // TODO: in future can validate segment files before loading them
                 // to check if they are not malicious or corrupted
-                for (path, segment, deletes) in segments {
+                for (path, mut segment, deletes) in segments {
+                    let rebuild_bloom = segment.bloom.is_none();
+                    let mut bloom = segment
+                        .bloom
+                        .take()
+                        .unwrap_or_else(|| BloomFilter::new(config.segment_bloom_bits));
+
+                    // sealed keys table can be used only for segments that won't be written anymore
+                    let mut table = match on_disk {
+                        true if path != cur_segment && fs::exists(path.join("keys"))? => {
+                            Some(KeysTable::open(&path, deletes.clone())?)
+                        }
+                        true => Some(KeysTable::new(&path, deletes.clone())?),
+                        false => None,
+                    };
+                    let scan = !on_disk || rebuild_bloom || table.as_ref().unwrap().keys.is_none();
+
                     let mut meta = File::open(path.join("meta"))?;
                     let meta_size = meta.metadata()?.len();
 
-                    while meta.stream_position()? < meta_size {
+                    // on disk only records behind the offsets file are read, the ones
+                    // covered by it are read when they are first looked up
+                    let offsets = OffsetsBlock::read(&path, meta_size)?;
+                    let offsets_end = offsets.last().map_or(0, |(_, _, end)| *end);
+                    if let Some(table) = &mut table
+                        && table.keys.is_none()
+                        && !rebuild_bloom
+                    {
+                        table.add_blocks(offsets);
+                        meta.seek(io::SeekFrom::Start(offsets_end))?;
+                    }
+                    let (mut block, mut entries) = (OffsetsBlock::default(), vec![]);
+
+                    while scan && meta.stream_position()? < meta_size {
+                        let offset = meta.stream_position()?;
                         let mut size = [0u8; 8];
                         meta.read_exact(&mut size)?;
                         let size = u64::from_be_bytes(size);
@@ -252,28 +978,94 @@ impl DocumentsManager {
                             bincode::decode_from_slice(&doc, bincode::config::standard())?;
 
                         let ulid = Ulid::from_bytes(doc.id);
-                        if deletes.contains(&ulid) {
+                        latest_id = latest_id.max(ulid.0);
+                        if rebuild_bloom {
+                            bloom.add(ulid.0);
+                        }
+
+                        // offsets of segments written without them are caught up
+                        if offset >= offsets_end {
+                            block.add(ulid.0);
+                            if block.len == OffsetsBlock::RECORDS {
+                                block.encode_into_vec(offset + 8 + size, &mut entries);
+                                block = OffsetsBlock::default();
+                            }
+                        }
+
+                        if let Some(table) = &mut table
+                            && table.keys.is_none()
+                        {
+                            table.pending.insert(ulid, offset);
+                        }
+
+                        if deletes.contains(&ulid) || on_disk {
                             continue;
                         }
 
                         documents.insert(Ulid::from_bytes(doc.id), doc);
                     }
 
-                    segments_map.insert(path, segment);
+                    if rebuild_bloom {
+                        bloom.save(&path.join("bloom"))?;
+                    }
+
+                    if !entries.is_empty() {
+                        File::options()
+                            .create(true)
+                            .append(true)
+                            .open(path.join("offsets"))?
+                            .write_all(&entries)?;
+                    }
+
+                    if let Some(mut table) = table {
+                        latest_id = latest_id.max(table.last_id().unwrap_or(0));
+                        if path != cur_segment
+                            && (!table.pending.is_empty() || !table.blocks.is_empty())
+                        {
+                            table.seal(&path)?;
+                        }
+                        disk_len += table.len() - deletes.len().min(table.len());
+                        tables.insert(path.clone(), table);
+                    }
+
+                    segments_map.insert(
+                        path,
+                        Segment {
+                            name: segment.name,
+                            size: segment.size,
+                            deleted: segment.deleted,
+                            bloom,
+                            meta_read: meta_size,
+                            del_read: segment.del_size,
+                            block: scan.then_some(block),
+                        },
+                    );
                 }
                 cur_segment
             }
             None => {
                 fs::create_dir_all(&dir)?;
-                let (path, segment) = Self::create_segment(&dir)?;
+                let (path, segment) = Self::create_segment(&dir, config.segment_bloom_bits)?;
+                if on_disk {
+                    tables.insert(path.clone(), KeysTable::new(&path, HashSet::new())?);
+                }
                 segments_map.insert(path.clone(), segment);
                 path
             }
         };
 
-        Ok(Self {
-            docs: documents,
-            deleted_docs_buffer: HashMap::with_capacity(100),
+        let docs = match on_disk {
+            true => Documents::Disk(DiskDocuments {
+                tables,
+                unflushed: HashMap::new(),
+                len: disk_len,
+            }),
+            false => Documents::Memory(documents),
+        };
+
+        let mut manager = Self {
+            docs,
+            deleted_docs_buffer: Self::pending_deletes(&dir)?,
             dir: dir,
             buffer: Buffer::new(),
             segments: segments_map,
@@ -281,22 +1073,154 @@ impl DocumentsManager {
             last_save: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)?
                 .as_secs(),
+            latest_id: Ulid(latest_id),
             config: config,
-        })
+        };
+        if manager.config.coalesce_on_open {
+            manager.coalesce()?;
+        }
+        Ok(manager)
+    }
+
+    fn coalesce(&mut self) -> Result<(), DocumentsManagerError> {
+        // rewrites small segments into the current one, oldest first
+        let mut small = self
+            .small_segments()
+            .into_iter()
+            .map(|path| (self.segments[&path].name, path))
+            .collect::<Vec<(u128, PathBuf)>>();
+        if small.is_empty() {
+            return Ok(());
+        }
+        small.sort_unstable();
+        for (_, path) in small {
+            self.merge_segment(path, 0, 0, &mut |_, _| true)?;
+        }
+        self.flush()?;
+        Ok(())
     }
 
+    pub fn latest_id(&self) -> Ulid {
+        self.latest_id
+    }
+
+    pub fn get(&self, id: &Ulid) -> Option<Cow<'_, Document>> {
+        // in disk mode documents are read from meta records, read errors are treated as missing documents
+        match &self.docs {
+            Documents::Memory(docs) => docs.get(id).map(Cow::Borrowed),
+            Documents::Disk(docs) => {
+                if let Some((doc, _)) = docs.unflushed.get(id) {
+                    return Some(Cow::Borrowed(doc));
+                }
+
+                self.segments
+                    .iter()
+                    .filter(|(_, segment)| segment.bloom.may_contain(id.0))
+                    .find_map(|(path, _)| docs.tables.get(path)?.get(id))
+                    .map(Cow::Owned)
+            }
+        }
+    }
+
+    pub fn get_many(&self, ids: &[Ulid]) -> Vec<Option<Cow<'_, Document>>> {
+        // like get for every id, but meta records of the same segment are read together
+        let docs = match &self.docs {
+            Documents::Memory(docs) => {
+                return ids
+                    .iter()
+                    .map(|id| docs.get(id).map(Cow::Borrowed))
+                    .collect();
+            }
+            Documents::Disk(docs) => docs,
+        };
+
+        let mut found = Vec::with_capacity(ids.len());
+        let mut reads: HashMap<&PathBuf, Vec<(usize, u64)>> = HashMap::new();
+        for (i, id) in ids.iter().enumerate() {
+            if let Some((doc, _)) = docs.unflushed.get(id) {
+                found.push(Some(Cow::Borrowed(doc)));
+                continue;
+            }
+            found.push(None);
+
+            let table = self
+                .segments
+                .iter()
+                .filter(|(_, segment)| segment.bloom.may_contain(id.0))
+                .find_map(|(path, _)| Some((path, docs.tables.get(path)?.offset(id)?)));
+            if let Some((path, offset)) = table {
+                reads.entry(path).or_default().push((i, offset));
+            }
+        }
+
+        for (path, reads) in reads {
+            let offsets = reads
+                .iter()
+                .map(|(_, offset)| *offset)
+                .collect::<Vec<u64>>();
+            for ((i, _), doc) in reads.iter().zip(docs.tables[path].get_many(&offsets)) {
+                found[*i] = doc.map(Cow::Owned);
+            }
+        }
+        found
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.docs {
+            Documents::Memory(docs) => docs.len(),
+            Documents::Disk(docs) => docs.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn ids(&self) -> Vec<Ulid> {
+        // ids of all live documents in ascending order, so also in order they were added
+        let mut ids: Vec<Ulid> = match &self.docs {
+            Documents::Memory(docs) => docs.keys().copied().collect(),
+            Documents::Disk(docs) => docs
+                .unflushed
+                .keys()
+                .copied()
+                .chain(docs.tables.values().flat_map(|table| table.ids()))
+                .collect(),
+        };
+        ids.sort_unstable();
+        ids
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn write(
         &mut self,
         id: Ulid,
         len: u32,
         tokens: Vec<u32>,
+        boost: f32,
+        fields: Vec<(String, u32)>,
+        truncated: bool,
         content: &str,
+        payload: &[u8],
     ) -> Result<(), DocumentsManagerError> {
-        // write segment to buffer
-        let (data_offset, size) = self.buffer.write_document(&content)?;
+        // write segment to buffer, small document is kept only in its meta record
+        let inline = match self.config.inline_documents_size {
+            Some(limit) if content.len() + payload.len() <= limit => Some(
+                [
+                    compress_prepend_size(content.as_bytes()).as_slice(),
+                    payload,
+                ]
+                .concat(),
+            ),
+            _ => None,
+        };
+        let (data_offset, size) = match &inline {
+            Some(inline) => (self.buffer.documents.len(), inline.len()),
+            None => self.buffer.write_document(content, payload)?,
+        };
         let offset = self.buffer.segment_size(&self.cur_segment)? + data_offset as u64;
 
-        let doc = Document::new(
+        let mut doc = Document::new(
             id.to_bytes(),
             DocLocation {
                 segment: self.cur_segment.clone(),
@@ -305,19 +1229,44 @@ impl DocumentsManager {
             },
             len,
             tokens,
+            boost,
+            fields,
+            truncated,
         );
+        doc.inline = inline;
+        doc.payload_size = payload.len() as u32;
 
-        self.buffer.write_meta(&doc)?;
-        self.save_buffer(offset + size as u64)?;
+        let meta_offset =
+            self.buffer.meta_size(&self.cur_segment)? + self.buffer.write_meta(&doc)? as u64;
+        if let Some(segment) = self.segments.get_mut(&self.cur_segment) {
+            segment.bloom.add(id.0);
+        }
+        self.latest_id = self.latest_id.max(id);
+        self.track_offsets(id.0)?;
 
-        self.docs.insert(id, doc);
+        match &mut self.docs {
+            Documents::Memory(docs) => {
+                docs.insert(id, doc);
+            }
+            Documents::Disk(docs) => {
+                docs.unflushed.insert(id, (doc, meta_offset));
+                docs.len += 1;
+            }
+        }
+
+        let segment_size = self.buffered_segment_size()?;
+        self.save_buffer(segment_size)?;
 
         return Ok(());
     }
 
-    pub fn delete(&mut self, id: Ulid) -> Result<(), io::Error> {
-        let doc = match self.docs.get(&id) {
-            Some(doc) => doc,
+    pub fn delete(&mut self, id: Ulid) -> Result<(), DocumentsManagerError> {
+        if !self.contains(&id) {
+            return Ok(());
+        }
+
+        let doc = match self.get(&id) {
+            Some(doc) => doc.into_owned(),
             None => return Ok(()),
         };
 
@@ -328,10 +1277,112 @@ impl DocumentsManager {
         deletes.write_all(&(doc.location.size as u64).to_be_bytes())?;
         if let Some(segment) = self.segments.get_mut(&doc.location.segment) {
             segment.deleted += doc.location.size as u64;
+            segment.del_read += 24;
         }
 
-        if let Some(doc) = self.docs.remove(&id) {
-            self.deleted_docs_buffer.insert(id, doc);
+        match &mut self.docs {
+            Documents::Memory(docs) => {
+                docs.remove(&id);
+            }
+            Documents::Disk(docs) => {
+                // unflushed documents never make it into keys table
+                if docs.unflushed.remove(&id).is_none()
+                    && let Some(table) = docs.tables.get_mut(&doc.location.segment)
+                {
+                    table.deleted.insert(id);
+                }
+                docs.len -= 1;
+            }
+        }
+
+        // deleted documents postings are removed from index only on flush, record
+        // pending delete so it isn't lost if process stops before that
+        let mut pending = doc.clone();
+        pending.data = None;
+        let bytes = bincode::encode_to_vec(&pending, bincode::config::standard())?;
+        let mut file = File::options()
+            .create(true)
+            .append(true)
+            .open(self.dir.join("pending_deletes"))?;
+        file.write_all(&(bytes.len() as u64).to_be_bytes())?;
+        file.write_all(&bytes)?;
+
+        self.deleted_docs_buffer.insert(id, doc);
+        Ok(())
+    }
+
+    pub fn clear_pending_deletes(&self) -> Result<(), io::Error> {
+        // called once deletes are removed from persisted index
+        match self.deleted_docs_buffer.is_empty() {
+            true => File::create(self.dir.join("pending_deletes")).map(|_| ()),
+            false => Ok(()),
+        }
+    }
+
+    fn pending_deletes(dir: &Path) -> Result<HashMap<Ulid, Document>, DocumentsManagerError> {
+        let mut docs = HashMap::with_capacity(100);
+        let path = dir.join("pending_deletes");
+        if !fs::exists(&path)? {
+            return Ok(docs);
+        }
+
+        let bytes = fs::read(path)?;
+        let mut offset = 0;
+        while offset + 8 <= bytes.len() {
+            let size = u64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap()) as usize;
+            // skip record which was partially written
+            if offset + 8 + size > bytes.len() {
+                break;
+            }
+
+            let (doc, _): (Document, usize) = bincode::decode_from_slice(
+                &bytes[offset + 8..offset + 8 + size],
+                bincode::config::standard(),
+            )?;
+            docs.insert(Ulid::from_bytes(doc.id), doc);
+            offset += 8 + size;
+        }
+
+        Ok(docs)
+    }
+
+    pub fn contains(&self, id: &Ulid) -> bool {
+        // on disk segments bloom filters allows to quickly reject ids that were never
+        // written, before their keys tables are looked up
+        match &self.docs {
+            Documents::Memory(docs) => docs.contains_key(id),
+            Documents::Disk(docs) => {
+                docs.unflushed.contains_key(id)
+                    || self
+                        .segments
+                        .iter()
+                        .filter(|(_, segment)| segment.bloom.may_contain(id.0))
+                        .any(|(path, _)| match docs.tables.get(path) {
+                            Some(table) => table.offset(id).is_some(),
+                            None => false,
+                        })
+            }
+        }
+    }
+
+    pub fn check_buffer(&self) -> Result<(), io::Error> {
+        // documents buffer is appended to current segment on flush
+        for file in ["data", "meta"] {
+            File::options()
+                .append(true)
+                .open(self.cur_segment.join(file))?;
+        }
+        Ok(())
+    }
+
+    pub fn check_segments(&self) -> Result<(), io::Error> {
+        // del file is created with the first delete from segment
+        for path in self.segments.keys() {
+            File::open(path.join("data"))?;
+            File::open(path.join("meta"))?;
+            if fs::exists(path.join("del"))? {
+                File::open(path.join("del"))?;
+            }
         }
         Ok(())
     }
@@ -348,43 +1399,248 @@ impl DocumentsManager {
         // flush data to disk
         data.write_all(&self.buffer.documents)?;
         meta.write_all(&self.buffer.meta)?;
+        // offsets are written after meta, so they never point to missing records
+        if !self.buffer.offsets.is_empty() {
+            File::options()
+                .create(true)
+                .append(true)
+                .open(self.cur_segment.join("offsets"))?
+                .write_all(&self.buffer.offsets)?;
+        }
+        if let Some(segment) = self.segments.get_mut(&self.cur_segment) {
+            segment.meta_read += self.buffer.meta.len() as u64;
+        }
         self.buffer.reset();
+
+        // flushed documents can be now read from meta records
+        if let Documents::Disk(docs) = &mut self.docs {
+            for (id, (doc, offset)) in docs.unflushed.drain() {
+                if let Some(table) = docs.tables.get_mut(&doc.location.segment) {
+                    table.pending.insert(id, offset);
+                }
+            }
+        }
+
+        // filter is large, so it's written only if flushed documents changed it
+        if let Some(segment) = self.segments.get_mut(&self.cur_segment)
+            && segment.bloom.is_changed()
+        {
+            segment.bloom.save(&self.cur_segment.join("bloom"))?;
+        }
+        Ok(())
+    }
+
+    pub fn reload(&mut self) -> Result<(), DocumentsManagerError> {
+        // Loads segments changes made by other writer process
+
+        let segments = Self::segment_dirs(&self.dir)?;
+        for (path, name) in segments.iter() {
+            if !self.segments.contains_key(path) {
+                let bloom = BloomFilter::load(&path.join("bloom"), self.config.segment_bloom_bits)?;
+                self.segments.insert(
+                    path.clone(),
+                    Segment {
+                        name: *name,
+                        size: 0,
+                        deleted: 0,
+                        bloom: bloom
+                            .unwrap_or_else(|| BloomFilter::new(self.config.segment_bloom_bits)),
+                        meta_read: 0,
+                        del_read: 0,
+                        block: None,
+                    },
+                );
+
+                if let Documents::Disk(docs) = &mut self.docs {
+                    docs.tables
+                        .insert(path.clone(), KeysTable::new(path, HashSet::new())?);
+                }
+            }
+
+            self.read_segment_tail(path)?;
+        }
+
+        // segments removed by merge, their live documents were already moved to other segments
+        let removed = self
+            .segments
+            .keys()
+            .filter(|path| !segments.iter().any(|(p, _)| p == *path))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+
+        for path in removed {
+            self.segments.remove(&path);
+            match &mut self.docs {
+                Documents::Memory(docs) => docs.retain(|_, doc| doc.location.segment != path),
+                Documents::Disk(docs) => {
+                    if let Some(table) = docs.tables.remove(&path) {
+                        docs.len -= table.len() - table.deleted.len();
+                    }
+                }
+            }
+        }
+
+        if let Some((path, _)) = segments.iter().max_by(|x, y| x.1.cmp(&y.1)) {
+            self.cur_segment = path.clone();
+            self.buffer.reset();
+        }
+
         Ok(())
     }
 
-    pub fn merge(&mut self) -> Result<(), DocumentsManagerError> {
-        // Merges the segments cleaning up deleted data
+    fn read_segment_tail(&mut self, path: &PathBuf) -> Result<(), DocumentsManagerError> {
+        // reads meta and del records appended to segment since the last read
+        let segment = match self.segments.get_mut(path) {
+            Some(segment) => segment,
+            None => return Ok(()),
+        };
+
+        let data_size = File::open(path.join("data"))?.metadata()?.len();
+        segment.size =
+            Self::stored_size(path, self.config.inline_documents_size.is_some(), data_size)?;
+
+        let mut meta = File::open(path.join("meta"))?;
+        let meta_size = meta.metadata()?.len();
+        meta.seek(io::SeekFrom::Start(segment.meta_read))?;
+        // records written by other process are covered by offsets it writes
+        if meta_size > segment.meta_read {
+            segment.block = None;
+        }
+
+        while meta.stream_position()? < meta_size {
+            let offset = meta.stream_position()?;
+            let mut size = [0u8; 8];
+            meta.read_exact(&mut size)?;
+            let mut doc = vec![0u8; u64::from_be_bytes(size) as usize];
+            meta.read_exact(&mut doc)?;
+            let (doc, _): (Document, usize) =
+                bincode::decode_from_slice(&doc, bincode::config::standard())?;
+
+            let ulid = Ulid::from_bytes(doc.id);
+            segment.bloom.add(ulid.0);
 
+            match &mut self.docs {
+                Documents::Memory(docs) => {
+                    docs.insert(ulid, doc);
+                }
+                Documents::Disk(docs) => {
+                    if let Some(table) = docs.tables.get_mut(path) {
+                        table.pending.insert(ulid, offset);
+                        docs.len += 1;
+                    }
+                }
+            }
+        }
+        segment.meta_read = meta_size;
+
+        let mut del = File::open(path.join("del"))?;
+        let del_size = del.metadata()?.len();
+        del.seek(io::SeekFrom::Start(segment.del_read))?;
+
+        while del.stream_position()? < del_size {
+            let (mut size, mut deleted) = ([0u8; 8], [0u8; 16]);
+            del.read_exact(&mut deleted)?;
+            del.read_exact(&mut size)?;
+            segment.deleted += u64::from_be_bytes(size);
+
+            let ulid = Ulid::from_bytes(deleted);
+            match &mut self.docs {
+                Documents::Memory(docs) => {
+                    if let Some(doc) = docs.get(&ulid)
+                        && doc.location.segment == *path
+                    {
+                        docs.remove(&ulid);
+                    }
+                }
+                Documents::Disk(docs) => {
+                    if let Some(table) = docs.tables.get_mut(path)
+                        && table.offset(&ulid).is_some()
+                    {
+                        table.deleted.insert(ulid);
+                        docs.len -= 1;
+                    }
+                }
+            }
+        }
+        segment.del_read = del_size;
+
+        Ok(())
+    }
+
+    pub fn merge(
+        &mut self,
+        progress: &mut dyn FnMut(u64, u64) -> bool,
+    ) -> Result<bool, DocumentsManagerError> {
+        // Merges the segments cleaning up deleted data, progress receives processed and
+        // total bytes of merged segments and returns false to cancel the merge, which
+        // takes effect once the segment being rewritten is finished, so no document
+        // is left in two segments, returns false if merge was cancelled
+
+        let small = self.small_segments();
         let mut segments = self
             .segments
             .clone()
             .into_iter()
+            .filter(|(path, segment)| {
+                *path != self.cur_segment
+                    && (small.contains(path)
+                        || segment.size > 0
+                            && (segment.deleted as f64 / segment.size as f64)
+                                >= self.config.merge_deleted_ratio)
+            })
             .collect::<Vec<(PathBuf, Segment)>>();
         segments.sort_by(|x, y| x.1.name.cmp(&y.1.name));
 
-        let mut merged = false;
+        let total = segments.iter().map(|(_, segment)| segment.size).sum();
+        let (mut processed, mut proceed) = (0, true);
         for (path, segment) in segments {
-            merged = merged || self.merge_segment(path, segment)?;
+            if !proceed {
+                break;
+            }
+            let size = segment.size;
+            proceed = self.merge_segment(path, processed, total, progress)?;
+            processed += size;
+            proceed &= progress(processed, total);
         }
 
-        if merged {
+        if processed > 0 || !small.is_empty() {
             self.flush()?;
         }
 
-        Ok(())
+        Ok(proceed)
+    }
+
+    fn small_segments(&self) -> HashSet<PathBuf> {
+        // segments smaller than configured size, which are rewritten into the current one
+        // by merge (and on open if enabled) if there are more of them, so number of
+        // segments and their open files stays bounded
+        let Some(limit) = self.config.coalesce_segments_below else {
+            return HashSet::new();
+        };
+        let small = self
+            .segments
+            .iter()
+            .filter(|(path, segment)| **path != self.cur_segment && segment.size < limit)
+            .map(|(path, _)| path.clone())
+            .collect::<HashSet<PathBuf>>();
+        let current_small = self
+            .segments
+            .get(&self.cur_segment)
+            .is_some_and(|segment| segment.size < limit);
+        match small.len() + (current_small as usize) < 2 {
+            true => HashSet::new(),
+            false => small,
+        }
     }
 
     fn merge_segment(
         &mut self,
         path: PathBuf,
-        segment: Segment,
+        processed: u64,
+        total: u64,
+        progress: &mut dyn FnMut(u64, u64) -> bool,
     ) -> Result<bool, DocumentsManagerError> {
-        if path == self.cur_segment
-            || segment.size == 0
-            || (segment.deleted as f64 / segment.size as f64) < self.config.merge_deleted_ratio
-        {
-            return Ok(false);
-        }
+        // returns false if cancellation was requested while segment was rewritten
 
         let mut deletes = HashSet::new();
         let mut del = File::open(path.join("del"))?;
@@ -397,46 +1653,88 @@ impl DocumentsManager {
             deletes.insert(Ulid::from_bytes(ulid));
         }
 
-        let data = File::open(path.join("data"))?;
+        let data = ReopeningFile::open(path.join("data"))?;
         let mut meta = File::open(path.join("meta"))?;
         let meta_size = meta.metadata()?.len();
 
+        let (mut ahead, mut proceed) = (ReadAhead::default(), true);
         while meta.stream_position()? < meta_size {
             let mut size_buf = [0u8; 8];
             meta.read_exact(&mut size_buf)?;
             let size = u64::from_be_bytes(size_buf);
             let mut doc_buf = vec![0u8; size as usize];
             meta.read_exact(&mut doc_buf)?;
-            let (mut doc, _): (Document, usize) =
+            let (doc, _): (Document, usize) =
                 bincode::decode_from_slice(&doc_buf, bincode::config::standard())?;
 
-            let ulid = Ulid::from_bytes(doc.id);
-            if deletes.contains(&ulid) {
+            if deletes.contains(&Ulid::from_bytes(doc.id)) {
                 continue;
             }
 
+            if !ahead.fits(&doc) {
+                let end = ahead.end();
+                self.merge_documents(&data, &mut ahead)?;
+                proceed &= progress(processed + end, total);
+            }
+            ahead.push(doc);
+        }
+        self.merge_documents(&data, &mut ahead)?;
+
+        remove_dir_all(&path)?;
+        self.segments.remove(&path);
+        if let Documents::Disk(docs) = &mut self.docs {
+            docs.tables.remove(&path);
+        }
+        Ok(proceed)
+    }
+
+    fn merge_documents(
+        &mut self,
+        data: &ReopeningFile,
+        ahead: &mut ReadAhead,
+    ) -> Result<(), DocumentsManagerError> {
+        // moves documents read together from merged segment into current one
+        let (content, start, docs) = ahead.read(data)?;
+        for mut doc in docs {
+            let ulid = Ulid::from_bytes(doc.id);
             let offset = self.buffer.documents.len();
-            self.buffer.documents.resize(offset + doc.location.size, 0);
-            data.read_exact_at(&mut self.buffer.documents[offset..], doc.location.offset)?;
+            if doc.inline.is_none() {
+                let from = (doc.location.offset - start) as usize;
+                self.buffer
+                    .documents
+                    .extend_from_slice(&content[from..from + doc.location.size]);
+            }
 
             // update in-memory document
             doc.location.segment = self.cur_segment.clone();
             doc.location.offset = self.buffer.segment_size(&self.cur_segment)? + offset as u64;
 
-            self.buffer.write_meta(&doc)?;
-            self.docs.insert(ulid, doc);
+            let meta_offset =
+                self.buffer.meta_size(&self.cur_segment)? + self.buffer.write_meta(&doc)? as u64;
+            if let Some(segment) = self.segments.get_mut(&self.cur_segment) {
+                segment.bloom.add(ulid.0);
+            }
+            self.track_offsets(ulid.0)?;
+
+            match &mut self.docs {
+                Documents::Memory(docs) => {
+                    docs.insert(ulid, doc);
+                }
+                Documents::Disk(docs) => {
+                    docs.unflushed.insert(ulid, (doc, meta_offset));
+                }
+            }
 
-            let segment_size =
-                self.buffer.segment_size(&self.cur_segment)? + self.buffer.documents.len() as u64;
+            let segment_size = self.buffered_segment_size()?;
             self.save_buffer(segment_size)?;
         }
-
-        remove_dir_all(&path)?;
-        self.segments.remove(&path);
-        return Ok(true);
+        Ok(())
     }
 
-    fn create_segment(dir: &PathBuf) -> Result<(PathBuf, Segment), DocumentsManagerError> {
+    fn create_segment(
+        dir: &PathBuf,
+        bloom_bits: u64,
+    ) -> Result<(PathBuf, Segment), DocumentsManagerError> {
         let ts = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
 
         let segment = Path::new(dir).join(ts.to_string());
@@ -452,69 +1750,122 @@ impl DocumentsManager {
                 name: ts,
                 size: 0,
                 deleted: 0,
+                bloom: BloomFilter::new(bloom_bits),
+                meta_read: 0,
+                del_read: 0,
+                block: Some(OffsetsBlock::default()),
             },
         ))
     }
 
+    fn segment_dirs(dir: &PathBuf) -> Result<Vec<(PathBuf, u128)>, io::Error> {
+        // returns paths of all segments with their names
+        let mut segments = vec![];
+        if !fs::exists(dir)? {
+            return Ok(segments);
+        }
+
+        for e in fs::read_dir(dir)? {
+            let path = e?.path();
+            if !path.is_dir() || path.is_symlink() {
+                continue;
+            }
+
+            let name = match path
+                .file_name()
+                .unwrap_or_default()
+                .to_os_string()
+                .to_str()
+                .unwrap_or_default()
+                .parse::<u128>()
+            {
+                Ok(val) => val,
+                Err(_) => continue,
+            };
+
+            segments.push((path, name));
+        }
+
+        Ok(segments)
+    }
+
     fn segments(
         dir: &PathBuf,
-    ) -> Result<Option<Vec<(PathBuf, Segment, HashSet<Ulid>)>>, io::Error> {
-        match fs::exists(&dir)? {
-            true => {
-                let mut segments = vec![];
-                for e in fs::read_dir(&dir)? {
-                    let path = e?.path();
-                    if !path.is_dir() || path.is_symlink() {
-                        continue;
-                    }
-
-                    let name = match path
-                        .file_name()
-                        .unwrap_or_default()
-                        .to_os_string()
-                        .to_str()
-                        .unwrap_or_default()
-                        .parse::<u128>()
-                    {
-                        Ok(val) => val,
-                        Err(_) => continue,
-                    };
+        bloom_bits: u64,
+        inline: bool,
+    ) -> Result<Option<StoredSegments>, io::Error> {
+        let mut segments = vec![];
+        for (path, name) in Self::segment_dirs(dir)? {
+            let data = File::open(path.join("data"))?;
+            let mut del = File::open(path.join("del"))?;
+
+            let del_size = del.metadata()?.len();
+            let mut deleted_bytes = 0;
+            let mut deletes = HashSet::new();
+
+            while del.stream_position()? < del_size {
+                let (mut size, mut deleted) = ([0u8; 8], [0u8; 16]);
+                del.read_exact(&mut deleted)?;
+                del.read_exact(&mut size)?;
+
+                deletes.insert(Ulid::from_bytes(deleted));
+                deleted_bytes += u64::from_be_bytes(size);
+            }
 
-                    let data = File::open(&path.join("data"))?;
-                    let mut del = File::open(path.join("del"))?;
+            segments.push((
+                path.clone(),
+                SegmentInfo {
+                    name,
+                    size: Self::stored_size(&path, inline, data.metadata()?.len())?,
+                    deleted: deleted_bytes,
+                    del_size,
+                    bloom: BloomFilter::load(&path.join("bloom"), bloom_bits)?,
+                },
+                deletes,
+            ));
+        }
 
-                    let del_size = del.metadata()?.len();
-                    let mut deleted_bytes = 0;
-                    let mut deletes = HashSet::new();
+        if !segments.is_empty() {
+            Ok(Some(segments))
+        } else {
+            Ok(None)
+        }
+    }
 
-                    while del.stream_position()? < del_size {
-                        let (mut size, mut deleted) = ([0u8; 8], [0u8; 16]);
-                        del.read_exact(&mut deleted)?;
-                        del.read_exact(&mut size)?;
+    fn stored_size(segment: &Path, inline: bool, data_size: u64) -> Result<u64, io::Error> {
+        // content of inline documents is kept in meta file, so it counts into segment size
+        match inline {
+            true => Ok(data_size + File::open(segment.join("meta"))?.metadata()?.len()),
+            false => Ok(data_size),
+        }
+    }
 
-                        deletes.insert(Ulid::from_bytes(deleted));
-                        deleted_bytes += u64::from_be_bytes(size);
-                    }
+    fn buffered_segment_size(&mut self) -> Result<u64, io::Error> {
+        // size of current segment including buffered documents
+        let mut size =
+            self.buffer.segment_size(&self.cur_segment)? + self.buffer.documents.len() as u64;
+        if self.config.inline_documents_size.is_some() {
+            size += self.buffer.meta_size(&self.cur_segment)? + self.buffer.meta.len() as u64;
+        }
+        Ok(size)
+    }
 
-                    segments.push((
-                        path.clone(),
-                        Segment {
-                            name: name,
-                            size: data.metadata()?.len(),
-                            deleted: deleted_bytes,
-                        },
-                        deletes,
-                    ));
-                }
+    fn track_offsets(&mut self, id: u128) -> Result<(), io::Error> {
+        // called after meta record of document is written into buffer
+        let end = self.buffer.meta_size(&self.cur_segment)? + self.buffer.meta.len() as u64;
+        let block = match self.segments.get_mut(&self.cur_segment) {
+            Some(Segment {
+                block: Some(block), ..
+            }) => block,
+            _ => return Ok(()),
+        };
 
-                if segments.len() > 0 {
-                    Ok(Some(segments))
-                } else {
-                    Ok(None)
-                }
-            }
-            false => Ok(None),
+        block.add(id);
+        if block.len == OffsetsBlock::RECORDS {
+            block.encode_into_vec(end, &mut self.buffer.offsets);
+            *block = OffsetsBlock::default();
         }
+        Ok(())
     }
 
     fn save_buffer(&mut self, segment_size: u64) -> Result<(), DocumentsManagerError> {
@@ -526,7 +1877,11 @@ impl DocumentsManager {
             .duration_since(SystemTime::UNIX_EPOCH)?
             .as_secs();
 
-        if self.buffer.documents.len() as u64 > self.config.documents_buffer_size
+        let mut buffered = self.buffer.documents.len();
+        if self.config.inline_documents_size.is_some() {
+            buffered += self.buffer.meta.len();
+        }
+        if buffered as u64 > self.config.documents_buffer_size
             || cur_ts >= self.last_save + self.config.documents_save_after_seconds
         {
             self.last_save = cur_ts;
@@ -536,7 +1891,15 @@ impl DocumentsManager {
         // check if segment size exceded threshold - 100MB
         if segment_size > self.config.segment_size {
             self.flush()?;
-            let (path, segment) = Self::create_segment(&self.dir)?;
+            let (path, segment) = Self::create_segment(&self.dir, self.config.segment_bloom_bits)?;
+
+            if let Documents::Disk(docs) = &mut self.docs {
+                if let Some(table) = docs.tables.get_mut(&self.cur_segment) {
+                    table.seal(&self.cur_segment)?;
+                }
+                docs.tables
+                    .insert(path.clone(), KeysTable::new(&path, HashSet::new())?);
+            }
             self.segments.insert(path.clone(), segment);
             self.buffer.reset();
             self.cur_segment = path;
@@ -0,0 +1,140 @@
+use bincode::{Decode, Encode};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use crate::errors::BincodePersistenceError;
+use crate::utils::memory::HeapSize;
+
+// weight of recorded query halves after a week without being searched again
+const HALFLIFE_MS: f64 = 7.0 * 24.0 * 3600.0 * 1000.0;
+
+#[derive(Decode, Encode, PartialEq, Debug, Clone, Default)]
+struct QueryStats {
+    weight: f64,    // number of searches, each decayed by time elapsed since it
+    last_seen: u64, // timestamp in milliseconds the weight was decayed to
+}
+
+impl QueryStats {
+    fn weight_at(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_seen) as f64;
+        self.weight * 0.5f64.powf(age / HALFLIFE_MS)
+    }
+}
+
+#[derive(Decode, Encode, PartialEq, Debug, Clone, Default)]
+struct QueryLogData {
+    queries: HashMap<String, QueryStats>, // normalized query -> its stats
+}
+
+// successful queries used for suggestions, queries which were searched often and
+// recently are suggested first, the least weighted ones are evicted above capacity
+pub struct QueryLog {
+    path: PathBuf,
+    capacity: usize,
+    data: QueryLogData,
+}
+
+impl HeapSize for QueryStats {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl QueryLog {
+    pub fn memory_usage(&self) -> usize {
+        self.data.queries.heap_size()
+    }
+
+    pub fn load(dir: &PathBuf, capacity: usize) -> Result<Self, BincodePersistenceError> {
+        let path = dir.join("queries");
+        if !fs::exists(&path)? {
+            fs::create_dir_all(dir)?;
+            File::create(&path)?;
+        }
+
+        Ok(Self {
+            data: Self::read(&path)?.unwrap_or_default(),
+            path,
+            capacity,
+        })
+    }
+
+    fn read(path: &PathBuf) -> Result<Option<QueryLogData>, BincodePersistenceError> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(None);
+        }
+
+        // file may be partially written by other process, it's treated as missing
+        Ok(bincode::decode_from_std_read(&mut file, bincode::config::standard()).ok())
+    }
+
+    fn normalize(query: &str) -> String {
+        query
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .to_lowercase()
+    }
+
+    pub fn record(&mut self, query: &str, now: u64) {
+        let query = Self::normalize(query);
+        if query.is_empty() {
+            return;
+        }
+
+        let stats = self.data.queries.entry(query.clone()).or_default();
+        stats.weight = stats.weight_at(now) + 1.0;
+        stats.last_seen = now;
+
+        // evicted query is the last one in suggestions order, except the recorded one
+        if self.data.queries.len() > self.capacity
+            && let Some(evicted) = self
+                .data
+                .queries
+                .iter()
+                .filter(|(recorded, _)| **recorded != query)
+                .min_by(|x, y| {
+                    x.1.weight_at(now)
+                        .total_cmp(&y.1.weight_at(now))
+                        .then_with(|| y.0.cmp(x.0))
+                })
+                .map(|(query, _)| query.clone())
+        {
+            self.data.queries.remove(&evicted);
+        }
+    }
+
+    pub fn suggest(&self, prefix: &str, limit: usize, now: u64) -> Vec<String> {
+        // queries with equal weight are ordered alphabetically
+        let prefix = Self::normalize(prefix);
+        let mut suggestions = self
+            .data
+            .queries
+            .iter()
+            .filter(|(query, _)| query.starts_with(&prefix))
+            .map(|(query, stats)| (query, stats.weight_at(now)))
+            .collect::<Vec<(&String, f64)>>();
+
+        suggestions.sort_by(|x, y| y.1.total_cmp(&x.1).then_with(|| x.0.cmp(y.0)));
+        suggestions
+            .into_iter()
+            .take(limit)
+            .map(|(query, _)| query.clone())
+            .collect()
+    }
+
+    pub fn flush(&self) -> Result<(), BincodePersistenceError> {
+        let mut file = File::create(&self.path)?;
+        bincode::encode_into_std_write(&self.data, &mut file, bincode::config::standard())?;
+        Ok(())
+    }
+
+    pub fn reload(&mut self) -> Result<(), BincodePersistenceError> {
+        if let Some(data) = Self::read(&self.path)? {
+            self.data = data;
+        }
+        Ok(())
+    }
+}
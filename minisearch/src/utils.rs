@@ -1,4 +1,12 @@
 pub mod automaton;
+pub mod bloom;
 pub mod fileext;
 pub mod hasher;
+pub mod logging;
+pub mod lru;
+pub mod lz4;
+pub mod memory;
+pub mod profiler;
 pub mod trie;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+pub mod uring;
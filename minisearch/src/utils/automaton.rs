@@ -1,9 +1,17 @@
 use hashbrown::HashMap;
 use nohash_hasher::BuildNoHashHasher;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
 use std::cmp;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::vec::Vec;
 
+// the highest supported degree of levenshtein automaton
+pub const MAX_DISTANCE: u8 = 2;
+
+// automata are expensive to build, so the ones used by python helpers are shared
+static BUILDERS: OnceLock<Vec<LevenshteinAutomatonBuilder>> = OnceLock::new();
+
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Hash)]
 struct State(u32, i32);
 
@@ -348,4 +356,48 @@ impl LevenshteinAutomatonBuilder {
     pub fn get(&self, query: &str) -> LevenshteinAutomaton {
         LevenshteinAutomaton::new(query, self.d, Arc::clone(&self.dfa))
     }
+
+    pub fn distance(&self, query: &str, word: &str) -> Option<u16> {
+        // distance between query and word, None if it is greater than automaton degree
+        let mut automaton = self.get(query);
+        let mut state = automaton.initial_state();
+        for c in word.chars() {
+            state = automaton.step(c, &state);
+            if !automaton.can_match(&state) {
+                return None;
+            }
+        }
+
+        match automaton.is_match(&state) {
+            true => Some(automaton.distance(&state)),
+            false => None,
+        }
+    }
+}
+
+fn builder(d: u8) -> PyResult<&'static LevenshteinAutomatonBuilder> {
+    // the same degrees are supported as by query fuzziness
+    if d > MAX_DISTANCE {
+        return Err(PyValueError::new_err(format!(
+            "Distance must be less or equal to {}, but it is {}.",
+            MAX_DISTANCE, d
+        )));
+    }
+
+    let builders = BUILDERS.get_or_init(|| {
+        (0..=MAX_DISTANCE)
+            .map(LevenshteinAutomatonBuilder::new)
+            .collect()
+    });
+    Ok(&builders[d as usize])
+}
+
+#[pyfunction]
+pub fn levenshtein(a: &str, b: &str, max_d: u8) -> PyResult<Option<u16>> {
+    Ok(builder(max_d)?.distance(a, b))
+}
+
+#[pyfunction]
+pub fn within_distance(a: &str, b: &str, d: u8) -> PyResult<bool> {
+    Ok(builder(d)?.distance(a, b).is_some())
 }
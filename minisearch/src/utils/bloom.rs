@@ -0,0 +1,95 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use crate::utils::memory::HeapSize;
+
+static HASHES_NUM: u64 = 7;
+
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    changed: bool, // filter wasn't saved yet or bits were set since it was loaded or saved
+}
+
+impl HeapSize for BloomFilter {
+    fn heap_size(&self) -> usize {
+        self.bits.heap_size()
+    }
+}
+
+impl BloomFilter {
+    pub fn new(bits_num: u64) -> Self {
+        Self {
+            bits: vec![0; bits_num.div_ceil(64).max(1) as usize],
+            changed: true,
+        }
+    }
+
+    pub fn load(path: &PathBuf, bits_num: u64) -> Result<Option<Self>, io::Error> {
+        // returns None if filter doesn't exist or was created with different size
+        if !fs::exists(path)? {
+            return Ok(None);
+        }
+
+        let mut file = File::open(path)?;
+        let words = bits_num.div_ceil(64).max(1) as usize;
+        if file.metadata()?.len() != words as u64 * 8 {
+            return Ok(None);
+        }
+
+        let mut buf = vec![0u8; words * 8];
+        file.read_exact(&mut buf)?;
+
+        Ok(Some(Self {
+            bits: buf
+                .chunks_exact(8)
+                .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+                .collect(),
+            changed: false,
+        }))
+    }
+
+    pub fn save(&mut self, path: &PathBuf) -> Result<(), io::Error> {
+        let mut buf = Vec::with_capacity(self.bits.len() * 8);
+        for word in self.bits.iter() {
+            buf.extend(word.to_le_bytes());
+        }
+
+        let tmp = path.with_extension("tmp");
+        File::create(&tmp)?.write_all(&buf)?;
+        fs::rename(&tmp, path)?;
+        self.changed = false;
+        Ok(())
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    pub fn add(&mut self, key: u128) {
+        for bit in Self::bits(key, self.bits.len() as u64 * 64) {
+            let word = &mut self.bits[(bit / 64) as usize];
+            self.changed |= *word & (1 << (bit % 64)) == 0;
+            *word |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn may_contain(&self, key: u128) -> bool {
+        Self::bits(key, self.bits.len() as u64 * 64)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn bits(key: u128, len: u64) -> impl Iterator<Item = u64> {
+        // double hashing, h1 + i * h2 simulates k independent hash functions
+        let (h1, h2) = (mix(key as u64), mix((key >> 64) as u64) | 1);
+        (0..HASHES_NUM).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+    }
+}
+
+fn mix(mut x: u64) -> u64 {
+    // splitmix64 finalizer
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
@@ -1,8 +1,28 @@
 use std::fs::File;
 use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+use crate::utils::uring;
+
+// handle of file on network filesystem (e.g. NFS) goes stale when the file is replaced
+// or server restarts, read is then retried this many times with file opened again
+const STALE_RETRIES: u32 = 3;
+const STALE_BACKOFF: Duration = Duration::from_millis(10);
 
 pub trait FileExt {
     fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    // fills every buffer from its offset, result of each read is returned separately
+    fn read_batch_at(&self, reads: &mut [(u64, Vec<u8>)]) -> Vec<io::Result<()>> {
+        reads
+            .iter_mut()
+            .map(|(offset, buf)| self.read_exact_at(buf, *offset))
+            .collect()
+    }
 }
 
 impl FileExt for File {
@@ -21,6 +41,8 @@ impl FileExt for File {
                     buf = &mut tmp[n..];
                     offset += n as u64;
                 }
+                // read interrupted by signal before anything was read is repeated
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
                 Err(e) => return Err(e),
             }
         }
@@ -33,4 +55,108 @@ impl FileExt for File {
             Ok(())
         }
     }
+
+    fn read_batch_at(&self, reads: &mut [(u64, Vec<u8>)]) -> Vec<io::Result<()>> {
+        // reads are submitted together with io_uring if it's enabled, failed and short
+        // ones are finished one by one
+        #[cfg(all(feature = "io_uring", target_os = "linux"))]
+        let done = uring::read_batch(std::os::fd::AsRawFd::as_raw_fd(self), reads);
+        #[cfg(not(all(feature = "io_uring", target_os = "linux")))]
+        let done = vec![0; reads.len()];
+
+        reads
+            .iter_mut()
+            .zip(done)
+            .map(|((offset, buf), n)| match n == buf.len() {
+                true => Ok(()),
+                false => self.read_exact_at(&mut buf[n..], *offset + n as u64),
+            })
+            .collect()
+    }
+}
+
+// file kept open for positional reads, which is opened again by its path when its
+// handle goes stale, so transient network filesystem errors don't fail reads
+#[derive(Debug)]
+pub struct ReopeningFile {
+    path: PathBuf,
+    file: RwLock<File>,
+}
+
+impl ReopeningFile {
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let file = retry_stale(|| File::open(&path))?;
+        Ok(Self {
+            path,
+            file: RwLock::new(file),
+        })
+    }
+
+    fn reopen(&self) -> io::Result<()> {
+        let file = File::open(&self.path)?;
+        *self.file.write().unwrap_or_else(PoisonError::into_inner) = file;
+        Ok(())
+    }
+}
+
+impl FileExt for ReopeningFile {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        retry_stale(|| {
+            let result = self
+                .file
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .read_exact_at(buf, offset);
+            match result {
+                Err(err) if is_stale(&err) => {
+                    self.reopen()?;
+                    Err(err)
+                }
+                result => result,
+            }
+        })
+    }
+
+    fn read_batch_at(&self, reads: &mut [(u64, Vec<u8>)]) -> Vec<io::Result<()>> {
+        // only reads which failed on stale handle are retried
+        let file = || self.file.read().unwrap_or_else(PoisonError::into_inner);
+        let mut results = file().read_batch_at(reads);
+        for attempt in 1..=STALE_RETRIES {
+            if !results.iter().any(|r| r.as_ref().is_err_and(is_stale)) {
+                break;
+            }
+            thread::sleep(STALE_BACKOFF * attempt);
+            if self.reopen().is_err() {
+                break;
+            }
+            for ((offset, buf), result) in reads.iter_mut().zip(results.iter_mut()) {
+                if result.as_ref().is_err_and(is_stale) {
+                    *result = file().read_exact_at(buf, *offset);
+                }
+            }
+        }
+        results
+    }
+}
+
+pub fn read_exact_at_path(path: &Path, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    // file is opened only for this read, stale handle is replaced by opening it again
+    retry_stale(|| File::open(path)?.read_exact_at(buf, offset))
+}
+
+fn is_stale(err: &io::Error) -> bool {
+    err.kind() == ErrorKind::StaleNetworkFileHandle
+}
+
+fn retry_stale<T>(mut read: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match read() {
+            Err(err) if is_stale(&err) && attempt < STALE_RETRIES => {
+                attempt += 1;
+                thread::sleep(STALE_BACKOFF * attempt);
+            }
+            result => return result,
+        }
+    }
 }
@@ -1,7 +1,6 @@
 use std::{
-    collections::hash_map::Keys,
+    collections::hash_map::{Keys, Values},
     fs::{self, File},
-    io,
     path::PathBuf,
     sync::Arc,
     time::SystemTime,
@@ -10,7 +9,10 @@ use std::{
 use bincode::{Decode, Encode};
 use std::collections::HashMap;
 
-use crate::{config::Config, errors::BincodePersistenceError};
+use crate::config::{Config, DecodeErrorPolicy};
+use crate::errors::BincodePersistenceError;
+use crate::utils::logging;
+use crate::utils::memory::HeapSize;
 
 #[derive(Decode, Encode, PartialEq, Debug, Clone)]
 struct TokensStore {
@@ -28,37 +30,95 @@ impl TokensStore {
         }
     }
 
-    fn load(path: &PathBuf) -> Result<Self, io::Error> {
+    fn load(
+        path: &PathBuf,
+        policy: DecodeErrorPolicy,
+    ) -> Result<Option<Self>, BincodePersistenceError> {
+        // returns None if the file can't be decoded and policy allows to continue
         if !fs::exists(path)? {
             File::create(path)?;
-            return Ok(Self::new(HashMap::new(), Vec::new(), Vec::new()));
+            return Ok(Some(Self::new(HashMap::new(), Vec::new(), Vec::new())));
         }
 
         let mut file = File::open(path)?;
         // if file is empty don't try to decode tokens
         if file.metadata()?.len() == 0 {
-            return Ok(Self::new(HashMap::new(), Vec::new(), Vec::new()));
+            return Ok(Some(Self::new(HashMap::new(), Vec::new(), Vec::new())));
         }
 
         match bincode::decode_from_std_read(&mut file, bincode::config::standard()) {
-            Ok(store) => Ok(store),
-            Err(e) => {
-                println!("Warning tokens decode error: {e}");
-                Ok(Self::new(HashMap::new(), Vec::new(), Vec::new()))
+            Ok(store) => Ok(Some(store)),
+            Err(e) if policy != DecodeErrorPolicy::Raise => {
+                let action = match policy {
+                    DecodeErrorPolicy::Rebuild => "rebuilt from index",
+                    _ => "reset",
+                };
+                logging::warning(&format!(
+                    "tokens decode error, tokens are {}: {} ({})",
+                    action,
+                    e,
+                    path.display()
+                ));
+                Ok(None)
             }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// representative surface forms of tokens indexed by token id, stored
+// in separate file next to tokens store, e.g. "Müller" for "müller"
+#[derive(Decode, Encode, PartialEq, Debug, Clone, Default)]
+struct DisplayTerms {
+    terms: Vec<Option<String>>,
+}
+
+impl DisplayTerms {
+    fn load(path: &PathBuf, policy: DecodeErrorPolicy) -> Result<Self, BincodePersistenceError> {
+        if !fs::exists(path)? {
+            return Ok(Self::default());
+        }
+
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok(Self::default());
+        }
+
+        match bincode::decode_from_std_read(&mut file, bincode::config::standard()) {
+            Ok(terms) => Ok(terms),
+            Err(e) if policy != DecodeErrorPolicy::Raise => {
+                logging::warning(&format!(
+                    "display terms decode error, display terms are reset: {} ({})",
+                    e,
+                    path.display()
+                ));
+                Ok(Self::default())
+            }
+            Err(e) => Err(e.into()),
         }
     }
 }
 
 pub struct TokenHasher {
     path: PathBuf,
+    display_path: PathBuf,
     operations: u64,
     last_save: u64,
     tokens_store: TokensStore,
+    display_terms: DisplayTerms,
+    pub unreadable: bool, // tokens file couldn't be decoded and tokens were reset
     config: Arc<Config>,
 }
 
 impl TokenHasher {
+    pub fn memory_usage(&self) -> usize {
+        // estimated bytes of tokens store and display terms
+        self.tokens_store.map.heap_size()
+            + self.tokens_store.tokens.heap_size()
+            + self.tokens_store.deleted.heap_size()
+            + self.display_terms.terms.heap_size()
+    }
+
     pub fn load(dir: &PathBuf, config: Arc<Config>) -> Result<Self, BincodePersistenceError> {
         let index_dir = dir.join("index");
         let tokens = index_dir.join("tokens");
@@ -67,9 +127,15 @@ impl TokenHasher {
             File::create(&tokens)?;
         }
 
+        let display = index_dir.join("display");
+        let tokens_store = TokensStore::load(&tokens, config.on_decode_error)?;
         Ok(Self {
-            tokens_store: TokensStore::load(&tokens)?,
+            unreadable: tokens_store.is_none(),
+            tokens_store: tokens_store
+                .unwrap_or_else(|| TokensStore::new(HashMap::new(), Vec::new(), Vec::new())),
+            display_terms: DisplayTerms::load(&display, config.on_decode_error)?,
             path: tokens,
+            display_path: display,
             operations: 0,
             last_save: SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)?
@@ -82,6 +148,10 @@ impl TokenHasher {
         self.tokens_store.map.keys()
     }
 
+    pub fn ids(&self) -> Values<'_, String, u32> {
+        self.tokens_store.map.values()
+    }
+
     pub fn add(&mut self, token: String) -> Result<u32, BincodePersistenceError> {
         if let Some(idx) = self.tokens_store.map.get(&token) {
             return Ok(*idx);
@@ -107,6 +177,9 @@ impl TokenHasher {
         {
             self.tokens_store.deleted.push(token);
             self.tokens_store.map.remove(&token_str);
+            if let Some(display) = self.display_terms.terms.get_mut(token as usize) {
+                display.take();
+            }
             self.operations += 1;
             self.save()?;
             return Ok(Some(token_str));
@@ -129,12 +202,117 @@ impl TokenHasher {
         }
     }
 
+    pub fn set_display(&mut self, token: u32, surface: String) {
+        // first seen surface form is kept as representative one
+        let terms = &mut self.display_terms.terms;
+        if terms.len() <= token as usize {
+            terms.resize(token as usize + 1, None);
+        }
+
+        terms[token as usize].get_or_insert(surface);
+    }
+
+    pub fn display(&self, token: u32) -> Option<&String> {
+        match self.display_terms.terms.get(token as usize) {
+            Some(val) => val.as_ref(),
+            None => None,
+        }
+    }
+
     pub fn flush(&self) -> Result<(), BincodePersistenceError> {
         let mut file = File::create(&self.path)?;
         bincode::encode_into_std_write(&self.tokens_store, &mut file, bincode::config::standard())?;
+
+        let mut file = File::create(&self.display_path)?;
+        bincode::encode_into_std_write(
+            &self.display_terms,
+            &mut file,
+            bincode::config::standard(),
+        )?;
         Ok(())
     }
 
+    pub fn reload(&mut self) -> Result<(Vec<String>, Vec<String>), BincodePersistenceError> {
+        // replaces tokens with ones saved by other process, returns added and removed tokens
+        let mut file = File::open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        // file may be partially written at the moment, keep current tokens in that case
+        let store: TokensStore =
+            match bincode::decode_from_std_read(&mut file, bincode::config::standard()) {
+                Ok(store) => store,
+                Err(_) => return Ok((Vec::new(), Vec::new())),
+            };
+
+        let added = store
+            .map
+            .keys()
+            .filter(|t| !self.tokens_store.map.contains_key(*t))
+            .cloned()
+            .collect();
+        let removed = self
+            .tokens_store
+            .map
+            .keys()
+            .filter(|t| !store.map.contains_key(*t))
+            .cloned()
+            .collect();
+
+        self.tokens_store = store;
+        // display terms are flushed after tokens, missing ones are filled on next add,
+        // partially written file keeps current ones
+        if let Ok(display_terms) = DisplayTerms::load(&self.display_path, DecodeErrorPolicy::Raise)
+        {
+            self.display_terms = display_terms;
+        }
+        Ok((added, removed))
+    }
+
+    pub fn restore(
+        &mut self,
+        indexed: impl Iterator<Item = u32>,
+        tokens: HashMap<u32, (String, Option<String>)>,
+    ) -> Result<(), BincodePersistenceError> {
+        // replaces tokens with ones recovered for indexed ids, ids of unrecovered tokens
+        // are not reused, so their postings never get mixed with postings of new tokens
+        let indexed = indexed.collect::<Vec<u32>>();
+        let len = indexed.iter().max().map_or(0, |max| *max as usize + 1);
+        let mut store = TokensStore::new(HashMap::new(), vec![None; len], Vec::new());
+        let mut display = DisplayTerms::default();
+        let mut unused = vec![true; len];
+        for token in indexed {
+            unused[token as usize] = false;
+        }
+        store.deleted = (0..len as u32).filter(|t| unused[*t as usize]).collect();
+
+        for (token, (text, surface)) in tokens {
+            if let Some(surface) = surface {
+                if display.terms.len() <= token as usize {
+                    display.terms.resize(token as usize + 1, None);
+                }
+                display.terms[token as usize] = Some(surface);
+            }
+            store.tokens[token as usize] = Some(text.clone());
+            store.map.insert(text, token);
+        }
+
+        self.tokens_store = store;
+        self.display_terms = display;
+        self.unreadable = false;
+        self.flush()
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        // every mapped token is stored under its id and there are no other stored tokens
+        let store = &self.tokens_store;
+        store.map.len() == store.tokens.iter().flatten().count()
+            && store.map.iter().all(|(token, id)| {
+                matches!(store.tokens.get(*id as usize), Some(Some(stored)) if stored == token)
+            })
+    }
+
     pub fn contains(&self, token: &str) -> bool {
         return self.tokens_store.map.contains_key(token);
     }
@@ -0,0 +1,39 @@
+use pyo3::prelude::*;
+
+// name of python logger messages are emitted to, configure it to filter or route them
+const LOGGER: &str = "minisearch";
+
+// levels of python logging module
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    Debug = 10,
+    Info = 20,
+    Warning = 30,
+    Error = 40,
+}
+
+pub fn log(level: Level, message: &str) {
+    // logging must never fail operation it reports on, so python errors are ignored
+    Python::with_gil(|py| {
+        let _ = py
+            .import("logging")
+            .and_then(|logging| logging.call_method1("getLogger", (LOGGER,)))
+            .and_then(|logger| logger.call_method1("log", (level as i32, message)));
+    });
+}
+
+pub fn debug(message: &str) {
+    log(Level::Debug, message);
+}
+
+pub fn info(message: &str) {
+    log(Level::Info, message);
+}
+
+pub fn warning(message: &str) {
+    log(Level::Warning, message);
+}
+
+pub fn error(message: &str) {
+    log(Level::Error, message);
+}
@@ -0,0 +1,125 @@
+use hashbrown::HashMap;
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::mem::size_of;
+
+use crate::utils::memory::HeapSize;
+
+const NIL: usize = usize::MAX;
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    prev: usize, // more recently used entry
+    next: usize, // less recently used entry
+}
+
+// cache of at most capacity entries, the least recently used one is evicted when it's
+// full, entries are stored in a vector and linked into a list by their indexes
+pub struct LRUCache<K, V> {
+    map: HashMap<K, usize>,
+    entries: Vec<Entry<K, V>>,
+    head: usize, // the most recently used entry
+    tail: usize, // the least recently used entry
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, V> LRUCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            entries: vec![],
+            head: NIL,
+            tail: NIL,
+            capacity,
+        }
+    }
+
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let idx = *self.map.get(key)?;
+        self.touch(idx);
+        Some(&self.entries[idx].value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(&idx) = self.map.get(&key) {
+            self.entries[idx].value = value;
+            self.touch(idx);
+            return;
+        }
+
+        let idx = match self.entries.len() < self.capacity {
+            true => {
+                self.entries.push(Entry {
+                    key: key.clone(),
+                    value,
+                    prev: NIL,
+                    next: NIL,
+                });
+                self.entries.len() - 1
+            }
+            // slot of the least recently used entry is reused
+            false => {
+                let idx = self.tail;
+                self.unlink(idx);
+                let entry = &mut self.entries[idx];
+                self.map.remove(&entry.key);
+                entry.key = key.clone();
+                entry.value = value;
+                idx
+            }
+        };
+        self.map.insert(key, idx);
+        self.push_front(idx);
+    }
+
+    fn touch(&mut self, idx: usize) {
+        if self.head != idx {
+            self.unlink(idx);
+            self.push_front(idx);
+        }
+    }
+
+    fn unlink(&mut self, idx: usize) {
+        let (prev, next) = (self.entries[idx].prev, self.entries[idx].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.entries[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.entries[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, idx: usize) {
+        self.entries[idx].prev = NIL;
+        self.entries[idx].next = self.head;
+        match self.head {
+            NIL => self.tail = idx,
+            head => self.entries[head].prev = idx,
+        }
+        self.head = idx;
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for LRUCache<K, V> {
+    fn heap_size(&self) -> usize {
+        // keys are stored in both map and entries
+        self.map.heap_size()
+            + self.entries.capacity() * size_of::<Entry<K, V>>()
+            + self
+                .entries
+                .iter()
+                .map(|entry| entry.key.heap_size() + entry.value.heap_size())
+                .sum::<usize>()
+    }
+}
@@ -0,0 +1,143 @@
+// decodes only beginning of lz4 block with prepended size (as written by lz4_flex), so
+// preview of large document doesn't need to decompress all of it, block is a sequence
+// of literals each followed by a match copying earlier output
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Lz4Error {
+    #[error("block is shorter than its size prefix")]
+    MissingSize,
+    #[error("match offset {0} points before start of block")]
+    InvalidOffset(usize),
+}
+
+pub fn decompressed_size(block: &[u8]) -> Result<usize, Lz4Error> {
+    let size = block.get(..4).ok_or(Lz4Error::MissingSize)?;
+    Ok(u32::from_le_bytes(size.try_into().unwrap()) as usize)
+}
+
+pub fn prefix_bound(limit: usize) -> usize {
+    // compressed bytes which are usually enough to decode limit bytes, every sequence
+    // takes about one byte per output byte and few bytes of token, offset and lengths,
+    // only length of a very long literal run can take more
+    4 + limit + limit / 255 + 32
+}
+
+pub fn decompress_prefix(block: &[u8], limit: usize) -> Result<Vec<u8>, Lz4Error> {
+    // at most limit bytes of content, block can be cut anywhere, decoding simply stops
+    // where the input ends, so fewer bytes are returned if it isn't long enough
+    let limit = limit.min(decompressed_size(block)?);
+    let mut input = &block[4..];
+    let mut out = Vec::with_capacity(limit);
+
+    while out.len() < limit && !input.is_empty() {
+        let token = input[0];
+        input = &input[1..];
+
+        let literals = read_length(&mut input, (token >> 4) as usize);
+        let take = literals.min(limit - out.len()).min(input.len());
+        out.extend_from_slice(&input[..take]);
+        input = &input[take..];
+        if out.len() >= limit || input.len() < 2 {
+            break;
+        }
+
+        let offset = u16::from_le_bytes([input[0], input[1]]) as usize;
+        input = &input[2..];
+        if offset == 0 || offset > out.len() {
+            return Err(Lz4Error::InvalidOffset(offset));
+        }
+        // match can overlap bytes it produces, so it's copied byte by byte
+        let matched = read_length(&mut input, (token & 0xf) as usize) + 4;
+        let start = out.len() - offset;
+        for i in 0..matched.min(limit - out.len()) {
+            out.push(out[start + i]);
+        }
+    }
+    Ok(out)
+}
+
+fn read_length(input: &mut &[u8], nibble: usize) -> usize {
+    // nibble of 15 is followed by bytes added to it until one is less than 255
+    let mut length = nibble;
+    if nibble == 15 {
+        while let Some((&byte, rest)) = input.split_first() {
+            *input = rest;
+            length += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    length
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lz4_flex::block::compress_prepend_size;
+
+    fn content() -> Vec<u8> {
+        // repeated words give matches, some of them overlapping, and long literal runs
+        let mut data = "quick brown fox jumps over lazy dog "
+            .repeat(40)
+            .into_bytes();
+        data.extend((0..600u32).map(|i| (i * 7919 % 251) as u8));
+        data.extend(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        data
+    }
+
+    #[test]
+    fn decodes_prefix() {
+        let data = content();
+        let block = compress_prepend_size(&data);
+        assert_eq!(decompressed_size(&block).unwrap(), data.len());
+        for limit in [0, 1, 15, 36, 100, 1440, 2000, data.len(), data.len() + 10] {
+            let prefix = decompress_prefix(&block, limit).unwrap();
+            assert_eq!(prefix, data[..limit.min(data.len())]);
+        }
+    }
+
+    #[test]
+    fn truncated_block_decodes_shorter_prefix() {
+        let data = content();
+        let block = compress_prepend_size(&data);
+        for cut in [4, 5, 10, 40, block.len() / 2, block.len() - 1] {
+            let prefix = decompress_prefix(&block[..cut], data.len()).unwrap();
+            assert!(prefix.len() < data.len());
+            assert_eq!(prefix, data[..prefix.len()]);
+        }
+        // bound is enough for compressible content
+        let limit = 200;
+        let bound = prefix_bound(limit).min(block.len());
+        let prefix = decompress_prefix(&block[..bound], limit).unwrap();
+        assert_eq!(prefix, data[..limit]);
+    }
+
+    #[test]
+    fn corrupt_block_is_rejected() {
+        assert!(matches!(
+            decompressed_size(&[1, 0]),
+            Err(Lz4Error::MissingSize)
+        ));
+        assert!(matches!(
+            decompress_prefix(&[], 10),
+            Err(Lz4Error::MissingSize)
+        ));
+
+        // one literal followed by match reaching 5 bytes back
+        let block = [10, 0, 0, 0, 0x10, b'a', 5, 0];
+        assert!(matches!(
+            decompress_prefix(&block, 10),
+            Err(Lz4Error::InvalidOffset(5))
+        ));
+        let block = [10, 0, 0, 0, 0x10, b'a', 0, 0];
+        assert!(matches!(
+            decompress_prefix(&block, 10),
+            Err(Lz4Error::InvalidOffset(0))
+        ));
+        // valid offset of the same block repeats the literal
+        let block = [10, 0, 0, 0, 0x10, b'a', 1, 0];
+        assert_eq!(decompress_prefix(&block, 10).unwrap(), b"aaaaa");
+    }
+}
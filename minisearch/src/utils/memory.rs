@@ -0,0 +1,87 @@
+use std::hash::BuildHasher;
+use std::mem::size_of;
+use std::path::PathBuf;
+
+// estimated number of bytes allocated on the heap by a value, the value itself isn't
+// included, maps are estimated by their capacity and one control byte per bucket
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+macro_rules! impl_no_heap {
+    ($($t:ty),*) => {
+        $(impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        })*
+    };
+}
+
+impl_no_heap!(char, u8, u32, u64, u128, usize, f64, ulid::Ulid);
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for PathBuf {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T: HeapSize, const N: usize> HeapSize for [T; N] {
+    fn heap_size(&self) -> usize {
+        self.iter().map(|v| v.heap_size()).sum()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, |v| v.heap_size())
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.capacity() * size_of::<T>() + self.iter().map(|v| v.heap_size()).sum::<usize>()
+    }
+}
+
+fn table_size<K, V>(capacity: usize) -> usize {
+    capacity * (size_of::<(K, V)>() + 1)
+}
+
+impl<K: HeapSize, V: HeapSize, S: BuildHasher> HeapSize for hashbrown::HashMap<K, V, S> {
+    fn heap_size(&self) -> usize {
+        table_size::<K, V>(self.capacity())
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_size() + v.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize, S: BuildHasher> HeapSize for std::collections::HashMap<K, V, S> {
+    fn heap_size(&self) -> usize {
+        table_size::<K, V>(self.capacity())
+            + self
+                .iter()
+                .map(|(k, v)| k.heap_size() + v.heap_size())
+                .sum::<usize>()
+    }
+}
+
+impl<T: HeapSize, S: BuildHasher> HeapSize for hashbrown::HashSet<T, S> {
+    fn heap_size(&self) -> usize {
+        table_size::<T, ()>(self.capacity()) + self.iter().map(|v| v.heap_size()).sum::<usize>()
+    }
+}
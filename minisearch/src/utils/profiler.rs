@@ -0,0 +1,108 @@
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+// components of search, children time is included in time of their parents
+#[derive(Clone, Copy)]
+pub enum Span {
+    Search,
+    Parse,
+    Expansion, // tokenizing query, fuzzy expansion and building matchers
+    Matching,
+    Intersection, // advancing posting lists to the next common document
+    Mis,          // minimal interval semantic match
+    Scoring,
+    Materialization, // reading matched documents
+}
+
+impl Span {
+    const COUNT: usize = 8;
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Search => "search",
+            Self::Parse => "parse",
+            Self::Expansion => "expansion",
+            Self::Matching => "matching",
+            Self::Intersection => "intersection",
+            Self::Mis => "mis",
+            Self::Scoring => "scoring",
+            Self::Materialization => "materialization",
+        }
+    }
+
+    fn children(&self) -> &'static [Span] {
+        match self {
+            Self::Search => &[
+                Self::Parse,
+                Self::Expansion,
+                Self::Matching,
+                Self::Materialization,
+            ],
+            Self::Matching => &[Self::Intersection, Self::Mis, Self::Scoring],
+            _ => &[],
+        }
+    }
+}
+
+// accumulates time and number of calls of every span, disabled profiler doesn't
+// read the clock at all
+#[derive(Default)]
+pub struct Profiler {
+    enabled: bool,
+    spans: RefCell<[(Duration, u64); Span::COUNT]>,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            spans: RefCell::new(Default::default()),
+        }
+    }
+
+    pub fn scope(&self, span: Span) -> Timer<'_> {
+        Timer {
+            profiler: self,
+            span,
+            start: self.enabled.then(Instant::now),
+        }
+    }
+
+    pub fn report<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        self.node(py, Span::Search)
+    }
+
+    fn node<'py>(&self, py: Python<'py>, span: Span) -> PyResult<Bound<'py, PyDict>> {
+        let (time, calls) = self.spans.borrow()[span as usize];
+        let children = PyList::empty(py);
+        for child in span.children() {
+            children.append(self.node(py, *child)?)?;
+        }
+
+        let node = PyDict::new(py);
+        node.set_item("name", span.name())?;
+        node.set_item("seconds", time.as_secs_f64())?;
+        node.set_item("calls", calls)?;
+        node.set_item("children", children)?;
+        Ok(node)
+    }
+}
+
+// records time elapsed since its creation when dropped
+pub struct Timer<'a> {
+    profiler: &'a Profiler,
+    span: Span,
+    start: Option<Instant>,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            let (time, calls) = &mut self.profiler.spans.borrow_mut()[self.span as usize];
+            *time += start.elapsed();
+            *calls += 1;
+        }
+    }
+}
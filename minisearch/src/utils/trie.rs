@@ -1,7 +1,28 @@
 use crate::utils::automaton::{
-    LevenshteinAutomaton, LevenshteinAutomatonBuilder, LevenshteinDfaState,
+    LevenshteinAutomaton, LevenshteinAutomatonBuilder, LevenshteinDfaState, MAX_DISTANCE,
 };
-use std::collections::HashMap;
+use crate::utils::memory::HeapSize;
+use pyo3::exceptions::PyValueError;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TrieError {
+    #[error("Fuzziness must be less or equal to {1}, but it is {0}.")]
+    InvalidDistance(u8, u8),
+    #[error("Fuzzy matching is disabled by enable_fuzzy option, but term '{0}' is fuzzy.")]
+    Disabled(String),
+}
+
+impl From<TrieError> for pyo3::PyErr {
+    fn from(err: TrieError) -> Self {
+        match err {
+            TrieError::InvalidDistance(..) | TrieError::Disabled(..) => {
+                PyValueError::new_err(err.to_string())
+            }
+        }
+    }
+}
 
 struct Node {
     is_word: bool,
@@ -9,8 +30,18 @@ struct Node {
 }
 
 pub struct Trie {
-    automaton_builders: HashMap<u8, LevenshteinAutomatonBuilder>,
+    // automata are built on first use of their distance and cached
+    automaton_builders: Vec<OnceLock<LevenshteinAutomatonBuilder>>,
     nodes: Vec<(char, Node)>,
+    len: usize,
+    // disabled trie keeps no words and rejects fuzzy searches
+    enabled: bool,
+}
+
+impl HeapSize for Node {
+    fn heap_size(&self) -> usize {
+        self.nodes.heap_size()
+    }
 }
 
 impl Node {
@@ -23,19 +54,45 @@ impl Node {
 }
 
 impl Trie {
-    pub fn new() -> Self {
+    pub fn new(max_distance: u8) -> Self {
         Self {
-            automaton_builders: HashMap::new(),
+            automaton_builders: (0..=max_distance.min(MAX_DISTANCE))
+                .map(|_| OnceLock::new())
+                .collect(),
             nodes: Vec::new(),
+            len: 0,
+            enabled: true,
         }
     }
 
-    pub fn init_automaton(&mut self, d: u8) {
-        self.automaton_builders
-            .insert(d, LevenshteinAutomatonBuilder::new(d));
+    pub fn disabled() -> Self {
+        Self {
+            automaton_builders: vec![],
+            nodes: Vec::new(),
+            len: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn init_automaton(&self, d: u8) -> Result<&LevenshteinAutomatonBuilder, TrieError> {
+        match self.automaton_builders.get(d as usize) {
+            Some(builder) => Ok(builder.get_or_init(|| LevenshteinAutomatonBuilder::new(d))),
+            None => Err(TrieError::InvalidDistance(
+                d,
+                self.automaton_builders.len() as u8 - 1,
+            )),
+        }
     }
 
     pub fn add(&mut self, word: &str) {
+        if !self.enabled {
+            return;
+        }
+
         let mut nodes = &mut self.nodes;
         let len = word.chars().count();
 
@@ -43,12 +100,18 @@ impl Trie {
             match nodes.binary_search_by(|t| t.0.cmp(&c)) {
                 Ok(index) => {
                     if i == len - 1 {
+                        if !nodes[index].1.is_word {
+                            self.len += 1;
+                        }
                         nodes[index].1.is_word = true;
                     }
                     nodes = &mut nodes[index].1.nodes;
                 }
                 Err(index) => {
                     let node = Node::new(i == len - 1);
+                    if i == len - 1 {
+                        self.len += 1;
+                    }
                     nodes.insert(index, (c, node));
                     nodes = &mut nodes[index].1.nodes;
                 }
@@ -57,28 +120,89 @@ impl Trie {
     }
 
     pub fn delete(&mut self, word: String) {
+        if !self.contains(&word) {
+            return;
+        }
+
         let mut chars: Vec<char> = word.chars().rev().collect();
         Self::_delete(&mut chars, &mut self.nodes);
+        self.len -= 1;
     }
 
-    pub fn search(&self, d: u8, query: &str) -> Vec<(u16, String)> {
-        match self.automaton_builders.get(&d) {
-            Some(builder) => {
-                let mut automaton = builder.get(query);
-                let state = automaton.initial_state();
-                let mut prefix = String::new();
-                let mut matches = Vec::new();
-                self._search(
-                    &mut prefix,
-                    &mut matches,
-                    &self.nodes,
-                    &state,
-                    &mut automaton,
-                );
-                matches
+    pub fn memory_usage(&self) -> usize {
+        // automata builders are built on demand and aren't included
+        self.nodes.heap_size()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        let mut nodes = &self.nodes;
+        let mut is_word = false;
+        for c in word.chars() {
+            match nodes.binary_search_by(|t| t.0.cmp(&c)) {
+                Ok(index) => {
+                    is_word = nodes[index].1.is_word;
+                    nodes = &nodes[index].1.nodes;
+                }
+                Err(_) => return false,
             }
-            None => vec![],
         }
+
+        is_word
+    }
+
+    pub fn prune(&mut self, cap: usize, frequency: impl Fn(&str) -> u64) -> Option<u64> {
+        // evicts least frequent words until trie holds 90% of cap words,
+        // returns the highest frequency of evicted words
+        if self.len <= cap {
+            return None;
+        }
+
+        let mut words = vec![];
+        Self::_words(&mut String::new(), &mut words, &self.nodes);
+        let mut words = words
+            .into_iter()
+            .map(|word| (frequency(&word), word))
+            .collect::<Vec<(u64, String)>>();
+        words.sort_unstable();
+
+        let mut floor = None;
+        for (freq, word) in words.into_iter().take(self.len - cap * 9 / 10) {
+            floor = Some(freq);
+            self.delete(word);
+        }
+
+        floor
+    }
+
+    pub fn search(&self, d: u8, query: &str) -> Result<Vec<(u16, String)>, TrieError> {
+        // exact token is looked up in hasher by caller
+        if !self.enabled {
+            return match d {
+                0 => Ok(vec![]),
+                _ => Err(TrieError::Disabled(query.to_string())),
+            };
+        }
+
+        let mut automaton = self.init_automaton(d)?.get(query);
+        let state = automaton.initial_state();
+        let mut prefix = String::new();
+        let mut matches = Vec::new();
+        self._search(
+            &mut prefix,
+            &mut matches,
+            &self.nodes,
+            &state,
+            &mut automaton,
+        );
+        Ok(matches)
     }
 }
 
@@ -112,6 +236,18 @@ impl Trie {
         return (0, false, false);
     }
 
+    fn _words(prefix: &mut String, words: &mut Vec<String>, nodes: &[(char, Node)]) {
+        for (c, node) in nodes.iter() {
+            prefix.push(*c);
+            if node.is_word {
+                words.push(prefix.clone());
+            }
+
+            Self::_words(prefix, words, &node.nodes);
+            prefix.pop();
+        }
+    }
+
     fn _search(
         &self,
         prefix: &mut String,
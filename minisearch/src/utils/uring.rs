@@ -0,0 +1,376 @@
+// minimal io_uring ring submitting batches of positional reads with a single syscall,
+// only IORING_OP_READ (linux 5.6+) is used and ring is created per thread on first use,
+// if kernel doesn't support io_uring (or it's blocked, e.g. by seccomp) nothing is read
+// and callers read the usual way
+use std::cell::RefCell;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const ENTRIES: u32 = 64;
+const IORING_OP_READ: u8 = 22;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+// memory shared with kernel, released when ring is dropped
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        match ptr == libc::MAP_FAILED {
+            true => Err(io::Error::last_os_error()),
+            false => Ok(Self { ptr, len }),
+        }
+    }
+
+    fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.cast::<u8>().add(offset as usize).cast::<T>() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+struct Ring {
+    fd: RawFd,
+    sq: Mapping,
+    cq: Option<Mapping>, // None if completion queue shares mapping with submission queue
+    sqes: Mapping,
+    params: Params,
+}
+
+impl Ring {
+    fn new() -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                ENTRIES,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = fd as RawFd;
+        // fd is closed by ring drop once ring exists, mappings are released by theirs
+        let mappings = || -> io::Result<(Mapping, Option<Mapping>, Mapping)> {
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len =
+                params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+            let (sq, cq) = match params.features & IORING_FEAT_SINGLE_MMAP != 0 {
+                true => (
+                    Mapping::new(fd, sq_len.max(cq_len), IORING_OFF_SQ_RING)?,
+                    None,
+                ),
+                false => (
+                    Mapping::new(fd, sq_len, IORING_OFF_SQ_RING)?,
+                    Some(Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?),
+                ),
+            };
+            let sqes = Mapping::new(
+                fd,
+                params.sq_entries as usize * size_of::<Sqe>(),
+                IORING_OFF_SQES,
+            )?;
+            Ok((sq, cq, sqes))
+        };
+        match mappings() {
+            Ok((sq, cq, sqes)) => Ok(Self {
+                fd,
+                sq,
+                cq,
+                sqes,
+                params,
+            }),
+            Err(err) => {
+                unsafe { libc::close(fd) };
+                Err(err)
+            }
+        }
+    }
+
+    fn cq(&self) -> &Mapping {
+        self.cq.as_ref().unwrap_or(&self.sq)
+    }
+
+    fn sq_atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*self.sq.at::<AtomicU32>(offset) }
+    }
+
+    fn cq_atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { &*self.cq().at::<AtomicU32>(offset) }
+    }
+
+    fn enter(&self, to_submit: u32, min_complete: u32) -> io::Result<()> {
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd,
+                to_submit,
+                min_complete,
+                IORING_ENTER_GETEVENTS,
+                ptr::null::<libc::c_void>(),
+                0usize,
+            )
+        };
+        match ret < 0 {
+            true => Err(io::Error::last_os_error()),
+            false => Ok(()),
+        }
+    }
+
+    fn read(&self, fd: RawFd, reads: &mut [(u64, Vec<u8>)], done: &mut [usize]) {
+        // reads at most sq_entries buffers, number of bytes read into every one of them
+        // is stored in done, it stays 0 for reads which failed or weren't submitted
+        let (sq_off, cq_off) = (&self.params.sq_off, &self.params.cq_off);
+        let sq_mask = unsafe { *self.sq.at::<u32>(sq_off.ring_mask) };
+        let cq_mask = unsafe { *self.cq().at::<u32>(cq_off.ring_mask) };
+        let (sq_head, sq_tail) = (self.sq_atomic(sq_off.head), self.sq_atomic(sq_off.tail));
+        let (cq_head, cq_tail) = (self.cq_atomic(cq_off.head), self.cq_atomic(cq_off.tail));
+
+        let mut tail = sq_tail.load(Ordering::Acquire);
+        for (i, (offset, buf)) in reads.iter_mut().enumerate() {
+            let idx = tail & sq_mask;
+            unsafe {
+                self.sqes.at::<Sqe>(0).add(idx as usize).write(Sqe {
+                    opcode: IORING_OP_READ,
+                    fd,
+                    off: *offset,
+                    addr: buf.as_mut_ptr() as u64,
+                    len: buf.len() as u32,
+                    user_data: i as u64,
+                    ..Default::default()
+                });
+                *self.sq.at::<u32>(sq_off.array).add(idx as usize) = idx;
+            }
+            tail = tail.wrapping_add(1);
+        }
+        sq_tail.store(tail, Ordering::Release);
+
+        // buffers are owned by caller, so all consumed entries have to complete before
+        // returning, entries kernel refused to consume are withdrawn and read by caller
+        let (mut submitted, mut completed) = (reads.len() as u32, 0);
+        while completed < submitted {
+            let pending = tail.wrapping_sub(sq_head.load(Ordering::Acquire));
+            if let Err(err) = self.enter(pending, 1) {
+                match err.raw_os_error() {
+                    Some(libc::EINTR | libc::EAGAIN | libc::EBUSY) => (),
+                    _ if pending > 0 => {
+                        tail = tail.wrapping_sub(pending);
+                        sq_tail.store(tail, Ordering::Release);
+                        submitted -= pending;
+                    }
+                    _ => (),
+                }
+            }
+
+            let mut head = cq_head.load(Ordering::Acquire);
+            while head != cq_tail.load(Ordering::Acquire) {
+                let cqe = unsafe {
+                    &*self
+                        .cq()
+                        .at::<Cqe>(cq_off.cqes)
+                        .add((head & cq_mask) as usize)
+                };
+                if cqe.res > 0 {
+                    done[cqe.user_data as usize] = cqe.res as usize;
+                }
+                head = head.wrapping_add(1);
+                completed += 1;
+            }
+            cq_head.store(head, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+thread_local! {
+    // None until the first batch, then ring or error if it couldn't be set up
+    static RING: RefCell<Option<io::Result<Ring>>> = const { RefCell::new(None) };
+}
+
+pub fn read_batch(fd: RawFd, reads: &mut [(u64, Vec<u8>)]) -> Vec<usize> {
+    // number of bytes read into every buffer, reads are submitted in chunks of ring size
+    let mut done = vec![0; reads.len()];
+    RING.with_borrow_mut(|ring| {
+        if let Ok(ring) = ring.get_or_insert_with(Ring::new) {
+            let size = ring.params.sq_entries as usize;
+            for (reads, done) in reads.chunks_mut(size).zip(done.chunks_mut(size)) {
+                ring.read(fd, reads, done);
+            }
+        }
+    });
+    done
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+
+    fn data_file(name: &str, len: usize) -> (File, Vec<u8>) {
+        let path =
+            std::env::temp_dir().join(format!("minisearch-uring-{}-{}", name, std::process::id()));
+        let data = (0..len).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (file, data)
+    }
+
+    fn supported() -> bool {
+        // kernel may not support io_uring or it can be disabled, then nothing is read
+        RING.with_borrow_mut(|ring| ring.get_or_insert_with(Ring::new).is_ok())
+    }
+
+    #[test]
+    fn reads_more_than_ring_entries() {
+        if !supported() {
+            return;
+        }
+        let (file, data) = data_file("entries", 64 * 1024);
+        let mut reads = (0..ENTRIES as u64 * 3 + 7)
+            .map(|i| ((i * 97) % 60000, vec![0u8; 16 + i as usize % 300]))
+            .collect::<Vec<(u64, Vec<u8>)>>();
+        let done = read_batch(file.as_raw_fd(), &mut reads);
+
+        for ((offset, buf), n) in reads.iter().zip(done) {
+            let offset = *offset as usize;
+            assert_eq!(n, buf.len());
+            assert_eq!(buf, &data[offset..offset + buf.len()]);
+        }
+    }
+
+    #[test]
+    fn short_read_at_end_of_file() {
+        if !supported() {
+            return;
+        }
+        let (file, data) = data_file("eof", 100);
+        let mut reads = vec![
+            (90, vec![0u8; 20]),
+            (100, vec![0u8; 10]),
+            (0, vec![0u8; 10]),
+        ];
+        let done = read_batch(file.as_raw_fd(), &mut reads);
+
+        assert_eq!(done, vec![10, 0, 10]);
+        assert_eq!(reads[0].1[..10], data[90..]);
+        assert_eq!(reads[2].1, data[..10]);
+    }
+
+    #[test]
+    fn bad_fd_reads_nothing() {
+        let mut reads = vec![(0, vec![0u8; 8]); ENTRIES as usize + 1];
+        let done = read_batch(-1, &mut reads);
+
+        assert_eq!(done, vec![0; reads.len()]);
+        assert!(reads.iter().all(|(_, buf)| buf.iter().all(|b| *b == 0)));
+
+        // ring is still usable after failed reads
+        if supported() {
+            let (file, data) = data_file("reuse", 10);
+            let mut reads = vec![(0, vec![0u8; 10])];
+            assert_eq!(read_batch(file.as_raw_fd(), &mut reads), vec![10]);
+            assert_eq!(reads[0].1, data);
+        }
+    }
+}
@@ -1,9 +1,20 @@
 import os
+import glob
 import time
+import csv
 import json
+import logging
 import shutil
 import pytest
-from minisearch import MiniSearch
+import random
+from minisearch import MiniSearch, QueryBuilder, SearchOptions, levenshtein, within_distance
+from minisearch.rust import (
+    BincodeDecodeError,
+    DocumentCorrupted,
+    InvalidEncoding,
+    TomlDeserializeException,
+    UlidDecodeError,
+)
 
 MINISEARCH_DIR = "data"
 
@@ -127,6 +138,7 @@ def test_search(subtests, data, queries, results):
 
     with subtests.test(msg="test_search [new data]"):
         # load persisted data
+        del search, index
         s = time.time()
         search = MiniSearch()
         _, index = search.add("wikipedia", MINISEARCH_DIR)
@@ -168,6 +180,7 @@ def test_search_after_deletes(subtests, data, queries, results):
     with subtests.test(msg="test_search_after_deletes [persisted data]"):
 
         # load persisted data
+        del search, index
         s = time.time()
         search = MiniSearch()
         _, index = search.add("wikipedia", MINISEARCH_DIR)
@@ -178,6 +191,202 @@ def test_search_after_deletes(subtests, data, queries, results):
         )
 
 
+def test_stats_after_deletes(subtests):
+    search = MiniSearch()
+    _, index = search.add("stats_after_deletes", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add("lazy fox")
+    expected = index.search("fox")[0].score
+
+    with subtests.test(msg="test_stats_after_deletes [all deleted]"):
+        # average length of empty index starts again from scratch
+        index.delete(first)
+        index.flush()
+        with index.session():
+            second = index.add("lazy fox")
+        results = index.search("fox")
+        assert [r.document.id for r in results] == [second]
+        assert results[0].score == pytest.approx(expected)
+
+    with subtests.test(msg="test_stats_after_deletes [reopened]"):
+        with index.session():
+            deleted = [index.add(f"quick fox {i}") for i in range(10)]
+        for id in deleted:
+            index.delete(id)
+        index.flush()
+        assert index.search("fox")[0].score == pytest.approx(expected)
+
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("stats_after_deletes", MINISEARCH_DIR)
+        assert index.search("fox")[0].score == pytest.approx(expected)
+
+
+def test_merge_read_ahead():
+    search = MiniSearch()
+    _, index = search.add("read_ahead", MINISEARCH_DIR, "tests/assets/merge_read_ahead_test_conf.toml")
+
+    def big(seed):
+        # hex words barely compress, so document is larger than a single read
+        data = random.Random(seed).randbytes(600_000).hex()
+        return " ".join(data[i : i + 8] for i in range(0, len(data), 8))
+
+    contents = [f"small {i}" for i in range(100)] + [big(1)]
+    contents += [f"small {i}" for i in range(100, 200)] + [big(2), big(3)]
+    contents += [f"small {i}" for i in range(200, 300)] + [big(i) for i in range(4, 9)]
+    with index.session():
+        ids = [index.add(content) for content in contents]
+
+    # deleted documents leave gaps both shorter and longer than read through ones
+    deleted = set(ids[:300:2]) | set(ids[150:250]) | {ids[201], ids[202], ids[303]}
+    with index.session():
+        for _id in deleted:
+            index.delete(_id)
+        index.merge()
+
+    del search, index
+    _, index = MiniSearch().add("read_ahead", MINISEARCH_DIR, "tests/assets/merge_read_ahead_test_conf.toml")
+    for _id, content in zip(ids, contents):
+        if _id in deleted:
+            with pytest.raises(KeyError):
+                index.get(_id)
+        else:
+            assert index.get(_id).content == content
+
+
+def test_merge_progress():
+    conf = "tests/assets/merge_test_conf.toml"
+    search = MiniSearch()
+    _, index = search.add("merge_progress", MINISEARCH_DIR, conf)
+
+    rng = random.Random(0)
+    contents = [f"doc{i} " + rng.randbytes(200).hex() for i in range(40)]
+    with index.session():
+        ids = [index.add(content) for content in contents]
+        for _id in ids[::2]:
+            index.delete(_id)
+
+    def data_size():
+        paths = glob.glob(f"{MINISEARCH_DIR}/*/data")
+        return sum(os.path.getsize(path) for path in paths)
+
+    def check():
+        for i, _id in enumerate(ids):
+            if i % 2 == 0:
+                with pytest.raises(KeyError):
+                    index.get(_id)
+            else:
+                assert index.get(_id).content == contents[i]
+
+    # cancelled merge finishes the segment being rewritten
+    sizes = [data_size()]
+    assert index.merge(lambda processed, total: False) is False
+    sizes.append(data_size())
+    check()
+
+    def failing(processed, total):
+        raise RuntimeError("merge aborted")
+
+    with pytest.raises(RuntimeError):
+        index.merge(failing)
+    sizes.append(data_size())
+    check()
+
+    calls = []
+    assert index.merge(lambda processed, total: calls.append((processed, total))) is True
+    assert calls and calls[-1][0] == calls[-1][1]
+    assert all(x[0] <= y[0] for x, y in zip(calls, calls[1:]))
+    sizes.append(data_size())
+    check()
+    assert index.merge() is True
+    # every merge rewrites some segments without their deleted documents
+    assert sizes[0] > sizes[1] > sizes[2] > sizes[3]
+
+    del search, index
+    _, index = MiniSearch().add("merge_progress", MINISEARCH_DIR, conf)
+    check()
+
+
+def test_coalesce_segments():
+    search = MiniSearch()
+    _, index = search.add("coalesce", MINISEARCH_DIR, "tests/assets/merge_test_conf.toml")
+
+    rng = random.Random(0)
+    contents = [f"document {i} " + rng.randbytes(300).hex() for i in range(20)]
+    with index.session():
+        ids = [index.add(content) for content in contents]
+        index.delete(ids[3])
+
+    def segments():
+        return len(glob.glob(f"{MINISEARCH_DIR}/*/data"))
+
+    assert segments() > 2
+
+    def files():
+        return {
+            path: os.path.getmtime(path)
+            for path in glob.glob(f"{MINISEARCH_DIR}/**", recursive=True)
+            if os.path.isfile(path)
+        }
+
+    # opening index doesn't rewrite segments, so readers can share the directory
+    del search, index
+    before = files()
+    search = MiniSearch()
+    _, index = search.add("coalesce", MINISEARCH_DIR, "tests/assets/coalesce_test_conf.toml")
+    index.reload()
+    assert segments() > 2
+    assert files() == before
+
+    # small segments are rewritten into the current one by merge
+    assert index.merge()
+    assert segments() == 1
+    for i, _id in enumerate(ids):
+        if i == 3:
+            with pytest.raises(KeyError):
+                index.get(_id)
+        else:
+            assert index.get(_id).content == contents[i]
+    assert len(index.search("document", top_k=0)) == 19
+
+    del search, index
+    _, index = MiniSearch().add("coalesce", MINISEARCH_DIR, "tests/assets/coalesce_test_conf.toml")
+    assert segments() == 1
+    assert index.get(ids[19]).content == contents[19]
+
+
+def test_coalesce_segments_on_open():
+    search = MiniSearch()
+    _, index = search.add("coalesce", MINISEARCH_DIR, "tests/assets/merge_test_conf.toml")
+
+    rng = random.Random(0)
+    contents = [f"document {i} " + rng.randbytes(300).hex() for i in range(20)]
+    with index.session():
+        ids = [index.add(content) for content in contents]
+        index.delete(ids[3])
+
+    def segments():
+        return len(glob.glob(f"{MINISEARCH_DIR}/*/data"))
+
+    assert segments() > 2
+
+    # writer opening index with coalesce_on_open rewrites small segments before
+    # it serves any query
+    del search, index
+    search = MiniSearch()
+    _, index = search.add("coalesce", MINISEARCH_DIR, "tests/assets/coalesce_on_open_test_conf.toml")
+    assert segments() == 1
+    assert len(index.search("document", top_k=0)) == 19
+    assert index.get(ids[19]).content == contents[19]
+    with pytest.raises(KeyError):
+        index.get(ids[3])
+
+    # it requires size of segments to coalesce
+    with pytest.raises(ValueError):
+        search.add("invalid_coalesce", MINISEARCH_DIR, "tests/assets/invalid_coalesce_test_conf.toml")
+
+
 def test_search_after_merge(subtests, data, queries, results):
 
     with subtests.test(msg="test_search_after_merge [new data]"):
@@ -211,6 +420,7 @@ def test_search_after_merge(subtests, data, queries, results):
 
     with subtests.test(msg="test_search_after_merge [persisted data]"):
         # load persisted data
+        del search, index
         s = time.time()
         search = MiniSearch()
         _, index = search.add("wikipedia", MINISEARCH_DIR)
@@ -219,3 +429,2823 @@ def test_search_after_merge(subtests, data, queries, results):
         validate_all_results(
             [0, 5, 10], range(0, 4), range(0, 3), index, queries, results
         )
+
+
+def test_search_phrase_order():
+    search = MiniSearch()
+    _, index = search.add("phrase_order", MINISEARCH_DIR)
+
+    with index.session():
+        ordered = index.add("new york city")
+        gap = index.add("new big york")
+        reversed = index.add("york new")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # slop allows extra tokens between query tokens, but never swapping them
+    assert found('"new york"') == {ordered}
+    assert found('"new york"~5') == {ordered, gap}
+    assert found('"york new"~5') == {reversed}
+
+
+def test_search_mixed_phrases_and_terms():
+    search = MiniSearch()
+    _, index = search.add("mixed", MINISEARCH_DIR)
+
+    with index.session():
+        apart = index.add("error handling guide, tokio runtime written in rust")
+        adjacent = index.add("rust tokio error handling")
+        loose = index.add("error and exception handling with rust tokio")
+        index.add("handling error in rust tokio")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # bare terms next to a phrase don't have to be adjacent
+    assert found('"error handling" rust tokio') == {apart, adjacent}
+    assert found('"error handling"~1 rust tokio') == {apart, adjacent, loose}
+    assert found('rust "error handling" "tokio runtime"') == {apart}
+    # without phrase bare terms are still matched as a phrase
+    assert found("tokio rust") == set()
+
+
+def test_stop_words_placeholders():
+    search = MiniSearch()
+    _, index = search.add(
+        "placeholders", MINISEARCH_DIR, "tests/assets/placeholders_test_conf.toml"
+    )
+
+    with index.session():
+        index.add("state of the art")
+        index.add("state art")
+
+    results = [r.document.content for r in index.search('"state of the art"', top_k=0)]
+    assert results == ["state of the art"]
+
+    results = [r.document.content for r in index.search('"state art"', top_k=0)]
+    assert results == ["state art"]
+
+    # removed stop words advance positions, so slop has to cover them
+    results = [r.document.content for r in index.search('"state art"~1', top_k=0)]
+    assert results == ["state art"]
+    results = {r.document.content for r in index.search('"state art"~2', top_k=0)}
+    assert results == {"state art", "state of the art"}
+
+
+def test_search_exact_phrase(subtests):
+    search = MiniSearch()
+    _, index = search.add(
+        "exact_phrase", MINISEARCH_DIR, "tests/assets/placeholders_test_conf.toml"
+    )
+
+    with index.session():
+        first = index.add("quick brown fox jumps over quick brown dog")
+        second = index.add("brown quick fox and fox brown")
+        third = index.add("fox fox fox the fox")
+        fourth = index.add("state art art")
+
+    for query, expected in [
+        ('"quick brown"', {first}),
+        ('"brown fox"', {first}),
+        ('"fox quick"', set()),
+        ('"quick fox"', {second}),
+        ('"fox fox"', {third}),
+        ('"fox the fox"', {second, third}),
+        ('"quck~1 brown dog"', {first}),
+        ('"state of art"', {fourth}),
+    ]:
+        with subtests.test(msg=f"test_search_exact_phrase [{query}]"):
+            assert {r.document.id for r in index.search(query, top_k=0)} == expected
+
+    with subtests.test(msg="test_search_exact_phrase [score]"):
+        # every occurrence of phrase counts in tf of its tokens as with slop
+        exact = index.search('"quick brown"')[0].score
+        assert exact == pytest.approx(index.search('"quick brown"~1')[0].score)
+
+
+def test_stop_words_fallback():
+    search = MiniSearch()
+    docs = ["to be or not to be", "be quick", "quick fox"]
+
+    def found(index, query):
+        return {r.document.content for r in index.search(query, top_k=0)}
+
+    # indexes with different configs can't share directory
+    _, index = search.add("stop_words_none", os.path.join(MINISEARCH_DIR, "none"))
+    with index.session():
+        for doc in docs:
+            index.add(doc)
+    assert found(index, "to be or not to be") == set()
+
+    _, index = search.add(
+        "stop_words_terms",
+        os.path.join(MINISEARCH_DIR, "terms"),
+        "tests/assets/stop_words_terms_test_conf.toml",
+    )
+    with index.session():
+        for doc in docs:
+            index.add(doc)
+    assert found(index, "to be or not to be") == {"to be or not to be"}
+    assert found(index, "be") == {"to be or not to be", "be quick"}
+    # stop words are still skipped in queries with other terms
+    assert found(index, "the quick") == {"be quick", "quick fox"}
+
+    _, index = search.add(
+        "stop_words_all",
+        os.path.join(MINISEARCH_DIR, "all"),
+        "tests/assets/stop_words_all_test_conf.toml",
+    )
+    with index.session():
+        for doc in docs:
+            index.add(doc)
+    assert found(index, "to be or not to be") == set(docs)
+    assert found(index, "the quick") == {"be quick", "quick fox"}
+
+
+def test_stop_words_presets():
+    search = MiniSearch()
+    _, index = search.add(
+        "stop_words_preset", MINISEARCH_DIR, "tests/assets/stop_words_preset_test_conf.toml"
+    )
+
+    with index.session():
+        german = index.add("der Hund und die Katze")
+        english = index.add("the dog and the cat")
+        index.add("bitte schön")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # german preset replaces english stop words, words from file are added to it
+    assert found("und") == set()
+    assert found("the") == {english}
+    assert found("Hund") == {german}
+    assert found("bitte") == set()
+
+    with pytest.raises(TomlDeserializeException):
+        search.add("invalid_stop_words", MINISEARCH_DIR, "tests/assets/invalid_stop_words_test_conf.toml")
+
+
+def test_stop_words_lang():
+    search = MiniSearch()
+    _, index = search.add("stop_words_lang", MINISEARCH_DIR, "tests/assets/stop_words_lang_test_conf.toml")
+
+    with index.session():
+        german = index.add("der Hund ist nicht da")
+        english = index.add("the dog and the cat")
+        index.add("bitte schön")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # language code selects german preset, individual words are added and removed
+    assert found("ist") == set()
+    assert found("the") == {english}
+    assert found("bitte") == set()
+    assert found("nicht") == {german}
+
+    with pytest.raises(ValueError):
+        search.add("invalid_stop_words_lang", MINISEARCH_DIR, "tests/assets/invalid_stop_words_lang_test_conf.toml")
+
+
+def test_analyze():
+    search = MiniSearch()
+    _, index = search.add("analyze", MINISEARCH_DIR, "tests/assets/placeholders_test_conf.toml")
+
+    # stop words are removed, but consume position, words are lowercased and stemmed
+    assert index.analyze("The Running of the Bulls") == [("run", 1), ("bull", 4)]
+    assert index.analyze("") == []
+
+    # tokens match what is indexed
+    with index.session():
+        doc = index.add("The Running of the Bulls")
+    assert [(doc, 1, [4])] == list(index.postings("bulls"))
+
+    _, ngrams = search.add("analyze_ngrams", os.path.join(MINISEARCH_DIR, "ngrams"), "tests/assets/edge_ngrams_test_conf.toml")
+    tokens = ngrams.analyze("sea shells")
+    assert tokens[0][1] == 0 and tokens[-1][1] == 1
+    assert ("sea", 0) in tokens and ("shell", 1) in tokens
+
+    def failing(text):
+        raise RuntimeError("tokenizer failed")
+
+    index.set_tokenizer(failing)
+    with pytest.raises(RuntimeError):
+        index.analyze("text")
+    index.set_tokenizer(lambda text: [(word, i) for i, word in enumerate(text.split())])
+    assert index.analyze("The Running") == [("The", 0), ("Running", 1)]
+
+
+def test_add_encoding():
+    search = MiniSearch()
+    _, index = search.add("encoding", MINISEARCH_DIR)
+
+    with index.session():
+        # utf-8 encoded bytes are the same as text
+        doc = index.add_idempotent("café au lait".encode())
+        assert index.add_idempotent("café au lait") == doc
+
+        # content which isn't valid utf-8 is rejected unless replaced
+        with pytest.raises(InvalidEncoding):
+            index.add(b"caf\xe9 noir")
+        with pytest.raises(InvalidEncoding):
+            index.add("caf\udce9 noir")
+        with pytest.raises(ValueError):
+            index.add("cafe", errors="ignore")
+        replaced = index.add(b"caf\xe9 noir", errors="replace")
+        surrogate = index.add_idempotent("caf\udce9 noir", errors="replace")
+
+    assert index.get(doc).content == "café au lait"
+    assert index.get(replaced).content == "caf\ufffd noir"
+    assert index.get(replaced).content_bytes == "caf\ufffd noir".encode()
+    assert index.get(surrogate).content == "caf\ufffd noir"
+    # nothing was added by rejected documents
+    assert {r.document.id for r in index.search("noir")} == {surrogate, replaced}
+
+
+def test_stem_cache():
+    search = MiniSearch()
+    _, cached = search.add("stem_cache", MINISEARCH_DIR, "tests/assets/stem_cache_test_conf.toml")
+    _, uncached = search.add(
+        "no_stem_cache", os.path.join(MINISEARCH_DIR, "uncached"), "tests/assets/no_stem_cache_test_conf.toml"
+    )
+
+    # cache of two words evicts and reuses entries, stems stay the same
+    text = "running runs runner running ran cats cat running cats"
+    assert cached.analyze(text) == uncached.analyze(text)
+    assert cached.analyze(text) == [(token, i) for i, token in enumerate(
+        ["run", "run", "runner", "run", "ran", "cat", "cat", "run", "cat"]
+    )]
+
+    with cached.session():
+        doc = cached.add(text)
+    assert [r.document.id for r in cached.search("run")] == [doc]
+    assert cached.memory_usage()["caches"] > uncached.memory_usage()["caches"]
+
+
+def test_compound_words():
+    search = MiniSearch()
+    _, index = search.add("compound", MINISEARCH_DIR, "tests/assets/compound_words_test_conf.toml")
+
+    with index.session():
+        ship = index.add("Das Donaudampfschiff legt an")
+        trip = index.add("Schifffahrtsgesellschaft gegründet")
+        other = index.add("Ein Schiff")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # dictionary words inside of compound are found, stemmed as any other word
+    assert found("schiff") == {ship, trip, other}
+    assert found("dampf") == {ship}
+    assert found("donau") == {ship}
+    assert found("fahrt") == {trip}
+    assert found("gesellschaft") == {trip}
+    assert found("donaudampfschiff") == {ship}
+    # parts take position of their compound
+    assert found('"donau legt"') == {ship}
+    assert index.analyze("Donaudampfschiff legt") == [
+        ("dampf", 0), ("donau", 0), ("schiff", 0), ("donaudampfschiff", 0), ("legt", 1)
+    ]
+
+
+def test_stemming_language():
+    search = MiniSearch()
+    _, index = search.add("language", MINISEARCH_DIR, "tests/assets/language_test_conf.toml")
+
+    with index.session():
+        houses = index.add("Die Häuser der Stadt sind alt")
+        friendly = index.add("Die Freundlichkeit der Menschen")
+        index.add("the houses of the city")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # inflected forms share german stem, non ascii capitals are lowercased
+    assert found("haus") == {houses}
+    assert found("Häusern") == {houses}
+    assert found("HÄUSER") == {houses}
+    assert found("freundlich") == {friendly}
+    assert found('"häuser stadt"') == {houses}
+
+    with pytest.raises(TomlDeserializeException):
+        search.add("invalid_language", MINISEARCH_DIR, "tests/assets/invalid_language_test_conf.toml")
+
+
+def test_edge_ngrams():
+    search = MiniSearch()
+    _, index = search.add("edge_ngrams", MINISEARCH_DIR, "tests/assets/edge_ngrams_test_conf.toml")
+
+    with index.session():
+        engines = index.add("Searching engines")
+        shells = index.add("sea shells")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # prefixes from 2 to 5 characters are indexed, longer ones match only whole words
+    assert found("se") == {engines, shells}
+    assert found("sea") == {engines, shells}
+    assert found("searc") == {engines}
+    assert found("search") == {engines}
+    assert found("searchi") == set()
+    assert found("s") == set()
+    assert found('"sea she"') == {shells}
+
+    with pytest.raises(ValueError):
+        search.add("invalid_edge_ngrams", MINISEARCH_DIR, "tests/assets/invalid_edge_ngrams_test_conf.toml")
+
+
+def test_char_ngrams():
+    search = MiniSearch()
+    _, index = search.add("char_ngrams", MINISEARCH_DIR, "tests/assets/char_ngrams_test_conf.toml")
+
+    with index.session():
+        tokyo = index.add("東京都に住む")
+        kyoto = index.add("京都の寺")
+        engine = index.add("Substring search engine")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # text without spaces is matched by any substring of at least two characters
+    assert found("京都") == {tokyo, kyoto}
+    assert found("東京") == {tokyo}
+    assert found("都に住") == {tokyo}
+    assert found("京寺") == set()
+    assert found("STRING") == {engine}
+    # phrase positions continue across words, but grams don't span them
+    assert found('"string search"') == {engine}
+    assert found('"string engine"') == set()
+    assert found("gs") == set()
+
+    with pytest.raises(ValueError):
+        search.add("invalid_char_ngrams", MINISEARCH_DIR, "tests/assets/invalid_char_ngrams_test_conf.toml")
+
+
+def test_max_positions():
+    search = MiniSearch()
+    _, index = search.add("max_positions", MINISEARCH_DIR, "tests/assets/max_positions_test_conf.toml")
+
+    with index.session():
+        long = index.add("one two three four five six seven")
+        short = index.add("one two three")
+        field = index.add("apple", fields={"tags": ["red", "green"]})
+
+    def found(index, query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # words past the limit aren't indexed, but document is stored whole
+    assert found(index, "five") == {long}
+    assert found(index, "six") == set()
+    assert index.get(long).content == "one two three four five six seven"
+    assert index.get(long).positions_truncated
+    assert not index.get(short).positions_truncated
+    # the second field value starts after the gap between values
+    assert found(index, "tags:red") == {field}
+    assert found(index, "tags:green") == set()
+    assert index.get(field).positions_truncated
+
+    # custom tokenizer positions are limited as well
+    index.set_tokenizer(lambda text: [(word, i * 2) for i, word in enumerate(text.split())])
+    with index.session():
+        custom = index.add("alpha beta gamma delta")
+    assert found(index, "gamma") == {custom}
+    assert found(index, "delta") == set()
+    assert index.get(custom).positions_truncated
+    index.set_tokenizer(None)
+
+    # flag is persisted
+    del index, search
+    _, index = MiniSearch().add("max_positions", MINISEARCH_DIR, "tests/assets/max_positions_test_conf.toml")
+    assert index.get(long).positions_truncated
+    assert not index.get(short).positions_truncated
+
+    _, reject = MiniSearch().add(
+        "reject", os.path.join(MINISEARCH_DIR, "reject"), "tests/assets/max_positions_reject_test_conf.toml"
+    )
+    with reject.session():
+        with pytest.raises(ValueError):
+            reject.add("one two three four five six")
+        with pytest.raises(ValueError):
+            reject.add("apple", fields={"tags": ["red", "green"]})
+        kept = reject.add("one two three four five")
+    assert found(reject, "one") == {kept}
+    assert not reject.get(kept).positions_truncated
+
+    with pytest.raises(ValueError):
+        MiniSearch().add("invalid", MINISEARCH_DIR, "tests/assets/invalid_max_positions_test_conf.toml")
+
+
+def test_ascii_folding():
+    search = MiniSearch()
+    _, index = search.add("ascii_folding", MINISEARCH_DIR, "tests/assets/ascii_folding_test_conf.toml")
+
+    with index.session():
+        cafe = index.add("Café au lait")
+        dessert = index.add("Crème brûlée recipes")
+        resumes = index.add("Résumés of engineers")
+        street = index.add("Große Straße")
+
+    def found(index, query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # letters with diacritics match their ascii base in both directions
+    assert found(index, "cafe") == {cafe}
+    assert found(index, "café") == {cafe}
+    assert found(index, "CAFÉ") == {cafe}
+    assert found(index, '"creme brulee"') == {dessert}
+    assert found(index, "strasse") == {street}
+    # folded words are stemmed as well
+    assert found(index, "resume") == {resumes}
+    assert found(index, "recipé") == {dessert}
+    # stored document keeps diacritics
+    assert index.search("cafe")[0].document.content == "Café au lait"
+
+    _, plain = search.add("plain", os.path.join(MINISEARCH_DIR, "plain"))
+    with plain.session():
+        plain_cafe = plain.add("Café au lait")
+    assert found(plain, "cafe") == set()
+    assert found(plain, "café") == {plain_cafe}
+
+
+def test_fuzzy_vocabulary_cap():
+    search = MiniSearch()
+    _, index = search.add(
+        "vocabulary", MINISEARCH_DIR, "tests/assets/vocabulary_cap_test_conf.toml"
+    )
+
+    with index.session():
+        for _ in range(3):
+            index.add("common words here")
+        index.add("rare")
+        index.add("unusual")
+
+    # rare words are evicted from fuzzy trie but still match exactly
+    assert [r.document.content for r in index.search("rare")] == ["rare"]
+    assert index.search("rarx~1") == []
+    assert len(index.search("commox~1", top_k=0)) == 3
+
+    # frequent enough words are added back to fuzzy trie
+    with index.session():
+        index.add("rare")
+    assert len(index.search("rarx~1", top_k=0)) == 2
+
+
+def test_fuzzy_disabled():
+    search = MiniSearch()
+    _, index = search.add("no_fuzzy", MINISEARCH_DIR, "tests/assets/no_fuzzy_test_conf.toml")
+
+    with index.session():
+        quick = index.add("quick brown fox")
+        index.add("lazy dog")
+
+    # fuzzy trie isn't built at all
+    assert index.memory_usage()["trie"] == 0
+    assert [r.document.id for r in index.search("brown fox")] == [quick]
+    assert [r.document.id for r in index.search('"brown fox" OR cat')] == [quick]
+
+    for query in ["quikc~1", "quick~", "lazy OR brown~2"]:
+        with pytest.raises(ValueError, match="enable_fuzzy"):
+            index.search(query)
+    with pytest.raises(ValueError, match="enable_fuzzy"):
+        index.search(QueryBuilder.term("quick", fuzziness=1))
+
+
+def test_numbers_normalization(subtests):
+    search = MiniSearch()
+    _, index = search.add(
+        "numbers", MINISEARCH_DIR, "tests/assets/numbers_test_conf.toml"
+    )
+
+    with index.session():
+        first = index.add("price 1,000 dollars")
+        second = index.add("price 1000.00 euro", {"number": 5})
+        index.add("price 1000.5 pounds")
+        index.add("price 1,00 yen")
+
+    for query in ["1000", "1,000", "01000.0"]:
+        with subtests.test(msg=f"query [{query}]"):
+            results = {r.document.id for r in index.search(query, top_k=0)}
+            assert results == {first, second}
+
+    assert index.attributes(first) == {"number": 1000.0}
+    assert index.attributes(second) == {"number": 5.0}
+    assert len(index.search("1,00", top_k=0)) == 1
+
+
+
+def test_adaptive_auto_fuzz(subtests):
+    search = MiniSearch()
+    _, default = search.add("auto_fuzz_default", os.path.join(MINISEARCH_DIR, "default"))
+    _, adaptive = search.add(
+        "auto_fuzz_adaptive",
+        os.path.join(MINISEARCH_DIR, "adaptive"),
+        "tests/assets/auto_fuzz_test_conf.toml",
+    )
+
+    for index in [default, adaptive]:
+        with index.session():
+            for i in range(5):
+                index.add(f"cart item {i}")
+            index.add("card item")
+
+    def words(index, query):
+        return sorted({r.document.content.split()[0] for r in index.search(query)})
+
+    with subtests.test(msg="test_adaptive_auto_fuzz [frequent term]"):
+        assert words(default, "cart~") == ["card", "cart"]
+        assert words(adaptive, "cart~") == ["cart"]
+
+    with subtests.test(msg="test_adaptive_auto_fuzz [rare term]"):
+        assert words(default, "card~") == ["card", "cart"]
+        assert words(adaptive, "card~") == ["card", "cart"]
+
+    with subtests.test(msg="test_adaptive_auto_fuzz [unknown term]"):
+        assert words(default, "cxrd~") == ["card"]
+        assert words(adaptive, "cxrd~") == ["card", "cart"]
+
+    with subtests.test(msg="test_adaptive_auto_fuzz [explicit fuzz]"):
+        assert words(adaptive, "cart~1") == ["card", "cart"]
+
+
+def test_levenshtein_helpers():
+    def reference(a, b):
+        row = list(range(len(b) + 1))
+        for i, ca in enumerate(a, 1):
+            prev, row[0] = row[0], i
+            for j, cb in enumerate(b, 1):
+                prev, row[j] = row[j], min(row[j] + 1, row[j - 1] + 1, prev + (ca != cb))
+        return row[-1]
+
+    words = ["", "a", "cat", "cart", "card", "call", "elephant", "elepahnt", "żółw", "zolw"]
+    for a in words:
+        for b in words:
+            for d in range(3):
+                expected = reference(a, b)
+                assert levenshtein(a, b, d) == (expected if expected <= d else None)
+                assert within_distance(a, b, d) == (expected <= d)
+
+    with pytest.raises(ValueError):
+        levenshtein("cat", "cart", 3)
+
+
+def test_search_fuzzy_distances():
+    search = MiniSearch()
+    _, index = search.add("distances", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("zebra")
+
+    # automata are built on first use, in any order of distances
+    for fuzz, expected in [(2, 1), (0, 0), (1, 0)]:
+        assert len(index.search(f"zbera~{fuzz}")) == expected
+
+    with pytest.raises(ValueError):
+        index.search("zbera~3")
+
+
+def test_search_fuzziness_config(subtests):
+    search = MiniSearch()
+    _, index = search.add("fuzziness", MINISEARCH_DIR, "tests/assets/fuzziness_test_conf.toml")
+
+    with index.session():
+        index.add("zebra cat")
+
+    with subtests.test(msg="test_search_fuzziness_config [auto fuzz lengths]"):
+        assert index.parse("zebrs~")["terms"][0]["fuzz"] == 1
+        assert index.parse("cat~")["terms"][0]["fuzz"] == 0
+        assert len(index.search("zebrs~")) == 1
+        assert len(index.search("cut~")) == 0
+
+    with subtests.test(msg="test_search_fuzziness_config [max fuzziness]"):
+        assert len(index.search("zbera~1")) == 0
+        for query in ["zbera~2", QueryBuilder.term("zbera", fuzziness=2)]:
+            with pytest.raises(ValueError):
+                index.search(query)
+
+    with subtests.test(msg="test_search_fuzziness_config [invalid config]"):
+        with pytest.raises(ValueError):
+            MiniSearch().add("invalid_fuzziness", MINISEARCH_DIR, "tests/assets/invalid_fuzziness_test_conf.toml")
+
+
+def test_search_synonyms(subtests):
+    search = MiniSearch()
+    _, index = search.add("synonyms", MINISEARCH_DIR, "tests/assets/synonyms_test_conf.toml")
+
+    with index.session():
+        car = index.add("red car parked outside")
+        automobile = index.add("red automobiles parked outside")
+        fast = index.add("fast brown fox", fields={"title": "fast cars"})
+        quick = index.add("quick brown fox")
+
+    def ids(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    with subtests.test(msg="test_search_synonyms [group]"):
+        assert ids("automobile") == {car, automobile}
+        assert ids("auto") == {car, automobile}
+        assert ids('"red car parked"') == {car, automobile}
+
+    with subtests.test(msg="test_search_synonyms [one direction]"):
+        assert ids('"quick brown"') == {fast, quick}
+        assert ids("fast") == {fast}
+
+    with subtests.test(msg="test_search_synonyms [field]"):
+        assert ids("title:quick") == {fast}
+        assert ids("title:automobile") == {fast}
+
+    with subtests.test(msg="test_search_synonyms [score]"):
+        results = index.search("car")
+        assert results[0].score == results[1].score
+
+
+def test_max_docs_per_term():
+    search = MiniSearch()
+    _, index = search.add("max_docs", MINISEARCH_DIR, "tests/assets/max_docs_test_conf.toml")
+
+    with index.session():
+        docs = [(1, "rare"), (3, "rare"), (2, "other"), (1, "other")]
+        ids = [index.add(" ".join(["common"] * tf + [word])) for tf, word in docs]
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    # only postings with the highest tf are scanned for common tokens
+    assert found("common") == {ids[1], ids[2]}
+    assert found("rare") == {ids[0], ids[1]}
+    assert found("common rare") == {ids[1]}
+
+    with index.session():
+        top = index.add("common common common common")
+    assert found("common") == {ids[1], top}
+
+    with index.session():
+        index.add("single")
+    assert len(found("single")) == 1
+
+
+def test_impact_ordered_postings(subtests):
+    search = MiniSearch()
+    _, index = search.add("impact", MINISEARCH_DIR, "tests/assets/impact_test_conf.toml")
+
+    words = ["alpha", "beta", "gamma", "delta", "omega"]
+    with index.session():
+        for i in range(300):
+            doc = [words[(i * j) % 5] for j in range(1, i % 13 + 2)]
+            index.add(" ".join(doc + ["filler"] * (i % 7)))
+
+    queries = ["alpha", "gamma OR omega", "beta OR delta OR alpha OR missing"]
+
+    def run(query):
+        # documents with equal score may be returned in any order
+        results, profile = index.search(query, top_k=10, profile="flame")
+        mis = profile["children"][2]["children"][1]["calls"]
+        return [round(r.score, 9) for r in results], mis
+
+    expected = {query: run(query)[0] for query in queries}
+    index.merge()
+
+    for query in queries:
+        with subtests.test(msg=f"test_impact_ordered_postings [{query}]"):
+            results, mis = run(query)
+            assert results == expected[query]
+            assert mis == 0
+
+    with subtests.test(msg="test_impact_ordered_postings [modified token]"):
+        with index.session():
+            added = index.add("alpha " * 20)
+        assert index.search("alpha", top_k=1)[0].document.id == added
+        assert run("alpha")[1] > 0
+
+
+def test_lazy_positions(subtests):
+    conf = "tests/assets/lazy_positions_test_conf.toml"
+    search = MiniSearch()
+    _, index = search.add("lazy", MINISEARCH_DIR, conf)
+
+    with index.session():
+        ids = [
+            index.add(doc)
+            for doc in [
+                "the quick brown fox jumps over lazy dog",
+                "brown dog and quick fox",
+                "fox fox brown brown fox",
+            ]
+        ]
+
+    queries = ["fox", "quick OR dog", '"brown fox"', '"quick fox"~2', "brwn~1 fox"]
+    expected = {
+        query: [(r.document.id, r.score) for r in index.search(query)]
+        for query in queries
+    }
+    assert [id for id, _ in expected['"brown fox"']] == [ids[2], ids[0]]
+    loaded = index.memory_usage()["index"]
+
+    # postings of loaded index keep only document id and term frequency
+    del search, index
+    search = MiniSearch()
+    _, index = search.add("lazy", MINISEARCH_DIR, conf)
+    assert index.memory_usage()["index"] < loaded
+
+    for query in queries:
+        with subtests.test(msg=f"test_lazy_positions [{query}]"):
+            results = [(r.document.id, r.score) for r in index.search(query)]
+            assert results == expected[query]
+
+    with subtests.test(msg="test_lazy_positions [postings]"):
+        assert list(index.postings("fox")) == [
+            (ids[0], 1, [2]),
+            (ids[1], 1, [3]),
+            (ids[2], 3, [0, 1, 4]),
+        ]
+
+    with subtests.test(msg="test_lazy_positions [new documents]"):
+        with index.session():
+            added = index.add("brown fox again")
+        results = [r.document.id for r in index.search('"brown fox"')]
+        assert results == [ids[2], added, ids[0]]
+
+
+def test_search_document_length(subtests):
+    search = MiniSearch()
+    _, index = search.add("document_length", MINISEARCH_DIR)
+
+    with index.session():
+        stub = index.add("fox")
+        short = index.add("quick brown fox", fields={"title": "fox"})
+        long = index.add("quick brown fox jumps over lazy dog near river bank")
+
+    for kwargs, expected in [
+        ({}, {stub, short, long}),
+        ({"min_len": 2}, {short, long}),
+        ({"max_len": 4}, {stub, short}),
+        ({"min_len": 4, "max_len": 4}, {short}),
+        ({"options": SearchOptions(min_len=5)}, {long}),
+    ]:
+        for query in ["fox", "*", '"brown fox"~1 OR fox']:
+            with subtests.test(msg=f"test_search_document_length [{query}] {kwargs}"):
+                results = {r.document.id for r in index.search(query, top_k=10, **kwargs)}
+                assert results == expected
+
+    with pytest.raises(ValueError, match="min_len"):
+        index.search("fox", min_len=3, max_len=2)
+
+
+def test_search_length_norm(subtests):
+    search = MiniSearch()
+    _, index = search.add("length_norm", MINISEARCH_DIR, "tests/assets/impact_test_conf.toml")
+
+    with index.session():
+        short = index.add("rust guide")
+        long = index.add("rust ownership explained with borrowing lifetimes traits and generics")
+        repeated = index.add("rust async rust runtime internals and executors for web services")
+        titled = index.add("notes", fields={"title": "rust"})
+        index.add("python guide")
+
+    def scores(query, top_k=10, **kwargs):
+        return {r.document.id: r.score for r in index.search(query, top_k=top_k, **kwargs)}
+
+    for merged in [False, True]:
+        if merged:
+            # impact ordered postings are quantized with length normalization
+            index.merge()
+
+        with subtests.test(msg=f"test_search_length_norm [merged={merged}]"):
+            # shorter documents score higher by default
+            normalized = scores("rust")
+            assert normalized[short] > normalized[long]
+
+            # without normalization only term frequency matters
+            plain = scores("rust", length_norm=False)
+            assert plain[short] == pytest.approx(plain[long])
+            assert plain[repeated] > plain[short]
+            assert scores("rust", options=SearchOptions(length_norm=False)) == plain
+            assert scores("rust", top_k=1, length_norm=False).keys() == {repeated}
+
+            # field length isn't taken into account either
+            fields = scores("title:rust", length_norm=False)
+            assert fields.keys() == {titled}
+
+
+
+def test_search_min_score(subtests):
+    search = MiniSearch()
+    _, index = search.add("min_score", MINISEARCH_DIR, "tests/assets/impact_test_conf.toml")
+
+    with index.session():
+        for i in range(20):
+            index.add(" ".join(["rust"] + [f"filler{chr(97 + j)}" for j in range(i)] + ["guide"]))
+        index.add("python guide")
+
+    def scores(query, top_k=5, **kwargs):
+        return {r.document.id: r.score for r in index.search(query, top_k=top_k, **kwargs)}
+
+    for merged in [False, True]:
+        if merged:
+            # single terms and disjunctions are answered from impact ordered postings
+            index.merge()
+
+        # min_len forces matching without impact ordered postings
+        for query, kwargs in [("rust", {}), ("rust OR guide", {}), ("rust", {"min_len": 1})]:
+            with subtests.test(msg=f"test_search_min_score [{query}, {kwargs}, merged={merged}]"):
+                full = scores(query, top_k=0, **kwargs)
+                cutoff = sorted(full.values(), reverse=True)[7]
+                expected = {id: score for id, score in full.items() if score >= cutoff}
+                # documents scoring exactly min score are kept
+                assert len(expected) == 8
+                assert scores(query, top_k=0, min_score=cutoff, **kwargs) == expected
+                assert scores(query, top_k=20, min_score=cutoff, **kwargs) == expected
+                assert scores(query, min_score=cutoff, **kwargs) == scores(query, **kwargs)
+                assert scores(query, min_score=max(full.values()) + 1, **kwargs) == {}
+                options = SearchOptions(top_k=20, min_score=cutoff)
+                results = index.search(query, options=options, **kwargs)
+                assert {r.document.id: r.score for r in results} == expected
+
+    with subtests.test(msg="test_search_min_score [score script]"):
+        # min score applies to score returned by script
+        best = max(scores("rust").values())
+        assert scores("rust", top_k=0, score_script="score * 100", min_score=best + 1) != {}
+        assert scores("rust", top_k=0, score_script="0 - score", min_score=0.0) == {}
+
+    with subtests.test(msg="test_search_min_score [invalid]"):
+        with pytest.raises(ValueError):
+            index.search("rust", min_score=float("nan"))
+        with pytest.raises(ValueError):
+            SearchOptions(min_score=float("inf"))
+
+def test_search_tie_break(subtests):
+    search = MiniSearch()
+    _, index = search.add("tie_break", MINISEARCH_DIR, "tests/assets/impact_test_conf.toml")
+
+    with index.session():
+        ids = [index.add("rust tutorial") for _ in range(6)]
+        index.add("python tutorial")
+
+    def found(query, tie_break=None, top_k=3):
+        options = SearchOptions(top_k=top_k, tie_break=tie_break)
+        return [r.document.id for r in index.search(query, options=options)]
+
+    # documents with equal score are ranked by id, older first by default
+    for merged in [False, True]:
+        if merged:
+            # single terms and disjunctions are answered from impact ordered postings
+            index.merge()
+
+        for query in ["rust", "rust OR golang", '"rust tutorial"']:
+            with subtests.test(msg=f"test_search_tie_break [{query}, merged={merged}]"):
+                assert found(query) == ids[:3]
+                assert found(query, "oldest") == ids[:3]
+                assert found(query, "newest") == ids[::-1][:3]
+                assert found(query, "newest", top_k=0) == ids[::-1]
+
+    with pytest.raises(ValueError):
+        SearchOptions(tie_break="random")
+
+
+def test_search_seed(subtests):
+    search = MiniSearch()
+    _, index = search.add("seed", MINISEARCH_DIR, "tests/assets/impact_test_conf.toml")
+
+    with index.session():
+        ids = [index.add("rust tutorial") for _ in range(8)]
+        other = index.add("python tutorial")
+
+    def found(query, seed=None, top_k=0):
+        return [r.document.id for r in index.search(query, top_k=top_k, seed=seed)]
+
+    for merged in [False, True]:
+        if merged:
+            index.merge()
+
+        for query in ["rust", "rust OR golang", '"rust tutorial"']:
+            with subtests.test(msg=f"test_search_seed [{query}, merged={merged}]"):
+                # the same seed always gives the same order of ties
+                shuffled = found(query, seed=7)
+                assert sorted(shuffled) == sorted(ids)
+                assert found(query, seed=7) == shuffled
+                assert found(query, seed=7, top_k=3) == shuffled[:3]
+                assert [found(query, seed=seed) for seed in range(5)] != [ids] * 5
+                assert len({tuple(found(query, seed=seed)) for seed in range(5)}) > 1
+
+    # ties are shuffled, but results are still ordered by score
+    results = index.search("rust OR python", top_k=0, seed=3)
+    assert results[0].document.id == other
+    assert sorted(r.document.id for r in results[1:]) == sorted(ids)
+
+    options = SearchOptions(seed=11)
+    assert found("rust", seed=11) == [r.document.id for r in index.search("rust", options=options)]
+
+    with pytest.raises(ValueError, match="seed"):
+        SearchOptions(seed=1, tie_break="newest")
+
+
+def test_quantized_posting_impacts(subtests):
+    search = MiniSearch()
+    _, index = search.add("quantized", MINISEARCH_DIR)
+
+    words = ["alpha", "beta", "gamma", "delta", "omega"]
+    with index.session():
+        for i in range(300):
+            doc = [words[(i * j) % 5] for j in range(1, i % 13 + 2)]
+            index.add(" ".join(doc + ["filler"] * (i % 7)))
+
+    queries = ["alpha beta", "gamma~1 omega", '"delta alpha"~3', "beta OR filler"]
+
+    def run(query):
+        # impacts only bound scores, so ranking has to stay the same
+        return [round(r.score, 9) for r in index.search(query, top_k=10)]
+
+    expected = {query: run(query) for query in queries}
+    index.merge()
+
+    for query in queries:
+        with subtests.test(msg=f"test_quantized_posting_impacts [{query}]"):
+            assert run(query) == expected[query]
+
+    with subtests.test(msg="test_quantized_posting_impacts [added after merge]"):
+        with index.session():
+            for _ in range(50):
+                index.add("alpha beta " + "filler " * 40)
+        for query in queries:
+            expected[query] = run(query)
+        index.merge()
+        for query in queries:
+            assert run(query) == expected[query]
+
+    with subtests.test(msg="test_quantized_posting_impacts [reload]"):
+        search.delete("quantized")
+        del index
+        _, index = search.add("quantized", MINISEARCH_DIR)
+        for query in queries:
+            assert run(query) == expected[query]
+
+
+def test_block_max_skipping(subtests):
+    search = MiniSearch()
+    _, index = search.add("block_max", MINISEARCH_DIR)
+
+    # the best documents are in the first block, so following blocks can be skipped
+    with index.session():
+        for i in range(600):
+            if i < 10:
+                index.add("rust tokio rust tokio")
+            else:
+                index.add("rust tokio " + "filler " * (i % 20 + 5))
+    index.merge()
+
+    def ranking(query, top_k):
+        return [round(r.score, 9) for r in index.search(query, top_k=top_k)]
+
+    def check(query):
+        # skipped blocks can't change top k scores, which are the head of exhaustive ranking
+        exhaustive = ranking(query, 0)
+        for top_k in (1, 3, 10):
+            assert ranking(query, top_k) == exhaustive[:top_k]
+
+    for query in ["rust tokio", '"rust tokio"', "rust~1 tokio"]:
+        with subtests.test(msg=f"test_block_max_skipping [{query}]"):
+            check(query)
+
+    with subtests.test(msg="test_block_max_skipping [added after merge]"):
+        with index.session():
+            best = index.add("rust tokio " * 4)
+        check("rust tokio")
+        assert index.search("rust tokio", top_k=1)[0].document.id == best
+
+    with subtests.test(msg="test_block_max_skipping [deleted]"):
+        index.delete(best)
+        check("rust tokio")
+
+
+def test_display_terms():
+    search = MiniSearch()
+    _, index = search.add("display", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("Müller was Running fast")
+        index.add("running müller")
+
+    assert index.display_term("müller") == "Müller"
+    assert index.display_term("runs") == "Running"
+    assert index.display_term("missing") is None
+    assert index.display_term("the") is None
+
+    search.delete("display")
+    _, index = search.add("display", MINISEARCH_DIR)
+    assert index.display_term("müller") == "Müller"
+
+
+def test_search_field_scoped_queries(subtests):
+    search = MiniSearch()
+    _, index = search.add("fields", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add(
+            "first", fields={"title": "Quick fox", "body": "jumps over the lazy dog"}
+        )
+        second = index.add(
+            "second", fields={"title": "Lazy dog", "body": "sleeps near quick fox"}
+        )
+
+    for query, expected in [
+        ("title:quick", {first}),
+        ("title:quikc~2", {first}),
+        ("body:quick", {second}),
+        ('body:"jumps lazy"~2', {first}),
+        ('title:lazy body:"quick fox"', {second}),
+        ("title:fox OR title:dog", {first, second}),
+        ("title:fox AND NOT body:dog", set()),
+        ("quick", set()),
+        ("first", {first}),
+        ("10:30", set()),
+    ]:
+        with subtests.test(msg=f"query [{query}]"):
+            results = {r.document.id for r in index.search(query, top_k=0)}
+            assert results == expected
+
+    with pytest.raises(ValueError):
+        index.add("third", fields={"1title": "fox"})
+
+
+def test_search_field_weights(subtests):
+    search = MiniSearch()
+    _, index = search.add("field_weights", MINISEARCH_DIR, "tests/assets/field_weights_test_conf.toml")
+
+    with index.session():
+        first = index.add("first", fields={"title": "rust", "body": "rust"})
+        index.add("second", fields={"title": "go", "body": "go"})
+
+    with subtests.test(msg="test_search_field_weights [weight]"):
+        title = index.search("title:rust")[0]
+        body = index.search("body:rust")[0]
+        assert title.document.id == body.document.id == first
+        assert title.score == pytest.approx(body.score * 3)
+        assert [r.document.id for r in index.search("title:go OR body:rust")][0] != first
+
+    with subtests.test(msg="test_search_field_weights [field length]"):
+        # short tag wins although the whole document is longer
+        with index.session():
+            short = index.add(" ".join(f"word{i}" for i in range(20)), fields={"tags": "rust"})
+            long = index.add("short", fields={"tags": "rust async tokio runtime"})
+        assert [r.document.id for r in index.search("tags:rust")] == [short, long]
+
+    with subtests.test(msg="test_search_field_weights [persisted]"):
+        expected = [(r.document.id, r.score) for r in index.search("tags:rust OR title:rust")]
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("field_weights", MINISEARCH_DIR, "tests/assets/field_weights_test_conf.toml")
+        assert [(r.document.id, r.score) for r in index.search("tags:rust OR title:rust")] == expected
+
+    with pytest.raises(ValueError, match="field 'title'"):
+        MiniSearch().add("invalid_field_weights", MINISEARCH_DIR, "tests/assets/invalid_field_weights_test_conf.toml")
+
+
+def test_search_multi_valued_fields(subtests):
+    search = MiniSearch()
+    _, index = search.add("multi_valued", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add("first", fields={"tags": ["new york", "city guide"], "title": "Guide"})
+        second = index.add("second", fields={"tags": "new york city guide"})
+
+    for query, expected in [
+        ('tags:"new york"', [first, second]),
+        ("tags:guide", [first, second]),
+        ('tags:"york city"', [second]),
+        ('tags:"york city"~99', [second]),
+        ('tags:"new guide"~5', [second]),
+    ]:
+        with subtests.test(msg=f"test_search_multi_valued_fields [{query}]"):
+            assert sorted(r.document.id for r in index.search(query, top_k=0)) == sorted(expected)
+
+    with subtests.test(msg="test_search_multi_valued_fields [invalid value]"):
+        with pytest.raises(TypeError):
+            index.add("third", fields={"tags": [1, 2]})
+
+
+def test_add_idempotent():
+    search = MiniSearch()
+    _, index = search.add("idempotent", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add_idempotent("quick brown fox")
+        assert index.add_idempotent("quick brown fox") == first
+        second = index.add_idempotent("lazy dog")
+
+    assert first != second
+    assert len(index.search("fox", top_k=0)) == 1
+
+    # digests are persisted, so restarted pipeline doesn't create duplicates
+    search.delete("idempotent")
+    del index
+    _, index = search.add("idempotent", MINISEARCH_DIR)
+    assert index.add_idempotent("quick brown fox") == first
+
+    # deleted document is added again
+    with index.session():
+        index.delete(first)
+        third = index.add_idempotent("quick brown fox")
+
+    assert third != first
+    assert [r.document.id for r in index.search("fox", top_k=0)] == [third]
+
+
+
+def test_ids_after_restart():
+    search = MiniSearch()
+    for conf in [None, "tests/assets/disk_test_conf.toml"]:
+        path = os.path.join(MINISEARCH_DIR, "disk" if conf else "memory")
+        ids = []
+        for _ in range(10):
+            # restarted generator may start below ids generated in the same millisecond,
+            # empty document has no postings, but its id has to be kept
+            _, index = search.add("restart", path, conf)
+            with index.session():
+                ids.append(index.add(""))
+                ids.append(index.add("quick brown fox"))
+            search.delete("restart")
+
+        assert ids == sorted(ids)
+        assert len(set(ids)) == len(ids)
+
+
+def test_contains_documents():
+    search = MiniSearch()
+    unknown = "01ARZ3NDEKTSV4RRFFQ69G5FAV"
+    for conf in [None, "tests/assets/disk_test_conf.toml"]:
+        path = os.path.join(MINISEARCH_DIR, "disk" if conf else "memory")
+        _, index = search.add("contains", path, conf)
+
+        # documents not yet flushed are found, ids never written aren't
+        unflushed = index.add("quick brown fox", attributes={"n": 1})
+        assert index.attributes(unflushed) == {"n": 1}
+        assert index.get(unflushed).id == unflushed
+        for _id in [unknown, unflushed[:-1] + ("0" if unflushed[-1] != "0" else "1")]:
+            with pytest.raises(KeyError):
+                index.attributes(_id)
+            with pytest.raises(KeyError):
+                index.get(_id)
+
+        index.flush()
+        assert index.attributes(unflushed) == {"n": 1}
+        index.delete(unflushed)
+        with pytest.raises(KeyError):
+            index.attributes(unflushed)
+        search.delete("contains")
+
+    # bloom filter of segment is written only when flushed documents changed it
+    _, index = search.add("contains", MINISEARCH_DIR, "tests/assets/disk_test_conf.toml")
+    index.add("quick brown fox")
+    index.flush()
+    blooms = glob.glob(f"{MINISEARCH_DIR}/**/bloom", recursive=True)
+    assert blooms
+    before = {path: os.stat(path).st_mtime_ns for path in blooms}
+    time.sleep(0.01)
+    index.update_attributes(index.search("fox")[0].document.id, {"n": 2})
+    index.flush()
+    assert {path: os.stat(path).st_mtime_ns for path in blooms} == before
+
+
+def test_search_recency_boost():
+    search = MiniSearch()
+    _, index = search.add("recency", MINISEARCH_DIR)
+
+    with index.session():
+        old = index.add("quick fox quick fox")
+        time.sleep(1.1)
+        new = index.add("quick fox")
+
+    assert index.search("fox")[0].document.id == old
+    assert index.search("fox", recency_boost=0.5)[0].document.id == new
+    options = SearchOptions(recency_boost=0.5)
+    assert index.search("fox", options=options)[0].document.id == new
+
+    with pytest.raises(ValueError):
+        index.search("fox", recency_boost=0)
+
+
+def test_search_score_script(subtests):
+    with subtests.test(msg="test_search_score_script [new data]"):
+        search = MiniSearch()
+        _, index = search.add("script", MINISEARCH_DIR)
+
+        with index.session():
+            popular = index.add("quick fox", {"views": 1000.0})
+            index.add("quick fox quick fox", {"views": 1.0})
+
+        results = index.search("fox", score_script="score * log(1 + attr.views)")
+        assert results[0].document.id == popular
+        assert index.attributes(popular) == {"views": 1000.0}
+
+        with pytest.raises(ValueError):
+            index.search("fox", score_script="score * unknown(attr.views)")
+
+    with subtests.test(msg="test_search_score_script [persisted data]"):
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("script", MINISEARCH_DIR)
+
+        results = index.search("fox", score_script="score * log(1 + attr.views)")
+        assert results[0].document.id == popular
+
+    with subtests.test(msg="test_search_score_script [non finite]"):
+        # missing attribute gives -inf, nan and inf, such documents are dropped
+        # instead of being ranked first
+        assert index.search("fox", score_script="log(attr.missing)") == []
+        results = index.search("fox", score_script="sqrt(attr.views - 5)")
+        assert [r.document.id for r in results] == [popular]
+        assert index.search("fox", score_script="score / attr.missing") == []
+        # zero score of log(1) is dropped as well
+        results = index.search("fox", score_script="score * log(attr.views)")
+        assert [r.document.id for r in results] == [popular]
+
+
+def test_update_attributes():
+    search = MiniSearch()
+    _, index = search.add("update_attributes", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add("quick fox", {"views": 1.0, "likes": 3.0})
+        second = index.add("quick fox quick fox")
+
+    postings = list(index.postings("fox"))
+    script = "score * log(1 + attr.views)"
+    assert index.search("fox", score_script=script)[0].document.id == first
+
+    # attributes are merged, document without attributes gets them
+    with index.session():
+        index.update_attributes(first, {"views": 2.0})
+        index.update_attributes(second, {"views": 1000.0})
+    assert index.attributes(first) == {"views": 2.0, "likes": 3.0}
+    assert index.attributes(second) == {"views": 1000.0}
+    assert index.search("fox", score_script=script)[0].document.id == second
+    assert list(index.postings("fox")) == postings
+
+    with pytest.raises(KeyError):
+        index.update_attributes("01ARZ3NDEKTSV4RRFFQ69G5FAV", {"views": 1.0})
+
+    # updates are persisted
+    del search, index
+    _, index = MiniSearch().add("update_attributes", MINISEARCH_DIR)
+    assert index.attributes(first) == {"views": 2.0, "likes": 3.0}
+    index.merge()
+    assert index.attributes(second) == {"views": 1000.0}
+
+
+def test_search_boolean_operators():
+    search = MiniSearch()
+    _, index = search.add("boolean", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("quick brown fox")
+        index.add("lazy brown dog")
+        index.add("quick dog")
+
+    def contents(query):
+        return sorted(r.document.content for r in index.search(query))
+
+    assert contents("fox OR dog") == ["lazy brown dog", "quick brown fox", "quick dog"]
+    assert contents("quick AND dog") == ["quick dog"]
+    assert contents("brown AND NOT fox") == ["lazy brown dog"]
+    assert contents("(fox OR lazy) AND brown") == ["lazy brown dog", "quick brown fox"]
+    assert contents('"quick brown" OR lazy brown') == ["lazy brown dog", "quick brown fox"]
+    assert contents("quick AND NOT (fox OR brown)") == ["quick dog"]
+    assert contents("dog AND cat") == []
+
+    # operators are case sensitive and bind as NOT > AND > OR
+    assert contents("quick or fox") == []
+    assert contents("fox OR quick AND dog") == ["quick brown fox", "quick dog"]
+
+    # disjunction scores sum scores of matched clauses
+    scores = {r.document.content: r.score for r in index.search("quick OR dog")}
+    assert scores["quick dog"] > scores["quick brown fox"]
+
+    for query in ["NOT fox", "fox OR NOT dog", "(fox", "fox AND"]:
+        with pytest.raises(ValueError):
+            index.search(query)
+
+
+def test_pending_deletes_survive_restart():
+    search = MiniSearch()
+    _, index = search.add("deletes", MINISEARCH_DIR)
+
+    with index.session():
+        deleted = index.add("quick fox")
+        index.add("quick dog")
+
+    # process stops before deleted document postings are removed on flush
+    index.delete(deleted)
+    search.delete("deletes")
+
+    _, index = search.add("deletes", MINISEARCH_DIR)
+    assert [r.document.content for r in index.search("quick")] == ["quick dog"]
+
+    index.flush()
+    assert index.display_term("fox") is None
+    assert index.sweep() == {"postings": 0, "tokens": 0}
+
+
+def test_search_term_modifiers():
+    search = MiniSearch()
+    _, index = search.add("modifiers", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("quick brown fox")
+        index.add("quick red fox")
+        index.add("lazy brown dog")
+        index.add("quick dog")
+
+    def contents(query):
+        return sorted(r.document.content for r in index.search(query))
+
+    assert contents("+quick -dog") == ["quick brown fox", "quick red fox"]
+    assert contents("fox dog -red") == ["lazy brown dog", "quick brown fox", "quick dog"]
+    assert contents('+"quick fox"~1 -red') == ["quick brown fox"]
+    assert contents("+quick +dog") == ["quick dog"]
+    assert contents('"quick brown" fox') == ["quick brown fox"]
+
+    # optional terms only contribute to the score
+    results = [r.document.content for r in index.search("+quick brown")]
+    assert results[0] == "quick brown fox"
+    assert sorted(results) == ["quick brown fox", "quick dog", "quick red fox"]
+
+    # without modifiers bare terms are still matched as a phrase
+    assert contents("quick fox") == []
+
+    with pytest.raises(ValueError):
+        index.search("-dog")
+
+
+def test_search_negative_phrase():
+    search = MiniSearch()
+    _, index = search.add("negative_phrase", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("release notes for the new york office")
+        index.add("release notes about york and new jersey")
+        index.add("release notes for new offices in york")
+
+    def contents(query):
+        return sorted(r.document.content for r in index.search(query))
+
+    # only documents containing the exact phrase are removed
+    assert contents('release -"new york"') == [
+        "release notes about york and new jersey",
+        "release notes for new offices in york",
+    ]
+    assert contents('release AND NOT "new york"') == contents('release -"new york"')
+    assert contents('release -"new york"~1') == ["release notes about york and new jersey"]
+
+
+def test_parse_query():
+    search = MiniSearch()
+    _, index = search.add("parse", MINISEARCH_DIR)
+
+    def term(text, fuzz=0, auto_fuzz=False, literal=False):
+        return {"text": text, "fuzz": fuzz, "auto_fuzz": auto_fuzz, "literal": literal}
+
+    assert index.parse('"Quick fox"~2 OR title:rust~ AND NOT `c++`', fuzziness=1) == {
+        "type": "or",
+        "queries": [
+            {"type": "clause", "terms": [term("quick", 1), term("fox", 1)], "slop": 2, "field": None},
+            {
+                "type": "and",
+                "queries": [
+                    {"type": "clause", "terms": [term("rust", 1, True)], "slop": 0, "field": "title"},
+                    {
+                        "type": "not",
+                        "query": {"type": "clause", "terms": [term("c++", 1, literal=True)], "slop": 0, "field": None},
+                    },
+                ],
+            },
+        ],
+    }
+    assert index.parse("+rust -java")["queries"][1]["type"] == "not"
+    assert index.parse("+rust go")["queries"][1]["type"] == "optional"
+    assert len(index.parse("rust go")["terms"]) == 2
+    assert index.parse("*") == {"type": "all"}
+
+    with pytest.raises(ValueError):
+        index.parse('"unclosed')
+
+
+def test_search_query_builder():
+    search = MiniSearch()
+    _, index = search.add("builder", MINISEARCH_DIR)
+
+    with index.session():
+        index.add('say "hello" world~', fields={"title": "greeting"})
+        index.add("hello big world")
+        index.add("quick brown fox")
+
+    def contents(query):
+        return sorted(r.document.content for r in index.search(query))
+
+    Q = QueryBuilder
+    assert contents(Q.phrase('"Hello" world~')) == ['say "hello" world~']
+    assert contents(Q.phrase("hello world", slop=1)) == [
+        "hello big world",
+        'say "hello" world~',
+    ]
+    assert contents(Q.term("quikc", fuzziness=2)) == ["quick brown fox"]
+    assert contents(Q.term("greeting", field="title")) == ['say "hello" world~']
+    assert contents(Q.any_of(Q.term("fox"), Q.term("big"))) == [
+        "hello big world",
+        "quick brown fox",
+    ]
+    assert contents(Q.all_of(Q.term("hello"), Q.term("big"))) == ["hello big world"]
+    assert contents(Q.term("world").exclude(Q.term("say"))) == ["hello big world"]
+
+    # builder produces the same query tree as the parser
+    built = index.search(Q.term("hello").optional(Q.term("big")))
+    parsed = index.search("+hello big")
+    assert [(r.document.id, r.score) for r in built] == [
+        (r.document.id, r.score) for r in parsed
+    ]
+
+    for build in [lambda: Q.term("~"), lambda: Q.term("fox", fuzziness=3), Q.any_of]:
+        with pytest.raises(ValueError):
+            build()
+
+
+def test_span_near():
+    search = MiniSearch()
+    _, index = search.add("span_near", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("quick brown fox")
+        index.add("fox jumps quick")
+        index.add("red car parked")
+        index.add("red old automobile")
+
+    def contents(query):
+        return sorted(r.document.content for r in index.search(query, top_k=0))
+
+    Q = QueryBuilder
+    assert contents(Q.span_near(["quick", "fox"], slop=1)) == ["quick brown fox"]
+    assert contents(Q.span_near(["quick", "fox"], slop=1, in_order=False)) == [
+        "fox jumps quick",
+        "quick brown fox",
+    ]
+    assert contents(Q.span_near(["fox", "quick"], slop=0, in_order=False)) == []
+    # every term can be a group of alternatives matched at its position
+    assert contents(Q.span_near(["red", ["car", "automobile"]], slop=1)) == [
+        "red car parked",
+        "red old automobile",
+    ]
+    assert contents(Q.span_near([["automobile", "car"], "red"], slop=0, in_order=False)) == [
+        "red car parked"
+    ]
+    assert contents(Q.any_of(Q.span_near(["jumps", "quick"]), Q.term("parked"))) == [
+        "fox jumps quick",
+        "red car parked",
+    ]
+
+    for terms in [[], ["quick fox"], [[]]]:
+        with pytest.raises(ValueError):
+            Q.span_near(terms)
+
+
+def test_search_escaped_queries(subtests):
+    search = MiniSearch()
+    _, index = search.add("escaping", MINISEARCH_DIR)
+
+    with index.session():
+        index.add('say "hello" world~')
+        index.add("a~b c")
+        index.add("see title:foo")
+        index.add("e-mail me")
+
+    for query, expected in [
+        (r"\"hello\"", ['say "hello" world~']),
+        (r'"\"hello\" world\~"', ['say "hello" world~']),
+        (r"a\~b", ["a~b c"]),
+        ("`a~b`", ["a~b c"]),
+        ('"`a~b` c"', ["a~b c"]),
+        ("`a~b c`~1", ["a~b c"]),
+        (r"title\:foo", ["see title:foo"]),
+        (r"e\-mail", ["e-mail me"]),
+        (r"\(hello\)", ['say "hello" world~']),
+    ]:
+        with subtests.test(msg=f"query [{query}]"):
+            assert [r.document.content for r in index.search(query)] == expected
+
+    for query in ["hello\\", "``", "`hello"]:
+        with pytest.raises(ValueError):
+            index.search(query)
+
+
+
+def test_search_restrict_ids():
+    search = MiniSearch()
+    _, index = search.add("restrict", MINISEARCH_DIR)
+
+    with index.session():
+        ids = [index.add(f"quick fox number {i}") for i in range(5)]
+        other = index.add("lazy dog")
+
+    def found(**kwargs):
+        return sorted(r.document.id for r in index.search("quick fox", **kwargs))
+
+    assert found(restrict_ids=[ids[3], ids[1], other, ids[1]]) == [ids[1], ids[3]]
+    assert found(exclude_ids=[ids[0], ids[4]]) == ids[1:4]
+    assert found(restrict_ids=ids[:3], exclude_ids=[ids[1]]) == [ids[0], ids[2]]
+    assert found(restrict_ids=[]) == []
+    assert found(options=SearchOptions(exclude_ids=ids)) == []
+
+    # explicit argument overrides options
+    options = SearchOptions(restrict_ids=[ids[0]])
+    assert found(options=options, restrict_ids=[ids[2]]) == [ids[2]]
+
+    with pytest.raises(UlidDecodeError):
+        index.search("quick", restrict_ids=["not an id"])
+
+
+def test_search_visibility_hook():
+    search = MiniSearch()
+    _, index = search.add("visibility", MINISEARCH_DIR)
+
+    with index.session():
+        ids = [index.add(f"quick fox number {i}") for i in range(6)]
+
+    allowed = set(ids[::2])
+    calls = []
+
+    def visible(doc_id):
+        calls.append(doc_id)
+        return doc_id in allowed
+
+    results = index.search("quick OR fox", visibility=visible)
+    assert sorted(r.document.id for r in results) == sorted(allowed)
+    # hook is consulted once per document even if it's matched by many clauses
+    assert sorted(calls) == ids
+
+    # excluded documents never reach the hook
+    calls.clear()
+    index.search("quick", exclude_ids=ids[:3], visibility=visible)
+    assert sorted(calls) == ids[3:]
+
+    def failing(doc_id):
+        raise RuntimeError("permission service unavailable")
+
+    with pytest.raises(RuntimeError):
+        index.search("quick", visibility=failing)
+
+
+def test_search_match_all():
+    search = MiniSearch()
+    _, index = search.add("match_all", MINISEARCH_DIR)
+
+    with index.session():
+        ids = []
+        for i in range(5):
+            ids.append(index.add(f"document number {i}" + (" quick fox" * (i % 2))))
+            time.sleep(0.002)
+
+    with index.session():
+        index.delete(ids[2])
+    live = [ids[0], ids[1], ids[3], ids[4]]
+
+    def found(query, **kwargs):
+        return [r.document.id for r in index.search(query, **kwargs)]
+
+    assert found("*") == live
+    assert found("  ") == live
+    assert found("*", top_k=2) == live[:2]
+    assert found("*", recency_boost=3600) == live[::-1]
+    assert found("*", exclude_ids=[ids[0]], restrict_ids=ids[:4]) == [ids[1], ids[3]]
+
+    Q = QueryBuilder
+    assert found(Q.all().exclude(Q.term("fox"))) == [ids[0], ids[4]]
+
+    with pytest.raises(ValueError):
+        index.register_query("everything", "*")
+
+
+def test_search_flame_profile():
+    search = MiniSearch()
+    _, index = search.add("profile", MINISEARCH_DIR)
+
+    with index.session():
+        for i in range(20):
+            index.add(f"quick brown fox number {i}")
+
+    results, profile = index.search("quikc~2 brown", profile="flame")
+    assert len(results) == 20
+    assert [r.document.id for r in results] == [
+        r.document.id for r in index.search("quikc~2 brown")
+    ]
+
+    def names(node):
+        return {node["name"]: [names(c) for c in node["children"]]}
+
+    assert names(profile) == {
+        "search": [
+            {"parse": []},
+            {"expansion": []},
+            {"matching": [{"intersection": []}, {"mis": []}, {"scoring": []}]},
+            {"materialization": []},
+        ]
+    }
+    assert profile["calls"] == 1
+    matching = profile["children"][2]
+    assert all(c["calls"] > 0 for c in matching["children"])
+    assert sum(c["seconds"] for c in profile["children"]) <= profile["seconds"]
+
+    with pytest.raises(ValueError):
+        index.search("fox", profile="counters")
+
+
+def test_search_min_should_match():
+    search = MiniSearch()
+    _, index = search.add("min_should_match", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("quick brown fox")
+        index.add("quick red dog")
+        index.add("lazy brown dog")
+        index.add("quick cat")
+        index.add("slow cat")
+
+    def contents(query, **kwargs):
+        return sorted(r.document.content for r in index.search(query, **kwargs))
+
+    assert contents("quick brown dog") == []
+    assert contents("quick brown dog", min_should_match=2) == [
+        "lazy brown dog",
+        "quick brown fox",
+        "quick red dog",
+    ]
+    assert contents("quick brown dog", min_should_match=1) == [
+        "lazy brown dog",
+        "quick brown fox",
+        "quick cat",
+        "quick red dog",
+    ]
+    # clauses with fewer terms are matched as usual
+    assert contents("quick brown dog", min_should_match=3) == []
+    assert contents("quick cat", min_should_match=2) == ["quick cat"]
+    assert contents("(quick brown dog) AND NOT lazy", min_should_match=2) == [
+        "quick brown fox",
+        "quick red dog",
+    ]
+
+    # documents matching more terms score higher
+    results = index.search("quick brown fox", min_should_match=1)
+    assert results[0].document.content == "quick brown fox"
+
+    with pytest.raises(ValueError):
+        index.search("quick", min_should_match=0)
+
+
+
+def test_search_coordination():
+    search = MiniSearch()
+    _, plain = search.add("coordination", MINISEARCH_DIR)
+
+    with plain.session():
+        one = plain.add("rust rust rust tutorial")
+        two = plain.add("rust python tutorial")
+
+    def scores(index, query, **kwargs):
+        return {r.document.id: r.score for r in index.search(query, **kwargs)}
+
+    queries = [
+        ("rust OR python OR golang", {}),
+        ("rust python golang", {"min_should_match": 1}),
+    ]
+    expected = {query: scores(plain, query, **kwargs) for query, kwargs in queries}
+    rust = scores(plain, "rust")
+    # directory can be opened with another config only after its handles are dropped
+    del search, plain
+    _, index = MiniSearch().add(
+        "coordination", MINISEARCH_DIR, "tests/assets/coordination_test_conf.toml"
+    )
+
+    # score is multiplied by fraction of disjunction terms matched by document
+    for query, kwargs in queries:
+        coordinated = scores(index, query, **kwargs)
+        assert coordinated[one] == pytest.approx(expected[query][one] / 3)
+        assert coordinated[two] == pytest.approx(expected[query][two] * 2 / 3)
+
+    # terms missing in index count too
+    assert scores(index, "rust OR golang")[one] == pytest.approx(rust[one] / 2)
+    # the same holds for top k results
+    assert list(scores(index, "rust OR python OR golang", top_k=1)) == [two]
+
+    with pytest.raises(ValueError):
+        MiniSearch().add(
+            "invalid_coordination", MINISEARCH_DIR, "tests/assets/invalid_coordination_test_conf.toml"
+        )
+
+def test_search_proximity_weight():
+    search = MiniSearch()
+    _, plain = search.add("proximity", MINISEARCH_DIR)
+
+    with plain.session():
+        adjacent = plain.add("rust tutorial for beginners")
+        gap = plain.add("rust async tutorial for beginners")
+
+    def scores(index, query):
+        return {r.document.id: r.score for r in index.search(query, top_k=0)}
+
+    expected = scores(plain, '"rust tutorial"~1')
+    del search, plain
+    _, index = MiniSearch().add("proximity", MINISEARCH_DIR, "tests/assets/proximity_test_conf.toml")
+
+    # score is divided by (slop + 1) raised to proximity weight instead of slop + 1
+    weighted = scores(index, '"rust tutorial"~1')
+    assert weighted[adjacent] == pytest.approx(expected[adjacent])
+    assert weighted[gap] == pytest.approx(expected[gap] * 2 / 2**0.5)
+
+    with pytest.raises(ValueError):
+        MiniSearch().add("invalid_proximity", MINISEARCH_DIR, "tests/assets/invalid_proximity_test_conf.toml")
+
+
+def test_search_position_boost():
+    search = MiniSearch()
+    _, index = search.add("position_boost", MINISEARCH_DIR)
+
+    with index.session():
+        late = index.add("alpha beta gamma delta rust")
+        early = index.add("rust alpha beta gamma delta")
+
+    results = index.search("rust")
+    assert results[0].score == pytest.approx(results[1].score)
+
+    results = index.search("rust", position_boost=1.0)
+    assert [r.document.id for r in results] == [early, late]
+    # match at the first position doubles the score
+    assert results[0].score == pytest.approx(2 * index.search("rust")[0].score)
+
+    # boost is given by the earliest position of the whole match
+    results = index.search(
+        '"beta gamma"', options=SearchOptions(top_k=1, position_boost=1.0)
+    )
+    assert [r.document.id for r in results] == [late]
+
+    with pytest.raises(ValueError):
+        index.search("rust", position_boost=0.0)
+
+
+def test_document_boost():
+    search = MiniSearch()
+    _, index = search.add("document_boost", MINISEARCH_DIR)
+
+    with index.session():
+        plain = index.add("rust search engine")
+        promoted = index.add("rust search engine", boost=2.0)
+        demoted = index.add("rust search engine", boost=0.5)
+
+    results = index.search("rust")
+    assert [r.document.id for r in results] == [promoted, plain, demoted]
+    assert results[0].score == pytest.approx(2 * results[1].score)
+    assert results[2].score == pytest.approx(0.5 * results[1].score)
+    assert index.get(promoted).boost == pytest.approx(2.0)
+
+    # boost is persisted with document
+    index.flush()
+    del search, index
+    _, reloaded = MiniSearch().add("document_boost", MINISEARCH_DIR)
+    assert reloaded.get(demoted).boost == pytest.approx(0.5)
+    assert [r.document.id for r in reloaded.search("rust")] == [promoted, plain, demoted]
+
+    for boost in (0.0, -1.0, float("nan")):
+        with pytest.raises(ValueError):
+            reloaded.add("rust", boost=boost)
+
+
+def test_search_diversify():
+    search = MiniSearch()
+    _, index = search.add("diversify", MINISEARCH_DIR)
+
+    with index.session():
+        duplicates = [
+            index.add("rust async runtime tokio tutorial"),
+            index.add("rust async runtime tokio guide"),
+            index.add("rust async runtime tokio intro"),
+        ]
+        different = index.add("rust ownership borrow checker rules")
+
+    def ids(**kwargs):
+        return [r.document.id for r in index.search("rust", top_k=2, **kwargs)]
+
+    assert ids() == duplicates[:2]
+    assert ids(diversify=1.0) == duplicates[:2]
+    assert ids(diversify=0.5) == [duplicates[0], different]
+    assert ids(options=SearchOptions(diversify=0.5)) == [duplicates[0], different]
+
+    with pytest.raises(ValueError):
+        index.search("rust", diversify=1.5)
+
+
+def test_suggest_queries():
+    search = MiniSearch()
+    _, index = search.add(
+        "query_log", MINISEARCH_DIR, "tests/assets/query_log_test_conf.toml"
+    )
+
+    with index.session():
+        index.add("rust async runtime")
+        index.add("rust ownership")
+        index.add("python typing")
+
+    for query in ["Rust  async", "rust ownership", "rust async", "python", "missing"]:
+        index.search(query)
+
+    # queries without results are not recorded
+    assert index.suggest_queries("mis") == []
+    assert index.suggest_queries("rust") == ["rust async", "rust ownership"]
+    assert index.suggest_queries("rust", limit=1) == ["rust async"]
+    assert index.suggest_queries("") == ["rust async", "python", "rust ownership"]
+
+    # the least searched query is evicted above capacity
+    for _ in range(3):
+        index.search("typing")
+    assert index.suggest_queries("") == ["typing", "rust async", "python"]
+
+    # query log is persisted on flush
+    index.flush()
+    del search, index
+    _, reloaded = MiniSearch().add(
+        "query_log", MINISEARCH_DIR, "tests/assets/query_log_test_conf.toml"
+    )
+    assert reloaded.suggest_queries("ty") == ["typing"]
+
+    del reloaded
+    _, index = MiniSearch().add("no_query_log", MINISEARCH_DIR)
+    with pytest.raises(ValueError):
+        index.suggest_queries("rust")
+
+
+def test_health():
+    search = MiniSearch()
+    _, index = search.add("health", MINISEARCH_DIR)
+
+    checks = {"buffers", "segments", "logs", "tokens", "healthy"}
+    assert index.health() == {check: True for check in checks}
+
+    with index.session():
+        index.add("quick brown fox")
+    index.flush()
+    assert index.health()["healthy"]
+
+    # the last log can't be read from truncated index file
+    log = os.path.join(MINISEARCH_DIR, "index", "index")
+    with open(log, "r+b") as f:
+        f.truncate(os.path.getsize(log) - 1)
+
+    health = index.health()
+    assert not health["logs"] and not health["healthy"]
+    assert health["segments"] and health["tokens"]
+
+
+def test_decode_error_policy():
+    search = MiniSearch()
+    _, index = search.add("decode_error", MINISEARCH_DIR)
+    with index.session():
+        fox = index.add("Quick brown fox")
+        index.add("lazy dog", fields={"title": "kennel"})
+
+    tokens = os.path.join(MINISEARCH_DIR, "index", "tokens")
+
+    def truncate():
+        with open(tokens, "r+b") as f:
+            f.truncate(os.path.getsize(tokens) // 2)
+
+    class Records(logging.Handler):
+        def __init__(self):
+            super().__init__()
+            self.records = []
+
+        def emit(self, record):
+            self.records.append(record)
+
+    def load(conf=None):
+        records = Records()
+        logger = logging.getLogger("minisearch")
+        logger.addHandler(records)
+        try:
+            _, index = MiniSearch().add("decode_error", MINISEARCH_DIR, conf)
+        finally:
+            logger.removeHandler(records)
+        return index, [r.getMessage() for r in records.records if r.levelno == logging.WARNING]
+
+    # by default index with unreadable tokens can't be opened
+    del search, index
+    truncate()
+    with pytest.raises(BincodeDecodeError):
+        MiniSearch().add("decode_error", MINISEARCH_DIR)
+
+    # tokens are recovered from stored documents, field tokens are lost together with
+    # document tokens at the same positions ("lazy" and "title kennel" at position 0)
+    index, warnings = load("tests/assets/decode_error_rebuild_test_conf.toml")
+    assert any("tokens are rebuilt from index" in w for w in warnings)
+    assert any("2 tokens couldn't be recovered" in w for w in warnings)
+    assert [r.document.id for r in index.search("fox")] == [fox]
+    assert index.get(fox).content == "Quick brown fox"
+    assert len(index.search("dog")) == 1
+    assert index.search("lazy") == [] and index.search("title:kennel") == []
+    with index.session():
+        added = index.add("brown bear")
+    assert len(index.search("brown")) == 2 and index.get(added).content == "brown bear"
+
+    # recovered tokens were saved, so index opens in strict mode again
+    del index
+    _, index = MiniSearch().add("decode_error", MINISEARCH_DIR)
+    assert [r.document.id for r in index.search("fox")] == [fox]
+
+    del index
+    truncate()
+    index, warnings = load("tests/assets/decode_error_reset_test_conf.toml")
+    assert any("tokens are reset" in w for w in warnings)
+    assert index.search("fox") == []
+
+
+def test_postings():
+    search = MiniSearch()
+    _, index = search.add("postings", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add("Running fast and running far")
+        second = index.add("walking", fields={"title": "running man"})
+        third = index.add("run")
+        deleted = index.add("runs away")
+    index.delete(deleted)
+
+    # term is stemmed like query terms and deleted documents are skipped
+    assert list(index.postings("RUNNING")) == [(first, 2, [0, 2]), (third, 1, [0])]
+    assert list(index.postings("running", field="title")) == [(second, 1, [0])]
+    assert list(index.postings("missing")) == []
+    assert list(index.postings("and")) == []
+
+    # iterator continues after the last returned document when index changes
+    postings = index.postings("run")
+    assert next(postings) == (first, 2, [0, 2])
+    with index.session():
+        added = index.add("run run")
+    assert [doc_id for doc_id, _, _ in postings] == [third, added]
+
+
+def test_custom_tokenizer():
+    search = MiniSearch()
+    _, index = search.add("custom_tokenizer", MINISEARCH_DIR)
+
+    # stop words and stemming are left to the callback, "the" and "running" are kept
+    def tokenize(text):
+        return [(word.lower(), i) for i, word in enumerate(text.split())]
+
+    index.set_tokenizer(tokenize)
+    with index.session():
+        first = index.add("The Running man")
+        second = index.add("run the man")
+
+    def found(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    assert found("running") == {first}
+    assert found("run") == {second}
+    assert found("the") == {first, second}
+    assert found('"the man"') == {second}
+    assert list(index.postings("RUNNING")) == [(first, 1, [1])]
+
+    def failing(text):
+        raise RuntimeError("tokenizer failed")
+
+    index.set_tokenizer(failing)
+    with pytest.raises(RuntimeError):
+        index.search("man")
+    with pytest.raises(RuntimeError):
+        with index.session():
+            index.add("man")
+
+    # native tokenizer stems query terms again
+    index.set_tokenizer(None)
+    assert found("runs") == {second}
+
+
+def test_memory_usage():
+    search = MiniSearch()
+    _, index = search.add("memory", MINISEARCH_DIR)
+
+    components = ["index", "impacts", "documents", "attributes", "trie", "tokens", "buffers", "caches", "other"]
+    usage = index.memory_usage()
+    assert set(usage) == set(components + ["total"])
+    assert usage["total"] == sum(usage[c] for c in components)
+
+    with index.session():
+        for i in range(100):
+            index.add(f"document number {i} about word{i}", attributes={"views": i})
+        before = index.memory_usage()
+    assert before["buffers"] > 0
+
+    after = index.memory_usage()
+    for component in ["index", "documents", "attributes", "trie", "tokens"]:
+        assert after[component] > usage[component]
+
+
+def test_export_stats():
+    search = MiniSearch()
+    _, index = search.add("export", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("Rust rust async")
+        index.add("async python", fields={"title": "guide"})
+        deleted = index.add("deleted rust")
+    index.delete(deleted)
+
+    path = os.path.join(MINISEARCH_DIR, "stats")
+    index.export_stats(path)
+
+    def rows(name):
+        with open(os.path.join(path, name)) as f:
+            return list(csv.DictReader(f))
+
+    terms = {r["term"]: r for r in rows("terms.csv")}
+    assert set(terms) == {"rust", "async", "python", "title guid"}
+    assert (terms["rust"]["display"], terms["rust"]["df"], terms["rust"]["tf"]) == (
+        "Rust",
+        "1",
+        "2",
+    )
+    assert (terms["async"]["df"], terms["async"]["tf"]) == ("2", "2")
+
+    # documents pending deletion are skipped, field terms count into length
+    lengths = [(r["length"], r["documents"]) for r in rows("doc_lengths.csv")]
+    assert lengths == [("3", "2")]
+    growth = [(r["documents"], r["vocabulary"]) for r in rows("vocabulary_growth.csv")]
+    assert growth == [("1", "2"), ("2", "4")]
+
+
+def test_search_options():
+    search = MiniSearch()
+    _, index = search.add("options", MINISEARCH_DIR)
+
+    with index.session():
+        for i in range(10):
+            index.add(f"quick brown fox {i}")
+
+    assert len(index.search("fox", options=SearchOptions(top_k=3))) == 3
+    assert len(index.search("fox", top_k=5, options=SearchOptions(top_k=3))) == 5
+    assert index.search("qick") == []
+    assert len(index.search("qick", options=SearchOptions(fuzziness=1))) == 10
+    assert index.search("qick~0", options=SearchOptions(fuzziness=1)) == []
+    assert len(index.search("fox", options=SearchOptions(timeout=60))) == 10
+
+    with pytest.raises(ValueError):
+        SearchOptions(fuzziness=3)
+
+    options = SearchOptions()
+    options.timeout = 0
+    with pytest.raises(ValueError):
+        index.search("fox", options=options)
+
+    # non finite values would panic computing deadline or turn scores into nan
+    for value in [float("nan"), float("inf")]:
+        with pytest.raises(ValueError):
+            SearchOptions(timeout=value)
+        with pytest.raises(ValueError):
+            index.search("fox", recency_boost=value)
+        with pytest.raises(ValueError):
+            index.search("fox", position_boost=value)
+
+    # timeout too long to be represented means no deadline
+    assert len(index.search("fox", options=SearchOptions(timeout=1e300))) == 10
+
+
+def test_search_options_fields():
+    search = MiniSearch()
+    _, index = search.add("options_fields", MINISEARCH_DIR)
+
+    with index.session():
+        titled = index.add("notes", fields={"title": "rust guide", "tags": ["systems"]})
+        tagged = index.add("rust notes", fields={"tags": ["rust"]})
+        content = index.add("rust rust rust")
+        index.add("python guide")
+
+    def found(query, options=None):
+        return {r.document.id for r in index.search(query, top_k=0, options=options)}
+
+    # terms without field match document content only by default
+    assert found("rust") == {tagged, content}
+    options = SearchOptions(fields=["title"])
+    assert found("rust", options) == {titled, tagged, content}
+    assert found("rust guide", options) == {titled}
+    assert found("systems", SearchOptions(fields=["title", "tags"])) == {titled}
+    assert found("notes -rust", options) == set()
+    # field scoped terms aren't affected
+    assert found("tags:rust", options) == {tagged}
+
+    with pytest.raises(ValueError):
+        SearchOptions(fields=["1title"])
+    options = SearchOptions()
+    options.fields = ["bad field"]
+    with pytest.raises(ValueError):
+        index.search("rust", options=options)
+
+
+def test_registered_queries_callback():
+    search = MiniSearch()
+    _, index = search.add("alerts", MINISEARCH_DIR)
+
+    matches = []
+    index.register_query("fox", "fox")
+    index.register_query("quick fox", '"quick fox"~1')
+    index.register_query("dog", "lazy dog~1")
+    index.on_match(lambda _id, names: matches.append((_id, names)))
+
+    with index.session():
+        first = index.add("quick brown fox")
+        index.add("nothing to see here")
+        second = index.add("lazy dogs and a fox")
+
+    assert matches == [(first, ["fox", "quick fox"]), (second, ["dog", "fox"])]
+
+    matches.clear()
+    assert index.unregister_query("fox")
+    assert not index.unregister_query("fox")
+    index.add("fox only")
+    assert matches == []
+
+    index.on_match(None)
+    index.add("quick fox")
+    assert matches == []
+
+    with pytest.raises(ValueError):
+        index.register_query("invalid", '"unclosed')
+
+
+def test_search_sweep():
+    search = MiniSearch()
+    _, index = search.add("sweep", MINISEARCH_DIR)
+
+    with index.session():
+        index.add("quick fox")
+        deleted = index.add("lazy dog")
+
+    index.delete(deleted)
+    assert index.sweep() == {"postings": 0, "tokens": 0}
+
+    with index.session():
+        pass
+
+    assert index.sweep() == {"postings": 0, "tokens": 0}
+    assert index.search("dog~1") == []
+    assert [r.document.content for r in index.search("fox")] == ["quick fox"]
+
+
+def test_document_corrupted():
+    search = MiniSearch()
+    _, index = search.add("corrupted", MINISEARCH_DIR)
+
+    with index.session():
+        _id = index.add("quick fox")
+
+    raw = index.read_raw(_id)
+    assert isinstance(raw, bytes) and len(raw) > 0
+
+    for path in glob.glob(f"{MINISEARCH_DIR}/**/data", recursive=True):
+        if os.path.isfile(path):
+            with open(path, "r+b") as f:
+                f.write(b"\xff" * os.path.getsize(path))
+
+    with pytest.raises(DocumentCorrupted):
+        index.get(_id).content
+
+    assert index.read_raw(_id) == b"\xff" * len(raw)
+
+
+def test_inline_documents():
+    conf = "tests/assets/inline_documents_test_conf.toml"
+    search = MiniSearch()
+    _, index = search.add("inline", MINISEARCH_DIR, conf)
+
+    with index.session():
+        short = index.add("quick fox")
+        long = index.add("the quick brown fox jumps over the lazy dog")
+        titles = [index.add(f"title {i}") for i in range(200)]
+
+    def data_size():
+        paths = glob.glob(f"{MINISEARCH_DIR}/**/data", recursive=True)
+        return sum(os.path.getsize(path) for path in paths)
+
+    # only the long document is in data files, meta counts into segment size
+    assert data_size() == len(index.read_raw(long))
+    assert len(glob.glob(f"{MINISEARCH_DIR}/*/meta")) > 1
+    assert index.get(short).content == "quick fox"
+
+    with index.session():
+        for _id in titles[:150]:
+            index.delete(_id)
+        index.merge()
+
+    del search, index
+    search = MiniSearch()
+    _, index = search.add("inline", MINISEARCH_DIR, conf)
+    assert index.get(short).content == "quick fox"
+    assert index.get(titles[199]).content == "title 199"
+    assert len(index.search("title", top_k=0)) == 50
+
+    # inline content doesn't depend on data file
+    for path in glob.glob(f"{MINISEARCH_DIR}/**/data", recursive=True):
+        with open(path, "r+b") as f:
+            f.write(b"\xff" * os.path.getsize(path))
+
+    del search, index
+    _, index = MiniSearch().add("inline", MINISEARCH_DIR, conf)
+    assert index.get(short).content == "quick fox"
+    with pytest.raises(DocumentCorrupted):
+        index.get(long).content
+
+
+def test_document_payload(subtests):
+    search = MiniSearch()
+    _, index = search.add("payload", MINISEARCH_DIR)
+
+    payload = {"url": "https://example.com/fox", "thumbnail": "fox.png"}
+    with index.session():
+        fox = index.add("quick brown fox", payload=payload)
+        dog = index.add("lazy dog", payload={"url": "https://example.com/dog"})
+        plain = index.add("lazy dog", payload={})
+        deleted = index.add("deleted fox", payload={"url": "https://example.com/deleted"})
+
+    with subtests.test(msg="test_document_payload [results]"):
+        results = index.search("fox")
+        assert {r.document.id: r.document.payload for r in results} == {fox: payload, deleted: {"url": "https://example.com/deleted"}}
+        assert index.get(fox).content == "quick brown fox"
+        assert index.get(plain).payload == {}
+        # raw content doesn't include payload
+        assert index.read_raw(dog) == index.read_raw(plain)
+        assert index.get(dog).content == "lazy dog"
+
+    with subtests.test(msg="test_document_payload [not indexed]"):
+        assert index.search("example") == []
+        assert index.search("png") == []
+
+    with subtests.test(msg="test_document_payload [merge]"):
+        with index.session():
+            index.delete(deleted)
+            index.merge()
+        del search, index
+        _, index = MiniSearch().add("payload", MINISEARCH_DIR)
+        assert index.get(fox).payload == payload
+        assert index.get(fox).content == "quick brown fox"
+
+    with subtests.test(msg="test_document_payload [reindex]"):
+        target = index.reindex_into(os.path.join(MINISEARCH_DIR, "target"))
+        assert target.get(fox).payload == payload
+        target = index.reindex_into(
+            os.path.join(MINISEARCH_DIR, "replaced"),
+            transform=lambda doc, attributes: {"payload": {"url": doc.payload["url"]}} if doc.payload else None,
+        )
+        assert target.get(fox).payload == {"url": payload["url"]}
+
+
+def test_inline_document_payload():
+    conf = "tests/assets/inline_documents_test_conf.toml"
+    _, index = MiniSearch().add("inline_payload", MINISEARCH_DIR, conf)
+
+    with index.session():
+        short = index.add("fox", payload={"id": "1"})
+        long = index.add("fox", payload={"url": "https://example.com/" + "x" * 100})
+
+    assert index.get(short).payload == {"id": "1"}
+    assert index.get(long).payload == {"url": "https://example.com/" + "x" * 100}
+    assert index.get(long).content == "fox"
+
+
+
+def test_max_content_bytes(subtests):
+    search = MiniSearch()
+    _, index = search.add("max_content", MINISEARCH_DIR)
+
+    rng = random.Random(7)
+    # repetitive content compresses into long matches, random one into long literal runs
+    repetitive = "fox jumps over the lazy dog " * 20000
+    noise = "".join(chr(rng.randint(33, 126)) for _ in range(100000))
+    with index.session():
+        long = index.add(repetitive, payload={"url": "https://example.com/fox"})
+        random_long = index.add("fox " + noise)
+        unicode = index.add("fox żółw")
+        short = index.add("fox")
+
+    with subtests.test(msg="test_max_content_bytes [search]"):
+        results = {r.document.id: r.document for r in index.search("fox", max_content_bytes=10)}
+        assert results[long].content == repetitive[:10]
+        assert results[long].content_bytes == repetitive[:10].encode()
+        assert results[long].is_truncated
+        assert results[long].payload == {"url": "https://example.com/fox"}
+        assert results[random_long].content == ("fox " + noise)[:10]
+        assert results[short].content == "fox"
+        assert not results[short].is_truncated
+        # "ż" doesn't fit whole, so content ends before it
+        assert index.search("fox", max_content_bytes=5, restrict_ids=[unicode])[0].document.content == "fox "
+        assert index.search("fox", max_content_bytes=6, restrict_ids=[unicode])[0].document.content == "fox ż"
+
+    with subtests.test(msg="test_max_content_bytes [long prefix]"):
+        results = {r.document.id: r.document for r in index.search("fox", max_content_bytes=300000)}
+        assert results[long].content == repetitive[:300000]
+        assert results[random_long].content == "fox " + noise
+        assert not results[random_long].is_truncated
+
+    with subtests.test(msg="test_max_content_bytes [get and options]"):
+        doc = index.get(long, max_content_bytes=100)
+        assert doc.content == repetitive[:100] and doc.is_truncated
+        assert index.get(long).content == repetitive
+        assert not index.get(long).is_truncated
+        results = index.search("fox", options=SearchOptions(top_k=10, max_content_bytes=3))
+        assert {r.document.content for r in results} == {"fox"}
+
+    with subtests.test(msg="test_max_content_bytes [invalid]"):
+        with pytest.raises(ValueError):
+            index.search("fox", max_content_bytes=0)
+        with pytest.raises(ValueError):
+            index.get(long, max_content_bytes=0)
+
+    with subtests.test(msg="test_max_content_bytes [inline]"):
+        _, inline = MiniSearch().add(
+            "max_content_inline", os.path.join(MINISEARCH_DIR, "inline"), "tests/assets/inline_documents_test_conf.toml"
+        )
+        with inline.session():
+            doc_id = inline.add("quick brown fox")
+        doc = inline.get(doc_id, max_content_bytes=5)
+        assert doc.content == "quick" and doc.is_truncated
+
+def test_search_disk_documents(subtests, data, queries, results):
+    conf = "tests/assets/disk_test_conf.toml"
+
+    with subtests.test(msg="test_search_disk_documents [new data]"):
+        (data, deletes), results = data("test_deletes"), results("test_deletes")
+
+        search = MiniSearch()
+        _, index = search.add("wikipedia", MINISEARCH_DIR, conf)
+
+        to_delete = []
+        with index.session():
+            for d in data:
+                index.add(d)
+
+            for d in deletes:
+                to_delete.append(index.add(d))
+
+        with index.session():
+            for _id in to_delete:
+                index.delete(_id)
+
+            index.merge()
+
+        validate_all_results([0, 5], range(0, 2), range(0, 2), index, queries, results)
+
+    with subtests.test(msg="test_search_disk_documents [persisted data]"):
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("wikipedia", MINISEARCH_DIR, conf)
+
+        validate_all_results([0, 5], range(0, 2), range(0, 2), index, queries, results)
+        with pytest.raises(KeyError):
+            index.get(to_delete[0])
+
+
+def test_documents_offsets(subtests):
+    conf = "tests/assets/offsets_test_conf.toml"
+    search = MiniSearch()
+    _, index = search.add("offsets", MINISEARCH_DIR, conf)
+
+    with index.session():
+        ids = [index.add(f"document {i} number{i}") for i in range(200)]
+
+    offsets = glob.glob(f"{MINISEARCH_DIR}/**/offsets", recursive=True)
+    with subtests.test(msg="test_documents_offsets [written]"):
+        # one entry per 64 meta records
+        assert [os.path.getsize(path) for path in offsets] == [3 * 40]
+
+    for storage in ["disk", "memory"]:
+        with subtests.test(msg=f"test_documents_offsets [{storage} reload]"):
+            del search, index
+            search = MiniSearch()
+            _, index = search.add("offsets", MINISEARCH_DIR, conf if storage == "disk" else None)
+
+            for i in [0, 63, 64, 150, 199]:
+                assert index.get(ids[i]).content == f"document {i} number{i}"
+                assert [r.document.id for r in index.search(f"number{i}")] == [ids[i]]
+            assert len(index.search("*", top_k=0)) == 200
+
+    with subtests.test(msg="test_documents_offsets [deletes]"):
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("offsets", MINISEARCH_DIR, conf)
+        with index.session():
+            index.delete(ids[10])
+            ids.append(index.add("document 200 number200"))
+
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("offsets", MINISEARCH_DIR, conf)
+        with pytest.raises(KeyError):
+            index.get(ids[10])
+        assert index.get(ids[200]).content == "document 200 number200"
+        assert len(index.search("document", top_k=0)) == 200
+
+
+def test_search_sharded_index(subtests, data, queries, results):
+    conf = "tests/assets/shards_test_conf.toml"
+
+    with subtests.test(msg="test_search_sharded_index [new data]"):
+        (data, deletes), results = data("test_deletes"), results("test_deletes")
+
+        search = MiniSearch()
+        _, index = search.add("sharded", MINISEARCH_DIR, conf)
+
+        to_delete = []
+        with index.session():
+            for d in data:
+                index.add(d)
+
+            for d in deletes:
+                to_delete.append(index.add(d))
+
+        with index.session():
+            for _id in to_delete:
+                index.delete(_id)
+
+            index.merge()
+
+        validate_all_results([0, 5], range(0, 2), range(0, 2), index, queries, results)
+
+    with subtests.test(msg="test_search_sharded_index [persisted data]"):
+        del search, index
+        search = MiniSearch()
+        _, index = search.add("sharded", MINISEARCH_DIR, conf)
+
+        validate_all_results([0, 5], range(0, 2), range(0, 2), index, queries, results)
+        with pytest.raises(KeyError):
+            index.get(to_delete[0])
+
+    with subtests.test(msg="test_search_sharded_index [shards mismatch]"):
+        del search, index
+        with pytest.raises(ValueError):
+            MiniSearch().add("sharded", MINISEARCH_DIR)
+
+
+def test_num_threads():
+    _, index = MiniSearch().add("threads", MINISEARCH_DIR, "tests/assets/threads_test_conf.toml")
+
+    docs = [f"document {i} about topic{i % 7}" for i in range(100)]
+    with index.session():
+        ids = [index.add(doc) for doc in docs]
+    expected = {q: [r.document.id for r in index.search(q, top_k=0)] for q in ["document", "topic3", "5"]}
+    assert set(expected["document"]) == set(ids)
+
+    # shards written by multiple threads are loaded one by one in the calling thread
+    del index
+    _, index = MiniSearch().add("single_thread", MINISEARCH_DIR, "tests/assets/single_thread_test_conf.toml")
+    for query, found in expected.items():
+        assert [r.document.id for r in index.search(query, top_k=0)] == found
+
+    with pytest.raises(ValueError):
+        MiniSearch().add("invalid_threads", MINISEARCH_DIR, "tests/assets/invalid_threads_test_conf.toml")
+
+
+def test_shared_directory():
+    _, first = MiniSearch().add("first", MINISEARCH_DIR)
+    _, second = MiniSearch().add("second", os.path.join(MINISEARCH_DIR, "."))
+
+    # handles of the same directory share state, so changes are visible without reload
+    with first.session():
+        fox = first.add("quick brown fox")
+    assert [r.document.id for r in second.search("fox")] == [fox]
+    second.delete(fox)
+    assert first.search("fox") == []
+
+    # open directory can't be opened with another config
+    with pytest.raises(ValueError):
+        MiniSearch().add("other", MINISEARCH_DIR, "tests/assets/coordination_test_conf.toml")
+
+    # directory is closed with its last handle
+    with first.session():
+        dog = first.add("lazy dog")
+    del first, second
+    _, index = MiniSearch().add("other", MINISEARCH_DIR, "tests/assets/coordination_test_conf.toml")
+    assert [r.document.id for r in index.search("dog")] == [dog]
+    assert index.search("fox") == []
+
+
+def test_index_alias():
+    search = MiniSearch()
+    _, v1 = search.add("products_v1", os.path.join(MINISEARCH_DIR, "v1"))
+    with v1.session():
+        old = v1.add("red shoes")
+
+    search.alias("live", "products_v1")
+    assert search.has_index("live")
+    assert search.aliases() == {"live": "products_v1"}
+    assert search.add("live", os.path.join(MINISEARCH_DIR, "v1")) == (False, v1)
+    serving = search.index("live")
+    assert [r.document.id for r in serving.search("shoes")] == [old]
+
+    # rebuilt index replaces the serving one, previous handle keeps working
+    _, v2 = search.add("products_v2", os.path.join(MINISEARCH_DIR, "v2"))
+    with v2.session():
+        new = v2.add("red shoes and boots")
+    search.alias("live", "products_v2")
+    assert [r.document.id for r in search.index("live").search("shoes")] == [new]
+    assert [r.document.id for r in serving.search("shoes")] == [old]
+
+    with pytest.raises(KeyError):
+        search.alias("staging", "products_v3")
+    with pytest.raises(ValueError):
+        search.alias("products_v1", "products_v2")
+    # index can't be removed while alias points to it
+    with pytest.raises(ValueError):
+        search.delete("products_v2")
+
+    search.delete("live")
+    assert not search.has_index("live")
+    search.delete("products_v2")
+    assert not search.has_index("products_v2")
+
+
+def test_reindex_into():
+    search = MiniSearch()
+    _, v1 = search.add("products_v1", os.path.join(MINISEARCH_DIR, "v1"))
+    with v1.session():
+        cafe = v1.add("Café menu", attributes={"price": 5.0}, boost=2.0)
+        shoes = v1.add("red shoes", fields={"title": "shoes"})
+        draft = v1.add("draft document")
+        removed = v1.add("removed document")
+        unique = v1.add_idempotent("unique document")
+    v1.delete(removed)
+    search.alias("live", "products_v1")
+
+    def transform(document, attributes):
+        if document.id == draft:
+            return None
+        if document.id == shoes:
+            return {"fields": {"title": "shoes"}, "doc": document.content + " and boots"}
+        return {}
+
+    # analyzer change, new index folds diacritics
+    v2 = v1.reindex_into(os.path.join(MINISEARCH_DIR, "v2"), "tests/assets/ascii_folding_test_conf.toml", transform)
+    search.add("products_v2", os.path.join(MINISEARCH_DIR, "v2"), "tests/assets/ascii_folding_test_conf.toml")
+    search.alias("live", "products_v2")
+    live = search.index("live")
+    assert live.get(cafe).content == v2.get(cafe).content
+
+    # documents keep their ids, attributes and boost, fields returned by transform are indexed
+    assert [r.document.id for r in live.search("cafe")] == [cafe]
+    assert v1.search("cafe") == []
+    assert live.attributes(cafe) == {"price": 5.0}
+    assert live.get(cafe).boost == 2.0
+    assert [r.document.id for r in live.search("title:shoes")] == [shoes]
+    assert live.get(shoes).content == "red shoes and boots"
+    assert {r.document.id for r in live.search("document", top_k=0)} == {unique}
+    assert live.add_idempotent("unique document") == unique
+    assert live.add("new document") > unique
+
+    with pytest.raises(ValueError):
+        v1.reindex_into(os.path.join(MINISEARCH_DIR, "v2"), "tests/assets/ascii_folding_test_conf.toml")
+    with pytest.raises(ValueError):
+        v1.reindex_into(os.path.join(MINISEARCH_DIR, "v1"))
+
+    # fields aren't stored, so they can't be silently lost
+    with pytest.raises(ValueError):
+        v1.reindex_into(os.path.join(MINISEARCH_DIR, "v3"))
+    _, v3 = search.add("products_v3", os.path.join(MINISEARCH_DIR, "v3"))
+    assert v3.search("document", top_k=0) == []
+    with pytest.raises(ValueError):
+        v1.reindex_into(os.path.join(MINISEARCH_DIR, "v4"), transform=lambda document, attributes: {})
+
+    def keep_title(document, attributes):
+        return {"fields": {"title": "shoes"}} if document.id == shoes else {}
+
+    v5 = v1.reindex_into(os.path.join(MINISEARCH_DIR, "v5"), transform=keep_title)
+    assert [r.document.id for r in v5.search("title:shoes")] == [shoes]
+    v6 = v1.reindex_into(
+        os.path.join(MINISEARCH_DIR, "v6"),
+        transform=lambda document, attributes: {"fields": {}} if document.id == shoes else {},
+    )
+    assert v6.search("title:shoes") == []
+    assert [r.document.id for r in v6.search("shoes")] == [shoes]
+
+
+def test_diff_results():
+    search = MiniSearch()
+    _, index = search.add("diff", MINISEARCH_DIR)
+
+    with index.session():
+        first = index.add("rust rust rust guide")
+        second = index.add("rust rust tutorial")
+        third = index.add("rust language and its ecosystem of crates")
+
+    snapshot = [r.document.id for r in index.search("rust", top_k=3)]
+    assert snapshot == [first, second, third]
+    assert index.diff_results("rust", snapshot) == {"added": [], "dropped": [], "moved": []}
+
+    with index.session():
+        newest = index.add("rust rust rust rust")
+    index.delete(second)
+
+    diff = index.diff_results("rust", snapshot)
+    assert diff == {
+        "added": [(newest, 0)],
+        "dropped": [(second, 1)],
+        "moved": [(first, 0, 1)],
+    }
+    # document pushed out of top k is dropped
+    diff = index.diff_results("rust", snapshot, top_k=2)
+    assert diff["dropped"] == [(second, 1), (third, 2)]
+    diff = index.diff_results("rust", snapshot, options=SearchOptions(top_k=1, exclude_ids=[newest]))
+    assert diff == {"added": [], "dropped": [(second, 1), (third, 2)], "moved": []}
+
+    with pytest.raises(UlidDecodeError):
+        index.diff_results("rust", ["invalid"])
+
+
+def test_shadow_search():
+    search = MiniSearch()
+    _, index = search.add("shadow", MINISEARCH_DIR)
+
+    with index.session():
+        short = index.add("rust guide")
+        long = index.add("rust rust ownership explained with borrowing lifetimes traits and generics")
+        index.add("python guide")
+
+    with pytest.raises(ValueError):
+        index.shadow_stats()
+    with pytest.raises(ValueError):
+        index.search("rust", shadow=True)
+
+    # experiment without length normalization ranks documents differently
+    index.set_shadow(SearchOptions(length_norm=False))
+    live = [r.document.id for r in index.search("rust", top_k=1)]
+    assert [r.document.id for r in index.search("rust", top_k=1, shadow=True)] == live == [short]
+    assert [r.document.id for r in index.search("guide", top_k=0, shadow=True)] == [
+        r.document.id for r in index.search("guide", top_k=0)
+    ]
+
+    stats = index.shadow_stats()
+    assert stats["queries"] == 2
+    assert stats["errors"] == 0
+    assert stats["overlap"] == pytest.approx(0.5)
+    assert stats["top_match"] == pytest.approx(0.5)
+    assert stats["live_seconds"] > 0 and stats["experiment_seconds"] > 0
+    assert [r.document.id for r in index.search("rust", top_k=1, length_norm=False)] == [long]
+
+    # failing experiment doesn't affect live results
+    index.set_shadow(SearchOptions(score_script="score *"))
+    assert [r.document.id for r in index.search("rust", top_k=1, shadow=True)] == [short]
+    assert index.shadow_stats()["queries"] == 1
+    assert index.shadow_stats()["errors"] == 1
+
+    index.set_shadow(None)
+    with pytest.raises(ValueError):
+        index.search("rust", shadow=True)
+
+
+def test_search_phrase_synonyms(subtests):
+    search = MiniSearch()
+    _, index = search.add("phrase_synonyms", MINISEARCH_DIR, "tests/assets/phrase_synonyms_test_conf.toml")
+
+    with index.session():
+        new_york = index.add("pizza in new york city")
+        nyc = index.add("best nyc pizza")
+        big_apple = index.add("the big apple pizza")
+        la = index.add("la pizza")
+        scattered = index.add("new pizza in york")
+
+    def ids(query):
+        return {r.document.id for r in index.search(query, top_k=0)}
+
+    with subtests.test(msg="test_search_phrase_synonyms [group]"):
+        assert ids("new york") == {new_york, nyc, big_apple}
+        assert ids("NYC") == {new_york, nyc, big_apple}
+        assert ids("big apple") == {new_york, nyc, big_apple}
+        assert ids('"new york city"') == {new_york}
+
+    with subtests.test(msg="test_search_phrase_synonyms [phrase]"):
+        # replaced words are matched at their place in the clause
+        assert ids("nyc pizza") == {nyc, big_apple}
+        assert ids("pizza -nyc") == {la, scattered}
+
+    with subtests.test(msg="test_search_phrase_synonyms [one direction]"):
+        assert ids("los angeles") == {la}
+        assert ids("la") == {la}
+
+    with subtests.test(msg="test_search_phrase_synonyms [score]"):
+        # document matching several alternatives is scored by the best one
+        results = index.search("nyc")
+        assert {r.document.id for r in results} == {new_york, nyc, big_apple}
+        assert all(r.score > 0 for r in results)
+
+    with subtests.test(msg="test_search_phrase_synonyms [parse]"):
+        tree = index.parse("nyc")
+        assert tree["type"] == "any"
+        assert [[t["text"] for t in q["terms"]] for q in tree["queries"]] == [
+            ["nyc"],
+            ["new", "york"],
+            ["big", "apple"],
+        ]
+
+def test_search_analyze_query():
+    search = MiniSearch()
+    _, index = search.add("analyze_query", MINISEARCH_DIR, "tests/assets/synonyms_test_conf.toml")
+
+    with index.session():
+        index.add("fast car running")
+        index.add("the cat ran home")
+
+    results, analysis = index.search("the quick running cars~1", analyze_query=True)
+    assert results == []
+    assert analysis == [
+        {"term": "the", "field": None, "tokens": [], "synonyms": [], "expansions": []},
+        {"term": "quick", "field": None, "tokens": ["quick"], "synonyms": ["fast"], "expansions": []},
+        {"term": "running", "field": None, "tokens": ["run"], "synonyms": [], "expansions": []},
+        {"term": "cars", "field": None, "tokens": ["car"], "synonyms": [], "expansions": ["cat"]},
+    ]
+
+    # profile report comes before analysis, negated terms are analyzed as well
+    results, profile, analysis = index.search('"quick car" -home', analyze_query=True, profile="flame")
+    assert len(results) == 1
+    assert profile["name"] == "search"
+    assert [(a["term"], a["tokens"]) for a in analysis] == [
+        ("quick", ["quick"]),
+        ("car", ["car"]),
+        ("home", ["home"]),
+    ]
+    assert analysis[1]["synonyms"] == ["automobil", "auto"]
+    assert len(index.search("quick car")) == 1
+
+def test_search_defaults():
+    search = MiniSearch()
+    _, index = search.add("search_defaults", MINISEARCH_DIR, "tests/assets/search_defaults_test_conf.toml")
+
+    with index.session():
+        ids = [index.add(f"brown fox {i}") for i in range(3)]
+        box = index.add("brown box")
+
+    # defaults apply to options which weren't set by argument or in passed options
+    assert len(index.search("brown")) == 2
+    assert len(index.search("brown", top_k=0)) == 4
+    assert len(index.search("brown", options=SearchOptions())) == 2
+    assert len(index.search("brown", options=SearchOptions(timeout=1.0))) == 2
+    assert len(index.search("brown", options=SearchOptions(top_k=0))) == 4
+    assert {r.document.id for r in index.search("fox", top_k=0)} == set(ids + [box])
+    assert {r.document.id for r in index.search("fox", options=SearchOptions(top_k=0))} == set(ids + [box])
+    assert {r.document.id for r in index.search("fox", options=SearchOptions(top_k=0, fuzziness=0))} == set(ids)
+    assert len(index.diff_results("brown", ids)["added"]) == 1
+
+    # fuzziness is limited by max_fuzziness of index config
+    with pytest.raises(ValueError):
+        index.search("fox", options=SearchOptions(fuzziness=2))
+
+    # unscoped terms match default fields too, unless options set other ones
+    titled = index.add("notes", fields={"title": "kettle"})
+    assert [r.document.id for r in index.search("kettle")] == [titled]
+    assert index.search("kettle", options=SearchOptions(fields=[])) == []
+    assert index.search("kettle", options=SearchOptions(fields=["tags"])) == []
+
+    with pytest.raises(ValueError):
+        MiniSearch().add("invalid", os.path.join(MINISEARCH_DIR, "invalid"), "tests/assets/invalid_search_defaults_test_conf.toml")
+    with pytest.raises(ValueError):
+        MiniSearch().add("invalid", os.path.join(MINISEARCH_DIR, "invalid"), "tests/assets/invalid_search_defaults_fields_test_conf.toml")
+
+
+def test_search_reload(subtests, data, queries, results):
+    (data, deletes), results = data("test_deletes"), results("test_deletes")
+
+    _, writer = MiniSearch().add("writer", MINISEARCH_DIR)
+    with writer.session():
+        for d in data[: len(data) // 2]:
+            writer.add(d)
+
+    _, replica = MiniSearch().add("replica", MINISEARCH_DIR)
+
+    with subtests.test(msg="test_search_reload [added data]"):
+        to_delete = []
+        with writer.session():
+            for d in data[len(data) // 2 :]:
+                writer.add(d)
+
+            for d in deletes:
+                to_delete.append(writer.add(d))
+
+        with writer.session():
+            for _id in to_delete:
+                writer.delete(_id)
+
+        replica.reload()
+        validate_all_results([0, 5], range(0, 2), range(0, 2), replica, queries, results)
+        with pytest.raises(KeyError):
+            replica.get(to_delete[0])
+
+    with subtests.test(msg="test_search_reload [same scores]"):
+        for q in queries:
+            expected = [(r.document.id, r.score) for r in writer.search(rust_query(q, 1, 1), top_k=0)]
+            assert [(r.document.id, r.score) for r in replica.search(rust_query(q, 1, 1), top_k=0)] == expected
+
+    with subtests.test(msg="test_search_reload [merged data]"):
+        writer.merge()
+        replica.reload()
+        validate_all_results([0, 5], range(0, 2), range(0, 2), replica, queries, results)